@@ -5,4 +5,20 @@
 
 package org.signal.libsignal.metadata;
 
-public class SelfSendException extends Exception {}
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+import org.signal.libsignal.protocol.HasProtocolErrorCode;
+
+public class SelfSendException extends Exception implements HasProtocolErrorCode {
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
+}
@@ -33,6 +33,7 @@ import org.signal.libsignal.protocol.message.CiphertextMessage;
 import org.signal.libsignal.protocol.message.PreKeySignalMessage;
 import org.signal.libsignal.protocol.message.SignalMessage;
 import org.signal.libsignal.protocol.state.IdentityKeyStore;
+import org.signal.libsignal.protocol.state.PqRatchetStatus;
 import org.signal.libsignal.protocol.state.PreKeyBundle;
 import org.signal.libsignal.protocol.state.SessionRecord;
 import org.signal.libsignal.protocol.state.SignalProtocolStore;
@@ -76,6 +77,8 @@ public class SessionBuilderTest {
 
         assertTrue(aliceStore.containsSession(BOB_ADDRESS));
         assertTrue(aliceStore.loadSession(BOB_ADDRESS).getSessionVersion() == expectedVersion);
+        assertEquals(
+            PqRatchetStatus.OFFERED, aliceStore.loadSession(BOB_ADDRESS).getPqRatchetStatus());
 
         String originalMessage = "initial hello!";
         SessionCipher aliceSessionCipher = new SessionCipher(aliceStore, BOB_ADDRESS);
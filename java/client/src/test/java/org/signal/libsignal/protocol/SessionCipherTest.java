@@ -11,6 +11,7 @@ import java.util.Arrays;
 import java.util.Collections;
 import java.util.LinkedList;
 import java.util.List;
+import java.util.OptionalInt;
 import java.util.Random;
 import junit.framework.TestCase;
 import org.signal.libsignal.protocol.ecc.ECKeyPair;
@@ -72,7 +73,7 @@ public class SessionCipherTest extends TestCase {
       bobCipher.decrypt(new SignalMessage(inflight.get(0).serialize()));
       throw new AssertionError("Should have failed!");
     } catch (DuplicateMessageException dme) {
-      // good
+      assertEquals(OptionalInt.of(401), dme.getProtocolErrorCode());
     }
   }
 
@@ -5,8 +5,23 @@
 
 package org.signal.libsignal.protocol;
 
-public class DuplicateMessageException extends Exception {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class DuplicateMessageException extends Exception implements HasProtocolErrorCode {
   public DuplicateMessageException(String s) {
     super(s);
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
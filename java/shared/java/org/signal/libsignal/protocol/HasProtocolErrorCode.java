@@ -0,0 +1,22 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.protocol;
+
+import java.util.OptionalInt;
+
+/**
+ * Implemented by the exceptions used to report protocol errors.
+ *
+ * <p>The hundreds digit of the code identifies the error's category, such as "replay or duplicate"
+ * or "untrusted identity". Codes are never reused, so they can be recorded as-is.
+ */
+public interface HasProtocolErrorCode {
+  /**
+   * Returns the stable numeric code of the protocol error, or nothing if this exception wasn't
+   * created for one.
+   */
+  OptionalInt getProtocolErrorCode();
+}
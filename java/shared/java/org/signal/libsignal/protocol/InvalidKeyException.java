@@ -5,7 +5,10 @@
 
 package org.signal.libsignal.protocol;
 
-public class InvalidKeyException extends Exception {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class InvalidKeyException extends Exception implements HasProtocolErrorCode {
 
   public InvalidKeyException() {}
 
@@ -20,4 +23,16 @@ public class InvalidKeyException extends Exception {
   public InvalidKeyException(String detailMessage, Throwable throwable) {
     super(detailMessage, throwable);
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
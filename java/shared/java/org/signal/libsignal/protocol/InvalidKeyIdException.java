@@ -5,7 +5,10 @@
 
 package org.signal.libsignal.protocol;
 
-public class InvalidKeyIdException extends Exception {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class InvalidKeyIdException extends Exception implements HasProtocolErrorCode {
   public InvalidKeyIdException(String detailMessage) {
     super(detailMessage);
   }
@@ -13,4 +16,16 @@ public class InvalidKeyIdException extends Exception {
   public InvalidKeyIdException(Throwable throwable) {
     super(throwable);
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
@@ -6,8 +6,10 @@
 package org.signal.libsignal.protocol;
 
 import java.util.List;
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
 
-public class InvalidMessageException extends Exception {
+public class InvalidMessageException extends Exception implements HasProtocolErrorCode {
 
   public InvalidMessageException() {}
 
@@ -26,4 +28,16 @@ public class InvalidMessageException extends Exception {
   public InvalidMessageException(String detailMessage, List<Exception> exceptions) {
     super(detailMessage, exceptions.get(0));
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
@@ -5,7 +5,10 @@
 
 package org.signal.libsignal.protocol;
 
-public class InvalidRegistrationIdException extends Exception {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class InvalidRegistrationIdException extends Exception implements HasProtocolErrorCode {
 
   private final SignalProtocolAddress address;
 
@@ -17,4 +20,16 @@ public class InvalidRegistrationIdException extends Exception {
   public SignalProtocolAddress getAddress() {
     return address;
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
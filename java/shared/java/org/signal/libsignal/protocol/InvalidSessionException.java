@@ -5,7 +5,10 @@
 
 package org.signal.libsignal.protocol;
 
-public class InvalidSessionException extends IllegalStateException {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class InvalidSessionException extends IllegalStateException implements HasProtocolErrorCode {
   public InvalidSessionException(String detailMessage) {
     super(detailMessage);
   }
@@ -13,4 +16,16 @@ public class InvalidSessionException extends IllegalStateException {
   public InvalidSessionException(String detailMessage, Throwable cause) {
     super(detailMessage, cause);
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
@@ -5,8 +5,23 @@
 
 package org.signal.libsignal.protocol;
 
-public class InvalidVersionException extends Exception {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class InvalidVersionException extends Exception implements HasProtocolErrorCode {
   public InvalidVersionException(String detailMessage) {
     super(detailMessage);
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
@@ -5,8 +5,23 @@
 
 package org.signal.libsignal.protocol;
 
-public class LegacyMessageException extends Exception {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class LegacyMessageException extends Exception implements HasProtocolErrorCode {
   public LegacyMessageException(String s) {
     super(s);
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
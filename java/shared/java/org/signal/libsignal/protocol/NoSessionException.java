@@ -5,7 +5,10 @@
 
 package org.signal.libsignal.protocol;
 
-public class NoSessionException extends Exception {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class NoSessionException extends Exception implements HasProtocolErrorCode {
   private final SignalProtocolAddress address;
 
   public NoSessionException(String message) {
@@ -20,4 +23,16 @@ public class NoSessionException extends Exception {
   public SignalProtocolAddress getAddress() {
     return address;
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
@@ -0,0 +1,29 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.protocol;
+
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+/** An {@link IllegalArgumentException} reported by the protocol, with its error code. */
+public class ProtocolArgumentException extends IllegalArgumentException
+    implements HasProtocolErrorCode {
+  public ProtocolArgumentException(String message) {
+    super(message);
+  }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
+}
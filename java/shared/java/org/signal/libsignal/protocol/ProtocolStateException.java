@@ -0,0 +1,28 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.protocol;
+
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+/** An {@link IllegalStateException} reported by the protocol, with its error code. */
+public class ProtocolStateException extends IllegalStateException implements HasProtocolErrorCode {
+  public ProtocolStateException(String message) {
+    super(message);
+  }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
+}
@@ -123,7 +123,7 @@ public class SessionBuilder {
                   sessionStore,
                   identityKeyStore,
                   now.toEpochMilli(),
-                  usePqRatchet == UsePqRatchet.YES));
+                  usePqRatchet.getValue()));
     }
   }
 }
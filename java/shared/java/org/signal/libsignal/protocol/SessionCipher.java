@@ -145,7 +145,7 @@ public class SessionCipher {
                   preKeyStore,
                   signedPreKeyStore,
                   kyberPreKeyStore,
-                  usePqRatchet == UsePqRatchet.YES));
+                  usePqRatchet.getValue()));
     }
   }
 
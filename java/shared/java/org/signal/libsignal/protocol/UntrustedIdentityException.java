@@ -5,7 +5,10 @@
 
 package org.signal.libsignal.protocol;
 
-public class UntrustedIdentityException extends Exception {
+import java.util.OptionalInt;
+import org.signal.libsignal.internal.CalledFromNative;
+
+public class UntrustedIdentityException extends Exception implements HasProtocolErrorCode {
 
   private final String name;
   private final IdentityKey key;
@@ -27,4 +30,16 @@ public class UntrustedIdentityException extends Exception {
   public String getName() {
     return name;
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
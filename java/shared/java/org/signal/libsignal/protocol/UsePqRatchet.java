@@ -6,6 +6,18 @@
 package org.signal.libsignal.protocol;
 
 public enum UsePqRatchet {
-  YES,
-  NO
+  YES(1),
+  NO(0),
+  /** Like {@link #YES}, but messages from a peer that doesn't use the PQ ratchet are rejected. */
+  REQUIRED(2);
+
+  private final int value;
+
+  UsePqRatchet(int value) {
+    this.value = value;
+  }
+
+  int getValue() {
+    return value;
+  }
 }
@@ -5,9 +5,13 @@
 
 package org.signal.libsignal.protocol.groups;
 
+import java.util.OptionalInt;
 import java.util.UUID;
+import org.signal.libsignal.internal.CalledFromNative;
+import org.signal.libsignal.protocol.HasProtocolErrorCode;
 
-public class InvalidSenderKeySessionException extends IllegalStateException {
+public class InvalidSenderKeySessionException extends IllegalStateException
+    implements HasProtocolErrorCode {
 
   private final UUID distributionId;
 
@@ -19,4 +23,16 @@ public class InvalidSenderKeySessionException extends IllegalStateException {
   public UUID getDistributionId() {
     return distributionId;
   }
+
+  private Integer protocolErrorCode;
+
+  @Override
+  public OptionalInt getProtocolErrorCode() {
+    return protocolErrorCode != null ? OptionalInt.of(protocolErrorCode) : OptionalInt.empty();
+  }
+
+  @CalledFromNative
+  private void setProtocolErrorCode(int code) {
+    protocolErrorCode = code;
+  }
 }
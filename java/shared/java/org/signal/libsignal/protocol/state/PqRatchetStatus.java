@@ -0,0 +1,20 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+package org.signal.libsignal.protocol.state;
+
+/** How far a session has got in negotiating the post-quantum ratchet with the peer. */
+public enum PqRatchetStatus {
+  // These must be kept in the same order as PqRatchetStatus in Rust.
+
+  /** The session was created without offering the post-quantum ratchet. */
+  DISABLED,
+  /** The post-quantum ratchet was offered, but no message from the peer has been decrypted yet. */
+  OFFERED,
+  /** The peer has used the post-quantum ratchet, and may not stop using it. */
+  ACTIVE,
+  /** The peer did not use the post-quantum ratchet, so the session uses the classical ratchet. */
+  DECLINED,
+}
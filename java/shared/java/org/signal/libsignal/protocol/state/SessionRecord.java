@@ -64,6 +64,17 @@ public class SessionRecord extends NativeHandleGuard.SimpleOwner {
     return filterExceptions(() -> guardedMapChecked(Native::SessionRecord_GetLocalRegistrationId));
   }
 
+  /**
+   * Returns how far the current session has got in negotiating the post-quantum ratchet.
+   *
+   * @throws IllegalStateException if there is no current session.
+   */
+  public PqRatchetStatus getPqRatchetStatus() {
+    int status =
+        filterExceptions(() -> guardedMapChecked(Native::SessionRecord_GetPqRatchetStatus));
+    return PqRatchetStatus.values()[status];
+  }
+
   public IdentityKey getRemoteIdentityKey() {
     try (NativeHandleGuard guard = new NativeHandleGuard(this)) {
       byte[] keyBytes =
@@ -70,13 +70,18 @@ export enum ErrorCode {
 export class LibSignalErrorBase extends Error {
   public readonly code: ErrorCode;
   public readonly operation: string;
+  /// The stable numeric code of the underlying protocol error, if this came from one.
+  ///
+  /// The hundreds digit identifies the error's category.
+  public readonly protocolErrorCode?: number;
   readonly _addr?: string | Native.ProtocolAddress;
 
   constructor(
     message: string,
     name: keyof typeof ErrorCode | undefined,
     operation: string,
-    extraProps?: Record<string, unknown>
+    extraProps?: Record<string, unknown>,
+    protocolErrorCode?: number
   ) {
     super(message);
     // Include the dynamic check for `name in ErrorCode` in case there's a bug in the Rust code.
@@ -88,6 +93,7 @@ export class LibSignalErrorBase extends Error {
       this.code = ErrorCode.Generic;
     }
     this.operation = operation;
+    this.protocolErrorCode = protocolErrorCode;
     if (extraProps !== undefined) {
       Object.assign(this, extraProps);
     }
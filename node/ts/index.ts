@@ -47,9 +47,26 @@ export enum ContentHint {
   Implicit = 2,
 }
 
+// These values must match UsePQRatchet in Rust.
 export enum UsePQRatchet {
-  Yes,
-  No,
+  No = 0,
+  Yes = 1,
+  /// Like `Yes`, but messages from a peer that doesn't use the PQ ratchet are rejected.
+  Required = 2,
+}
+
+/// How far a session has got in negotiating the post-quantum ratchet with the peer.
+///
+/// These values must match PqRatchetStatus in Rust.
+export enum PqRatchetStatus {
+  /// The session was created without offering the post-quantum ratchet.
+  Disabled = 0,
+  /// The post-quantum ratchet was offered, but no message from the peer has been decrypted yet.
+  Offered = 1,
+  /// The peer has used the post-quantum ratchet, and may not stop using it.
+  Active = 2,
+  /// The peer did not use the post-quantum ratchet, so the session uses the classical ratchet.
+  Declined = 3,
 }
 
 export type Uuid = string;
@@ -655,6 +672,15 @@ export class SessionRecord {
     return Native.SessionRecord_GetRemoteRegistrationId(this);
   }
 
+  /**
+   * Returns how far the current session has got in negotiating the post-quantum ratchet.
+   *
+   * Throws if there is no current session.
+   */
+  pqRatchetStatus(): PqRatchetStatus {
+    return Native.SessionRecord_GetPqRatchetStatus(this) as PqRatchetStatus;
+  }
+
   /**
    * Returns whether the current session can be used to send messages.
    *
@@ -1458,7 +1484,7 @@ export function processPreKeyBundle(
     sessionStore,
     identityStore,
     now.getTime(),
-    usePqRatchet == UsePQRatchet.Yes
+    usePqRatchet
   );
 }
 
@@ -1512,7 +1538,7 @@ export function signalDecryptPreKey(
     prekeyStore,
     signedPrekeyStore,
     kyberPrekeyStore,
-    usePqRatchet == UsePQRatchet.Yes
+    usePqRatchet
   );
 }
 
@@ -1630,7 +1656,7 @@ export async function sealedSenderDecryptMessage(
     prekeyStore,
     signedPrekeyStore,
     kyberPrekeyStore,
-    usePqRatchet == UsePQRatchet.Yes
+    usePqRatchet
   );
   return SealedSenderDecryptionResult._fromNativeHandle(ssdr);
 }
@@ -805,6 +805,10 @@ for (const testCase of sessionVersionTestCases) {
       assert(session.serialize().length > 0);
       assert.deepEqual(session.localRegistrationId(), 5);
       assert.deepEqual(session.remoteRegistrationId(), 5);
+      assert.equal(
+        session.pqRatchetStatus(),
+        SignalClient.PqRatchetStatus.Active
+      );
       assert(session.hasCurrentState());
       assert(
         !session.currentRatchetKeyMatches(
@@ -885,6 +889,7 @@ for (const testCase of sessionVersionTestCases) {
         const err = e as SignalClient.LibSignalError;
         assert.equal(err.name, 'DuplicatedMessage');
         assert.equal(err.code, SignalClient.ErrorCode.DuplicatedMessage);
+        assert.equal(err.protocolErrorCode, 401);
         assert.equal(err.operation, 'SessionCipher_DecryptPreKeySignalMessage'); // the Rust entry point
         assert.exists(err.stack); // Make sure we're still getting the benefits of Error.
       }
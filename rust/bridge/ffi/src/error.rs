@@ -156,6 +156,13 @@ fn Error_GetTheirFingerprintVersion(err: &SignalFfiError) -> Result<u32, Illegal
         .theirs)
 }
 
+#[bridge_fn(jni = false, node = false)]
+fn Error_GetProtocolErrorCode(err: &SignalFfiError) -> Result<u32, IllegalArgumentError> {
+    err.provide_protocol_error_code().map_err(|_| {
+        IllegalArgumentError::new(format!("cannot get protocol_error_code from error ({err})"))
+    })
+}
+
 #[bridge_fn(jni = false, node = false)]
 fn Error_GetRetryAfterSeconds(err: &SignalFfiError) -> Result<u32, IllegalArgumentError> {
     err.provide_retry_after_seconds().map_err(|_| {
@@ -97,6 +97,7 @@ async fn MessageBackupValidator_Validate(
                 let ReadResult {
                     result,
                     found_unknown_fields,
+                    ..
                 } = reader.validate_all().await;
 
                 (result.err().map(Into::into), found_unknown_fields)
@@ -3,6 +3,8 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::time::SystemTime;
+
 // Will be unused when building for Node only.
 #[allow(unused_imports)]
 use futures_util::FutureExt;
@@ -1000,6 +1002,14 @@ bridge_get!(
 bridge_get!(SessionRecord::local_registration_id -> u32);
 bridge_get!(SessionRecord::remote_registration_id -> u32);
 
+#[bridge_fn]
+fn SessionRecord_GetPqRatchetStatus(s: &SessionRecord) -> Result<u8> {
+    let status = s.pq_ratchet_status().ok_or_else(|| {
+        SignalProtocolError::InvalidState("pq_ratchet_status", "No current session".into())
+    })?;
+    Ok(status as u8)
+}
+
 bridge_get!(SealedSenderDecryptionResult::sender_uuid -> String, ffi = false, jni = false);
 bridge_get!(SealedSenderDecryptionResult::sender_e164 -> Option<String>, ffi = false, jni = false);
 bridge_get!(SealedSenderDecryptionResult::device_id -> u32, ffi = false, jni = false);
@@ -1009,8 +1019,9 @@ bridge_get!(
     jni = false
 );
 
-#[bridge_fn(ffi = "process_prekey_bundle")]
-async fn SessionBuilder_ProcessPreKeyBundle(
+// Kept so that existing FFI clients, which pass a bool, keep working.
+#[bridge_fn(ffi = "process_prekey_bundle", jni = false, node = false)]
+async fn SessionBuilder_ProcessPreKeyBundleWithBool(
     bundle: &PreKeyBundle,
     protocol_address: &ProtocolAddress,
     session_store: &mut dyn SessionStore,
@@ -1031,6 +1042,28 @@ async fn SessionBuilder_ProcessPreKeyBundle(
     .await
 }
 
+#[bridge_fn(ffi = "process_prekey_bundle_with_pq_ratchet_policy")]
+async fn SessionBuilder_ProcessPreKeyBundle(
+    bundle: &PreKeyBundle,
+    protocol_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_key_store: &mut dyn IdentityKeyStore,
+    now: Timestamp,
+    use_pq_ratchet: AsType<UsePQRatchet, u8>,
+) -> Result<()> {
+    let mut csprng = rand::rngs::OsRng.unwrap_err();
+    process_prekey_bundle(
+        protocol_address,
+        session_store,
+        identity_key_store,
+        bundle,
+        now.into(),
+        &mut csprng,
+        use_pq_ratchet.into_inner(),
+    )
+    .await
+}
+
 #[bridge_fn(ffi = "encrypt_message")]
 async fn SessionCipher_EncryptMessage(
     ptext: &[u8],
@@ -1064,13 +1097,16 @@ async fn SessionCipher_DecryptSignalMessage(
         protocol_address,
         session_store,
         identity_key_store,
+        SystemTime::now(),
         &mut csprng,
+        &SessionConfig::default(),
     )
     .await
 }
 
-#[bridge_fn(ffi = "decrypt_pre_key_message")]
-async fn SessionCipher_DecryptPreKeySignalMessage(
+// Kept so that existing FFI clients, which pass a bool, keep working.
+#[bridge_fn(ffi = "decrypt_pre_key_message", jni = false, node = false)]
+async fn SessionCipher_DecryptPreKeySignalMessageWithBool(
     message: &PreKeySignalMessage,
     protocol_address: &ProtocolAddress,
     session_store: &mut dyn SessionStore,
@@ -1089,8 +1125,38 @@ async fn SessionCipher_DecryptPreKeySignalMessage(
         prekey_store,
         signed_prekey_store,
         kyber_prekey_store,
+        SystemTime::now(),
         &mut csprng,
         UsePQRatchet::from(use_pq_ratchet),
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+#[bridge_fn(ffi = "decrypt_pre_key_message_with_pq_ratchet_policy")]
+async fn SessionCipher_DecryptPreKeySignalMessage(
+    message: &PreKeySignalMessage,
+    protocol_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_key_store: &mut dyn IdentityKeyStore,
+    prekey_store: &mut dyn PreKeyStore,
+    signed_prekey_store: &mut dyn SignedPreKeyStore,
+    kyber_prekey_store: &mut dyn KyberPreKeyStore,
+    use_pq_ratchet: AsType<UsePQRatchet, u8>,
+) -> Result<Vec<u8>> {
+    let mut csprng = rand::rngs::OsRng.unwrap_err();
+    message_decrypt_prekey(
+        message,
+        protocol_address,
+        session_store,
+        identity_key_store,
+        prekey_store,
+        signed_prekey_store,
+        kyber_prekey_store,
+        SystemTime::now(),
+        &mut csprng,
+        use_pq_ratchet.into_inner(),
+        &SessionConfig::default(),
     )
     .await
 }
@@ -1185,7 +1251,7 @@ async fn SealedSender_DecryptMessage(
     prekey_store: &mut dyn PreKeyStore,
     signed_prekey_store: &mut dyn SignedPreKeyStore,
     kyber_prekey_store: &mut dyn KyberPreKeyStore,
-    use_pq_ratchet: bool,
+    use_pq_ratchet: AsType<UsePQRatchet, u8>,
 ) -> Result<SealedSenderDecryptionResult> {
     let local_device_id = local_device_id
         .try_into()
@@ -1203,7 +1269,7 @@ async fn SealedSender_DecryptMessage(
         prekey_store,
         signed_prekey_store,
         kyber_prekey_store,
-        UsePQRatchet::from(use_pq_ratchet),
+        use_pq_ratchet.into_inner(),
     )
     .await
 }
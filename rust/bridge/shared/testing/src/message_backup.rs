@@ -29,6 +29,7 @@ async fn ComparableBackup_ReadUnencrypted(
     let ReadResult {
         result,
         found_unknown_fields,
+        ..
     } = reader.read_all().await;
 
     match result {
@@ -187,6 +187,9 @@ pub trait FfiError: UpcastAsAny + fmt::Debug + Send + 'static {
     fn provide_fingerprint_versions(&self) -> Result<FingerprintVersions, WrongErrorKind> {
         Err(WrongErrorKind)
     }
+    fn provide_protocol_error_code(&self) -> Result<u32, WrongErrorKind> {
+        Err(WrongErrorKind)
+    }
 }
 
 /// An [`FfiError`] that only has a code and message.
@@ -292,6 +295,7 @@ impl IntoFfiError for IllegalArgumentError {
 struct InvalidRegistrationId {
     peer_addr: ProtocolAddress,
     invalid_id: u32,
+    protocol_code: u32,
 }
 
 impl FfiError for InvalidRegistrationId {
@@ -307,6 +311,10 @@ impl FfiError for InvalidRegistrationId {
         SignalErrorCode::InvalidRegistrationId
     }
 
+    fn provide_protocol_error_code(&self) -> Result<u32, WrongErrorKind> {
+        Ok(self.protocol_code)
+    }
+
     fn provide_address(&self) -> Result<ProtocolAddress, WrongErrorKind> {
         Ok(self.peer_addr.clone())
     }
@@ -315,6 +323,7 @@ impl FfiError for InvalidRegistrationId {
 #[derive(Debug)]
 struct InvalidSenderKeySession {
     distribution_id: uuid::Uuid,
+    protocol_code: u32,
 }
 
 impl FfiError for InvalidSenderKeySession {
@@ -330,6 +339,10 @@ impl FfiError for InvalidSenderKeySession {
         SignalErrorCode::InvalidSenderKeySession
     }
 
+    fn provide_protocol_error_code(&self) -> Result<u32, WrongErrorKind> {
+        Ok(self.protocol_code)
+    }
+
     fn provide_uuid(&self) -> Result<uuid::Uuid, WrongErrorKind> {
         Ok(self.distribution_id)
     }
@@ -339,6 +352,7 @@ impl FfiError for InvalidSenderKeySession {
 struct InvalidProtocolAddress {
     name: String,
     device_id: u32,
+    protocol_code: u32,
 }
 
 impl FfiError for InvalidProtocolAddress {
@@ -354,16 +368,46 @@ impl FfiError for InvalidProtocolAddress {
         SignalErrorCode::InvalidProtocolAddress
     }
 
+    fn provide_protocol_error_code(&self) -> Result<u32, WrongErrorKind> {
+        Ok(self.protocol_code)
+    }
+
     fn provide_invalid_address(&self) -> Result<(&str, u32), WrongErrorKind> {
         Ok((&self.name, self.device_id))
     }
 }
 
+/// A [`SignalProtocolError`] that doesn't need any other special handling.
+#[derive(Debug)]
+struct ProtocolError {
+    code: SignalErrorCode,
+    protocol_code: u32,
+    message: String,
+}
+
+impl FfiError for ProtocolError {
+    fn describe(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.message)
+    }
+
+    fn code(&self) -> SignalErrorCode {
+        self.code
+    }
+
+    fn provide_protocol_error_code(&self) -> Result<u32, WrongErrorKind> {
+        Ok(self.protocol_code)
+    }
+}
+
 impl IntoFfiError for SignalProtocolError {
     fn into_ffi_error(self) -> impl Into<SignalFfiError> {
+        let protocol_code = self.code();
         let code = match &self {
             &Self::InvalidSenderKeySession { distribution_id } => {
-                return SignalFfiError::from(InvalidSenderKeySession { distribution_id });
+                return SignalFfiError::from(InvalidSenderKeySession {
+                    distribution_id,
+                    protocol_code,
+                });
             }
             Self::InvalidRegistrationId(_, _) => {
                 // Re-match as owned.
@@ -371,7 +415,7 @@ impl IntoFfiError for SignalProtocolError {
                     self,
                     Self::InvalidRegistrationId(peer_addr, invalid_id) =>
                     InvalidRegistrationId {
-                        peer_addr, invalid_id
+                        peer_addr, invalid_id, protocol_code
                     }
                 )
                 .into();
@@ -382,17 +426,22 @@ impl IntoFfiError for SignalProtocolError {
                     self,
                     Self::InvalidProtocolAddress { name, device_id } =>
                     InvalidProtocolAddress {
-                        name, device_id
+                        name, device_id, protocol_code
                     }
                 )
                 .into();
             }
 
             Self::InvalidArgument(_) => SignalErrorCode::InvalidArgument,
-            Self::InvalidState(_, _) => SignalErrorCode::InvalidState,
+            Self::InvalidState(_, _) | Self::SessionChanged(_) | Self::PreKeyIdsExhausted => {
+                SignalErrorCode::InvalidState
+            }
             Self::InvalidProtobufEncoding => SignalErrorCode::ProtobufError,
             Self::CiphertextMessageTooShort(_)
             | Self::InvalidMessage(_, _)
+            | Self::PqRatchetDowngrade(_)
+            | Self::InvalidStreamMessage(_)
+            | Self::ReusedKyberPreKeyBaseKey { .. }
             | Self::InvalidSealedSenderMessage(_)
             | Self::BadKEMCiphertextLength(_, _) => SignalErrorCode::InvalidMessage,
             Self::LegacyCiphertextVersion(_) => SignalErrorCode::LegacyCiphertextVersion,
@@ -423,7 +472,12 @@ impl IntoFfiError for SignalProtocolError {
             Self::UnknownSealedSenderServerCertificateId(_) => SignalErrorCode::VerificationFailure,
         };
 
-        SimpleError::new(code, self.to_string()).into()
+        ProtocolError {
+            code,
+            protocol_code,
+            message: self.to_string(),
+        }
+        .into()
     }
 }
 
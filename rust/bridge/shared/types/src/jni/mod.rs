@@ -170,149 +170,175 @@ impl JniError for IllegalArgumentError {
 
 impl JniError for SignalProtocolError {
     fn to_throwable<'a>(&self, env: &mut JNIEnv<'a>) -> Result<JThrowable<'a>, BridgeLayerError> {
-        fn to_java_string<'env>(
-            env: &mut JNIEnv<'env>,
-            s: impl Into<jni::strings::JNIString>,
-        ) -> Result<JString<'env>, BridgeLayerError> {
-            env.new_string(s)
-                .check_exceptions(env, "JniError::into_throwable")
+        if let SignalProtocolError::ApplicationCallbackError(_callback, exception) = self {
+            if let Some(exception) = <dyn Error>::downcast_ref::<ThrownException>(&**exception) {
+                return env
+                    .new_local_ref(exception.as_obj())
+                    .expect_no_exceptions()
+                    .map(JThrowable::from);
+            }
         }
 
-        let class_name = match self {
-            SignalProtocolError::ApplicationCallbackError(_callback, exception) => {
-                if let Some(exception) = <dyn Error>::downcast_ref::<ThrownException>(&**exception)
-                {
-                    return env
-                        .new_local_ref(exception.as_obj())
-                        .expect_no_exceptions()
-                        .map(JThrowable::from);
-                }
+        let throwable = protocol_error_to_throwable(self, env)?;
 
-                ClassName("java.lang.RuntimeException")
-            }
+        // A failure in an app callback that wasn't an exception is reported as a plain
+        // RuntimeException, which has nowhere to put the code.
+        if !matches!(self, SignalProtocolError::ApplicationCallbackError(_, _)) {
+            _ = call_method_checked(
+                env,
+                &throwable,
+                "setProtocolErrorCode",
+                jni_args!((self.code() as jint => int) -> void),
+            )?;
+        }
+        Ok(throwable)
+    }
+}
 
-            SignalProtocolError::UntrustedIdentity(addr) => {
-                let addr_name = to_java_string(env, addr.name())?;
-                return new_instance(
-                    env,
-                    ClassName("org.signal.libsignal.protocol.UntrustedIdentityException"),
-                    jni_args!((addr_name => java.lang.String) -> void),
-                )
-                .map(Into::into);
-            }
-            SignalProtocolError::SessionNotFound(addr) => {
-                let addr_object = protocol_address_to_jobject(env, addr)?;
-                let message = to_java_string(env, self.to_string())?;
-                return new_instance(
-                    env,
-                    ClassName("org.signal.libsignal.protocol.NoSessionException"),
-                    jni_args!((
-                        addr_object => org.signal.libsignal.protocol.SignalProtocolAddress,
-                        message => java.lang.String,
-                    ) -> void),
-                )
-                .map(Into::into);
-            }
+/// Creates the exception for `error`, other than an exception thrown by an app callback.
+fn protocol_error_to_throwable<'a>(
+    error: &SignalProtocolError,
+    env: &mut JNIEnv<'a>,
+) -> Result<JThrowable<'a>, BridgeLayerError> {
+    fn to_java_string<'env>(
+        env: &mut JNIEnv<'env>,
+        s: impl Into<jni::strings::JNIString>,
+    ) -> Result<JString<'env>, BridgeLayerError> {
+        env.new_string(s)
+            .check_exceptions(env, "JniError::into_throwable")
+    }
 
-            SignalProtocolError::InvalidRegistrationId(addr, _value) => {
-                let addr_object = protocol_address_to_jobject(env, addr)?;
-                let message = to_java_string(env, self.to_string())?;
-                return new_instance(
-                    env,
-                    ClassName("org.signal.libsignal.protocol.InvalidRegistrationIdException"),
-                    jni_args!((
-                        addr_object => org.signal.libsignal.protocol.SignalProtocolAddress,
-                        message => java.lang.String,
-                    ) -> void),
-                )
-                .map(Into::into);
-            }
-            SignalProtocolError::InvalidSenderKeySession { distribution_id } => {
-                let distribution_id = distribution_id.convert_into(env)?;
-                let message = to_java_string(env, self.to_string())?;
-                return new_instance(
-                    env,
-                    ClassName(
-                        "org.signal.libsignal.protocol.groups.InvalidSenderKeySessionException",
-                    ),
-                    jni_args!((
-                        distribution_id => java.util.UUID,
-                        message => java.lang.String,
-                    ) -> void),
-                )
-                .map(Into::into);
-            }
+    let class_name = match error {
+        SignalProtocolError::ApplicationCallbackError(_callback, _exception) => {
+            ClassName("java.lang.RuntimeException")
+        }
 
-            SignalProtocolError::SealedSenderSelfSend => {
-                return new_instance(
-                    env,
-                    ClassName("org.signal.libsignal.metadata.SelfSendException"),
-                    jni_args!(() -> void),
-                )
-                .map(Into::into);
-            }
+        SignalProtocolError::UntrustedIdentity(addr) => {
+            let addr_name = to_java_string(env, addr.name())?;
+            return new_instance(
+                env,
+                ClassName("org.signal.libsignal.protocol.UntrustedIdentityException"),
+                jni_args!((addr_name => java.lang.String) -> void),
+            )
+            .map(Into::into);
+        }
+        SignalProtocolError::SessionNotFound(addr) => {
+            let addr_object = protocol_address_to_jobject(env, addr)?;
+            let message = to_java_string(env, error.to_string())?;
+            return new_instance(
+                env,
+                ClassName("org.signal.libsignal.protocol.NoSessionException"),
+                jni_args!((
+                    addr_object => org.signal.libsignal.protocol.SignalProtocolAddress,
+                    message => java.lang.String,
+                ) -> void),
+            )
+            .map(Into::into);
+        }
 
-            SignalProtocolError::InvalidState(_, _) => ClassName("java.lang.IllegalStateException"),
+        SignalProtocolError::InvalidRegistrationId(addr, _value) => {
+            let addr_object = protocol_address_to_jobject(env, addr)?;
+            let message = to_java_string(env, error.to_string())?;
+            return new_instance(
+                env,
+                ClassName("org.signal.libsignal.protocol.InvalidRegistrationIdException"),
+                jni_args!((
+                    addr_object => org.signal.libsignal.protocol.SignalProtocolAddress,
+                    message => java.lang.String,
+                ) -> void),
+            )
+            .map(Into::into);
+        }
+        SignalProtocolError::InvalidSenderKeySession { distribution_id } => {
+            let distribution_id = distribution_id.convert_into(env)?;
+            let message = to_java_string(env, error.to_string())?;
+            return new_instance(
+                env,
+                ClassName("org.signal.libsignal.protocol.groups.InvalidSenderKeySessionException"),
+                jni_args!((
+                    distribution_id => java.util.UUID,
+                    message => java.lang.String,
+                ) -> void),
+            )
+            .map(Into::into);
+        }
 
-            SignalProtocolError::InvalidProtocolAddress {
-                name: _,
-                device_id: _,
-            }
-            | SignalProtocolError::InvalidArgument(_) => {
-                ClassName("java.lang.IllegalArgumentException")
-            }
-            SignalProtocolError::FfiBindingError(_) => ClassName("java.lang.RuntimeException"),
+        SignalProtocolError::SealedSenderSelfSend => {
+            return new_instance(
+                env,
+                ClassName("org.signal.libsignal.metadata.SelfSendException"),
+                jni_args!(() -> void),
+            )
+            .map(Into::into);
+        }
 
-            SignalProtocolError::DuplicatedMessage(_, _) => {
-                ClassName("org.signal.libsignal.protocol.DuplicateMessageException")
-            }
+        SignalProtocolError::InvalidState(_, _)
+        | SignalProtocolError::SessionChanged(_)
+        | SignalProtocolError::PreKeyIdsExhausted
+        | SignalProtocolError::FfiBindingError(_) => {
+            ClassName("org.signal.libsignal.protocol.ProtocolStateException")
+        }
 
-            SignalProtocolError::InvalidPreKeyId
-            | SignalProtocolError::InvalidSignedPreKeyId
-            | SignalProtocolError::InvalidKyberPreKeyId => {
-                ClassName("org.signal.libsignal.protocol.InvalidKeyIdException")
-            }
+        SignalProtocolError::InvalidProtocolAddress {
+            name: _,
+            device_id: _,
+        }
+        | SignalProtocolError::InvalidArgument(_) => {
+            ClassName("org.signal.libsignal.protocol.ProtocolArgumentException")
+        }
 
-            SignalProtocolError::NoKeyTypeIdentifier
-            | SignalProtocolError::SignatureValidationFailed
-            | SignalProtocolError::UnknownSealedSenderServerCertificateId(_)
-            | SignalProtocolError::BadKeyType(_)
-            | SignalProtocolError::BadKeyLength(_, _)
-            | SignalProtocolError::InvalidMacKeyLength(_)
-            | SignalProtocolError::BadKEMKeyType(_)
-            | SignalProtocolError::WrongKEMKeyType(_, _)
-            | SignalProtocolError::BadKEMKeyLength(_, _) => {
-                ClassName("org.signal.libsignal.protocol.InvalidKeyException")
-            }
+        SignalProtocolError::DuplicatedMessage(_, _) => {
+            ClassName("org.signal.libsignal.protocol.DuplicateMessageException")
+        }
 
-            SignalProtocolError::NoSenderKeyState { .. } => {
-                ClassName("org.signal.libsignal.protocol.NoSessionException")
-            }
+        SignalProtocolError::InvalidPreKeyId
+        | SignalProtocolError::InvalidSignedPreKeyId
+        | SignalProtocolError::InvalidKyberPreKeyId => {
+            ClassName("org.signal.libsignal.protocol.InvalidKeyIdException")
+        }
 
-            SignalProtocolError::InvalidSessionStructure(_) => {
-                ClassName("org.signal.libsignal.protocol.InvalidSessionException")
-            }
+        SignalProtocolError::NoKeyTypeIdentifier
+        | SignalProtocolError::SignatureValidationFailed
+        | SignalProtocolError::UnknownSealedSenderServerCertificateId(_)
+        | SignalProtocolError::BadKeyType(_)
+        | SignalProtocolError::BadKeyLength(_, _)
+        | SignalProtocolError::InvalidMacKeyLength(_)
+        | SignalProtocolError::BadKEMKeyType(_)
+        | SignalProtocolError::WrongKEMKeyType(_, _)
+        | SignalProtocolError::BadKEMKeyLength(_, _) => {
+            ClassName("org.signal.libsignal.protocol.InvalidKeyException")
+        }
 
-            SignalProtocolError::InvalidMessage(..)
-            | SignalProtocolError::CiphertextMessageTooShort(_)
-            | SignalProtocolError::InvalidProtobufEncoding
-            | SignalProtocolError::InvalidSealedSenderMessage(_)
-            | SignalProtocolError::BadKEMCiphertextLength(_, _) => {
-                ClassName("org.signal.libsignal.protocol.InvalidMessageException")
-            }
-            SignalProtocolError::UnrecognizedCiphertextVersion(_)
-            | SignalProtocolError::UnrecognizedMessageVersion(_)
-            | SignalProtocolError::UnknownSealedSenderVersion(_) => {
-                ClassName("org.signal.libsignal.protocol.InvalidVersionException")
-            }
+        SignalProtocolError::NoSenderKeyState { .. } => {
+            ClassName("org.signal.libsignal.protocol.NoSessionException")
+        }
 
-            SignalProtocolError::LegacyCiphertextVersion(_) => {
-                ClassName("org.signal.libsignal.protocol.LegacyMessageException")
-            }
-        };
+        SignalProtocolError::InvalidSessionStructure(_) => {
+            ClassName("org.signal.libsignal.protocol.InvalidSessionException")
+        }
 
-        make_single_message_throwable(env, &self.to_string(), class_name)
-    }
+        SignalProtocolError::InvalidMessage(..)
+        | SignalProtocolError::CiphertextMessageTooShort(_)
+        | SignalProtocolError::InvalidProtobufEncoding
+        | SignalProtocolError::InvalidStreamMessage(_)
+        | SignalProtocolError::ReusedKyberPreKeyBaseKey { .. }
+        | SignalProtocolError::InvalidSealedSenderMessage(_)
+        | SignalProtocolError::PqRatchetDowngrade(_)
+        | SignalProtocolError::BadKEMCiphertextLength(_, _) => {
+            ClassName("org.signal.libsignal.protocol.InvalidMessageException")
+        }
+        SignalProtocolError::UnrecognizedCiphertextVersion(_)
+        | SignalProtocolError::UnrecognizedMessageVersion(_)
+        | SignalProtocolError::UnknownSealedSenderVersion(_) => {
+            ClassName("org.signal.libsignal.protocol.InvalidVersionException")
+        }
+
+        SignalProtocolError::LegacyCiphertextVersion(_) => {
+            ClassName("org.signal.libsignal.protocol.LegacyMessageException")
+        }
+    };
+
+    make_single_message_throwable(env, &error.to_string(), class_name)
 }
 
 impl JniError for libsignal_protocol::FingerprintError {
@@ -37,6 +37,19 @@ fn new_js_error<'a, C: Context<'a>>(
     message: &str,
     operation: &str,
     make_extra_props: impl FnOnce(&mut C) -> JsResult<'a, JsValue>,
+) -> Handle<'a, JsError> {
+    new_js_error_with_protocol_code(cx, module, name, message, operation, None, make_extra_props)
+}
+
+/// Like [`new_js_error`], but also sets the error's `protocolErrorCode`.
+fn new_js_error_with_protocol_code<'a, C: Context<'a>>(
+    cx: &mut C,
+    module: Handle<'a, JsObject>,
+    name: Option<&str>,
+    message: &str,
+    operation: &str,
+    protocol_error_code: Option<u32>,
+    make_extra_props: impl FnOnce(&mut C) -> JsResult<'a, JsValue>,
 ) -> Handle<'a, JsError> {
     let result = cx.try_catch(|cx| {
         let errors_module: Handle<JsObject> = module.get(cx, ERRORS_PROPERTY_NAME)?;
@@ -46,12 +59,17 @@ fn new_js_error<'a, C: Context<'a>>(
             None => cx.undefined().upcast(),
         };
         let extra_props_arg = make_extra_props(cx)?;
+        let protocol_error_code_arg = match protocol_error_code {
+            Some(code) => cx.number(code).upcast::<JsValue>(),
+            None => cx.undefined().upcast(),
+        };
 
         let args = (
             cx.string(message),
             name_arg,
             cx.string(operation),
             extra_props_arg,
+            protocol_error_code_arg,
         );
         error_class.construct_with(cx).args(args).apply(cx)
     });
@@ -163,21 +181,24 @@ impl SignalNodeError for SignalProtocolError {
         operation_name: &str,
     ) -> Handle<'a, JsError> {
         let message = self.to_string();
+        let protocol_error_code = Some(self.code());
         match self {
-            SignalProtocolError::DuplicatedMessage(..) => new_js_error(
+            SignalProtocolError::DuplicatedMessage(..) => new_js_error_with_protocol_code(
                 cx,
                 module,
                 Some("DuplicatedMessage"),
                 &message,
                 operation_name,
+                protocol_error_code,
                 no_extra_properties,
             ),
-            SignalProtocolError::SealedSenderSelfSend => new_js_error(
+            SignalProtocolError::SealedSenderSelfSend => new_js_error_with_protocol_code(
                 cx,
                 module,
                 Some("SealedSenderSelfSend"),
                 &message,
                 operation_name,
+                protocol_error_code,
                 no_extra_properties,
             ),
             SignalProtocolError::UntrustedIdentity(addr) => {
@@ -187,12 +208,13 @@ impl SignalNodeError for SignalProtocolError {
                     props.set(cx, "_addr", addr_string)?;
                     Ok(props.upcast())
                 };
-                new_js_error(
+                new_js_error_with_protocol_code(
                     cx,
                     module,
                     Some("UntrustedIdentity"),
                     &message,
                     operation_name,
+                    protocol_error_code,
                     make_extra_props,
                 )
             }
@@ -203,12 +225,13 @@ impl SignalNodeError for SignalProtocolError {
                     props.set(cx, "_addr", addr)?;
                     Ok(props.upcast())
                 };
-                new_js_error(
+                new_js_error_with_protocol_code(
                     cx,
                     module,
                     Some("InvalidRegistrationId"),
                     &message,
                     operation_name,
+                    protocol_error_code,
                     make_extra_props,
                 )
             }
@@ -221,21 +244,23 @@ impl SignalNodeError for SignalProtocolError {
                     props.set(cx, "deviceId", device_id)?;
                     Ok(props.upcast())
                 };
-                new_js_error(
+                new_js_error_with_protocol_code(
                     cx,
                     module,
                     Some("InvalidProtocolAddress"),
                     &message,
                     operation_name,
+                    protocol_error_code,
                     make_extra_props,
                 )
             }
-            SignalProtocolError::InvalidSessionStructure(..) => new_js_error(
+            SignalProtocolError::InvalidSessionStructure(..) => new_js_error_with_protocol_code(
                 cx,
                 module,
                 Some("InvalidSession"),
                 &message,
                 operation_name,
+                protocol_error_code,
                 no_extra_properties,
             ),
             SignalProtocolError::InvalidSenderKeySession { distribution_id } => {
@@ -246,21 +271,23 @@ impl SignalNodeError for SignalProtocolError {
                     props.set(cx, "distribution_id", distribution_id_str)?;
                     Ok(props.upcast())
                 };
-                new_js_error(
+                new_js_error_with_protocol_code(
                     cx,
                     module,
                     Some("InvalidSenderKeySession"),
                     &message,
                     operation_name,
+                    protocol_error_code,
                     make_extra_props,
                 )
             }
-            _ => new_js_error(
+            _ => new_js_error_with_protocol_code(
                 cx,
                 module,
                 None,
                 &message,
                 operation_name,
+                protocol_error_code,
                 no_extra_properties,
             ),
         }
@@ -3,17 +3,15 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
-use std::io::{Write as _, stdout};
+use std::io::stdout;
 
 use aes::cipher::crypto_common::rand_core::{OsRng, RngCore};
-use clap::{ArgAction, Parser};
+use clap::Parser;
 use clap_stdin::FileOrStdin;
 use libsignal_cli_utils::read_file;
+use libsignal_message_backup::BackupWriter;
 use libsignal_message_backup::args::parse_hex_bytes;
-use libsignal_message_backup::export::{
-    aes_cbc_encrypt, gzip_compress, hmac_checksum, pad_gzipped_bucketed,
-};
-use libsignal_message_backup::key::MessageBackupKey;
+use libsignal_message_backup::parse::VarintDelimitedReader;
 use libsignal_svrb::proto::Message as _;
 use libsignal_svrb::proto::backup_metadata::{MetadataPb, metadata_pb};
 
@@ -38,10 +36,6 @@ struct CliArgs {
     #[arg(long, value_parser=parse_hex_bytes::<16>)]
     iv: Option<[u8; 16]>,
 
-    /// pad the compressed output to a bucket boundary before encrypting
-    #[arg(long, default_value_t = true, action=ArgAction::Set)]
-    pad_bucketed: bool,
-
     /// use the modern forward-secrecy format, or the legacy just-ciphertext format
     #[arg(long, default_value = "modern")]
     format: Format,
@@ -51,10 +45,13 @@ struct CliArgs {
 }
 
 fn main() {
+    futures::executor::block_on(async_main())
+}
+
+async fn async_main() {
     let CliArgs {
         input,
         iv,
-        pad_bucketed,
         format,
         key_args,
     } = CliArgs::parse();
@@ -72,51 +69,40 @@ fn main() {
     let contents = read_file(input);
     eprintln!("read {} bytes", contents.len());
 
-    let mut compressed_contents = gzip_compress(futures::io::Cursor::new(contents));
-    eprintln!("compressed to {} bytes", compressed_contents.len());
-
-    if pad_bucketed {
-        pad_gzipped_bucketed(&mut compressed_contents);
-        eprintln!("padded to {} bytes", compressed_contents.len());
-    }
-
-    if let Format::Modern = format {
-        write_bytes(
-            "magic number",
-            libsignal_message_backup::frame::forward_secrecy::MAGIC_NUMBER,
-        );
-        let faux_metadata = MetadataPb {
-            iv: b"iv_12_bytes_".to_vec(),
-            pair: vec![metadata_pb::Pair {
-                ct: [0xCC; 48].to_vec(),
-                pw_salt: [0x50; 32].to_vec(),
+    let faux_metadata = match format {
+        Format::Legacy => None,
+        Format::Modern => Some(
+            MetadataPb {
+                iv: b"iv_12_bytes_".to_vec(),
+                pair: vec![metadata_pb::Pair {
+                    ct: [0xCC; 48].to_vec(),
+                    pw_salt: [0x50; 32].to_vec(),
+                    ..Default::default()
+                }],
                 ..Default::default()
-            }],
-            ..Default::default()
-        };
-        write_bytes(
-            "faux metadata",
-            faux_metadata
-                .write_length_delimited_to_bytes()
-                .expect("can serialize"),
-        );
+            }
+            .write_to_bytes()
+            .expect("can serialize"),
+        ),
+    };
+
+    let output = futures::io::AllowStdIo::new(stdout().lock());
+    let mut writer = BackupWriter::new(&key, iv, faux_metadata.as_deref(), output)
+        .await
+        .expect("valid metadata");
+
+    let mut frames = VarintDelimitedReader::new(futures::io::Cursor::new(contents));
+    while let Some(frame) = frames
+        .read_next()
+        .await
+        .expect("valid varint-delimited input")
+    {
+        writer
+            .write_serialized_frame(&frame)
+            .await
+            .expect("can write");
     }
+    eprintln!("compressed and encrypted {} frames", writer.frame_count());
 
-    let MessageBackupKey { hmac_key, aes_key } = &key;
-
-    write_bytes("IV", iv);
-
-    aes_cbc_encrypt(aes_key, &iv, &mut compressed_contents);
-    eprintln!("encrypted to {} bytes", compressed_contents.len());
-
-    let hmac = hmac_checksum(hmac_key, &iv, &compressed_contents);
-    write_bytes("encrypted", compressed_contents);
-
-    write_bytes("HMAC", hmac);
-}
-
-fn write_bytes(label: &'static str, bytes: impl AsRef<[u8]>) {
-    let bytes = bytes.as_ref();
-    stdout().write_all(bytes).expect("failed to write");
-    eprintln!("wrote {} {label} bytes", bytes.len())
+    writer.finish().await.expect("failed to write");
 }
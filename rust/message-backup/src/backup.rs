@@ -14,19 +14,25 @@ use libsignal_account_keys::BACKUP_KEY_LEN;
 use libsignal_core::{Aci, Pni};
 use serde_with::serde_as;
 
-pub(crate) use crate::backup::account_data::{AccountData, AccountDataError};
-use crate::backup::call::{AdHocCall, CallError};
+pub use crate::backup::account_data::AccountData;
+pub(crate) use crate::backup::account_data::AccountDataError;
+pub use crate::backup::call::AdHocCall;
+use crate::backup::call::CallError;
 use crate::backup::chat::chat_style::{CustomChatColor, CustomColorId};
-use crate::backup::chat::{ChatData, ChatError, ChatItemData, ChatItemError, PinOrder};
+pub use crate::backup::chat::{ChatData, ChatItemData, ChatItemMessageDiscriminants};
+use crate::backup::chat::{ChatError, ChatItemError, PinOrder};
 use crate::backup::chat_folder::{ChatFolder, ChatFolderError};
-use crate::backup::frame::{ChatId, RecipientId};
+pub use crate::backup::file::LocatorKind;
+pub use crate::backup::frame::{ChatId, RecipientId};
 use crate::backup::hashutil::{AssumedRandomInputHasher, HashBytesAllAtOnce};
 use crate::backup::method::{Lookup, LookupPair, Method};
-pub use crate::backup::method::{Store, ValidateOnly};
+pub use crate::backup::method::{Store, Stream, ValidateOnly};
 use crate::backup::notification_profile::{NotificationProfile, NotificationProfileError};
-use crate::backup::recipient::{FullRecipientData, MinimalRecipientData, RecipientError};
+pub use crate::backup::recipient::FullRecipientData;
+use crate::backup::recipient::{MinimalRecipientData, RecipientError};
 use crate::backup::serialize::{SerializeOrder, UnorderedList};
-use crate::backup::sticker::{PackId as StickerPackId, StickerPack, StickerPackError};
+use crate::backup::sticker::StickerPackError;
+pub use crate::backup::sticker::{PackId as StickerPackId, StickerPack};
 use crate::backup::time::{
     ReportUnusualTimestamp, Timestamp, TimestampError, TimestampIssue, UnusualTimestampTracker,
 };
@@ -44,6 +50,7 @@ pub(crate) mod method;
 mod notification_profile;
 mod recipient;
 pub mod serialize;
+pub mod stats;
 mod sticker;
 mod time;
 
@@ -81,6 +88,61 @@ pub trait ReferencedTypes {
     -> bool;
 }
 
+/// Receives the items of a backup one at a time, as soon as each has been validated.
+///
+/// Items are visited in the order they appear in the backup, after being checked against
+/// everything before them. Checks that need the whole backup (see [`CompletionError`]) only happen
+/// after the last item has been visited, so anything visited should be discarded if reading the
+/// backup ultimately fails.
+///
+/// Every method does nothing by default.
+pub trait BackupVisitor<M: Method + ReferencedTypes> {
+    fn on_account_data(&mut self, _account_data: &AccountData<M>) {}
+    fn on_recipient(&mut self, _id: RecipientId, _recipient: &M::RecipientData) {}
+    fn on_chat(&mut self, _id: ChatId, _chat: &ChatData<M>) {}
+    /// Called for the items of all chats in a single sequence, so items from different chats may
+    /// be interleaved.
+    fn on_chat_item(&mut self, _chat_id: ChatId, _item: &ChatItemData<M>) {}
+    fn on_ad_hoc_call(&mut self, _call: &AdHocCall<M::RecipientReference>) {}
+    fn on_sticker_pack(&mut self, _id: StickerPackId, _pack: &StickerPack<M>) {}
+    /// Called after each frame's item has been visited, with the length of the serialized frame.
+    ///
+    /// Only frames read from their serialized form are reported, not those passed to
+    /// [`PartialBackup::add_frame_visiting`] directly.
+    fn on_frame(&mut self, _kind: FrameKind, _serialized_len: usize) {}
+}
+
+/// The kind of item in a backup frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+pub enum FrameKind {
+    AccountData,
+    Recipient,
+    Chat,
+    ChatItem,
+    StickerPack,
+    AdHocCall,
+    NotificationProfile,
+    ChatFolder,
+}
+
+impl From<&FrameItem> for FrameKind {
+    fn from(item: &FrameItem) -> Self {
+        match item {
+            FrameItem::Account(_) => Self::AccountData,
+            FrameItem::Recipient(_) => Self::Recipient,
+            FrameItem::Chat(_) => Self::Chat,
+            FrameItem::ChatItem(_) => Self::ChatItem,
+            FrameItem::StickerPack(_) => Self::StickerPack,
+            FrameItem::AdHocCall(_) => Self::AdHocCall,
+            FrameItem::NotificationProfile(_) => Self::NotificationProfile,
+            FrameItem::ChatFolder(_) => Self::ChatFolder,
+        }
+    }
+}
+
+/// Visits nothing.
+impl<M: Method + ReferencedTypes> BackupVisitor<M> for () {}
+
 pub struct PartialBackup<M: Method + ReferencedTypes> {
     meta: BackupMeta,
     account_data: Option<AccountData<M>>,
@@ -108,6 +170,16 @@ pub struct CompletedBackup<M: Method + ReferencedTypes> {
 
 pub type Backup = CompletedBackup<Store>;
 
+/// Summary counts of what a backup contains, for reporting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct BackupCounts {
+    pub recipients: usize,
+    pub chats: usize,
+    pub chat_items: usize,
+    /// Includes timestamps whose warnings were suppressed for recurring too often.
+    pub unusual_timestamps: usize,
+}
+
 #[derive_where(Debug, Default)]
 struct ChatsData<M: Method + ReferencedTypes> {
     items: IntMap<ChatId, ChatData<M>>,
@@ -517,6 +589,35 @@ impl ReferencedTypes for Store {
     }
 }
 
+impl ReferencedTypes for Stream {
+    type RecipientReference = FullRecipientData;
+    type RecipientData = FullRecipientData;
+
+    type CustomColorData = Arc<CustomChatColor>;
+    type CustomColorReference = Arc<CustomChatColor>;
+
+    fn color_reference<'a>(
+        _id: &'a CustomColorId,
+        data: &'a Self::CustomColorData,
+    ) -> &'a Self::CustomColorReference {
+        data
+    }
+
+    fn recipient_reference<'a>(
+        _id: &'a RecipientId,
+        data: &'a Self::RecipientData,
+    ) -> &'a Self::RecipientReference {
+        data
+    }
+
+    fn is_same_reference(
+        left: &Self::RecipientReference,
+        right: &Self::RecipientReference,
+    ) -> bool {
+        left.is_same_reference(right)
+    }
+}
+
 impl ReferencedTypes for ValidateOnly {
     type RecipientReference = RecipientId;
     type RecipientData = MinimalRecipientData;
@@ -626,27 +727,81 @@ impl<M: Method + ReferencedTypes> PartialBackup<M> {
     }
 
     pub fn add_frame(&mut self, frame: proto::Frame) -> Result<(), ValidationError> {
-        self.add_frame_item(frame.item.ok_or(ValidationError::EmptyFrame)?)
+        self.add_frame_visiting(frame, &mut ())
+    }
+
+    /// Counts the items added so far.
+    pub fn counts(&self) -> BackupCounts {
+        BackupCounts {
+            recipients: self.recipients.len(),
+            chats: self.chats.items.len(),
+            chat_items: self.chats.chat_items_count,
+            unusual_timestamps: self.unusual_timestamp_tracker.borrow().total(),
+        }
     }
 
+    /// Like [`add_frame`](Self::add_frame), but also passes the validated item to `visitor`.
+    pub fn add_frame_visiting(
+        &mut self,
+        frame: proto::Frame,
+        visitor: &mut impl BackupVisitor<M>,
+    ) -> Result<(), ValidationError> {
+        self.add_frame_item_visiting(frame.item.ok_or(ValidationError::EmptyFrame)?, visitor)
+    }
+
+    #[cfg(test)]
     fn add_frame_item(&mut self, item: FrameItem) -> Result<(), ValidationError> {
+        self.add_frame_item_visiting(item, &mut ())
+    }
+
+    fn add_frame_item_visiting(
+        &mut self,
+        item: FrameItem,
+        visitor: &mut impl BackupVisitor<M>,
+    ) -> Result<(), ValidationError> {
         match item {
-            FrameItem::Account(account_data) => self.add_account_data(account_data),
-            FrameItem::Recipient(recipient) => self.add_recipient(recipient).map_err(Into::into),
-            FrameItem::Chat(chat) => self.add_chat(chat).map_err(Into::into),
-            FrameItem::ChatItem(chat_item) => self.add_chat_item(chat_item),
+            FrameItem::Account(account_data) => {
+                self.add_account_data(account_data)?;
+                if let Some(account_data) = &self.account_data {
+                    visitor.on_account_data(account_data);
+                }
+            }
+            FrameItem::Recipient(recipient) => {
+                let id = recipient.id();
+                self.add_recipient(recipient)?;
+                if let Some(recipient) = self.recipients.get(id) {
+                    visitor.on_recipient(id, recipient);
+                }
+            }
+            FrameItem::Chat(chat) => {
+                let id = chat.id();
+                self.add_chat(chat)?;
+                if let Some(chat) = self.chats.items.get(id) {
+                    visitor.on_chat(id, chat);
+                }
+            }
+            // Chat items and calls aren't necessarily kept, so they're visited as they're added.
+            FrameItem::ChatItem(chat_item) => self.add_chat_item(chat_item, visitor)?,
+            FrameItem::AdHocCall(call) => self.add_ad_hoc_call(call, visitor)?,
             FrameItem::StickerPack(sticker_pack) => {
-                self.add_sticker_pack(sticker_pack).map_err(Into::into)
+                let id = self.add_sticker_pack(sticker_pack)?;
+                if let Some(pack) = self.sticker_packs.get(&id) {
+                    visitor.on_sticker_pack(id, pack);
+                }
             }
-            FrameItem::AdHocCall(call) => self.add_ad_hoc_call(call).map_err(Into::into),
             FrameItem::NotificationProfile(notification_profile) => {
-                self.add_notification_profile(notification_profile)
+                self.add_notification_profile(notification_profile)?
             }
-            FrameItem::ChatFolder(chat_folder) => self.add_chat_folder(chat_folder),
+            FrameItem::ChatFolder(chat_folder) => self.add_chat_folder(chat_folder)?,
         }
+        Ok(())
     }
 
-    fn add_ad_hoc_call(&mut self, call: proto::AdHocCall) -> Result<(), CallFrameError> {
+    fn add_ad_hoc_call(
+        &mut self,
+        call: proto::AdHocCall,
+        visitor: &mut impl BackupVisitor<M>,
+    ) -> Result<(), CallFrameError> {
         let recipient_id = call.recipientId;
         let call_id = call.callId;
         let call = call.try_into_with(self).map_err(|error| CallFrameError {
@@ -654,6 +809,7 @@ impl<M: Method + ReferencedTypes> PartialBackup<M> {
             call_id,
             error,
         })?;
+        visitor.on_ad_hoc_call(&call);
         self.ad_hoc_calls.extend(Some(call));
         Ok(())
     }
@@ -700,7 +856,11 @@ impl<M: Method + ReferencedTypes> PartialBackup<M> {
         Ok(())
     }
 
-    fn add_chat_item(&mut self, chat_item: proto::ChatItem) -> Result<(), ValidationError> {
+    fn add_chat_item(
+        &mut self,
+        chat_item: proto::ChatItem,
+        visitor: &mut impl BackupVisitor<M>,
+    ) -> Result<(), ValidationError> {
         let chat_id = ChatId(chat_item.chatId);
         let raw_timestamp = chat_item.dateSent;
 
@@ -716,10 +876,13 @@ impl<M: Method + ReferencedTypes> PartialBackup<M> {
                 )
             })?;
 
-        Ok(self.chats.add_chat_item(chat_id, chat_item_data)?)
+        Ok(self.chats.add_chat_item(chat_id, chat_item_data, visitor)?)
     }
 
-    fn add_sticker_pack(&mut self, sticker_pack: proto::StickerPack) -> Result<(), StickerError> {
+    fn add_sticker_pack(
+        &mut self,
+        sticker_pack: proto::StickerPack,
+    ) -> Result<StickerPackId, StickerError> {
         let id = sticker_pack
             .packId
             .as_slice()
@@ -732,7 +895,7 @@ impl<M: Method + ReferencedTypes> PartialBackup<M> {
             hash_map::Entry::Occupied(_) => Err(StickerError::DuplicateId(id)),
             hash_map::Entry::Vacant(v) => {
                 v.insert(pack);
-                Ok(())
+                Ok(id)
             }
         }
     }
@@ -777,6 +940,7 @@ impl<M: Method + ReferencedTypes> ChatsData<M> {
         &mut self,
         chat_id: ChatId,
         mut item: ChatItemData<M>,
+        visitor: &mut impl BackupVisitor<M>,
     ) -> Result<(), ChatFrameError> {
         let Self {
             chat_items_count,
@@ -803,6 +967,7 @@ impl<M: Method + ReferencedTypes> ChatsData<M> {
 
         item.total_chat_item_order_index = *chat_items_count;
 
+        visitor.on_chat_item(chat_id, &item);
         chat_data.items.extend([item]);
 
         *chat_items_count += 1;
@@ -1190,10 +1355,13 @@ mod test {
         for _ in 0..3 {
             for chat_id in CHAT_IDS {
                 partial
-                    .add_chat_item(proto::ChatItem {
-                        chatId: chat_id,
-                        ..proto::ChatItem::test_data()
-                    })
+                    .add_chat_item(
+                        proto::ChatItem {
+                            chatId: chat_id,
+                            ..proto::ChatItem::test_data()
+                        },
+                        &mut (),
+                    )
                     .expect("valid chat item");
             }
         }
@@ -1221,6 +1389,80 @@ mod test {
         );
     }
 
+    #[derive(Default)]
+    struct RecordingVisitor {
+        account_data: usize,
+        recipients: Vec<RecipientId>,
+        chats: Vec<ChatId>,
+        chat_items: Vec<(ChatId, usize)>,
+    }
+
+    impl<M: Method + ReferencedTypes> BackupVisitor<M> for RecordingVisitor {
+        fn on_account_data(&mut self, _account_data: &AccountData<M>) {
+            self.account_data += 1;
+        }
+        fn on_recipient(&mut self, id: RecipientId, _recipient: &M::RecipientData) {
+            self.recipients.push(id);
+        }
+        fn on_chat(&mut self, id: ChatId, _chat: &ChatData<M>) {
+            self.chats.push(id);
+        }
+        fn on_chat_item(&mut self, chat_id: ChatId, item: &ChatItemData<M>) {
+            self.chat_items
+                .push((chat_id, item.total_chat_item_order_index));
+        }
+    }
+
+    #[test]
+    fn visitor_sees_validated_items() {
+        let mut partial = Stream::empty();
+        let mut visitor = RecordingVisitor::default();
+
+        let frames: [FrameItem; 6] = [
+            proto::AccountData::test_data().into(),
+            proto::Recipient::test_data().into(),
+            proto::Recipient::test_data_contact().into(),
+            proto::Chat::test_data().into(),
+            proto::ChatItem::test_data().into(),
+            proto::ChatItem::test_data().into(),
+        ];
+        for frame in frames {
+            partial
+                .add_frame_item_visiting(frame, &mut visitor)
+                .expect("valid frame");
+        }
+        partial
+            .add_frame_item_visiting(
+                proto::ChatItem::test_data_wrong_author().into(),
+                &mut visitor,
+            )
+            .expect_err("invalid chat item");
+
+        let chat_id = ChatId(proto::Chat::TEST_ID);
+        assert_eq!(visitor.account_data, 1);
+        assert_eq!(
+            visitor.recipients,
+            [
+                RecipientId(proto::Recipient::TEST_ID),
+                RecipientId(proto::Recipient::test_data_contact().id),
+            ]
+        );
+        assert_eq!(visitor.chats, [chat_id]);
+        assert_eq!(visitor.chat_items, [(chat_id, 0), (chat_id, 1)]);
+        assert_eq!(
+            partial.counts(),
+            BackupCounts {
+                recipients: 2,
+                chats: 1,
+                chat_items: 2,
+                unusual_timestamps: 0,
+            }
+        );
+
+        let completed = CompletedBackup::try_from(partial).expect("valid completed backup");
+        assert_eq!(completed.chats.chat_items_count, 2);
+    }
+
     #[test_matrix(
         [ValidateOnly::empty(), Store::empty()],
         [
@@ -54,7 +54,7 @@ use gossip::*;
 mod sticker_message;
 use sticker_message::*;
 
-mod story_reply;
+pub(crate) mod story_reply;
 use story_reply::*;
 
 pub(crate) mod text;
@@ -356,6 +356,7 @@ const MAX_REMOTE_BACKUP_DISAPPEARING_MESSAGE_TIME: Duration = Duration::from_hou
 /// Validated version of [`proto::chat_item::Item`].
 #[derive_where(Debug)]
 #[derive(serde::Serialize, strum::EnumDiscriminants)]
+#[strum_discriminants(derive(PartialOrd, Ord, Hash, serde::Serialize))]
 #[cfg_attr(test, derive_where(PartialEq;
     M::BoxedValue<GiftBadge>: PartialEq,
     M::RecipientReference: PartialEq
@@ -57,6 +57,45 @@ pub struct TransitTierLocator {
     upload_timestamp: Option<Timestamp>,
 }
 
+/// Whether the file referenced by a [`Locator`] was ever downloaded by the client that made the
+/// backup.
+///
+/// This is determined by the locator's integrity check. It says nothing about which CDN tiers the
+/// file can currently be fetched from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+pub enum LocatorKind {
+    /// The locator doesn't reference a file.
+    Invalid,
+    /// The file was never downloaded, so only its encrypted digest is known.
+    NotDownloaded,
+    /// The file was downloaded, so its plaintext hash is known.
+    Downloaded,
+}
+
+impl Locator {
+    pub fn kind(&self) -> LocatorKind {
+        match self {
+            Locator::Invalid => LocatorKind::Invalid,
+            Locator::LocatorInfo(LocatorInfo {
+                integrity_check: IntegrityCheck::EncryptedDigest { .. },
+                ..
+            }) => LocatorKind::NotDownloaded,
+            Locator::LocatorInfo(LocatorInfo {
+                integrity_check: IntegrityCheck::PlaintextHash { .. },
+                ..
+            }) => LocatorKind::Downloaded,
+        }
+    }
+
+    /// The size of the referenced file before encryption, if it's available.
+    pub fn plaintext_size(&self) -> Option<u32> {
+        match self {
+            Locator::Invalid => None,
+            Locator::LocatorInfo(info) => Some(info.plaintext_size),
+        }
+    }
+}
+
 #[derive(Debug, displaydoc::Display, thiserror::Error)]
 #[cfg_attr(test, derive(PartialEq))]
 pub enum LocatorError {
@@ -12,7 +12,7 @@ use crate::proto::backup::{Chat, Recipient};
 pub struct RecipientId(pub(super) u64);
 
 /// Foreign key
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, serde::Serialize)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, serde::Serialize)]
 pub struct ChatId(pub(super) u64);
 
 macro_rules! impl_with_id {
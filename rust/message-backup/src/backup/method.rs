@@ -82,3 +82,23 @@ impl Method for Store {
         Box::new(value)
     }
 }
+
+/// Like [`Store`], but without keeping chat items or ad-hoc calls.
+///
+/// Used with a [`BackupVisitor`](crate::backup::BackupVisitor), which sees each of those as it's
+/// validated, so that memory use doesn't grow with the number of messages in a backup.
+#[derive(serde::Serialize)]
+pub enum Stream {}
+
+impl Method for Stream {
+    type Value<T: Debug + serde::Serialize> = T;
+    type BoxedValue<T: Debug + serde::Serialize> = Box<T>;
+    type List<T: Debug> = ValidateOnlyList;
+
+    fn value<T: Debug + serde::Serialize>(value: T) -> Self::Value<T> {
+        value
+    }
+    fn boxed_value<T: Debug + serde::Serialize>(value: T) -> Self::BoxedValue<T> {
+        Box::new(value)
+    }
+}
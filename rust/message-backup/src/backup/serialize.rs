@@ -23,6 +23,8 @@ use crate::backup::sticker::{PackId as StickerPackId, StickerPack};
 use crate::backup::{BackupMeta, ChatsData, CompletedBackup};
 use crate::proto::backup as proto;
 
+#[cfg(feature = "json")]
+pub mod diff;
 mod unordered_list;
 pub use unordered_list::UnorderedList;
 
@@ -400,7 +402,7 @@ mod test {
         }
     }
 
-    fn backup_from_frames(
+    pub(super) fn backup_from_frames(
         frames: impl IntoIterator<Item = proto::Frame>,
     ) -> crate::CompletedBackup<Store> {
         let mut reader = crate::backup::PartialBackup::new(
@@ -467,7 +469,7 @@ mod test {
         }
     }
 
-    fn make_contact(name: &str, index: u8) -> proto::Contact {
+    pub(super) fn make_contact(name: &str, index: u8) -> proto::Contact {
         proto::Contact {
             aci: Some(Uuid::from_bytes([index; 16]).as_bytes().to_vec()),
             profileGivenName: Some(name.to_owned()),
@@ -480,7 +482,7 @@ mod test {
         }
     }
 
-    fn make_chat(id: ChatId, recipient: RecipientId) -> proto::Frame {
+    pub(super) fn make_chat(id: ChatId, recipient: RecipientId) -> proto::Frame {
         proto::Frame {
             item: Some(
                 proto::Chat {
@@ -494,7 +496,11 @@ mod test {
         }
     }
 
-    fn make_chat_item(id: ChatId, author: RecipientId, message: &'static str) -> proto::Frame {
+    pub(super) fn make_chat_item(
+        id: ChatId,
+        author: RecipientId,
+        message: &'static str,
+    ) -> proto::Frame {
         proto::Frame {
             item: Some(
                 proto::ChatItem {
@@ -512,7 +518,7 @@ mod test {
         }
     }
 
-    fn make_recipient(
+    pub(super) fn make_recipient(
         id: RecipientId,
         destination: &(impl Clone + Into<proto::recipient::Destination>),
     ) -> proto::Frame {
@@ -540,14 +546,14 @@ mod test {
         }
     }
 
-    const FIRST_CONTACT_CHAT_ID: ChatId = ChatId(1);
-    const SECOND_CONTACT_CHAT_ID: ChatId = ChatId(2);
-    const GROUP_CHAT_ID: ChatId = ChatId(3);
+    pub(super) const FIRST_CONTACT_CHAT_ID: ChatId = ChatId(1);
+    pub(super) const SECOND_CONTACT_CHAT_ID: ChatId = ChatId(2);
+    pub(super) const GROUP_CHAT_ID: ChatId = ChatId(3);
 
-    const FIRST_CONTACT_ID: RecipientId = RecipientId(100);
-    const SECOND_CONTACT_ID: RecipientId = RecipientId(101);
-    const GROUP_ID: RecipientId = RecipientId(102);
-    const SELF_ID: RecipientId = RecipientId(10);
+    pub(super) const FIRST_CONTACT_ID: RecipientId = RecipientId(100);
+    pub(super) const SECOND_CONTACT_ID: RecipientId = RecipientId(101);
+    pub(super) const GROUP_ID: RecipientId = RecipientId(102);
+    pub(super) const SELF_ID: RecipientId = RecipientId(10);
 
     #[test]
     fn shuffled_chats_and_recipient_ids() {
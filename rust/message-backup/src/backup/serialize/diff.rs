@@ -0,0 +1,500 @@
+//
+// Copyright (C) 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Structural comparison of two backups in their canonical form.
+//!
+//! A restore implementation should satisfy
+//! `serialize(backup) == serialize(export(restoreFrom(backup)))`. When it doesn't, comparing the
+//! two serialized strings line by line is impractical for a large backup, since a single missing
+//! recipient or chat item shifts everything after it. [`diff`] instead matches recipients, chats,
+//! and chat items by their identity and reports each added, removed, or changed value along with
+//! its path.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use libsignal_core::ServiceId;
+use serde_json::Value;
+
+use crate::backup::chat::{ChatData, ChatItemData};
+use crate::backup::method::Store;
+use crate::backup::recipient::{Destination, DistributionListItem, FullRecipientData};
+use crate::backup::serialize::Backup;
+
+/// A single difference between two backups.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct Difference {
+    /// Where the difference is, like `chats[contact 6c9b…].items[self @ 1700000000000].sms`.
+    ///
+    /// Recipients, chats, and chat items are identified by what they refer to rather than by
+    /// their position, and entities that would otherwise share an identity get a `#2`, `#3`, etc.
+    /// suffix in the order they appear.
+    pub path: String,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// How a value differs between two backups, in its canonical JSON form.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    /// Only present in the second backup.
+    Added { after: Value },
+    /// Only present in the first backup.
+    Removed { before: Value },
+    /// Present in both backups with different values.
+    Changed { before: Value, after: Value },
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self { path, change } = self;
+        match change {
+            Change::Added { after } => write!(f, "+ {path}: {after}"),
+            Change::Removed { before } => write!(f, "- {path}: {before}"),
+            Change::Changed { before, after } => write!(f, "~ {path}: {before} -> {after}"),
+        }
+    }
+}
+
+/// Compares `before` and `after`, returning every difference between them.
+///
+/// Differences are reported section by section, in the order the sections are serialized.
+/// Recipients and chats are reported in order of their identity; chat items are reported in the
+/// order they appear in `before`, followed by any that only appear in `after`. An empty result
+/// means the two backups serialize identically.
+pub fn diff(before: &Backup, after: &Backup) -> Vec<Difference> {
+    let Backup {
+        meta: before_meta,
+        account_data: before_account_data,
+        recipients: before_recipients,
+        chats: before_chats,
+        ad_hoc_calls: before_ad_hoc_calls,
+        pinned_chats: before_pinned_chats,
+        sticker_packs: before_sticker_packs,
+        notification_profiles: before_notification_profiles,
+        chat_folders: before_chat_folders,
+    } = before;
+
+    let mut differences = Differences::default();
+    differences.values("meta", &to_value(before_meta), &to_value(&after.meta));
+    differences.values(
+        "account_data",
+        &to_value(before_account_data),
+        &to_value(&after.account_data),
+    );
+    differences.keyed(
+        "recipients",
+        by_identity(&before_recipients.0, recipient_identity),
+        by_identity(&after.recipients.0, recipient_identity),
+        |differences, path, before, after| {
+            differences.values(path, &to_value(before), &to_value(after))
+        },
+    );
+    differences.keyed(
+        "chats",
+        by_identity(&before_chats.0, |chat| recipient_identity(&chat.recipient)),
+        by_identity(&after.chats.0, |chat| recipient_identity(&chat.recipient)),
+        Differences::chats,
+    );
+
+    for (name, before, after) in [
+        (
+            "ad_hoc_calls",
+            to_value(before_ad_hoc_calls),
+            to_value(&after.ad_hoc_calls),
+        ),
+        (
+            "pinned_chats",
+            to_value(before_pinned_chats),
+            to_value(&after.pinned_chats),
+        ),
+        (
+            "sticker_packs",
+            to_value(before_sticker_packs),
+            to_value(&after.sticker_packs),
+        ),
+        (
+            "notification_profiles",
+            to_value(before_notification_profiles),
+            to_value(&after.notification_profiles),
+        ),
+        (
+            "chat_folders",
+            to_value(before_chat_folders),
+            to_value(&after.chat_folders),
+        ),
+    ] {
+        differences.values(name, &before, &after);
+    }
+
+    differences.0
+}
+
+#[derive(Default)]
+struct Differences(Vec<Difference>);
+
+impl Differences {
+    fn push(&mut self, path: impl Into<String>, change: Change) {
+        self.0.push(Difference {
+            path: path.into(),
+            change,
+        })
+    }
+
+    /// Compares two entities matched up by identity.
+    ///
+    /// The keys of `before` are reported first, in order, then those only in `after`.
+    fn keyed<'a, T: serde::Serialize + 'a>(
+        &mut self,
+        path: &str,
+        before: impl IntoIterator<Item = (String, &'a T)>,
+        after: impl IntoIterator<Item = (String, &'a T)>,
+        mut compare: impl FnMut(&mut Self, &str, &'a T, &'a T),
+    ) {
+        let after: Vec<_> = after.into_iter().collect();
+        let mut unmatched: HashMap<&str, &'a T> = after
+            .iter()
+            .map(|(key, item)| (key.as_str(), *item))
+            .collect();
+
+        for (key, before) in before {
+            let path = format!("{path}[{key}]");
+            match unmatched.remove(key.as_str()) {
+                Some(after) => compare(self, &path, before, after),
+                None => self.push(
+                    path,
+                    Change::Removed {
+                        before: to_value(before),
+                    },
+                ),
+            }
+        }
+        for (key, after) in &after {
+            if !unmatched.contains_key(key.as_str()) {
+                continue;
+            }
+            self.push(
+                format!("{path}[{key}]"),
+                Change::Added {
+                    after: to_value(*after),
+                },
+            );
+        }
+    }
+
+    /// Compares two chats with the same recipient, matching up their items by identity.
+    fn chats(&mut self, path: &str, before: &ChatData<Store>, after: &ChatData<Store>) {
+        let [before_value, after_value] = [before, after].map(|chat| {
+            let mut value = to_value(chat);
+            if let Value::Object(fields) = &mut value {
+                fields.remove("items");
+            }
+            value
+        });
+        self.values(path, &before_value, &after_value);
+
+        self.keyed(
+            &format!("{path}.items"),
+            chat_items(before),
+            chat_items(after),
+            |differences, path, before, after| {
+                differences.values(path, &to_value(before), &to_value(after))
+            },
+        );
+    }
+
+    /// Compares two JSON values field by field, and arrays element by element.
+    fn values(&mut self, path: &str, before: &Value, after: &Value) {
+        match (before, after) {
+            (Value::Object(before), Value::Object(after)) => {
+                for (key, before) in before {
+                    let path = format!("{path}.{key}");
+                    match after.get(key) {
+                        Some(after) => self.values(&path, before, after),
+                        None => self.push(
+                            path,
+                            Change::Removed {
+                                before: before.clone(),
+                            },
+                        ),
+                    }
+                }
+                for (key, after) in after {
+                    if !before.contains_key(key) {
+                        self.push(
+                            format!("{path}.{key}"),
+                            Change::Added {
+                                after: after.clone(),
+                            },
+                        );
+                    }
+                }
+            }
+            (Value::Array(before), Value::Array(after)) => {
+                for (i, (before, after)) in before.iter().zip(after).enumerate() {
+                    self.values(&format!("{path}[{i}]"), before, after);
+                }
+                for (i, before) in before.iter().enumerate().skip(after.len()) {
+                    self.push(
+                        format!("{path}[{i}]"),
+                        Change::Removed {
+                            before: before.clone(),
+                        },
+                    );
+                }
+                for (i, after) in after.iter().enumerate().skip(before.len()) {
+                    self.push(
+                        format!("{path}[{i}]"),
+                        Change::Added {
+                            after: after.clone(),
+                        },
+                    );
+                }
+            }
+            (before, after) => {
+                if before != after {
+                    self.push(
+                        path,
+                        Change::Changed {
+                            before: before.clone(),
+                            after: after.clone(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn to_value(value: &impl serde::Serialize) -> Value {
+    serde_json::to_value(value).expect("can't fail serialization")
+}
+
+/// Pairs each of `items` with its identity, sorted by identity.
+fn by_identity<T>(items: &[T], identity: impl Fn(&T) -> String) -> Vec<(String, &T)> {
+    let mut sorted: BTreeMap<String, Vec<&T>> = BTreeMap::new();
+    for item in items {
+        sorted.entry(identity(item)).or_default().push(item);
+    }
+    numbered(
+        sorted
+            .into_iter()
+            .flat_map(|(key, items)| items.into_iter().map(move |item| (key.clone(), item))),
+    )
+}
+
+/// Appends `#2`, `#3`, etc. to repeated keys so that every key is unique.
+fn numbered<T>(items: impl IntoIterator<Item = (String, T)>) -> Vec<(String, T)> {
+    let mut seen = HashMap::<String, usize>::new();
+    items
+        .into_iter()
+        .map(|(key, item)| {
+            let count = seen.entry(key.clone()).or_default();
+            *count += 1;
+            match *count {
+                1 => (key, item),
+                n => (format!("{key} #{n}"), item),
+            }
+        })
+        .collect()
+}
+
+fn recipient_identity(recipient: &FullRecipientData) -> String {
+    match &**recipient {
+        Destination::Contact(contact) => {
+            let service_id = contact
+                .aci
+                .map(ServiceId::from)
+                .or(contact.pni.map(ServiceId::from));
+            match (service_id, contact.e164) {
+                (Some(service_id), _) => format!("contact {}", service_id.service_id_string()),
+                (None, Some(e164)) => format!("contact {e164}"),
+                (None, None) => "contact".to_owned(),
+            }
+        }
+        Destination::Group(group) => {
+            format!("group {}", hex::encode(group.master_key))
+        }
+        Destination::DistributionList(
+            DistributionListItem::Deleted {
+                distribution_id, ..
+            }
+            | DistributionListItem::List {
+                distribution_id, ..
+            },
+        ) => format!("distribution list {distribution_id}"),
+        Destination::Self_(_) => "self".to_owned(),
+        Destination::ReleaseNotes => "release notes".to_owned(),
+        Destination::CallLink(call_link) => {
+            format!("call link {}", hex::encode(call_link.root_key))
+        }
+    }
+}
+
+/// Pairs each of a chat's items with its identity.
+///
+/// Unlike [`by_identity`], this keeps the items in the order they were sent.
+fn chat_items(chat: &ChatData<Store>) -> Vec<(String, &ChatItemData<Store>)> {
+    numbered(
+        chat.items
+            .iter()
+            .map(|item| (chat_item_identity(item), item)),
+    )
+}
+
+fn chat_item_identity(item: &ChatItemData<Store>) -> String {
+    format!(
+        "{} @ {}",
+        recipient_identity(&item.author),
+        item.sent_at.as_millis()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use serde_json::json;
+
+    use super::*;
+    use crate::backup::frame::ChatId;
+    use crate::backup::serialize::test::*;
+    use crate::proto::backup as proto;
+
+    fn backup(frames: impl IntoIterator<Item = proto::Frame>) -> Backup {
+        let account_data = proto::Frame {
+            item: Some(proto::AccountData::test_data().into()),
+            special_fields: Default::default(),
+        };
+        let self_recipient = make_recipient(
+            SELF_ID,
+            &proto::recipient::Destination::Self_(Default::default()),
+        );
+        backup_from_frames([account_data, self_recipient].into_iter().chain(frames)).into()
+    }
+
+    fn chat_with(
+        contact_id: RecipientId,
+        chat_id: ChatId,
+        messages: &[&'static str],
+    ) -> Vec<proto::Frame> {
+        [make_chat(chat_id, contact_id)]
+            .into_iter()
+            .chain(
+                messages
+                    .iter()
+                    .map(|message| make_chat_item(chat_id, contact_id, message)),
+            )
+            .collect()
+    }
+
+    #[test]
+    fn identical_backups_have_no_differences() {
+        let frames = || {
+            [make_recipient(FIRST_CONTACT_ID, &make_contact("first", 1))]
+                .into_iter()
+                .chain(chat_with(FIRST_CONTACT_ID, FIRST_CONTACT_CHAT_ID, &["hi"]))
+        };
+        assert_eq!(diff(&backup(frames()), &backup(frames())), []);
+    }
+
+    #[test]
+    fn matches_recipients_and_chats_by_identity() {
+        let first_contact = make_contact("first", 1);
+        let second_contact = make_contact("second", 2);
+
+        let before = backup(
+            [
+                make_recipient(FIRST_CONTACT_ID, &first_contact),
+                make_recipient(SECOND_CONTACT_ID, &second_contact),
+            ]
+            .into_iter()
+            .chain(chat_with(
+                SECOND_CONTACT_ID,
+                SECOND_CONTACT_CHAT_ID,
+                &["hi"],
+            )),
+        );
+        // Renumbered, with the first contact renamed and a chat item edited.
+        let after = backup(
+            [
+                make_recipient(
+                    SECOND_CONTACT_ID,
+                    &proto::Contact {
+                        profileGivenName: Some("renamed".to_owned()),
+                        ..first_contact
+                    },
+                ),
+                make_recipient(FIRST_CONTACT_ID, &second_contact),
+            ]
+            .into_iter()
+            .chain(chat_with(FIRST_CONTACT_ID, FIRST_CONTACT_CHAT_ID, &["bye"])),
+        );
+
+        let first_contact_path = format!("recipients[contact {}]", uuid::Uuid::from_bytes([1; 16]));
+        let second_contact_id = uuid::Uuid::from_bytes([2; 16]);
+        assert_eq!(
+            diff(&before, &after),
+            [
+                Difference {
+                    path: format!("{first_contact_path}.Contact.profile_given_name"),
+                    change: Change::Changed {
+                        before: json!("first"),
+                        after: json!("renamed"),
+                    },
+                },
+                Difference {
+                    path: format!(
+                        "chats[contact {second_contact_id}].items[contact {second_contact_id} @ 0]\
+                         .message.Standard.text.text"
+                    ),
+                    change: Change::Changed {
+                        before: json!("hi"),
+                        after: json!("bye"),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_added_and_removed_entities() {
+        let before = backup(
+            [make_recipient(FIRST_CONTACT_ID, &make_contact("first", 1))]
+                .into_iter()
+                .chain(chat_with(
+                    FIRST_CONTACT_ID,
+                    FIRST_CONTACT_CHAT_ID,
+                    &["a", "b"],
+                )),
+        );
+        let after = backup(
+            [
+                make_recipient(FIRST_CONTACT_ID, &make_contact("first", 1)),
+                make_recipient(SECOND_CONTACT_ID, &make_contact("second", 2)),
+            ]
+            .into_iter()
+            .chain(chat_with(FIRST_CONTACT_ID, FIRST_CONTACT_CHAT_ID, &["a"])),
+        );
+
+        let differences = diff(&before, &after);
+        let [added_recipient, removed_item] = differences.as_slice() else {
+            panic!("unexpected differences: {differences:#?}");
+        };
+
+        assert_eq!(
+            added_recipient.path,
+            format!("recipients[contact {}]", uuid::Uuid::from_bytes([2; 16]))
+        );
+        assert_matches!(added_recipient.change, Change::Added { .. });
+
+        let first_contact = format!("contact {}", uuid::Uuid::from_bytes([1; 16]));
+        assert_eq!(
+            removed_item.path,
+            format!("chats[{first_contact}].items[{first_contact} @ 0 #2]")
+        );
+        assert_matches!(removed_item.change, Change::Removed { .. });
+    }
+}
@@ -0,0 +1,297 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Statistics about what a backup contains and where its size comes from.
+//!
+//! [`BackupStats`] is a [`BackupVisitor`], so it can be collected while a backup is validated
+//! without keeping every chat item in memory; see [`BackupReader::read_stats`].
+//!
+//! [`BackupReader::read_stats`]: crate::BackupReader::read_stats
+
+use std::collections::BTreeMap;
+
+use crate::backup::chat::ChatItemMessage;
+use crate::backup::chat::chat_style::{ChatStyle, Wallpaper};
+use crate::backup::chat::story_reply::DirectStoryReplyContent;
+use crate::backup::file::FilePointer;
+use crate::backup::{
+    AccountData, BackupVisitor, ChatData, ChatId, ChatItemData, ChatItemMessageDiscriminants,
+    FrameKind, LocatorKind, Stream,
+};
+
+/// Counts and sizes collected from a backup's frames.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct BackupStats {
+    /// How many frames there are of each kind, and their total serialized size.
+    ///
+    /// The `BackupInfo` at the start of every backup isn't included.
+    pub frames: BTreeMap<FrameKind, FrameStats>,
+    /// How many chat items are in each chat, including chats with no items at all.
+    pub chat_items_by_chat: BTreeMap<ChatId, usize>,
+    /// How many chat items have each kind of message, not counting previous revisions.
+    pub chat_item_messages: BTreeMap<ChatItemMessageDiscriminants, usize>,
+    /// How many referenced files were or weren't downloaded, or have invalid locators.
+    ///
+    /// This includes attachments of every kind (long text, quote thumbnails, link preview images,
+    /// contact avatars, stickers, and wallpapers as well as message attachments), including those
+    /// of previous revisions of edited messages.
+    pub attachments: BTreeMap<LocatorKind, usize>,
+    /// The total plaintext size of every file counted in `attachments`.
+    ///
+    /// A file referenced more than once is counted each time.
+    pub referenced_media_bytes: u64,
+}
+
+/// The frames of one [`FrameKind`] in a backup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct FrameStats {
+    /// How many frames there are.
+    pub count: usize,
+    /// The total serialized size of the frames, not counting their length prefixes.
+    pub bytes: u64,
+}
+
+impl BackupStats {
+    fn add_file(&mut self, file: &FilePointer) {
+        *self
+            .attachments
+            .entry(file.locator_info.kind())
+            .or_default() += 1;
+        self.referenced_media_bytes += u64::from(file.locator_info.plaintext_size().unwrap_or(0));
+    }
+
+    fn add_chat_style(&mut self, style: &ChatStyle<Stream>) {
+        if let Some(Wallpaper::Photo(photo)) = &style.wallpaper {
+            self.add_file(photo);
+        }
+    }
+
+    fn add_message_files(&mut self, message: &ChatItemMessage<Stream>) {
+        match message {
+            ChatItemMessage::Standard(message) => {
+                for attachment in &message.attachments {
+                    self.add_file(&attachment.pointer);
+                }
+                if let Some(long_text) = &message.long_text {
+                    self.add_file(long_text);
+                }
+                for quoted in message.quote.iter().flat_map(|quote| &quote.attachments) {
+                    if let Some(thumbnail) = &quoted.thumbnail {
+                        self.add_file(&thumbnail.pointer);
+                    }
+                }
+                for image in message
+                    .link_previews
+                    .iter()
+                    .filter_map(|p| p.image.as_ref())
+                {
+                    self.add_file(image);
+                }
+            }
+            ChatItemMessage::Contact(message) => {
+                if let Some(avatar) = &message.contact.avatar {
+                    self.add_file(avatar);
+                }
+            }
+            ChatItemMessage::Voice(message) => {
+                self.add_file(&message.attachment.pointer);
+                for quoted in message.quote.iter().flat_map(|quote| &quote.attachments) {
+                    if let Some(thumbnail) = &quoted.thumbnail {
+                        self.add_file(&thumbnail.pointer);
+                    }
+                }
+            }
+            ChatItemMessage::Sticker(message) => self.add_file(&message.sticker.data),
+            ChatItemMessage::ViewOnce(message) => {
+                if let Some(attachment) = &message.attachment {
+                    self.add_file(&attachment.pointer);
+                }
+            }
+            ChatItemMessage::DirectStoryReply(message) => {
+                if let DirectStoryReplyContent::Text {
+                    long_text: Some(long_text),
+                    ..
+                } = &message.content
+                {
+                    self.add_file(long_text);
+                }
+            }
+            ChatItemMessage::RemoteDeleted
+            | ChatItemMessage::Update(_)
+            | ChatItemMessage::PaymentNotification(_)
+            | ChatItemMessage::GiftBadge(_) => {}
+        }
+    }
+}
+
+impl BackupVisitor<Stream> for BackupStats {
+    fn on_account_data(&mut self, account_data: &AccountData<Stream>) {
+        if let Some(style) = &account_data.account_settings.default_chat_style {
+            self.add_chat_style(style);
+        }
+    }
+
+    fn on_chat(&mut self, id: ChatId, chat: &ChatData<Stream>) {
+        self.chat_items_by_chat.entry(id).or_default();
+        if let Some(style) = &chat.style {
+            self.add_chat_style(style);
+        }
+    }
+
+    fn on_chat_item(&mut self, chat_id: ChatId, item: &ChatItemData<Stream>) {
+        *self.chat_items_by_chat.entry(chat_id).or_default() += 1;
+        *self
+            .chat_item_messages
+            .entry(ChatItemMessageDiscriminants::from(&item.message))
+            .or_default() += 1;
+
+        self.add_message_files(&item.message);
+        for revision in &item.revisions {
+            self.add_message_files(&revision.message);
+        }
+    }
+
+    fn on_frame(&mut self, kind: FrameKind, serialized_len: usize) {
+        let stats = self.frames.entry(kind).or_default();
+        stats.count += 1;
+        stats.bytes += u64::try_from(serialized_len).expect("usize fits in u64");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use libsignal_account_keys::BACKUP_KEY_LEN;
+    use protobuf::Message as _;
+
+    use super::*;
+    use crate::backup::{PartialBackup, Purpose};
+    use crate::proto::backup as proto;
+
+    fn file_pointer(locator: proto::file_pointer::LocatorInfo) -> proto::FilePointer {
+        proto::FilePointer {
+            locatorInfo: Some(locator).into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn counts_frames_messages_and_attachments() {
+        let downloaded = proto::file_pointer::LocatorInfo {
+            key: vec![1; 64],
+            integrityCheck: Some(
+                proto::file_pointer::locator_info::IntegrityCheck::PlaintextHash(vec![2; 32]),
+            ),
+            size: 1000,
+            ..Default::default()
+        };
+        let not_downloaded = proto::file_pointer::LocatorInfo {
+            key: vec![1; 64],
+            integrityCheck: Some(
+                proto::file_pointer::locator_info::IntegrityCheck::EncryptedDigest(vec![3; 32]),
+            ),
+            size: 234,
+            transitCdnKey: Some("cdn key".into()),
+            transitCdnNumber: Some(2),
+            ..Default::default()
+        };
+        let chat_item = proto::ChatItem {
+            item: Some(proto::chat_item::Item::StandardMessage(
+                proto::StandardMessage {
+                    attachments: vec![
+                        proto::MessageAttachment {
+                            pointer: Some(file_pointer(downloaded)).into(),
+                            ..Default::default()
+                        },
+                        proto::MessageAttachment {
+                            pointer: Some(file_pointer(not_downloaded)).into(),
+                            ..Default::default()
+                        },
+                    ],
+                    text: Some(proto::Text::test_data()).into(),
+                    longText: Some(proto::FilePointer::minimal_test_data()).into(),
+                    ..Default::default()
+                },
+            )),
+            ..proto::ChatItem::test_data()
+        };
+
+        let frames: Vec<Vec<u8>> = [
+            proto::Recipient::test_data().into(),
+            proto::Recipient::test_data_contact().into(),
+            proto::Chat::test_data().into(),
+            proto::frame::Item::ChatItem(chat_item),
+        ]
+        .into_iter()
+        .map(|item| {
+            proto::Frame {
+                item: Some(item),
+                ..Default::default()
+            }
+            .write_to_bytes()
+            .expect("can serialize")
+        })
+        .collect();
+
+        let mut backup = PartialBackup::<Stream>::new(
+            proto::BackupInfo {
+                mediaRootBackupKey: vec![0; BACKUP_KEY_LEN],
+                ..Default::default()
+            },
+            Purpose::RemoteBackup,
+        )
+        .expect("valid");
+        let mut stats = BackupStats::default();
+        for frame in &frames {
+            backup
+                .parse_and_add_frame_visiting(frame, |_| (), &mut stats)
+                .expect("valid frame");
+        }
+
+        let recipient_bytes = frames[0].len() + frames[1].len();
+        assert_eq!(
+            stats.frames,
+            BTreeMap::from([
+                (
+                    FrameKind::Recipient,
+                    FrameStats {
+                        count: 2,
+                        bytes: recipient_bytes as u64
+                    }
+                ),
+                (
+                    FrameKind::Chat,
+                    FrameStats {
+                        count: 1,
+                        bytes: frames[2].len() as u64
+                    }
+                ),
+                (
+                    FrameKind::ChatItem,
+                    FrameStats {
+                        count: 1,
+                        bytes: frames[3].len() as u64
+                    }
+                ),
+            ])
+        );
+        assert_eq!(
+            stats.chat_items_by_chat,
+            BTreeMap::from([(ChatId(proto::Chat::TEST_ID), 1)])
+        );
+        assert_eq!(
+            stats.chat_item_messages,
+            BTreeMap::from([(ChatItemMessageDiscriminants::Standard, 1)])
+        );
+        assert_eq!(
+            stats.attachments,
+            BTreeMap::from([
+                (LocatorKind::Invalid, 1),
+                (LocatorKind::NotDownloaded, 1),
+                (LocatorKind::Downloaded, 1),
+            ])
+        );
+        assert_eq!(stats.referenced_media_bytes, 1234);
+    }
+}
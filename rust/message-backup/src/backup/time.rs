@@ -48,14 +48,16 @@ pub trait ReportUnusualTimestamp {
 /// A [`ReportUnusualTimestamp`] implementor that suppresses warnings about particular issues if
 /// they recur too many times.
 #[derive(Default)]
-pub struct UnusualTimestampTracker(
+pub struct UnusualTimestampTracker {
     // We track *both* caller location *and* context string in case either is insufficient to
     // uniquely identify the source of an issue (a reused helper could result in the same location;
     // an overly general description could result in the same context string). In practice, they
     // will probably line up. We also track the issue detected, since different issues may have
     // different causes.
-    HashMap<(std::panic::Location<'static>, &'static str, TimestampIssue), u8>,
-);
+    by_source: HashMap<(std::panic::Location<'static>, &'static str, TimestampIssue), u8>,
+    /// Every report, including suppressed ones.
+    total: usize,
+}
 
 impl Timestamp {
     /// A reasonable range for timestamps found in backup files; timestamps outside of this range
@@ -189,8 +191,9 @@ impl UnusualTimestampTracker {
     // Note: distinct from ReportUnusualTimestamp, this requires `&mut self`.
     pub fn report(&mut self, since_epoch: u64, context: &'static str, issue: TimestampIssue) {
         const SUPPRESS_AFTER_N_LOGS: u8 = 4;
+        self.total += 1;
         let entry = self
-            .0
+            .by_source
             .entry((*std::panic::Location::caller(), context, issue))
             .or_default();
         let suppression_note = match (*entry).cmp(&SUPPRESS_AFTER_N_LOGS) {
@@ -202,6 +205,11 @@ impl UnusualTimestampTracker {
 
         log::warn!("timestamp {context} value {since_epoch} is {issue}{suppression_note}");
     }
+
+    /// The number of unusual timestamps reported, including those whose warnings were suppressed.
+    pub fn total(&self) -> usize {
+        self.total
+    }
 }
 
 impl ReportUnusualTimestamp for std::cell::RefCell<UnusualTimestampTracker> {
@@ -275,7 +283,7 @@ mod test {
     fn timestamp_accepted(milliseconds: MillisecondsSinceEpoch) {
         let tracker: RefCell<UnusualTimestampTracker> = Default::default();
         let _ = Timestamp::from_millis(milliseconds.0, "test_field", &tracker);
-        assert_eq!(&tracker.into_inner().0, &HashMap::default());
+        assert_eq!(&tracker.into_inner().by_source, &HashMap::default());
     }
 
     #[test_matrix((FIXED_DATE, non_hermetic_current_time()), (mistakenly_seconds, mistakenly_microseconds))]
@@ -289,7 +297,8 @@ mod test {
         let tracker: RefCell<UnusualTimestampTracker> = Default::default();
         let _ = Timestamp::from_millis(allegedly_milliseconds, description, &tracker);
 
-        let &[((_location, context, problem), count)] = &Vec::from_iter(tracker.into_inner().0)[..]
+        let &[((_location, context, problem), count)] =
+            &Vec::from_iter(tracker.into_inner().by_source)[..]
         else {
             panic!("failed to reject {allegedly_milliseconds}");
         };
@@ -306,6 +315,17 @@ mod test {
         );
     }
 
+    #[test]
+    fn suppressed_reports_are_still_counted() {
+        let tracker: RefCell<UnusualTimestampTracker> = Default::default();
+        for _ in 0..10 {
+            let _ = Timestamp::from_millis(0, "test", &tracker);
+        }
+        let tracker = tracker.into_inner();
+        assert_eq!(tracker.total(), 10);
+        assert_eq!(tracker.by_source.len(), 1);
+    }
+
     #[test_matrix([MillisecondsSinceEpoch::FAR_FUTURE.0, i64::MAX as u64, u64::MAX])]
     fn timestamp_hard_error(raw_timestamp: u64) {
         let tracker: RefCell<UnusualTimestampTracker> = Default::default();
@@ -315,7 +335,7 @@ mod test {
         );
         assert_eq!(
             0,
-            tracker.into_inner().0.len(),
+            tracker.into_inner().by_source.len(),
             "nothing should be added to the tracker"
         );
     }
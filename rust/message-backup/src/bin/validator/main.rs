@@ -3,17 +3,27 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
-use clap::Parser;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
 use futures::AsyncRead;
-use libsignal_message_backup::backup::Purpose;
+use libsignal_message_backup::backup::serialize::diff::diff;
+use libsignal_message_backup::backup::stats::BackupStats;
+use libsignal_message_backup::backup::{
+    Backup, BackupCounts, PartialBackup, Purpose, Store, serialize,
+};
 use libsignal_message_backup::frame::{
-    FramesReader, ReaderFactory as _, UnvalidatedHmacReader, VerifyHmac,
+    FramesReader, ReaderFactory, UnvalidatedHmacReader, VerifyHmac,
 };
-use libsignal_message_backup::{BackupReader, Error, FoundUnknownField, ReadResult};
+use libsignal_message_backup::key::MessageBackupKey;
+use libsignal_message_backup::{BackupReader, FoundUnknownField, ReadResult};
+use mediasan_common::AsyncSkip;
 
 use crate::args::ParseVerbosity;
+use crate::report::{DIFFERENCES_FOUND_EXIT_CODE, Failure, Report};
 
 mod args;
+mod report;
 
 #[path = "../support/mod.rs"]
 mod support;
@@ -25,20 +35,71 @@ use support::{AsyncReaderFactory, FilenameOrContents, KeyArgs};
 /// backup is assumed to be a sequence of varint-delimited protos. Otherwise,
 /// the backup file is assumed to be an encrypted gzip-compressed sequence of
 /// followed by an HMAC of the contents.
+///
+/// Exits with status 1 if the backup is invalid, 3 if it is malformed
+/// (including truncated), 4 if it can't be decrypted or authenticated, and 5
+/// if it can't be read. `diff` exits with status 6 if the backups are valid
+/// but differ.
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
     /// filename to read the backup from, or - for stdin
-    #[arg(value_hint = clap::ValueHint::FilePath)]
-    file: clap_stdin::FileOrStdin,
+    #[arg(value_hint = clap::ValueHint::FilePath, required = true)]
+    file: Option<clap_stdin::FileOrStdin>,
 
     /// causes additional output to be printed to stderr; passing the flag multiple times increases the verbosity
     #[arg(short='v', action=clap::ArgAction::Count)]
     verbose: u8,
 
     /// when set, the validated backup contents are printed to stdout
-    #[arg(long)]
+    #[arg(long, conflicts_with = "format")]
     print: bool,
 
+    /// how to report the result of validation
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// the purpose the backup is intended for
+    #[arg(long, default_value_t=Purpose::RemoteBackup)]
+    purpose: Purpose,
+
+    #[command(flatten)]
+    key_args: KeyArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// errors and unknown fields are printed to stderr
+    Text,
+    /// a JSON report is printed to stdout, including errors, unknown fields, and summary counts
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Compares the contents of two backups, matching up recipients, chats, and chat items by
+    /// identity.
+    ///
+    /// Prints each difference to stdout and exits with status 6 if there are any. Both backups
+    /// must be valid, and are decrypted with the same keys; if either isn't, exits with the same
+    /// status as validating it would.
+    Diff(DiffArgs),
+    /// Validates a backup and prints statistics about its contents as JSON.
+    ///
+    /// Includes counts and sizes of each kind of frame, chat items per chat and by kind of
+    /// message, and attachments by whether they were ever downloaded.
+    Stats(StatsArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct StatsArgs {
+    /// filename to read the backup from
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    file: String,
+
     /// the purpose the backup is intended for
     #[arg(long, default_value_t=Purpose::RemoteBackup)]
     purpose: Purpose,
@@ -47,46 +108,186 @@ struct Cli {
     key_args: KeyArgs,
 }
 
-fn main() {
+#[derive(Debug, clap::Args)]
+struct DiffArgs {
+    /// filename to read the original backup from
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    before: String,
+
+    /// filename to read the backup to compare against the original
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    after: String,
+
+    /// the purpose the backups are intended for
+    #[arg(long, default_value_t=Purpose::RemoteBackup)]
+    purpose: Purpose,
+
+    #[command(flatten)]
+    key_args: KeyArgs,
+}
+
+fn main() -> ExitCode {
     futures::executor::block_on(async_main())
 }
 
-async fn async_main() {
+async fn async_main() -> ExitCode {
     let Cli {
         file: file_or_stdin,
         key_args,
         purpose,
         print,
+        format,
         verbose,
+        command,
     } = Cli::parse();
     env_logger::init();
 
+    match command {
+        Some(Command::Diff(args)) => return diff_backups(args).await,
+        Some(Command::Stats(args)) => return print_stats(args).await,
+        None => {}
+    }
+
     let print = PrintOutput(print);
 
     let verbosity = verbose.into();
 
     let key = key_args.into_key();
 
-    let contents = FilenameOrContents::from(file_or_stdin);
-    let mut factory = AsyncReaderFactory::from(&contents);
+    let contents =
+        FilenameOrContents::from(file_or_stdin.expect("required unless using a subcommand"));
+    let factory = AsyncReaderFactory::from(&contents);
+
+    let (result, found_unknown_fields) =
+        match MaybeEncryptedBackupReader::new(key.as_ref(), factory, purpose).await {
+            Ok(reader) => reader.execute(print, verbosity).await,
+            Err(failure) => (Err(failure), vec![]),
+        };
+    let exit_code = match &result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(failure) => ExitCode::from(failure.class.exit_code()),
+    };
+
+    match format {
+        OutputFormat::Text => {
+            print_unknown_fields(&found_unknown_fields);
+            if let Err(failure) = result {
+                eprintln!("backup error: {failure}");
+            }
+        }
+        OutputFormat::Json => {
+            let report = Report::new(result, &found_unknown_fields);
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).expect("can serialize report")
+            );
+        }
+    }
+    exit_code
+}
+
+async fn diff_backups(args: DiffArgs) -> ExitCode {
+    let DiffArgs {
+        before,
+        after,
+        purpose,
+        key_args,
+    } = args;
+    let key = key_args.into_key();
+
+    let comparable = async |filename: String| {
+        read_comparable(&filename, key.as_ref(), purpose)
+            .await
+            .map_err(|failure| {
+                eprintln!("backup error in {filename}: {failure}");
+                ExitCode::from(failure.class.exit_code())
+            })
+    };
+    let before = match comparable(before).await {
+        Ok(backup) => backup,
+        Err(exit_code) => return exit_code,
+    };
+    let after = match comparable(after).await {
+        Ok(backup) => backup,
+        Err(exit_code) => return exit_code,
+    };
 
-    let reader = if let Some(key) = key {
-        MaybeEncryptedBackupReader::EncryptedCompressed(Box::new(
-            BackupReader::new_encrypted_compressed(&key, factory, purpose)
-                .await
-                .unwrap_or_else(|e| panic!("invalid encrypted backup: {e:#}")),
-        ))
+    let differences = diff(&before, &after);
+    for difference in &differences {
+        println!("{difference}");
+    }
+    if differences.is_empty() {
+        ExitCode::SUCCESS
     } else {
-        MaybeEncryptedBackupReader::PlaintextBinproto(BackupReader::new_unencrypted(
-            factory.make_reader().expect("failed to read"),
-            purpose,
-        ))
+        eprintln!("found {} differences", differences.len());
+        ExitCode::from(DIFFERENCES_FOUND_EXIT_CODE)
+    }
+}
+
+async fn print_stats(args: StatsArgs) -> ExitCode {
+    let StatsArgs {
+        file,
+        purpose,
+        key_args,
+    } = args;
+    let key = key_args.into_key();
+    let contents = FilenameOrContents::Filename(file);
+    let factory = AsyncReaderFactory::from(&contents);
+
+    let reader = match MaybeEncryptedBackupReader::new(key.as_ref(), factory, purpose).await {
+        Ok(reader) => reader,
+        Err(failure) => {
+            eprintln!("backup error: {failure}");
+            return ExitCode::from(failure.class.exit_code());
+        }
     };
+    let ReadResult {
+        found_unknown_fields,
+        result,
+        error_frame_index,
+        ..
+    } = reader.read_stats().await;
+
+    print_unknown_fields(&found_unknown_fields);
+    match result {
+        Ok(stats) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&stats).expect("can serialize stats")
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            let failure = Failure::from_read_error(&e, error_frame_index);
+            eprintln!("backup error: {failure}");
+            ExitCode::from(failure.class.exit_code())
+        }
+    }
+}
 
-    reader
-        .execute(print, verbosity)
-        .await
-        .unwrap_or_else(|e| panic!("backup error: {e:#}"));
+/// Reads and validates the backup in `filename`, then converts it to its comparable form.
+async fn read_comparable(
+    filename: &str,
+    key: Option<&MessageBackupKey>,
+    purpose: Purpose,
+) -> Result<serialize::Backup, Failure> {
+    let contents = FilenameOrContents::Filename(filename.to_owned());
+    let factory = AsyncReaderFactory::from(&contents);
+    let ReadResult {
+        found_unknown_fields,
+        result,
+        error_frame_index,
+        ..
+    } = MaybeEncryptedBackupReader::new(key, factory, purpose)
+        .await?
+        .collect_all(ParseVerbosity::None)
+        .await;
+
+    print_unknown_fields(&found_unknown_fields);
+    let backup = result
+        .and_then(|partial| Ok(Backup::try_from(partial)?))
+        .map_err(|e| Failure::from_read_error(&e, error_frame_index))?;
+    Ok(serialize::Backup::from(backup))
 }
 
 /// Wrapper over encrypted- or plaintext-sourced [`BackupReader`].
@@ -97,38 +298,83 @@ enum MaybeEncryptedBackupReader<R: AsyncRead + Unpin> {
 
 struct PrintOutput(bool);
 
+impl<R: AsyncRead + AsyncSkip + Unpin> MaybeEncryptedBackupReader<R> {
+    /// Reads an encrypted backup if a key is provided, or a plaintext one otherwise.
+    async fn new(
+        key: Option<&MessageBackupKey>,
+        mut factory: impl ReaderFactory<Reader = R>,
+        purpose: Purpose,
+    ) -> Result<Self, Failure> {
+        Ok(if let Some(key) = key {
+            Self::EncryptedCompressed(Box::new(
+                BackupReader::new_encrypted_compressed(key, factory, purpose)
+                    .await
+                    .map_err(|e| Failure::from_header_error(&e))?,
+            ))
+        } else {
+            Self::PlaintextBinproto(BackupReader::new_unencrypted(
+                factory
+                    .make_reader()
+                    .map_err(|e| Failure::from_io_error(&e))?,
+                purpose,
+            ))
+        })
+    }
+}
+
 impl<R: AsyncRead + Unpin> MaybeEncryptedBackupReader<R> {
-    async fn execute(self, print: PrintOutput, verbosity: ParseVerbosity) -> Result<(), Error> {
-        async fn validate(
+    /// Validates the backup, returning its summary counts if it's valid.
+    async fn execute(
+        self,
+        PrintOutput(print): PrintOutput,
+        verbosity: ParseVerbosity,
+    ) -> (Result<BackupCounts, Failure>, Vec<FoundUnknownField>) {
+        let ReadResult {
+            found_unknown_fields,
+            result,
+            error_frame_index,
+            ..
+        } = self.collect_all(verbosity).await;
+
+        let result = result
+            .and_then(|partial| {
+                let counts = partial.counts();
+                let backup = Backup::try_from(partial)?;
+                if print {
+                    println!("{backup:#?}");
+                }
+                Ok(counts)
+            })
+            .map_err(|e| Failure::from_read_error(&e, error_frame_index));
+        (result, found_unknown_fields)
+    }
+
+    async fn read_stats(self) -> ReadResult<BackupStats> {
+        match self {
+            Self::EncryptedCompressed(reader) => reader.read_stats().await,
+            Self::PlaintextBinproto(reader) => reader.read_stats().await,
+        }
+    }
+
+    async fn collect_all(self, verbosity: ParseVerbosity) -> ReadResult<PartialBackup<Store>> {
+        async fn collect_all(
             mut backup_reader: BackupReader<impl AsyncRead + Unpin + VerifyHmac>,
-            PrintOutput(print): PrintOutput,
             verbosity: ParseVerbosity,
-        ) -> Result<(), Error> {
+        ) -> ReadResult<PartialBackup<Store>> {
             if let Some(visitor) = verbosity.into_visitor() {
                 backup_reader.visitor = visitor;
             }
-            let ReadResult {
-                found_unknown_fields,
-                result,
-            } = backup_reader.read_all().await;
-
-            print_unknown_fields(found_unknown_fields);
-            let backup = result?;
-
-            if print {
-                println!("{backup:#?}");
-            }
-            Ok(())
+            backup_reader.collect_all().await
         }
 
         match self {
-            Self::EncryptedCompressed(reader) => validate(*reader, print, verbosity).await,
-            Self::PlaintextBinproto(reader) => validate(reader, print, verbosity).await,
+            Self::EncryptedCompressed(reader) => collect_all(*reader, verbosity).await,
+            Self::PlaintextBinproto(reader) => collect_all(reader, verbosity).await,
         }
     }
 }
 
-fn print_unknown_fields(found_unknown_fields: Vec<FoundUnknownField>) {
+fn print_unknown_fields(found_unknown_fields: &[FoundUnknownField]) {
     if found_unknown_fields.is_empty() {
         return;
     }
@@ -163,14 +409,16 @@ mod test {
         const INPUT: &[&str] = &[EXECUTABLE_NAME, "filename"];
 
         let file = assert_matches!(Cli::try_parse_from(INPUT), Ok(Cli {
-            file,
+            file: Some(file),
             verbose: 0,
             print: false,
+            format: OutputFormat::Text,
             purpose: Purpose::RemoteBackup,
             key_args: KeyArgs {
                 derive_key: DeriveKey { account_entropy: None, aci: None, forward_secrecy_token: None },
                 key_parts: KeyParts { hmac_key: None, aes_key: None }
             },
+            command: None,
         }) => file);
         assert_eq!(file.filename(), "filename");
     }
@@ -189,14 +437,16 @@ mod test {
         ];
 
         let (file, derive_key) = assert_matches!(Cli::try_parse_from(INPUT), Ok(Cli {
-            file,
+            file: Some(file),
             verbose: 0,
             print: false,
+            format: OutputFormat::Text,
             purpose: Purpose::RemoteBackup,
             key_args: KeyArgs {
                 derive_key,
                 key_parts: KeyParts { hmac_key: None, aes_key: None }
             },
+            command: None,
         }) => (file, derive_key));
         assert_eq!(file.filename(), "filename");
         assert_eq!(
@@ -221,14 +471,16 @@ mod test {
         ];
 
         let (file, key_parts) = assert_matches!(Cli::try_parse_from(INPUT), Ok(Cli {
-            file,
+            file: Some(file),
             verbose: 0,
             print: false,
+            format: OutputFormat::Text,
             purpose: Purpose::RemoteBackup,
             key_args: KeyArgs {
                 derive_key: DeriveKey { account_entropy: None, aci: None, forward_secrecy_token: None },
                 key_parts,
-            }
+            },
+            command: None,
         }) => (file, key_parts));
         assert_eq!(file.filename(), "filename");
         assert_eq!(
@@ -298,6 +550,69 @@ mod test {
         }
     }
 
+    #[test]
+    fn cli_parse_format_json() {
+        const INPUT: &[&str] = &[EXECUTABLE_NAME, "filename", "--format", "json"];
+        let cli = Cli::try_parse_from(INPUT).expect("parse failed");
+        assert_eq!(cli.format, OutputFormat::Json);
+    }
+
+    #[test]
+    fn cli_parse_print_conflicts_with_format() {
+        const INPUT: &[&str] = &[EXECUTABLE_NAME, "filename", "--print", "--format", "json"];
+        let e = assert_matches!(Cli::try_parse_from(INPUT), Err(e) => e);
+        assert_eq!(e.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn cli_parse_diff() {
+        const INPUT: &[&str] = &[
+            EXECUTABLE_NAME,
+            "diff",
+            "before",
+            "after",
+            "--purpose",
+            "transfer",
+        ];
+
+        let args = assert_matches!(Cli::try_parse_from(INPUT), Ok(Cli {
+            file: None,
+            command: Some(Command::Diff(args)),
+            ..
+        }) => args);
+        assert_matches!(args, DiffArgs {
+            before,
+            after,
+            purpose: Purpose::DeviceTransfer,
+            key_args: _,
+        } if before == "before" && after == "after");
+    }
+
+    #[test]
+    fn cli_parse_stats() {
+        const INPUT: &[&str] = &[EXECUTABLE_NAME, "stats", "filename"];
+
+        let args = assert_matches!(Cli::try_parse_from(INPUT), Ok(Cli {
+            file: None,
+            command: Some(Command::Stats(args)),
+            ..
+        }) => args);
+        assert_matches!(args, StatsArgs {
+            file,
+            purpose: Purpose::RemoteBackup,
+            key_args: _,
+        } if file == "filename");
+    }
+
+    #[test]
+    fn cli_parse_diff_requires_both_files() {
+        const INPUT: &[&str] = &[EXECUTABLE_NAME, "diff", "before"];
+        let e = assert_matches!(Cli::try_parse_from(INPUT), Err(e) => e);
+        assert_eq!(e.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+
+        assert!(e.to_string().contains("<AFTER>"), "{e}");
+    }
+
     #[test_case("backup", Purpose::RemoteBackup; "remote")]
     #[test_case("remote_backup", Purpose::RemoteBackup; "remote underscore")]
     #[test_case("remote-backup", Purpose::RemoteBackup; "remote hyphen")]
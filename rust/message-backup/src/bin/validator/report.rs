@@ -0,0 +1,272 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! The machine-readable report printed with `--format json`.
+//!
+//! The schema is identified by [`SCHEMA_VERSION`]. Within a version, fields may be added but are
+//! never removed or changed, and the set of error kinds and failure classes only grows.
+
+use libsignal_message_backup::backup::BackupCounts;
+use libsignal_message_backup::unknown::FormatPath;
+use libsignal_message_backup::{Error, FoundUnknownField, frame};
+
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Report {
+    pub(crate) schema_version: u32,
+    pub(crate) success: bool,
+    pub(crate) error: Option<Failure>,
+    pub(crate) unknown_fields: Vec<UnknownField>,
+    /// Only present for valid backups.
+    pub(crate) counts: Option<BackupCounts>,
+}
+
+impl Report {
+    pub(crate) fn new(
+        result: Result<BackupCounts, Failure>,
+        found_unknown_fields: &[FoundUnknownField],
+    ) -> Self {
+        let (counts, error) = match result {
+            Ok(counts) => (Some(counts), None),
+            Err(failure) => (None, Some(failure)),
+        };
+        Self {
+            schema_version: SCHEMA_VERSION,
+            success: error.is_none(),
+            error,
+            unknown_fields: found_unknown_fields
+                .iter()
+                .map(UnknownField::from)
+                .collect(),
+            counts,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct UnknownField {
+    pub(crate) frame_index: usize,
+    pub(crate) path: String,
+    pub(crate) value: String,
+}
+
+impl From<&FoundUnknownField> for UnknownField {
+    fn from(field: &FoundUnknownField) -> Self {
+        let FoundUnknownField {
+            frame_index,
+            path,
+            value,
+        } = field;
+        Self {
+            frame_index: *frame_index,
+            path: FormatPath(path.as_slice()).to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Why a backup couldn't be validated.
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Failure {
+    /// The name of the [`Error`] variant, or `InvalidMetadata` for a bad encryption header.
+    pub(crate) kind: &'static str,
+    pub(crate) class: FailureClass,
+    pub(crate) message: String,
+    /// The frame the error came from, if it came from a single frame.
+    pub(crate) frame_index: Option<usize>,
+}
+
+/// Broad categories of [`Failure`], each with its own exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FailureClass {
+    /// The backup was read successfully, but its contents aren't valid.
+    Invalid,
+    /// The backup couldn't be parsed.
+    Malformed,
+    /// The backup couldn't be authenticated, usually because the key is wrong.
+    Encryption,
+    /// The backup couldn't be read.
+    Io,
+}
+
+/// The exit code for `validator diff` when both backups are valid but not the same.
+///
+/// This is distinct from every [`FailureClass::exit_code`], so that differences can't be mistaken
+/// for a problem with either backup.
+pub(crate) const DIFFERENCES_FOUND_EXIT_CODE: u8 = 6;
+
+impl FailureClass {
+    /// The process exit code for this class of failure.
+    ///
+    /// 2 is skipped because it's used for command-line usage errors.
+    pub(crate) fn exit_code(self) -> u8 {
+        match self {
+            Self::Invalid => 1,
+            Self::Malformed => 3,
+            Self::Encryption => 4,
+            Self::Io => 5,
+        }
+    }
+
+    /// Classifies an error from reading the backup's bytes.
+    ///
+    /// Running out of input partway through, or finding bytes that can't be decoded (such as a bad
+    /// length prefix), means the backup itself is truncated or corrupt.
+    fn for_parse_error(error: &std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::InvalidData => Self::Malformed,
+            _ => Self::Io,
+        }
+    }
+}
+
+impl Failure {
+    pub(crate) fn from_read_error(error: &Error, frame_index: Option<usize>) -> Self {
+        let (kind, class) = match error {
+            Error::BackupValidation(_) => ("BackupValidation", FailureClass::Invalid),
+            Error::BackupCompletion(_) => ("BackupCompletion", FailureClass::Invalid),
+            Error::Parse(e) => ("Parse", FailureClass::for_parse_error(e)),
+            Error::NoFrames => ("NoFrames", FailureClass::Malformed),
+            Error::InvalidProtobuf(_) => ("InvalidProtobuf", FailureClass::Malformed),
+            Error::HmacMismatch(_) => ("HmacMismatch", FailureClass::Encryption),
+        };
+        Self {
+            kind,
+            class,
+            message: error.to_string(),
+            frame_index,
+        }
+    }
+
+    /// For errors opening an encrypted backup, before any frames are read.
+    pub(crate) fn from_header_error(error: &frame::ValidationError) -> Self {
+        let (kind, class) = match error {
+            frame::ValidationError::Io(e) => ("Parse", FailureClass::for_parse_error(e)),
+            frame::ValidationError::InvalidHmac(_) => ("HmacMismatch", FailureClass::Encryption),
+            frame::ValidationError::MissingMetadataField(_)
+            | frame::ValidationError::InvalidLength { .. }
+            | frame::ValidationError::TooManyForwardSecrecyPairs(_) => {
+                ("InvalidMetadata", FailureClass::Malformed)
+            }
+        };
+        Self {
+            kind,
+            class,
+            message: error.to_string(),
+            frame_index: None,
+        }
+    }
+
+    pub(crate) fn from_io_error(error: &std::io::Error) -> Self {
+        Self {
+            kind: "Parse",
+            class: FailureClass::Io,
+            message: error.to_string(),
+            frame_index: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            kind: _,
+            class: _,
+            message,
+            frame_index,
+        } = self;
+        match frame_index {
+            Some(frame_index) => write!(f, "in frame {frame_index}, {message}"),
+            None => write!(f, "{message}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use libsignal_message_backup::unknown::{PathPart, UnknownValue};
+
+    use super::*;
+
+    #[test]
+    fn report_schema() {
+        let unknown_fields = [FoundUnknownField {
+            frame_index: 3,
+            path: vec![
+                PathPart::Field {
+                    field_name: "chatItem".to_owned(),
+                },
+                PathPart::Repeated {
+                    field_name: "revisions".to_owned(),
+                    index: 1,
+                },
+            ],
+            value: UnknownValue::Field { tag: 99 },
+        }];
+        let failure = Failure::from_read_error(&Error::NoFrames, None);
+        let report = Report::new(Err(failure), &unknown_fields);
+
+        assert_eq!(
+            serde_json::to_value(&report).expect("can serialize"),
+            serde_json::json!({
+                "schema_version": 1,
+                "success": false,
+                "error": {
+                    "kind": "NoFrames",
+                    "class": "malformed",
+                    "message": "no frames found",
+                    "frame_index": null,
+                },
+                "unknown_fields": [{
+                    "frame_index": 3,
+                    "path": "chatItem.revisions[1]",
+                    "value": "field with tag 99",
+                }],
+                "counts": null,
+            })
+        );
+    }
+
+    #[test]
+    fn truncated_input_is_malformed() {
+        let truncated = Error::Parse(std::io::ErrorKind::UnexpectedEof.into());
+        let failure = Failure::from_read_error(&truncated, Some(2));
+        assert_eq!(failure.kind, "Parse");
+        assert_eq!(failure.class, FailureClass::Malformed);
+
+        let unreadable = Error::Parse(std::io::ErrorKind::PermissionDenied.into());
+        let failure = Failure::from_read_error(&unreadable, None);
+        assert_eq!(failure.class, FailureClass::Io);
+    }
+
+    #[test]
+    fn successful_report_has_counts() {
+        let counts = BackupCounts {
+            recipients: 2,
+            chats: 1,
+            chat_items: 5,
+            unusual_timestamps: 0,
+        };
+        let report = Report::new(Ok(counts), &[]);
+
+        assert_eq!(
+            serde_json::to_value(&report).expect("can serialize"),
+            serde_json::json!({
+                "schema_version": 1,
+                "success": true,
+                "error": null,
+                "unknown_fields": [],
+                "counts": {
+                    "recipients": 2,
+                    "chats": 1,
+                    "chat_items": 5,
+                    "unusual_timestamps": 0,
+                },
+            })
+        );
+    }
+}
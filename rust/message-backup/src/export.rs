@@ -5,7 +5,9 @@
 
 //! Utilities for exporting backups.
 //!
-//! See `encrypt_backup` or `generation/mod.rs` for how they fit together.
+//! See `generation/mod.rs` for how they fit together. Outside of tests, prefer
+//! [`BackupWriter`](crate::BackupWriter), which does the same work without holding the whole
+//! backup in memory.
 
 use aes::cipher::{BlockEncryptMut as _, BlockSizeUser as _, KeyIvInit as _};
 use async_compression::futures::bufread::GzipEncoder;
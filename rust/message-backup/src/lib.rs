@@ -5,7 +5,7 @@
 
 //! Signal remote message backup utilities.
 //!
-//! Contains code to read and validate message backup files.
+//! Contains code to read, validate, and write message backup files.
 
 use std::time::Duration;
 
@@ -13,7 +13,7 @@ use futures::AsyncRead;
 use mediasan_common::AsyncSkip;
 use protobuf::Message as _;
 
-use crate::backup::method::{Store, ValidateOnly};
+use crate::backup::method::{Store, Stream, ValidateOnly};
 use crate::backup::{CompletedBackup, Purpose};
 use crate::frame::{
     HmacMismatchError, ReaderFactory, UnvalidatedHmacReader, VerifyHmac, VerifyHmacError,
@@ -28,6 +28,7 @@ pub mod frame;
 pub mod key;
 pub mod parse;
 pub mod unknown;
+pub mod writer;
 
 // visibility::make isn't supported for modules, so we have to write it twice instead.
 #[cfg(feature = "test-util")]
@@ -41,6 +42,8 @@ pub mod export;
 #[cfg(feature = "scramble")]
 pub mod scramble;
 
+pub use writer::BackupWriter;
+
 pub struct BackupReader<R> {
     purpose: Purpose,
     reader: VarintDelimitedReader<R>,
@@ -64,9 +67,14 @@ pub enum Error {
 }
 
 #[must_use]
+#[non_exhaustive]
 pub struct ReadResult<B> {
     pub result: Result<B, Error>,
     pub found_unknown_fields: Vec<FoundUnknownField>,
+    /// The index of the frame that `result`'s error came from, if it came from a single frame.
+    ///
+    /// As with [`FoundUnknownField::frame_index`], the `BackupInfo` is frame 0.
+    pub error_frame_index: Option<usize>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -134,10 +142,12 @@ impl<R> ReadResult<R> {
         let Self {
             result,
             found_unknown_fields,
+            error_frame_index,
         } = self;
         ReadResult {
             found_unknown_fields,
             result: result.and_then(f),
+            error_frame_index,
         }
     }
 }
@@ -156,11 +166,48 @@ impl<R: AsyncRead + Unpin + VerifyHmac> BackupReader<R> {
         })
     }
 
+    /// Reads and validates the whole backup, passing each item to `backup_visitor` along the way.
+    ///
+    /// Unlike [`read_all`](Self::read_all), chat items and ad-hoc calls aren't kept after being
+    /// visited, so memory use doesn't grow with the number of messages in the backup. The visitor
+    /// is returned once the backup has been completely validated.
+    pub async fn visit_all<V: backup::BackupVisitor<Stream> + Send + 'static>(
+        self,
+        backup_visitor: V,
+    ) -> ReadResult<V> {
+        self.collect_all_visiting(backup_visitor)
+            .await
+            .and_then(|(partial, backup_visitor)| {
+                let _: CompletedBackup<Stream> = partial.try_into()?;
+                Ok(backup_visitor)
+            })
+    }
+
+    /// Reads and validates the whole backup, collecting [`BackupStats`](backup::stats::BackupStats)
+    /// along the way.
+    pub async fn read_stats(self) -> ReadResult<backup::stats::BackupStats> {
+        self.visit_all(backup::stats::BackupStats::default()).await
+    }
+
     pub async fn collect_all<M: backup::method::Method + backup::ReferencedTypes>(
         self,
     ) -> ReadResult<backup::PartialBackup<M>>
     where
         backup::PartialBackup<M>: Send,
+    {
+        self.collect_all_visiting(())
+            .await
+            .and_then(|(partial, ())| Ok(partial))
+    }
+
+    async fn collect_all_visiting<M, V>(
+        self,
+        backup_visitor: V,
+    ) -> ReadResult<(backup::PartialBackup<M>, V)>
+    where
+        M: backup::method::Method + backup::ReferencedTypes,
+        V: backup::BackupVisitor<M> + Send + 'static,
+        backup::PartialBackup<M>: Send,
     {
         let Self {
             reader,
@@ -169,10 +216,20 @@ impl<R: AsyncRead + Unpin + VerifyHmac> BackupReader<R> {
         } = self;
 
         let mut found_unknown_fields = Vec::new();
-        let result = read_all_frames(purpose, reader, visitor, &mut found_unknown_fields).await;
+        let mut error_frame_index = None;
+        let result = read_all_frames(
+            purpose,
+            reader,
+            visitor,
+            backup_visitor,
+            &mut found_unknown_fields,
+            &mut error_frame_index,
+        )
+        .await;
         ReadResult {
             found_unknown_fields,
             result,
+            error_frame_index,
         }
     }
 }
@@ -203,13 +260,16 @@ impl<R: AsyncRead + AsyncSkip + Unpin> BackupReader<frame::FramesReader<R>> {
     }
 }
 
-async fn read_all_frames<M: backup::method::Method + backup::ReferencedTypes>(
+async fn read_all_frames<M: backup::method::Method + backup::ReferencedTypes, V>(
     purpose: Purpose,
     mut reader: VarintDelimitedReader<impl AsyncRead + Unpin + VerifyHmac>,
     mut visitor: impl FnMut(&dyn std::fmt::Debug) + Send + 'static,
+    mut backup_visitor: V,
     unknown_fields: &mut Vec<FoundUnknownField>,
-) -> Result<backup::PartialBackup<M>, Error>
+    error_frame_index: &mut Option<usize>,
+) -> Result<(backup::PartialBackup<M>, V), Error>
 where
+    V: backup::BackupVisitor<M> + Send + 'static,
     backup::PartialBackup<M>: Send,
 {
     let add_found_unknown =
@@ -229,12 +289,16 @@ where
         .await
         .map_err(Error::Parse)?
         .ok_or(Error::NoFrames)?;
+
+    // Any error from here until the frame-processing thread starts is about the BackupInfo.
+    *error_frame_index = Some(0);
     let backup_info = proto::backup::BackupInfo::parse_from_bytes(&first)?;
 
     visitor(&backup_info);
     add_found_unknown(unknown_fields, backup_info.collect_unknown_fields(), 0);
 
     let mut backup = backup::PartialBackup::new(backup_info, purpose)?;
+    *error_frame_index = None;
 
     // From here on we split the work into two separate threads:
     // - this thread, which reads frames from the reader
@@ -260,7 +324,11 @@ where
                         Ok(frame) => break frame,
                         Err(std::sync::mpsc::TryRecvError::Disconnected) => {
                             // ...as signalled by the sender being dropped.
-                            return Ok::<_, Error>((backup, unknown_fields));
+                            return Ok::<_, (Error, usize)>((
+                                backup,
+                                backup_visitor,
+                                unknown_fields,
+                            ));
                         }
                         Err(std::sync::mpsc::TryRecvError::Empty) => {
                             // Rather than doing a blocking read, just sleep quickly to let the
@@ -273,15 +341,26 @@ where
                     }
                 };
 
-                let these_unknown_fields =
-                    backup.parse_and_add_frame(&frame, |frame| visitor(frame))?;
+                let these_unknown_fields = backup
+                    .parse_and_add_frame_visiting(
+                        &frame,
+                        |frame| visitor(frame),
+                        &mut backup_visitor,
+                    )
+                    .map_err(|error| (error, frame_index))?;
                 add_found_unknown(&mut unknown_fields, these_unknown_fields, frame_index);
                 frame_index += 1;
             }
         })
         .expect("can create threads");
 
-    'outer: while let Some(mut buf) = reader.read_next().await.map_err(Error::Parse)? {
+    let mut next_frame_index = 1;
+    'outer: while let Some(mut buf) = reader.read_next().await.map_err(|error| {
+        *error_frame_index = Some(next_frame_index);
+        Error::Parse(error)
+    })? {
+        next_frame_index += 1;
+
         // Try to send to the processing thread in a spin-loop.
         // Normally the processing thread is faster than the reader thread, so this should only spin
         // a few times before success, which is faster than going to sleep and waiting to be woken.
@@ -303,9 +382,12 @@ where
     // Let the frame-processing thread know there's nothing more to read.
     drop(frame_tx);
 
-    let (backup, inner_unknown_fields) = match frame_processing_thread.join() {
+    let (backup, backup_visitor, inner_unknown_fields) = match frame_processing_thread.join() {
         Ok(Ok(success)) => success,
-        Ok(Err(validation_error)) => return Err(validation_error),
+        Ok(Err((validation_error, frame_index))) => {
+            *error_frame_index = Some(frame_index);
+            return Err(validation_error);
+        }
         Err(panic) => std::panic::resume_unwind(panic),
     };
     unknown_fields.extend(inner_unknown_fields);
@@ -314,7 +396,7 @@ where
     // prevents TOC/TOU issues.
     reader.into_inner().verify_hmac().await?;
 
-    Ok(backup)
+    Ok((backup, backup_visitor))
 }
 
 /// For APIs that don't have a good way to report unknown fields, logging is the best we can do if
@@ -345,9 +427,20 @@ impl<M: backup::method::Method + backup::ReferencedTypes> backup::PartialBackup<
     }
 
     pub fn parse_and_add_frame(
+        &mut self,
+        raw_frame: &[u8],
+        visitor: impl FnMut(&proto::backup::Frame) + Send,
+    ) -> Result<Vec<(Vec<PathPart>, UnknownValue)>, crate::Error> {
+        self.parse_and_add_frame_visiting(raw_frame, visitor, &mut ())
+    }
+
+    /// Like [`parse_and_add_frame`](Self::parse_and_add_frame), but also passes the validated
+    /// item to `backup_visitor`.
+    pub fn parse_and_add_frame_visiting(
         &mut self,
         raw_frame: &[u8],
         mut visitor: impl FnMut(&proto::backup::Frame) + Send,
+        backup_visitor: &mut impl backup::BackupVisitor<M>,
     ) -> Result<Vec<(Vec<PathPart>, UnknownValue)>, crate::Error> {
         // Using `merge_from_bytes` instead of `parse_from_bytes` avoids having to unpack the Ok
         // case of the Result. (This is guaranteed equivalent by protobuf.)
@@ -355,7 +448,11 @@ impl<M: backup::method::Method + backup::ReferencedTypes> backup::PartialBackup<
         frame_proto.merge_from_bytes(raw_frame)?;
         visitor(&frame_proto);
         let unknown_fields = frame_proto.collect_unknown_fields();
-        self.add_frame(frame_proto)?;
+        let kind = frame_proto.item.as_ref().map(backup::FrameKind::from);
+        self.add_frame_visiting(frame_proto, backup_visitor)?;
+        if let Some(kind) = kind {
+            backup_visitor.on_frame(kind, raw_frame.len());
+        }
         Ok(unknown_fields)
     }
 }
@@ -371,11 +468,18 @@ impl From<VerifyHmacError> for Error {
 
 /// Rounds `content_length` up to obscure the exact size of a backup.
 pub fn padded_length(content_length: u32) -> u32 {
+    padded_length_u64(content_length.into())
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
+
+/// Like [`padded_length`], for backups that may be larger than 4GB.
+fn padded_length_u64(content_length: u64) -> u64 {
     const BASE: f64 = 1.05;
-    let exp = f64::log(content_length.into(), BASE).ceil();
+    let exp = f64::log(content_length as f64, BASE).ceil();
 
     #[expect(clippy::cast_possible_truncation)]
     {
-        u32::max(541, BASE.powf(exp).floor() as u32)
+        u64::max(541, BASE.powf(exp).floor() as u64)
     }
 }
@@ -64,7 +64,9 @@ impl<R: AsyncRead + Unpin> VarintDelimitedReader<R> {
 
         let mut proto_reader = protobuf::CodedInputStream::from_bytes(buffer);
 
-        let length = proto_reader.read_raw_varint32()?;
+        let length = proto_reader
+            .read_raw_varint32()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 
         // Remove the consumed bytes from the buffer.
         let consumed_byte_count: usize =
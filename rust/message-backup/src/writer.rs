@@ -0,0 +1,314 @@
+//
+// Copyright (C) 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Producing encrypted backup files.
+//!
+//! [`BackupWriter`] is the counterpart of [`BackupReader`](crate::BackupReader): everything it
+//! writes can be read back with [`BackupReader::new_encrypted_compressed`].
+//!
+//! [`BackupReader::new_encrypted_compressed`]: crate::BackupReader::new_encrypted_compressed
+
+use aes::Aes256;
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncryptMut as _, BlockSizeUser, KeyIvInit as _, Unsigned};
+use async_compression::futures::write::GzipEncoder;
+use futures::{AsyncWrite, AsyncWriteExt as _};
+use hmac::{Hmac, Mac as _};
+use sha2::Sha256;
+
+use crate::frame::forward_secrecy::MAGIC_NUMBER;
+use crate::frame::{FramesReader, ValidationError};
+use crate::key::MessageBackupKey;
+
+const AES_BLOCK_SIZE: usize = <<Aes256 as BlockSizeUser>::BlockSize as Unsigned>::USIZE;
+
+/// How many bytes of padding to encrypt at once when finishing a backup.
+///
+/// Padding can be several megabytes for a large backup, so it isn't all allocated at once.
+const PADDING_CHUNK_LEN: usize = 64 * 1024;
+
+/// Writes an encrypted, compressed backup file one frame at a time.
+///
+/// Frames are varint-delimited, gzip-compressed, padded to obscure the exact size of the backup,
+/// encrypted with AES-256-CBC, and MAC'd with HMAC-SHA256, all as they are written. Only a small,
+/// bounded amount of data is buffered between calls to [`write_frame`](Self::write_frame), so
+/// backups of any size can be produced without holding them in memory.
+///
+/// Call [`finish`](Self::finish) once all frames have been written; a backup that isn't finished
+/// is incomplete and can't be read.
+pub struct BackupWriter<W> {
+    compressor: GzipEncoder<Vec<u8>>,
+    sink: EncryptingSink<W>,
+    frame_count: usize,
+}
+
+#[derive(Debug, thiserror::Error, displaydoc::Display)]
+pub enum WriteError {
+    /// {0}
+    Io(#[from] std::io::Error),
+    /// invalid forward secrecy metadata: {0}
+    InvalidMetadata(ValidationError),
+    /// failed to serialize frame: {0}
+    InvalidProtobuf(#[from] protobuf::Error),
+}
+
+impl<W: AsyncWrite + Unpin> BackupWriter<W> {
+    /// Starts writing a backup to `output`.
+    ///
+    /// `forward_secrecy_metadata` is the serialized metadata produced when the backup's
+    /// forward secrecy token was stored, and `key` must have been derived using that token. If
+    /// it's `None`, the legacy format without forward secrecy is written instead.
+    ///
+    /// `iv` must be randomly generated for each backup.
+    pub async fn new(
+        key: &MessageBackupKey,
+        iv: [u8; AES_BLOCK_SIZE],
+        forward_secrecy_metadata: Option<&[u8]>,
+        mut output: W,
+    ) -> Result<Self, WriteError> {
+        if let Some(metadata) = forward_secrecy_metadata {
+            let mut header = MAGIC_NUMBER.to_vec();
+            let metadata_start = header.len();
+            write_varint_delimited(metadata, &mut header)?;
+
+            // Check the metadata the same way a reader will, so a bad backup is never produced.
+            FramesReader::verify_metadata(&mut futures::io::Cursor::new(&header[metadata_start..]))
+                .await
+                .map_err(WriteError::InvalidMetadata)?;
+
+            output.write_all(&header).await?;
+        }
+
+        // The IV isn't encrypted, but it is covered by the HMAC.
+        output.write_all(&iv).await?;
+        let mut hmac =
+            Hmac::<Sha256>::new_from_slice(&key.hmac_key).expect("HMAC-SHA256 accepts any key");
+        hmac.update(&iv);
+
+        Ok(Self {
+            compressor: GzipEncoder::new(Vec::new()),
+            sink: EncryptingSink {
+                encryptor: cbc::Encryptor::new(&key.aes_key.into(), &iv.into()),
+                hmac,
+                compressed_len: 0,
+                output,
+            },
+            frame_count: 0,
+        })
+    }
+
+    /// Writes `frame` to the backup.
+    ///
+    /// The first frame of a backup must be its `BackupInfo`; every frame after that is a `Frame`.
+    pub async fn write_frame(&mut self, frame: &impl protobuf::Message) -> Result<(), WriteError> {
+        self.write_serialized_frame(&frame.write_to_bytes()?).await
+    }
+
+    /// Like [`write_frame`](Self::write_frame), but for a frame that has already been serialized.
+    pub async fn write_serialized_frame(&mut self, frame: &[u8]) -> Result<(), WriteError> {
+        let mut delimited = Vec::with_capacity(frame.len() + 5);
+        write_varint_delimited(frame, &mut delimited)?;
+        self.compressor.write_all(&delimited).await?;
+        self.frame_count += 1;
+
+        let Self {
+            compressor, sink, ..
+        } = self;
+        sink.write_complete_blocks(compressor.get_mut()).await?;
+        Ok(())
+    }
+
+    /// The number of frames written so far, including the `BackupInfo`.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Pads, encrypts, and MACs whatever remains, then returns the output.
+    ///
+    /// The output is flushed but not closed.
+    pub async fn finish(self) -> Result<W, WriteError> {
+        let Self {
+            mut compressor,
+            mut sink,
+            frame_count: _,
+        } = self;
+        compressor.close().await?;
+        let mut remaining = compressor.into_inner();
+
+        let compressed_len = sink.compressed_len + remaining.len() as u64;
+        let mut padding_len = crate::padded_length_u64(compressed_len) - compressed_len;
+        while padding_len > 0 {
+            let chunk_len = padding_len.min(PADDING_CHUNK_LEN as u64);
+            padding_len -= chunk_len;
+            let chunk_len = usize::try_from(chunk_len).expect("at most PADDING_CHUNK_LEN");
+            remaining.resize(remaining.len() + chunk_len, 0);
+            sink.write_complete_blocks(&mut remaining).await?;
+        }
+
+        Ok(sink.finish(remaining).await?)
+    }
+}
+
+/// The encrypting and MAC'ing end of a [`BackupWriter`].
+struct EncryptingSink<W> {
+    encryptor: cbc::Encryptor<Aes256>,
+    hmac: Hmac<Sha256>,
+    /// The number of plaintext bytes encrypted so far.
+    compressed_len: u64,
+    output: W,
+}
+
+impl<W: AsyncWrite + Unpin> EncryptingSink<W> {
+    /// Encrypts and writes as many whole blocks from the front of `buffer` as possible, leaving
+    /// any partial block behind.
+    async fn write_complete_blocks(&mut self, buffer: &mut Vec<u8>) -> std::io::Result<()> {
+        let complete_len = buffer.len() - buffer.len() % AES_BLOCK_SIZE;
+        if complete_len == 0 {
+            return Ok(());
+        }
+        for block in buffer[..complete_len].chunks_exact_mut(AES_BLOCK_SIZE) {
+            self.encryptor
+                .encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        self.hmac.update(&buffer[..complete_len]);
+        self.output.write_all(&buffer[..complete_len]).await?;
+        self.compressed_len += complete_len as u64;
+        buffer.drain(..complete_len);
+        Ok(())
+    }
+
+    /// Encrypts the final partial block in `remainder` with PKCS7 padding, then writes the HMAC.
+    async fn finish(self, mut remainder: Vec<u8>) -> std::io::Result<W> {
+        let Self {
+            encryptor,
+            mut hmac,
+            compressed_len: _,
+            mut output,
+        } = self;
+        debug_assert!(remainder.len() < AES_BLOCK_SIZE);
+
+        let len_to_encrypt = remainder.len();
+        remainder.resize(AES_BLOCK_SIZE, 0);
+        let last_block = encryptor
+            .encrypt_padded_mut::<Pkcs7>(&mut remainder, len_to_encrypt)
+            .expect("provided enough room for padding");
+        hmac.update(last_block);
+        output.write_all(last_block).await?;
+
+        output.write_all(&hmac.finalize().into_bytes()).await?;
+        output.flush().await?;
+        Ok(output)
+    }
+}
+
+fn write_varint_delimited(bytes: &[u8], out: &mut Vec<u8>) -> protobuf::Result<()> {
+    // Protobuf messages are limited to 2GB, so this only fails for bytes that aren't one.
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "frame too large"))?;
+    let mut writer = protobuf::CodedOutputStream::vec(out);
+    writer.write_raw_varint32(len)?;
+    writer.write_raw_bytes(bytes)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use futures::executor::block_on;
+    use protobuf::Message as _;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::frame::forward_secrecy::test::test_metadata;
+    use crate::frame::{CursorFactory, VerifyHmac as _};
+    use crate::key::test::FAKE_MESSAGE_BACKUP_KEY;
+    use crate::parse::VarintDelimitedReader;
+
+    const IV: [u8; AES_BLOCK_SIZE] = [b'I'; AES_BLOCK_SIZE];
+
+    fn write_backup(frames: &[&[u8]], metadata: Option<&[u8]>) -> Vec<u8> {
+        block_on(async {
+            let mut writer = BackupWriter::new(&FAKE_MESSAGE_BACKUP_KEY, IV, metadata, Vec::new())
+                .await
+                .expect("valid metadata");
+            for frame in frames {
+                writer
+                    .write_serialized_frame(frame)
+                    .await
+                    .expect("can write");
+            }
+            assert_eq!(writer.frame_count(), frames.len());
+            writer.finish().await.expect("can finish")
+        })
+    }
+
+    fn read_backup(backup: &[u8]) -> Vec<Box<[u8]>> {
+        block_on(async {
+            let reader = FramesReader::new(&FAKE_MESSAGE_BACKUP_KEY, CursorFactory::new(&backup))
+                .await
+                .expect("valid HMAC");
+            let mut reader = VarintDelimitedReader::new(reader);
+            let mut frames = vec![];
+            while let Some(frame) = reader.read_next().await.expect("can read") {
+                frames.push(frame);
+            }
+            reader
+                .into_inner()
+                .verify_hmac()
+                .await
+                .expect("HMAC still matches");
+            frames
+        })
+    }
+
+    #[test_case(false; "legacy")]
+    #[test_case(true; "forward secrecy")]
+    fn round_trip(with_metadata: bool) {
+        let metadata = test_metadata().write_to_bytes().expect("can serialize");
+        let metadata = with_metadata.then_some(metadata.as_slice());
+
+        // Enough data to produce several compressed chunks, and a frame that's empty.
+        let large_frame: Vec<u8> = (0..200_000u32).flat_map(u32::to_le_bytes).collect();
+        let frames: [&[u8]; 4] = [b"backup info", &large_frame, b"", b"last"];
+
+        let backup = write_backup(&frames, metadata);
+        assert_eq!(backup.starts_with(MAGIC_NUMBER), with_metadata);
+
+        let read = read_backup(&backup);
+        itertools::assert_equal(read.iter().map(|f| &**f), frames);
+    }
+
+    #[test]
+    fn output_is_padded() {
+        let backup = write_backup(&[b"a tiny backup"], None);
+        // IV, then at least the minimum padded length (rounded up to a whole block), then HMAC.
+        let min_len = AES_BLOCK_SIZE + crate::padded_length(0) as usize + 32;
+        assert!(backup.len() >= min_len, "{} < {min_len}", backup.len());
+        assert_eq!((backup.len() - AES_BLOCK_SIZE - 32) % AES_BLOCK_SIZE, 0);
+    }
+
+    #[test]
+    fn rejects_invalid_metadata() {
+        let mut metadata = test_metadata();
+        metadata.iv.clear();
+        let metadata = metadata.write_to_bytes().expect("can serialize");
+
+        let mut output = Vec::new();
+        let result = block_on(BackupWriter::new(
+            &FAKE_MESSAGE_BACKUP_KEY,
+            IV,
+            Some(&metadata),
+            &mut output,
+        ));
+        assert_matches!(
+            result,
+            Err(WriteError::InvalidMetadata(
+                ValidationError::InvalidLength { field: "iv", .. }
+            ))
+        );
+        assert!(output.is_empty(), "nothing is written for invalid metadata");
+    }
+}
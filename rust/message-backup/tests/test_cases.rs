@@ -120,6 +120,91 @@ fn scrambler_smoke_test() {
     pretty_assertions::assert_str_eq!(expected_canonical_str, canonical_repr)
 }
 
+#[test]
+fn validator_diff_reports_scrambled_differences() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/res/canonical-backup.binproto");
+    let scrambled_binproto = Command::cargo_bin("examples/scramble")
+        .expect("bin exists")
+        .arg(&path)
+        .ok()
+        .expect("valid binproto")
+        .stdout;
+    let scrambled_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("scrambled-for-diff.binproto");
+    std::fs::write(&scrambled_path, scrambled_binproto).expect("can write scrambled backup");
+
+    validator_command()
+        .arg("diff")
+        .args([&path, &path])
+        .assert()
+        .success()
+        .stdout("");
+
+    let output = validator_command()
+        .arg("diff")
+        .args([&path, &scrambled_path])
+        .assert()
+        .code(6)
+        .get_output()
+        .stdout
+        .clone();
+    let output = String::from_utf8(output).expect("valid UTF-8");
+    assert!(
+        output.contains("~ account_data.given_name: "),
+        "unexpected output: {output}"
+    );
+}
+
+#[test]
+fn validator_json_report_includes_counts() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/res/canonical-backup.binproto");
+    let report = validator_command()
+        .arg(&path)
+        .args(["--format", "json"])
+        .ok()
+        .expect("valid backup")
+        .stdout;
+    let report: serde_json::Value = serde_json::from_slice(&report).expect("valid JSON report");
+
+    assert_eq!(report["schema_version"], 1);
+    assert_eq!(report["success"], true);
+    assert_eq!(report["error"], serde_json::Value::Null);
+    assert_eq!(report["unknown_fields"], serde_json::json!([]));
+    let counts = &report["counts"];
+    for count in ["recipients", "chats", "chat_items"] {
+        assert!(
+            counts[count].as_u64().is_some_and(|n| n > 0),
+            "unexpected {count} in {counts}"
+        );
+    }
+}
+
+#[test]
+fn validator_stats_matches_library() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/res/canonical-backup.binproto");
+    let binproto = std::fs::read(&path).expect("can read");
+
+    let reader = BackupReader::new_unencrypted(Cursor::new(&binproto), BACKUP_PURPOSE);
+    let ReadResult {
+        result,
+        found_unknown_fields: _,
+        ..
+    } = futures::executor::block_on(reader.read_stats());
+    let stats = result.expect("valid backup");
+    assert!(!stats.chat_item_messages.is_empty(), "{stats:?}");
+
+    let output = validator_command()
+        .arg("stats")
+        .arg(&path)
+        .ok()
+        .expect("valid backup")
+        .stdout;
+    let output: serde_json::Value = serde_json::from_slice(&output).expect("valid JSON");
+    assert_eq!(
+        output,
+        serde_json::to_value(&stats).expect("can serialize stats")
+    );
+}
+
 const ENCRYPTED_SOURCE_SUFFIX: &str = ".source.jsonproto";
 
 fn is_legacy_test(path: &Path) -> bool {
@@ -358,10 +443,26 @@ fn invalid_jsonproto(input: Fixture<PathBuf>) {
     let ReadResult {
         result,
         found_unknown_fields: _,
+        ..
     } = futures::executor::block_on(reader.read_all());
 
     let text = result.expect_err("unexpectedly valid").to_string();
 
+    // The CLI tool should report the same error.
+    let report = validator_command()
+        .arg("-")
+        .args(["--format", "json"])
+        .write_stdin(&*binproto)
+        .assert()
+        .code(1)
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&report).expect("valid JSON report");
+    assert_eq!(report["success"], false);
+    assert_eq!(report["error"]["class"], "invalid");
+    assert_eq!(report["error"]["message"], text.as_str());
+
     if write_expected_output() {
         eprintln!("writing expected value to {expected_path:?}");
         std::fs::write(expected_path, text).expect("failed to overwrite expected contents");
@@ -399,6 +500,7 @@ fn validate(mut reader: BackupReader<impl AsyncRead + Unpin + VerifyHmac>) {
     let ReadResult {
         result,
         found_unknown_fields,
+        ..
     } = futures::executor::block_on(reader.read_all());
     assert_eq!(found_unknown_fields, Vec::new());
 
@@ -10,7 +10,7 @@ use std::time::Duration;
 use libsignal_core::{Aci, Pni, ServiceIdKind};
 use libsignal_net::auth::Auth;
 use libsignal_net::chat::LanguageList;
-use libsignal_protocol::{GenericSignedPreKey, PublicKey};
+use libsignal_protocol::{GenericSignedPreKey, PublicKey, SignedPreKeyUpload};
 use serde_with::{
     DurationMilliSeconds, DurationSeconds, FromInto, serde_as, skip_serializing_none,
 };
@@ -367,6 +367,16 @@ impl<'a, T: GenericSignedPreKey> From<&'a T> for SignedPreKeyBody<&'a [u8]> {
     }
 }
 
+impl<'a> From<&'a SignedPreKeyUpload> for SignedPreKeyBody<&'a [u8]> {
+    fn from(upload: &'a SignedPreKeyUpload) -> Self {
+        Self {
+            key_id: upload.key_id,
+            public_key: &upload.public_key,
+            signature: &upload.signature,
+        }
+    }
+}
+
 impl<T> SignedPreKeyBody<T> {
     pub fn to_owned(&self) -> SignedPreKeyBody<T::Owned>
     where
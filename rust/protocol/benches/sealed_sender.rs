@@ -306,6 +306,49 @@ pub fn v2(c: &mut Criterion) {
         );
     }
     group.finish();
+
+    let mut group = c.benchmark_group("v2/server");
+    for recipient_count in [10, 100, 1000] {
+        let recipients: Vec<_> = recipients.iter().take(recipient_count).collect();
+        let outgoing = sealed_sender_multi_recipient_encrypt(
+            &recipients,
+            &alice_store
+                .session_store
+                .load_existing_sessions(&recipients)
+                .expect("present"),
+            [],
+            &usmc,
+            &alice_store.identity_store,
+            &mut rng,
+        )
+        .now_or_never()
+        .expect("sync")
+        .expect("valid");
+
+        let parsed = SealedSenderV2SentMessage::parse(&outgoing).expect("valid");
+        let mut directory = sealed_sender_server::DeviceDirectory::new();
+        for (service_id, recipient) in &parsed.recipients {
+            directory.set_devices(*service_id, recipient.devices.iter().copied());
+        }
+        directory.validate(&parsed).expect("valid");
+
+        group.bench_with_input(
+            BenchmarkId::new("parse_and_validate", recipient_count),
+            &outgoing,
+            |b, outgoing| {
+                b.iter(|| {
+                    let parsed = SealedSenderV2SentMessage::parse(outgoing).expect("valid");
+                    directory.validate(&parsed).expect("valid")
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("fan_out", recipient_count),
+            &parsed,
+            |b, parsed| b.iter(|| black_box(sealed_sender_server::device_messages(parsed))),
+        );
+    }
+    group.finish();
 }
 
 criterion_group!(benches, v1, v2);
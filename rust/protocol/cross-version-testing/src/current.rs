@@ -8,6 +8,7 @@ use std::time::SystemTime;
 use futures_util::FutureExt;
 use libsignal_protocol_current::*;
 use rand::{Rng, rng};
+use uuid::Uuid;
 
 fn address(id: &str) -> ProtocolAddress {
     ProtocolAddress::new(id.into(), DeviceId::new(1).unwrap())
@@ -159,18 +160,24 @@ impl super::LibSignalProtocolStore for LibSignalProtocolCurrent {
         (encrypted.serialize().to_vec(), encrypted.message_type())
     }
 
-    fn decrypt(&mut self, remote: &str, msg: &[u8], msg_type: CiphertextMessageType) -> Vec<u8> {
-        match msg_type {
+    fn try_decrypt(
+        &mut self,
+        remote: &str,
+        msg: &[u8],
+        msg_type: CiphertextMessageType,
+    ) -> Result<Vec<u8>, super::DecryptError> {
+        let result = match msg_type {
             CiphertextMessageType::Whisper => message_decrypt_signal(
                 &SignalMessage::try_from(msg).expect("valid"),
                 &address(remote),
                 &mut self.0.session_store,
                 &mut self.0.identity_store,
+                SystemTime::now(),
                 &mut rng(),
+                &SessionConfig::default(),
             )
             .now_or_never()
-            .expect("synchronous")
-            .expect("can decrypt messages"),
+            .expect("synchronous"),
             CiphertextMessageType::PreKey => message_decrypt_prekey(
                 &PreKeySignalMessage::try_from(msg).expect("valid"),
                 &address(remote),
@@ -179,14 +186,16 @@ impl super::LibSignalProtocolStore for LibSignalProtocolCurrent {
                 &mut self.0.pre_key_store,
                 &self.0.signed_pre_key_store,
                 &mut self.0.kyber_pre_key_store,
+                SystemTime::now(),
                 &mut rng(),
                 UsePQRatchet::Yes,
+                &SessionConfig::default(),
             )
             .now_or_never()
-            .expect("synchronous")
-            .expect("can decrypt messages"),
+            .expect("synchronous"),
             _ => panic!("unexpected 1:1 message type"),
-        }
+        };
+        result.map_err(decrypt_error)
     }
 
     fn encrypt_sealed_sender_v1(
@@ -231,4 +240,70 @@ impl super::LibSignalProtocolStore for LibSignalProtocolCurrent {
             .expect("synchronous")
             .expect("can decrypt messages")
     }
+
+    fn create_sender_key_distribution_message(
+        &mut self,
+        own_name: &str,
+        distribution_id: Uuid,
+    ) -> Vec<u8> {
+        create_sender_key_distribution_message(
+            &address(own_name),
+            distribution_id,
+            &mut self.0,
+            &mut rng(),
+        )
+        .now_or_never()
+        .expect("synchronous")
+        .expect("can create distribution messages")
+        .serialized()
+        .to_vec()
+    }
+
+    fn process_sender_key_distribution_message(&mut self, sender: &str, msg: &[u8]) {
+        process_sender_key_distribution_message(
+            &address(sender),
+            &SenderKeyDistributionMessage::try_from(msg).expect("valid"),
+            &mut self.0,
+        )
+        .now_or_never()
+        .expect("synchronous")
+        .expect("can process distribution messages")
+    }
+
+    fn group_encrypt(&mut self, own_name: &str, distribution_id: Uuid, msg: &[u8]) -> Vec<u8> {
+        group_encrypt(
+            &mut self.0,
+            &address(own_name),
+            distribution_id,
+            msg,
+            &mut rng(),
+        )
+        .now_or_never()
+        .expect("synchronous")
+        .expect("can encrypt messages")
+        .serialized()
+        .to_vec()
+    }
+
+    fn try_group_decrypt(
+        &mut self,
+        sender: &str,
+        msg: &[u8],
+    ) -> Result<Vec<u8>, super::DecryptError> {
+        group_decrypt(msg, &mut self.0, &address(sender))
+            .now_or_never()
+            .expect("synchronous")
+            .map_err(decrypt_error)
+    }
+}
+
+fn decrypt_error(e: SignalProtocolError) -> super::DecryptError {
+    match e {
+        SignalProtocolError::DuplicatedMessage(..) => super::DecryptError::Duplicate,
+        SignalProtocolError::InvalidMessage(_, "message from too far into the future") => {
+            super::DecryptError::TooFarAhead
+        }
+        SignalProtocolError::InvalidMessage(..) => super::DecryptError::InvalidMessage,
+        _ => super::DecryptError::Other,
+    }
 }
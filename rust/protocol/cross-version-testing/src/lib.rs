@@ -8,13 +8,36 @@
 pub use libsignal_protocol_current::{
     CiphertextMessageType, PreKeyBundle, UnidentifiedSenderMessageContent,
 };
+use uuid::Uuid;
+
+/// Why a message couldn't be decrypted, in a form that can be compared across versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecryptError {
+    /// The message was already decrypted.
+    Duplicate,
+    /// The message was too far ahead of the receiving chain.
+    TooFarAhead,
+    /// The message was rejected as invalid for any other reason.
+    InvalidMessage,
+    /// Any other error.
+    Other,
+}
 
 pub trait LibSignalProtocolStore {
     fn version(&self) -> &'static str;
     fn create_pre_key_bundle(&mut self) -> PreKeyBundle;
     fn process_pre_key_bundle(&mut self, remote: &str, pre_key_bundle: PreKeyBundle);
     fn encrypt(&mut self, remote: &str, msg: &[u8]) -> (Vec<u8>, CiphertextMessageType);
-    fn decrypt(&mut self, remote: &str, msg: &[u8], msg_type: CiphertextMessageType) -> Vec<u8>;
+    fn try_decrypt(
+        &mut self,
+        remote: &str,
+        msg: &[u8],
+        msg_type: CiphertextMessageType,
+    ) -> Result<Vec<u8>, DecryptError>;
+    fn decrypt(&mut self, remote: &str, msg: &[u8], msg_type: CiphertextMessageType) -> Vec<u8> {
+        self.try_decrypt(remote, msg, msg_type)
+            .expect("can decrypt messages")
+    }
 
     fn encrypt_sealed_sender_v1(
         &self,
@@ -27,6 +50,19 @@ pub trait LibSignalProtocolStore {
         msg: &UnidentifiedSenderMessageContent,
     ) -> Vec<u8>;
     fn decrypt_sealed_sender(&self, msg: &[u8]) -> UnidentifiedSenderMessageContent;
+
+    fn create_sender_key_distribution_message(
+        &mut self,
+        own_name: &str,
+        distribution_id: Uuid,
+    ) -> Vec<u8>;
+    fn process_sender_key_distribution_message(&mut self, sender: &str, msg: &[u8]);
+    fn group_encrypt(&mut self, own_name: &str, distribution_id: Uuid, msg: &[u8]) -> Vec<u8>;
+    fn try_group_decrypt(&mut self, sender: &str, msg: &[u8]) -> Result<Vec<u8>, DecryptError>;
+    fn group_decrypt(&mut self, sender: &str, msg: &[u8]) -> Vec<u8> {
+        self.try_group_decrypt(sender, msg)
+            .expect("can decrypt messages")
+    }
 }
 
 mod current;
@@ -0,0 +1,350 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Runs randomly generated conversations between Alice and Bob, once with both on the current
+//! implementation and once for each pairing with an older one, and checks that every pairing
+//! observes exactly the same thing.
+
+use libsignal_protocol_cross_version_testing::*;
+use libsignal_protocol_current::{
+    ContentHint, DeviceId, KeyPair, SealedSenderV2SentMessage, SenderCertificate,
+    ServerCertificate, Timestamp,
+};
+use proptest::prelude::*;
+use proptest::sample::Index;
+use rand::rng;
+
+// Sealed sender v2 needs the names to be service IDs.
+const ALICE_UUID: uuid::Uuid = uuid::uuid!("a000000a-6199-486a-ba89-2ba2bb4f2154");
+const BOB_UUID: uuid::Uuid = uuid::uuid!("b000000b-6199-486a-ba89-2ba2bb4f2154");
+const DISTRIBUTION_ID: uuid::Uuid = uuid::uuid!("d1d1d1d1-7000-11eb-b32a-33b8a8a487a6");
+
+const PREVIOUS: &[fn() -> Box<dyn LibSignalProtocolStore>] =
+    &[|| Box::new(LibSignalProtocolV70::new())];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Party {
+    Alice,
+    Bob,
+}
+
+impl Party {
+    fn other(self) -> Self {
+        match self {
+            Party::Alice => Party::Bob,
+            Party::Bob => Party::Alice,
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            Party::Alice => ALICE_UUID.to_string(),
+            Party::Bob => BOB_UUID.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wrapping {
+    None,
+    SealedSenderV1,
+    SealedSenderV2,
+}
+
+#[derive(Debug, Clone)]
+enum Action {
+    Send { from: Party, wrapping: Wrapping },
+    Deliver { to: Party, which: Index },
+    Redeliver { to: Party, which: Index },
+    Drop { to: Party, which: Index },
+    DistributeSenderKey,
+    SendGroup,
+    DeliverGroup(Index),
+    RedeliverGroup(Index),
+    DropGroup(Index),
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    let party = prop_oneof![Just(Party::Alice), Just(Party::Bob)];
+    let wrapping = prop_oneof![
+        Just(Wrapping::None),
+        Just(Wrapping::SealedSenderV1),
+        Just(Wrapping::SealedSenderV2),
+    ];
+    prop_oneof![
+        4 => (party.clone(), wrapping).prop_map(|(from, wrapping)| Action::Send { from, wrapping }),
+        4 => (party.clone(), any::<Index>()).prop_map(|(to, which)| Action::Deliver { to, which }),
+        1 => (party.clone(), any::<Index>()).prop_map(|(to, which)| Action::Redeliver { to, which }),
+        1 => (party, any::<Index>()).prop_map(|(to, which)| Action::Drop { to, which }),
+        1 => Just(Action::DistributeSenderKey),
+        2 => Just(Action::SendGroup),
+        2 => any::<Index>().prop_map(Action::DeliverGroup),
+        1 => any::<Index>().prop_map(Action::RedeliverGroup),
+        1 => any::<Index>().prop_map(Action::DropGroup),
+    ]
+}
+
+/// What a recipient saw, recorded so that runs with different implementations can be compared.
+///
+/// Failed decryptions are recorded too, so a message one implementation rejects (or accepts) where
+/// the other doesn't shows up as a difference rather than a panic.
+#[derive(Debug, PartialEq, Eq)]
+enum Event {
+    Received {
+        to: Party,
+        msg_type: CiphertextMessageType,
+        wrapping: Wrapping,
+        sent: Vec<u8>,
+        result: Result<Vec<u8>, DecryptError>,
+    },
+    ReceivedGroup {
+        sent: Vec<u8>,
+        result: Result<Vec<u8>, DecryptError>,
+    },
+}
+
+#[derive(Clone)]
+struct InFlight {
+    msg_type: CiphertextMessageType,
+    wrapping: Wrapping,
+    payload: Vec<u8>,
+    plaintext: Vec<u8>,
+}
+
+fn make_sender_cert(store: &mut dyn LibSignalProtocolStore, party: Party) -> SenderCertificate {
+    let fake_trust_root = KeyPair::generate(&mut rng());
+    let signer_key = KeyPair::generate(&mut rng());
+    let server_cert = ServerCertificate::new(
+        0xfa75e,
+        signer_key.public_key,
+        &fake_trust_root.private_key,
+        &mut rng(),
+    )
+    .expect("valid");
+    let identity_key = *store
+        .create_pre_key_bundle()
+        .identity_key()
+        .expect("has identity key");
+    SenderCertificate::new(
+        party.name(),
+        None,
+        *identity_key.public_key(),
+        DeviceId::new(1).expect("valid"),
+        Timestamp::from_epoch_millis(0),
+        server_cert,
+        &signer_key.private_key,
+        &mut rng(),
+    )
+    .expect("valid")
+}
+
+fn run_script(
+    alice_store: &mut dyn LibSignalProtocolStore,
+    bob_store: &mut dyn LibSignalProtocolStore,
+    script: &[Action],
+) -> Vec<Event> {
+    log::info!(
+        "alice: {}, bob: {}",
+        alice_store.version(),
+        bob_store.version()
+    );
+
+    let alice_cert = make_sender_cert(alice_store, Party::Alice);
+    let bob_cert = make_sender_cert(bob_store, Party::Bob);
+
+    alice_store.process_pre_key_bundle(&Party::Bob.name(), bob_store.create_pre_key_bundle());
+    let distribution_message =
+        alice_store.create_sender_key_distribution_message(&Party::Alice.name(), DISTRIBUTION_ID);
+
+    // Bob can't send anything until he has received Alice's first message.
+    let mut bob_has_session = false;
+    let mut distributed = false;
+    let mut sent_count = [0usize; 2];
+    let mut in_flight: [Vec<InFlight>; 2] = Default::default();
+    // Everything delivered so far, to be replayed by the `Redeliver` actions.
+    let mut delivered: [Vec<InFlight>; 2] = Default::default();
+    let mut group_in_flight: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+    let mut group_delivered: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+    let mut events = vec![];
+
+    let queue_index = |party: Party| match party {
+        Party::Alice => 0,
+        Party::Bob => 1,
+    };
+
+    for action in script {
+        match *action {
+            Action::Send { from, wrapping } => {
+                if from == Party::Bob && !bob_has_session {
+                    continue;
+                }
+                let (sender, cert) = match from {
+                    Party::Alice => (&mut *alice_store, &alice_cert),
+                    Party::Bob => (&mut *bob_store, &bob_cert),
+                };
+                let to = from.other();
+
+                let count = &mut sent_count[queue_index(from)];
+                let plaintext = format!("{from:?}->{to:?} message {count}").into_bytes();
+                *count += 1;
+
+                let (ciphertext, msg_type) = sender.encrypt(&to.name(), &plaintext);
+                let payload = match wrapping {
+                    Wrapping::None => ciphertext,
+                    Wrapping::SealedSenderV1 | Wrapping::SealedSenderV2 => {
+                        let usmc = UnidentifiedSenderMessageContent::new(
+                            msg_type,
+                            cert.clone(),
+                            ciphertext,
+                            ContentHint::Default,
+                            None,
+                        )
+                        .expect("valid");
+                        if wrapping == Wrapping::SealedSenderV1 {
+                            sender.encrypt_sealed_sender_v1(&to.name(), &usmc)
+                        } else {
+                            let sent = sender.encrypt_sealed_sender_v2(&to.name(), &usmc);
+                            let parsed = SealedSenderV2SentMessage::parse(&sent).expect("valid");
+                            parsed
+                                .received_message_parts_for_recipient(&parsed.recipients[0])
+                                .as_ref()
+                                .concat()
+                        }
+                    }
+                };
+
+                in_flight[queue_index(to)].push(InFlight {
+                    msg_type,
+                    wrapping,
+                    payload,
+                    plaintext,
+                });
+            }
+            Action::Deliver { to, which } | Action::Redeliver { to, which } => {
+                let message = if matches!(action, Action::Deliver { .. }) {
+                    let queue = &mut in_flight[queue_index(to)];
+                    if queue.is_empty() {
+                        continue;
+                    }
+                    let message = queue.remove(which.index(queue.len()));
+                    delivered[queue_index(to)].push(message.clone());
+                    message
+                } else {
+                    let queue = &delivered[queue_index(to)];
+                    if queue.is_empty() {
+                        continue;
+                    }
+                    queue[which.index(queue.len())].clone()
+                };
+                let recipient = match to {
+                    Party::Alice => &mut *alice_store,
+                    Party::Bob => &mut *bob_store,
+                };
+
+                let ciphertext = match message.wrapping {
+                    Wrapping::None => message.payload,
+                    Wrapping::SealedSenderV1 | Wrapping::SealedSenderV2 => {
+                        let usmc = recipient.decrypt_sealed_sender(&message.payload);
+                        assert_eq!(usmc.msg_type().expect("has field"), message.msg_type);
+                        assert_eq!(
+                            usmc.sender()
+                                .expect("has field")
+                                .sender_uuid()
+                                .expect("valid"),
+                            to.other().name()
+                        );
+                        usmc.contents().expect("has field").to_vec()
+                    }
+                };
+
+                let result =
+                    recipient.try_decrypt(&to.other().name(), &ciphertext, message.msg_type);
+                if to == Party::Bob && result.is_ok() {
+                    bob_has_session = true;
+                }
+                events.push(Event::Received {
+                    to,
+                    msg_type: message.msg_type,
+                    wrapping: message.wrapping,
+                    sent: message.plaintext,
+                    result,
+                });
+            }
+            Action::Drop { to, which } => {
+                let queue = &mut in_flight[queue_index(to)];
+                if !queue.is_empty() {
+                    queue.remove(which.index(queue.len()));
+                }
+            }
+            Action::DistributeSenderKey => {
+                if !distributed {
+                    bob_store.process_sender_key_distribution_message(
+                        &Party::Alice.name(),
+                        &distribution_message,
+                    );
+                    distributed = true;
+                }
+            }
+            Action::SendGroup => {
+                let plaintext = format!("group message {}", group_in_flight.len()).into_bytes();
+                let ciphertext =
+                    alice_store.group_encrypt(&Party::Alice.name(), DISTRIBUTION_ID, &plaintext);
+                group_in_flight.push((plaintext, ciphertext));
+            }
+            Action::DeliverGroup(which) | Action::RedeliverGroup(which) => {
+                // Group messages wait in the queue until Bob has the sender key.
+                if !distributed {
+                    continue;
+                }
+                let (sent, ciphertext) = if matches!(action, Action::DeliverGroup(_)) {
+                    if group_in_flight.is_empty() {
+                        continue;
+                    }
+                    let message = group_in_flight.remove(which.index(group_in_flight.len()));
+                    group_delivered.push(message.clone());
+                    message
+                } else {
+                    if group_delivered.is_empty() {
+                        continue;
+                    }
+                    group_delivered[which.index(group_delivered.len())].clone()
+                };
+                let result = bob_store.try_group_decrypt(&Party::Alice.name(), &ciphertext);
+                events.push(Event::ReceivedGroup { sent, result });
+            }
+            Action::DropGroup(which) => {
+                if !group_in_flight.is_empty() {
+                    group_in_flight.remove(which.index(group_in_flight.len()));
+                }
+            }
+        }
+    }
+
+    events
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn random_conversations_decrypt_identically(
+        script in prop::collection::vec(action(), 1..64),
+    ) {
+        // Current<->Current, as the baseline the older versions must match.
+        let expected = run_script(
+            &mut LibSignalProtocolCurrent::new(),
+            &mut LibSignalProtocolCurrent::new(),
+            &script,
+        );
+
+        for make_previous in PREVIOUS {
+            let events = run_script(&mut LibSignalProtocolCurrent::new(), &mut *make_previous(), &script);
+            prop_assert_eq!(&events, &expected);
+
+            let events = run_script(&mut *make_previous(), &mut LibSignalProtocolCurrent::new(), &script);
+            prop_assert_eq!(&events, &expected);
+        }
+    }
+}
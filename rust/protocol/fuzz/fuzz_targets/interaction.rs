@@ -207,8 +207,10 @@ impl Participant {
                 &mut self.store.pre_key_store,
                 &mut self.store.signed_pre_key_store,
                 &mut self.store.kyber_pre_key_store,
+                SystemTime::now(),
                 rng,
                 UsePQRatchet::Yes,
+                &SessionConfig::default(),
             )
             .await
             .unwrap();
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::fmt;
 use std::panic::UnwindSafe;
 
 use displaydoc::Display;
@@ -52,6 +53,11 @@ pub enum SignalProtocolError {
     InvalidSignedPreKeyId,
     /// invalid Kyber prekey identifier
     InvalidKyberPreKeyId,
+    /// reused base key for Kyber prekey {kyber_pre_key_id} and signed prekey {signed_pre_key_id}
+    ReusedKyberPreKeyBaseKey {
+        kyber_pre_key_id: crate::KyberPreKeyId,
+        signed_pre_key_id: crate::SignedPreKeyId,
+    },
 
     /// invalid MAC key length <{0}>
     InvalidMacKeyLength(usize),
@@ -72,8 +78,16 @@ pub enum SignalProtocolError {
 
     /// message with old counter {0} / {1}
     DuplicatedMessage(u32, u32),
+    /// {0} stopped using the post-quantum ratchet
+    PqRatchetDowngrade(crate::ProtocolAddress),
     /// invalid {0:?} message: {1}
     InvalidMessage(crate::CiphertextMessageType, &'static str),
+    /// invalid streamed message: {0}
+    InvalidStreamMessage(&'static str),
+    /// session for {0} changed since the message was decrypted
+    SessionChanged(crate::ProtocolAddress),
+    /// no free pre-key IDs
+    PreKeyIdsExhausted,
 
     /// error while invoking an ffi callback: {0}
     FfiBindingError(String),
@@ -110,6 +124,168 @@ impl SignalProtocolError {
     ) -> impl FnOnce(E) -> Self {
         move |error| Self::ApplicationCallbackError(method, Box::new(error))
     }
+
+    /// The broad category of this error, for telemetry.
+    pub fn category(&self) -> ErrorCategory {
+        self.classify().0
+    }
+
+    /// A stable numeric code for this kind of error.
+    ///
+    /// The hundreds digit is the [category's code](ErrorCategory::code); the rest identifies the
+    /// variant within that category. Codes are never reused, so they can be passed across the app
+    /// bridges and recorded as-is.
+    pub fn code(&self) -> u32 {
+        self.classify().1
+    }
+
+    /// Returns an adapter that displays this error without any addresses, key material, or
+    /// free-form text that might contain either.
+    pub fn log_safe(&self) -> LogSafeError<'_> {
+        LogSafeError(self)
+    }
+
+    fn classify(&self) -> (ErrorCategory, u32, &'static str) {
+        use ErrorCategory::*;
+        match self {
+            Self::InvalidArgument(_) => (CallerError, 101, "InvalidArgument"),
+            Self::InvalidProtocolAddress { .. } => (CallerError, 102, "InvalidProtocolAddress"),
+
+            Self::InvalidProtobufEncoding => (PeerBug, 201, "InvalidProtobufEncoding"),
+            Self::CiphertextMessageTooShort(_) => (PeerBug, 202, "CiphertextMessageTooShort"),
+            Self::NoKeyTypeIdentifier => (PeerBug, 203, "NoKeyTypeIdentifier"),
+            Self::BadKeyType(_) => (PeerBug, 204, "BadKeyType"),
+            Self::BadKeyLength(_, _) => (PeerBug, 205, "BadKeyLength"),
+            Self::SignatureValidationFailed => (PeerBug, 206, "SignatureValidationFailed"),
+            Self::InvalidMacKeyLength(_) => (PeerBug, 207, "InvalidMacKeyLength"),
+            Self::InvalidRegistrationId(_, _) => (PeerBug, 208, "InvalidRegistrationId"),
+            Self::InvalidMessage(_, _) => (PeerBug, 209, "InvalidMessage"),
+            Self::InvalidStreamMessage(_) => (PeerBug, 210, "InvalidStreamMessage"),
+            Self::InvalidSealedSenderMessage(_) => (PeerBug, 211, "InvalidSealedSenderMessage"),
+            Self::BadKEMKeyType(_) => (PeerBug, 212, "BadKEMKeyType"),
+            Self::WrongKEMKeyType(_, _) => (PeerBug, 213, "WrongKEMKeyType"),
+            Self::BadKEMKeyLength(_, _) => (PeerBug, 214, "BadKEMKeyLength"),
+            Self::BadKEMCiphertextLength(_, _) => (PeerBug, 215, "BadKEMCiphertextLength"),
+
+            Self::InvalidState(_, _) => (LocalStorage, 301, "InvalidState"),
+            Self::InvalidSessionStructure(_) => (LocalStorage, 302, "InvalidSessionStructure"),
+            Self::FfiBindingError(_) => (LocalStorage, 303, "FfiBindingError"),
+            Self::ApplicationCallbackError(_, _) => (LocalStorage, 304, "ApplicationCallbackError"),
+
+            Self::DuplicatedMessage(_, _) => (ReplayOrDuplicate, 401, "DuplicatedMessage"),
+            Self::ReusedKyberPreKeyBaseKey { .. } => {
+                (ReplayOrDuplicate, 402, "ReusedKyberPreKeyBaseKey")
+            }
+            Self::SealedSenderSelfSend => (ReplayOrDuplicate, 403, "SealedSenderSelfSend"),
+
+            Self::UntrustedIdentity(_) => (UntrustedIdentity, 501, "UntrustedIdentity"),
+
+            Self::LegacyCiphertextVersion(_) => {
+                (UnsupportedVersion, 601, "LegacyCiphertextVersion")
+            }
+            Self::UnrecognizedCiphertextVersion(_) => {
+                (UnsupportedVersion, 602, "UnrecognizedCiphertextVersion")
+            }
+            Self::UnrecognizedMessageVersion(_) => {
+                (UnsupportedVersion, 603, "UnrecognizedMessageVersion")
+            }
+            Self::UnknownSealedSenderVersion(_) => {
+                (UnsupportedVersion, 604, "UnknownSealedSenderVersion")
+            }
+            Self::PqRatchetDowngrade(_) => (UnsupportedVersion, 605, "PqRatchetDowngrade"),
+
+            Self::InvalidPreKeyId => (MissingState, 701, "InvalidPreKeyId"),
+            Self::InvalidSignedPreKeyId => (MissingState, 702, "InvalidSignedPreKeyId"),
+            Self::InvalidKyberPreKeyId => (MissingState, 703, "InvalidKyberPreKeyId"),
+            Self::NoSenderKeyState { .. } => (MissingState, 704, "NoSenderKeyState"),
+            Self::SessionNotFound(_) => (MissingState, 705, "SessionNotFound"),
+            Self::InvalidSenderKeySession { .. } => (MissingState, 706, "InvalidSenderKeySession"),
+            Self::UnknownSealedSenderServerCertificateId(_) => {
+                (MissingState, 707, "UnknownSealedSenderServerCertificateId")
+            }
+
+            Self::SessionChanged(_) => (ConcurrentUpdate, 801, "SessionChanged"),
+
+            Self::PreKeyIdsExhausted => (Exhausted, 901, "PreKeyIdsExhausted"),
+        }
+    }
+}
+
+/// A coarse, stable classification of [`SignalProtocolError`]s.
+///
+/// Many variants share a category, and new variants are assigned to an existing category
+/// wherever possible, so telemetry can bucket errors without matching on every variant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Display)]
+#[non_exhaustive]
+pub enum ErrorCategory {
+    /// caller error
+    CallerError,
+    /// peer bug
+    PeerBug,
+    /// local storage failure
+    LocalStorage,
+    /// replay or duplicate
+    ReplayOrDuplicate,
+    /// untrusted identity
+    UntrustedIdentity,
+    /// unsupported version
+    UnsupportedVersion,
+    /// missing state
+    MissingState,
+    /// concurrent update
+    ConcurrentUpdate,
+    /// exhausted
+    Exhausted,
+}
+
+impl ErrorCategory {
+    /// A stable numeric code for this category, also the hundreds digit of
+    /// [`SignalProtocolError::code`].
+    pub fn code(self) -> u32 {
+        match self {
+            Self::CallerError => 1,
+            Self::PeerBug => 2,
+            Self::LocalStorage => 3,
+            Self::ReplayOrDuplicate => 4,
+            Self::UntrustedIdentity => 5,
+            Self::UnsupportedVersion => 6,
+            Self::MissingState => 7,
+            Self::ConcurrentUpdate => 8,
+            Self::Exhausted => 9,
+        }
+    }
+}
+
+/// Displays a [`SignalProtocolError`] without addresses, key material, or free-form text.
+///
+/// Only the variant name, static descriptions, and small numbers such as versions and lengths are
+/// included. Created by [`SignalProtocolError::log_safe`].
+pub struct LogSafeError<'a>(&'a SignalProtocolError);
+
+impl fmt::Display for LogSafeError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (category, code, name) = self.0.classify();
+        f.write_str(name)?;
+        match self.0 {
+            SignalProtocolError::InvalidState(func, _)
+            | SignalProtocolError::ApplicationCallbackError(func, _) => write!(f, " in {func}")?,
+            SignalProtocolError::InvalidSessionStructure(reason)
+            | SignalProtocolError::InvalidStreamMessage(reason) => write!(f, ": {reason}")?,
+            SignalProtocolError::InvalidMessage(message_type, reason) => {
+                write!(f, " ({message_type:?}): {reason}")?
+            }
+            SignalProtocolError::LegacyCiphertextVersion(version)
+            | SignalProtocolError::UnrecognizedCiphertextVersion(version)
+            | SignalProtocolError::UnknownSealedSenderVersion(version) => {
+                write!(f, " <{version}>")?
+            }
+            SignalProtocolError::UnrecognizedMessageVersion(version) => write!(f, " <{version}>")?,
+            SignalProtocolError::CiphertextMessageTooShort(len)
+            | SignalProtocolError::InvalidMacKeyLength(len) => write!(f, " <{len}>")?,
+            _ => {}
+        }
+        write!(f, " [{category}, code {code}]")
+    }
 }
 
 impl From<CurveError> for SignalProtocolError {
@@ -121,3 +297,53 @@ impl From<CurveError> for SignalProtocolError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceId, ProtocolAddress};
+
+    #[test]
+    fn test_log_safe_display_omits_addresses() {
+        let address = ProtocolAddress::new(
+            "9d0652a3-dcc3-4d11-975f-74d61598733f".to_owned(),
+            DeviceId::new(1).expect("valid"),
+        );
+        let error = SignalProtocolError::UntrustedIdentity(address);
+        assert_eq!(error.category(), ErrorCategory::UntrustedIdentity);
+        assert_eq!(error.code(), 501);
+        assert_eq!(
+            error.log_safe().to_string(),
+            "UntrustedIdentity [untrusted identity, code 501]"
+        );
+        assert!(error.to_string().contains("9d0652a3"));
+
+        let error = SignalProtocolError::InvalidArgument("secret".to_owned());
+        assert!(!error.log_safe().to_string().contains("secret"));
+
+        let error = SignalProtocolError::UnrecognizedMessageVersion(9);
+        assert_eq!(error.category().code(), error.code() / 100);
+        assert_eq!(
+            error.log_safe().to_string(),
+            "UnrecognizedMessageVersion <9> [unsupported version, code 603]"
+        );
+    }
+
+    #[test]
+    fn test_non_storage_state_errors_have_their_own_categories() {
+        let address = ProtocolAddress::new(
+            "9d0652a3-dcc3-4d11-975f-74d61598733f".to_owned(),
+            DeviceId::new(1).expect("valid"),
+        );
+        let error = SignalProtocolError::SessionChanged(address);
+        assert_eq!(error.category(), ErrorCategory::ConcurrentUpdate);
+        assert_eq!(
+            error.log_safe().to_string(),
+            "SessionChanged [concurrent update, code 801]"
+        );
+
+        let error = SignalProtocolError::PreKeyIdsExhausted;
+        assert_eq!(error.category(), ErrorCategory::Exhausted);
+        assert_eq!(error.code(), 901);
+    }
+}
@@ -13,6 +13,9 @@ use subtle::ConstantTimeEq;
 
 use crate::{IdentityKey, proto};
 
+mod v2;
+pub use v2::{FingerprintComparison, FingerprintIdentity, FingerprintV2, ScannableFingerprintV2};
+
 #[derive(Debug, displaydoc::Display)]
 pub enum Error {
     /// fingerprint version number mismatch them {theirs} us {ours}
@@ -112,9 +115,11 @@ impl ScannableFingerprint {
             version: Some(self.version),
             local_fingerprint: Some(proto::fingerprint::LogicalFingerprint {
                 content: Some(self.local_fingerprint.to_owned()),
+                identifier: None,
             }),
             remote_fingerprint: Some(proto::fingerprint::LogicalFingerprint {
                 content: Some(self.remote_fingerprint.to_owned()),
+                identifier: None,
             }),
         };
 
@@ -0,0 +1,406 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use libsignal_core::{Aci, Pni, ServiceId};
+use prost::Message;
+use sha2::Sha512;
+use sha2::digest::Digest;
+use subtle::ConstantTimeEq;
+
+use super::{DisplayableFingerprint, Error};
+use crate::{IdentityKey, proto};
+
+/// Distinguishes the hash input from the legacy [`Fingerprint`](super::Fingerprint) (0x0000).
+const HASH_VERSION: [u8; 2] = [0, 1];
+const ITERATIONS: u32 = 5200;
+const IDENTIFIER_DIGEST_LABEL: &[u8] = b"Signal Fingerprint Identifier";
+const SCANNABLE_LEN: usize = 32;
+
+/// One side of a [`FingerprintV2`]: the service IDs of an account and their identity keys.
+#[derive(Debug, Clone)]
+pub struct FingerprintIdentity {
+    aci: Option<(Aci, IdentityKey)>,
+    pni: Option<(Pni, IdentityKey)>,
+}
+
+impl FingerprintIdentity {
+    /// An account known by a single service ID.
+    pub fn new(service_id: ServiceId, identity_key: IdentityKey) -> Self {
+        match service_id {
+            ServiceId::Aci(aci) => Self {
+                aci: Some((aci, identity_key)),
+                pni: None,
+            },
+            ServiceId::Pni(pni) => Self {
+                aci: None,
+                pni: Some((pni, identity_key)),
+            },
+        }
+    }
+
+    /// An account known by both its ACI and its PNI, each with their own identity key.
+    ///
+    /// Changing either key changes the resulting fingerprint.
+    pub fn with_aci_and_pni(
+        aci: Aci,
+        aci_identity_key: IdentityKey,
+        pni: Pni,
+        pni_identity_key: IdentityKey,
+    ) -> Self {
+        Self {
+            aci: Some((aci, aci_identity_key)),
+            pni: Some((pni, pni_identity_key)),
+        }
+    }
+
+    /// ACI first, then PNI.
+    fn entries(&self) -> impl Iterator<Item = (ServiceId, &IdentityKey)> {
+        self.aci
+            .iter()
+            .map(|(aci, key)| (ServiceId::from(*aci), key))
+            .chain(
+                self.pni
+                    .iter()
+                    .map(|(pni, key)| (ServiceId::from(*pni), key)),
+            )
+    }
+
+    fn fingerprint(&self) -> Vec<u8> {
+        let mut all_key_bytes = Vec::new();
+
+        let mut sha512 = Sha512::new();
+        sha512.update(&HASH_VERSION[..]);
+        for (service_id, key) in self.entries() {
+            let key_bytes = key.serialize();
+            sha512.update(&service_id.service_id_fixed_width_binary()[..]);
+            sha512.update(&key_bytes);
+            all_key_bytes.extend_from_slice(&key_bytes);
+        }
+        let mut buf = sha512.finalize();
+
+        for _i in 1..ITERATIONS {
+            let mut sha512 = Sha512::new();
+            sha512.update(&buf[..]);
+            sha512.update(&all_key_bytes);
+            buf = sha512.finalize();
+        }
+
+        buf.to_vec()
+    }
+
+    fn identifier_digest(&self) -> Vec<u8> {
+        let mut sha512 = Sha512::new();
+        sha512.update(IDENTIFIER_DIGEST_LABEL);
+        for (service_id, _) in self.entries() {
+            sha512.update(&service_id.service_id_fixed_width_binary()[..]);
+        }
+        sha512.finalize()[..SCANNABLE_LEN].to_vec()
+    }
+}
+
+/// The result of comparing a scanned fingerprint against our own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerprintComparison {
+    /// Both parties see the same identities and keys.
+    Match,
+    /// The scanned fingerprint uses a different format, so nothing else can be compared.
+    VersionMismatch { theirs: u32, ours: u32 },
+    /// The scanned fingerprint is for a different set of accounts.
+    IdentifierMismatch,
+    /// The accounts match, but at least one identity key differs.
+    KeyMismatch,
+}
+
+impl FingerprintComparison {
+    pub fn is_match(self) -> bool {
+        self == Self::Match
+    }
+}
+
+/// The scannable (QR code) form of a [`FingerprintV2`].
+///
+/// This uses the same `CombinedFingerprints` protobuf as [`ScannableFingerprint`], with
+/// [`ScannableFingerprintV2::VERSION`] and a digest of each side's service IDs, so that older
+/// clients reject it as a version mismatch.
+///
+/// [`ScannableFingerprint`]: super::ScannableFingerprint
+#[derive(Debug, Clone)]
+pub struct ScannableFingerprintV2 {
+    local_fingerprint: Vec<u8>,
+    local_identifier: Vec<u8>,
+    remote_fingerprint: Vec<u8>,
+    remote_identifier: Vec<u8>,
+}
+
+impl ScannableFingerprintV2 {
+    /// The scannable version; 1 and 2 are used by [`Fingerprint`](super::Fingerprint).
+    pub const VERSION: u32 = 3;
+
+    pub fn deserialize(protobuf: &[u8]) -> Result<Self, Error> {
+        let fingerprint = proto::fingerprint::CombinedFingerprints::decode(protobuf)
+            .map_err(|_| Error::ParsingError("failed to decode protobuf"))?;
+
+        let version = fingerprint
+            .version
+            .ok_or(Error::ParsingError("missing version"))?;
+        if version != Self::VERSION {
+            return Err(Error::VersionMismatch {
+                theirs: version,
+                ours: Self::VERSION,
+            });
+        }
+
+        let (local_fingerprint, local_identifier) =
+            logical_fingerprint_parts(fingerprint.local_fingerprint, "missing local fingerprint")?;
+        let (remote_fingerprint, remote_identifier) = logical_fingerprint_parts(
+            fingerprint.remote_fingerprint,
+            "missing remote fingerprint",
+        )?;
+
+        Ok(Self {
+            local_fingerprint,
+            local_identifier,
+            remote_fingerprint,
+            remote_identifier,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        proto::fingerprint::CombinedFingerprints {
+            version: Some(Self::VERSION),
+            local_fingerprint: Some(proto::fingerprint::LogicalFingerprint {
+                content: Some(self.local_fingerprint.clone()),
+                identifier: Some(self.local_identifier.clone()),
+            }),
+            remote_fingerprint: Some(proto::fingerprint::LogicalFingerprint {
+                content: Some(self.remote_fingerprint.clone()),
+                identifier: Some(self.remote_identifier.clone()),
+            }),
+        }
+        .encode_to_vec()
+    }
+
+    /// Compares a fingerprint scanned from the other party's device against this one.
+    ///
+    /// Only malformed input is reported as an error; every well-formed fingerprint produces a
+    /// [`FingerprintComparison`], checking the version, then the identifiers, then the keys.
+    pub fn compare(&self, combined: &[u8]) -> Result<FingerprintComparison, Error> {
+        let combined = proto::fingerprint::CombinedFingerprints::decode(combined)
+            .map_err(|_| Error::ParsingError("failed to decode their protobuf"))?;
+
+        let their_version = combined.version.unwrap_or(0);
+        if their_version != Self::VERSION {
+            return Ok(FingerprintComparison::VersionMismatch {
+                theirs: their_version,
+                ours: Self::VERSION,
+            });
+        }
+
+        let (their_local, their_local_identifier) = logical_fingerprint_parts(
+            combined.local_fingerprint,
+            "missing their local fingerprint",
+        )?;
+        let (their_remote, their_remote_identifier) = logical_fingerprint_parts(
+            combined.remote_fingerprint,
+            "missing their remote fingerprint",
+        )?;
+
+        let same_identifiers = their_local_identifier.ct_eq(&self.remote_identifier)
+            & their_remote_identifier.ct_eq(&self.local_identifier);
+        if !bool::from(same_identifiers) {
+            return Ok(FingerprintComparison::IdentifierMismatch);
+        }
+
+        let same_keys = their_local.ct_eq(&self.remote_fingerprint)
+            & their_remote.ct_eq(&self.local_fingerprint);
+        if !bool::from(same_keys) {
+            return Ok(FingerprintComparison::KeyMismatch);
+        }
+
+        Ok(FingerprintComparison::Match)
+    }
+}
+
+fn logical_fingerprint_parts(
+    fingerprint: Option<proto::fingerprint::LogicalFingerprint>,
+    missing: &'static str,
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let fingerprint = fingerprint.ok_or(Error::ParsingError(missing))?;
+    match (fingerprint.content, fingerprint.identifier) {
+        (Some(content), Some(identifier)) => Ok((content, identifier)),
+        _ => Err(Error::ParsingError(missing)),
+    }
+}
+
+/// A safety number that covers every service ID and identity key of both parties.
+///
+/// Unlike [`Fingerprint`](super::Fingerprint), the version and iteration count are fixed, and
+/// the identifiers are [`ServiceId`]s rather than arbitrary bytes.
+#[derive(Debug, Clone)]
+pub struct FingerprintV2 {
+    pub display: DisplayableFingerprint,
+    pub scannable: ScannableFingerprintV2,
+}
+
+impl FingerprintV2 {
+    pub fn new(local: &FingerprintIdentity, remote: &FingerprintIdentity) -> Result<Self, Error> {
+        let local_fingerprint = local.fingerprint();
+        let remote_fingerprint = remote.fingerprint();
+
+        Ok(Self {
+            display: DisplayableFingerprint::new(&local_fingerprint, &remote_fingerprint)?,
+            scannable: ScannableFingerprintV2 {
+                local_fingerprint: local_fingerprint[..SCANNABLE_LEN].to_vec(),
+                local_identifier: local.identifier_digest(),
+                remote_fingerprint: remote_fingerprint[..SCANNABLE_LEN].to_vec(),
+                remote_identifier: remote.identifier_digest(),
+            },
+        })
+    }
+
+    pub fn display_string(&self) -> String {
+        self.display.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use const_str::hex;
+
+    use super::*;
+    use crate::Fingerprint;
+
+    const ALICE_IDENTITY: &[u8] =
+        &hex!("0506863bc66d02b40d27b8d49ca7c09e9239236f9d7d25d6fcca5ce13c7064d868");
+    const BOB_IDENTITY: &[u8] =
+        &hex!("05f781b6fb32fed9ba1cf2de978d4d5da28dc34046ae814402b5c0dbd96fda907b");
+
+    const DISPLAYABLE_FINGERPRINT_V2: &str =
+        "303835379313460236215637899448544060092015164025060366244010";
+
+    fn alice() -> FingerprintIdentity {
+        FingerprintIdentity::new(
+            Aci::from_uuid_bytes([0xAA; 16]).into(),
+            IdentityKey::decode(ALICE_IDENTITY).expect("valid"),
+        )
+    }
+
+    fn bob() -> FingerprintIdentity {
+        // Reuses Alice's key for Bob's PNI, which is unrealistic but fine for a test vector.
+        FingerprintIdentity::with_aci_and_pni(
+            Aci::from_uuid_bytes([0xBB; 16]),
+            IdentityKey::decode(BOB_IDENTITY).expect("valid"),
+            Pni::from_uuid_bytes([0xBC; 16]),
+            IdentityKey::decode(ALICE_IDENTITY).expect("valid"),
+        )
+    }
+
+    #[test]
+    fn fingerprint_v2_test_vector() -> Result<(), Error> {
+        let a_fprint = FingerprintV2::new(&alice(), &bob())?;
+        let b_fprint = FingerprintV2::new(&bob(), &alice())?;
+
+        assert_eq!(a_fprint.display_string(), DISPLAYABLE_FINGERPRINT_V2);
+        assert_eq!(b_fprint.display_string(), DISPLAYABLE_FINGERPRINT_V2);
+
+        assert_eq!(
+            a_fprint
+                .scannable
+                .compare(&b_fprint.scannable.serialize())?,
+            FingerprintComparison::Match
+        );
+        assert_eq!(
+            b_fprint
+                .scannable
+                .compare(&a_fprint.scannable.serialize())?,
+            FingerprintComparison::Match
+        );
+
+        let round_tripped = ScannableFingerprintV2::deserialize(&a_fprint.scannable.serialize())?;
+        assert_eq!(round_tripped.serialize(), a_fprint.scannable.serialize());
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_v2_key_mismatch() -> Result<(), Error> {
+        let mallory_pni_key = FingerprintIdentity::with_aci_and_pni(
+            Aci::from_uuid_bytes([0xBB; 16]),
+            IdentityKey::decode(BOB_IDENTITY).expect("valid"),
+            Pni::from_uuid_bytes([0xBC; 16]),
+            IdentityKey::decode(BOB_IDENTITY).expect("valid"),
+        );
+
+        let a_fprint = FingerprintV2::new(&alice(), &mallory_pni_key)?;
+        let b_fprint = FingerprintV2::new(&bob(), &alice())?;
+
+        assert_ne!(a_fprint.display_string(), b_fprint.display_string());
+        assert_eq!(
+            a_fprint
+                .scannable
+                .compare(&b_fprint.scannable.serialize())?,
+            FingerprintComparison::KeyMismatch
+        );
+        assert_eq!(
+            b_fprint
+                .scannable
+                .compare(&a_fprint.scannable.serialize())?,
+            FingerprintComparison::KeyMismatch
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_v2_identifier_mismatch() -> Result<(), Error> {
+        let bob_aci_only = FingerprintIdentity::new(
+            Aci::from_uuid_bytes([0xBB; 16]).into(),
+            IdentityKey::decode(BOB_IDENTITY).expect("valid"),
+        );
+
+        let a_fprint = FingerprintV2::new(&alice(), &bob_aci_only)?;
+        let b_fprint = FingerprintV2::new(&bob(), &alice())?;
+
+        assert_eq!(
+            a_fprint
+                .scannable
+                .compare(&b_fprint.scannable.serialize())?,
+            FingerprintComparison::IdentifierMismatch
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_v2_version_mismatch() -> Result<(), Error> {
+        let a_key = IdentityKey::decode(ALICE_IDENTITY).expect("valid");
+        let b_key = IdentityKey::decode(BOB_IDENTITY).expect("valid");
+        let legacy = Fingerprint::new(2, 5200, &[0xBB; 16], &b_key, &[0xAA; 16], &a_key)?;
+        let legacy_scannable = legacy.scannable.serialize()?;
+
+        let a_fprint = FingerprintV2::new(&alice(), &bob())?;
+        assert_eq!(
+            a_fprint.scannable.compare(&legacy_scannable)?,
+            FingerprintComparison::VersionMismatch { theirs: 2, ours: 3 }
+        );
+        assert_matches!(
+            ScannableFingerprintV2::deserialize(&legacy_scannable),
+            Err(Error::VersionMismatch { theirs: 2, ours: 3 })
+        );
+        // Older clients see a version mismatch too, rather than a key mismatch.
+        assert_matches!(
+            legacy.scannable.compare(&a_fprint.scannable.serialize()),
+            Err(Error::VersionMismatch { theirs: 3, ours: 2 })
+        );
+
+        assert_matches!(
+            a_fprint.scannable.compare(b"not a protobuf"),
+            Err(Error::ParsingError(_))
+        );
+
+        Ok(())
+    }
+}
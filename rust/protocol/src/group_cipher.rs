@@ -3,14 +3,19 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
+use std::time::{Duration, SystemTime};
+
+use itertools::Itertools;
 use rand::{CryptoRng, Rng};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
 use crate::protocol::SENDERKEY_MESSAGE_CURRENT_VERSION;
 use crate::sender_keys::{SenderKeyState, SenderMessageKey};
 use crate::{
     CiphertextMessageType, KeyPair, ProtocolAddress, Result, SenderKeyDistributionMessage,
-    SenderKeyMessage, SenderKeyRecord, SenderKeyStore, SignalProtocolError, consts,
+    SenderKeyMessage, SenderKeyRecord, SenderKeyStore, ServiceId, SessionConfig,
+    SignalProtocolError,
 };
 
 pub async fn group_encrypt<R: Rng + CryptoRng>(
@@ -20,6 +25,101 @@ pub async fn group_encrypt<R: Rng + CryptoRng>(
     plaintext: &[u8],
     csprng: &mut R,
 ) -> Result<SenderKeyMessage> {
+    let (skm, _) = group_encrypt_with_message_key(
+        sender_key_store,
+        sender,
+        distribution_id,
+        plaintext,
+        csprng,
+    )
+    .await?;
+    Ok(skm)
+}
+
+/// Like [`group_encrypt`], but also returns the message key `plaintext` was encrypted with.
+pub(crate) async fn group_encrypt_with_message_key<R: Rng + CryptoRng>(
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    plaintext: &[u8],
+    csprng: &mut R,
+) -> Result<(SenderKeyMessage, SenderMessageKey)> {
+    let mut record = sender_key_store
+        .load_sender_key(sender, distribution_id)
+        .await?
+        .ok_or(SignalProtocolError::NoSenderKeyState { distribution_id })?;
+
+    let sender_key_state = record
+        .sender_key_state_mut()
+        .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?;
+
+    let result = encrypt_with_state(sender_key_state, distribution_id, plaintext, csprng)?;
+
+    sender_key_store
+        .store_sender_key(sender, distribution_id, &record)
+        .await?;
+
+    Ok(result)
+}
+
+/// Conditions under which [`group_encrypt_with_rotation_policy`] stops using a sender key.
+///
+/// The default policy never requires rotation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SenderKeyRotationPolicy {
+    /// Rotate once this many messages have been sent with the current sender key.
+    pub max_messages: Option<u32>,
+    /// Rotate once the current sender key is older than this.
+    ///
+    /// Sender keys created before the creation time was tracked are treated as created the first
+    /// time they are checked against a policy.
+    pub max_age: Option<Duration>,
+    /// Rotate when the set of group members differs from the one the current sender key was
+    /// distributed to.
+    ///
+    /// Sender keys created before membership was tracked adopt the members they are first
+    /// checked against.
+    pub rotate_on_membership_change: bool,
+}
+
+/// Why a sender key must be rotated, as reported by [`group_encrypt_with_rotation_policy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, displaydoc::Display)]
+pub enum SenderKeyRotationReason {
+    /// message limit reached
+    MessageLimit,
+    /// maximum age exceeded
+    MaxAge,
+    /// group membership changed
+    MembershipChanged,
+}
+
+/// The result of [`group_encrypt_with_rotation_policy`].
+#[derive(Debug, Clone)]
+pub enum GroupEncryptOutcome {
+    /// The message was encrypted with the current sender key.
+    Encrypted(SenderKeyMessage),
+    /// The policy requires a new sender key, so nothing was encrypted.
+    RotationRequired(SenderKeyRotationReason),
+}
+
+/// Like [`group_encrypt`], but first checks the current sender key against `policy`.
+///
+/// If the policy requires rotation, returns [`GroupEncryptOutcome::RotationRequired`] without
+/// encrypting anything. The caller should then call [`rotate_sender_key`], send the resulting
+/// distribution message to `members`, and retry.
+///
+/// `members` is only consulted if `policy.rotate_on_membership_change` is set; order and
+/// duplicates do not matter.
+pub async fn group_encrypt_with_rotation_policy<R: Rng + CryptoRng>(
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    plaintext: &[u8],
+    policy: &SenderKeyRotationPolicy,
+    members: &[ServiceId],
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<GroupEncryptOutcome> {
     let mut record = sender_key_store
         .load_sender_key(sender, distribution_id)
         .await?
@@ -29,6 +129,86 @@ pub async fn group_encrypt<R: Rng + CryptoRng>(
         .sender_key_state_mut()
         .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?;
 
+    let mut adopted = false;
+    let rotation_reason = 'check: {
+        if let Some(max_messages) = policy.max_messages {
+            let sent = sender_key_state
+                .sender_chain_key()
+                .ok_or(SignalProtocolError::InvalidSenderKeySession { distribution_id })?
+                .iteration();
+            if sent >= max_messages {
+                break 'check Some(SenderKeyRotationReason::MessageLimit);
+            }
+        }
+
+        if let Some(max_age) = policy.max_age {
+            match sender_key_state.created_at() {
+                Some(created_at) if created_at + max_age < now => {
+                    break 'check Some(SenderKeyRotationReason::MaxAge);
+                }
+                Some(_) => {}
+                None => {
+                    sender_key_state.set_created_at(now);
+                    adopted = true;
+                }
+            }
+        }
+
+        if policy.rotate_on_membership_change {
+            let digest = membership_digest(members);
+            match sender_key_state.membership_digest() {
+                Some(existing) if existing != digest => {
+                    break 'check Some(SenderKeyRotationReason::MembershipChanged);
+                }
+                Some(_) => {}
+                None => {
+                    sender_key_state.set_membership_digest(&digest);
+                    adopted = true;
+                }
+            }
+        }
+
+        None
+    };
+
+    if let Some(reason) = rotation_reason {
+        if adopted {
+            // Keep what a legacy sender key adopted above, even though it won't be used now.
+            sender_key_store
+                .store_sender_key(sender, distribution_id, &record)
+                .await?;
+        }
+        return Ok(GroupEncryptOutcome::RotationRequired(reason));
+    }
+
+    let (skm, _) = encrypt_with_state(sender_key_state, distribution_id, plaintext, csprng)?;
+
+    sender_key_store
+        .store_sender_key(sender, distribution_id, &record)
+        .await?;
+
+    Ok(GroupEncryptOutcome::Encrypted(skm))
+}
+
+fn membership_digest(members: &[ServiceId]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for member in members
+        .iter()
+        .map(ServiceId::service_id_fixed_width_binary)
+        .sorted()
+        .dedup()
+    {
+        hasher.update(member);
+    }
+    hasher.finalize().into()
+}
+
+fn encrypt_with_state<R: Rng + CryptoRng>(
+    sender_key_state: &mut SenderKeyState,
+    distribution_id: Uuid,
+    plaintext: &[u8],
+    csprng: &mut R,
+) -> Result<(SenderKeyMessage, SenderMessageKey)> {
     let message_version = sender_key_state
         .message_version()
         .try_into()
@@ -65,17 +245,14 @@ pub async fn group_encrypt<R: Rng + CryptoRng>(
 
     sender_key_state.set_sender_chain_key(sender_chain_key.next()?);
 
-    sender_key_store
-        .store_sender_key(sender, distribution_id, &record)
-        .await?;
-
-    Ok(skm)
+    Ok((skm, message_keys))
 }
 
 fn get_sender_key(
     state: &mut SenderKeyState,
     iteration: u32,
     distribution_id: Uuid,
+    config: &SessionConfig,
 ) -> Result<SenderMessageKey> {
     let sender_chain_key = state
         .sender_chain_key()
@@ -97,11 +274,11 @@ fn get_sender_key(
     }
 
     let jump = (iteration - current_iteration) as usize;
-    if jump > consts::MAX_FORWARD_JUMPS {
+    if jump > config.max_forward_jumps {
         log::error!(
             "SenderKey distribution {} Exceeded future message limit: {}, current iteration: {})",
             distribution_id,
-            consts::MAX_FORWARD_JUMPS,
+            config.max_forward_jumps,
             current_iteration
         );
         return Err(SignalProtocolError::InvalidMessage(
@@ -113,7 +290,7 @@ fn get_sender_key(
     let mut sender_chain_key = sender_chain_key;
 
     while sender_chain_key.iteration() < iteration {
-        state.add_sender_message_key(&sender_chain_key.sender_message_key());
+        state.add_sender_message_key(&sender_chain_key.sender_message_key(), config);
         sender_chain_key = sender_chain_key.next()?;
     }
 
@@ -126,6 +303,40 @@ pub async fn group_decrypt(
     sender_key_store: &mut dyn SenderKeyStore,
     sender: &ProtocolAddress,
 ) -> Result<Vec<u8>> {
+    group_decrypt_with_config(
+        skm_bytes,
+        sender_key_store,
+        sender,
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+/// Like [`group_decrypt`], but enforces the limits in `config` instead of the defaults.
+///
+/// This governs how far ahead of the sender chain a message may be and how many skipped message
+/// keys are retained.
+pub async fn group_decrypt_with_config(
+    skm_bytes: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    config: &SessionConfig,
+) -> Result<Vec<u8>> {
+    let (plaintext, _) =
+        group_decrypt_with_message_key(skm_bytes, sender_key_store, sender, config).await?;
+    Ok(plaintext)
+}
+
+/// Like [`group_decrypt_with_config`], but also returns the message key the plaintext was
+/// decrypted with.
+pub(crate) async fn group_decrypt_with_message_key(
+    skm_bytes: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, SenderMessageKey)> {
+    config.validate()?;
+
     let skm = SenderKeyMessage::try_from(skm_bytes)?;
 
     let distribution_id = skm.distribution_id();
@@ -163,7 +374,7 @@ pub async fn group_decrypt(
         return Err(SignalProtocolError::SignatureValidationFailed);
     }
 
-    let sender_key = get_sender_key(sender_key_state, skm.iteration(), distribution_id)?;
+    let sender_key = get_sender_key(sender_key_state, skm.iteration(), distribution_id, config)?;
 
     let plaintext = match signal_crypto::aes_256_cbc_decrypt(
         skm.ciphertext(),
@@ -190,7 +401,7 @@ pub async fn group_decrypt(
         .store_sender_key(sender, distribution_id, &record)
         .await?;
 
-    Ok(plaintext)
+    Ok((plaintext, sender_key))
 }
 
 pub async fn process_sender_key_distribution_message(
@@ -198,6 +409,25 @@ pub async fn process_sender_key_distribution_message(
     skdm: &SenderKeyDistributionMessage,
     sender_key_store: &mut dyn SenderKeyStore,
 ) -> Result<()> {
+    process_sender_key_distribution_message_with_config(
+        sender,
+        skdm,
+        sender_key_store,
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+/// Like [`process_sender_key_distribution_message`], but keeps at most
+/// `config.max_sender_key_states` sender key states in the updated record.
+pub async fn process_sender_key_distribution_message_with_config(
+    sender: &ProtocolAddress,
+    skdm: &SenderKeyDistributionMessage,
+    sender_key_store: &mut dyn SenderKeyStore,
+    config: &SessionConfig,
+) -> Result<()> {
+    config.validate()?;
+
     let distribution_id = skdm.distribution_id()?;
     log::info!(
         "{} Processing SenderKey distribution {} with chain ID {}",
@@ -218,6 +448,7 @@ pub async fn process_sender_key_distribution_message(
         skdm.chain_key()?,
         *skdm.signing_key()?,
         None,
+        config,
     );
     sender_key_store
         .store_sender_key(sender, distribution_id, &sender_key_record)
@@ -231,6 +462,27 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
     sender_key_store: &mut dyn SenderKeyStore,
     csprng: &mut R,
 ) -> Result<SenderKeyDistributionMessage> {
+    create_sender_key_distribution_message_with_config(
+        sender,
+        distribution_id,
+        sender_key_store,
+        csprng,
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+/// Like [`create_sender_key_distribution_message`], but keeps at most
+/// `config.max_sender_key_states` sender key states in a newly created record.
+pub async fn create_sender_key_distribution_message_with_config<R: Rng + CryptoRng>(
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    sender_key_store: &mut dyn SenderKeyStore,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<SenderKeyDistributionMessage> {
+    config.validate()?;
+
     let sender_key_record = sender_key_store
         .load_sender_key(sender, distribution_id)
         .await?;
@@ -238,24 +490,8 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
     let sender_key_record = match sender_key_record {
         Some(record) => record,
         None => {
-            // libsignal-protocol-java uses 31-bit integers for sender key chain IDs
-            let chain_id = (csprng.random::<u32>()) >> 1;
-            log::info!(
-                "Creating SenderKey for distribution {distribution_id} with chain ID {chain_id}"
-            );
-
-            let iteration = 0;
-            let sender_key: [u8; 32] = csprng.random();
-            let signing_key = KeyPair::generate(csprng);
             let mut record = SenderKeyRecord::new_empty();
-            record.add_sender_key_state(
-                SENDERKEY_MESSAGE_CURRENT_VERSION,
-                chain_id,
-                iteration,
-                &sender_key,
-                signing_key.public_key,
-                Some(signing_key.private_key),
-            );
+            add_new_sending_state(&mut record, distribution_id, csprng, config);
             sender_key_store
                 .store_sender_key(sender, distribution_id, &record)
                 .await?;
@@ -263,6 +499,97 @@ pub async fn create_sender_key_distribution_message<R: Rng + CryptoRng>(
         }
     };
 
+    distribution_message_for_current_state(&sender_key_record, distribution_id)
+}
+
+/// Starts a new sender key chain for `distribution_id`, returning the distribution message for it.
+///
+/// Unlike [`create_sender_key_distribution_message`], this always creates a new chain, even if
+/// one already exists. Recipients keep older chains (up to a limit) after processing the new
+/// distribution message, so messages already sent with them can still be decrypted.
+///
+/// The new chain is recorded as created at `now` and distributed to `members`, for use with
+/// [`group_encrypt_with_rotation_policy`].
+pub async fn rotate_sender_key<R: Rng + CryptoRng>(
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    sender_key_store: &mut dyn SenderKeyStore,
+    members: &[ServiceId],
+    now: SystemTime,
+    csprng: &mut R,
+) -> Result<SenderKeyDistributionMessage> {
+    rotate_sender_key_with_config(
+        sender,
+        distribution_id,
+        sender_key_store,
+        members,
+        now,
+        csprng,
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+/// Like [`rotate_sender_key`], but keeps at most `config.max_sender_key_states` sender key states
+/// in the updated record, including the new one.
+pub async fn rotate_sender_key_with_config<R: Rng + CryptoRng>(
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    sender_key_store: &mut dyn SenderKeyStore,
+    members: &[ServiceId],
+    now: SystemTime,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<SenderKeyDistributionMessage> {
+    config.validate()?;
+
+    let mut record = sender_key_store
+        .load_sender_key(sender, distribution_id)
+        .await?
+        .unwrap_or_else(SenderKeyRecord::new_empty);
+
+    add_new_sending_state(&mut record, distribution_id, csprng, config);
+    let state = record
+        .sender_key_state_mut()
+        .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?;
+    state.set_created_at(now);
+    state.set_membership_digest(&membership_digest(members));
+
+    sender_key_store
+        .store_sender_key(sender, distribution_id, &record)
+        .await?;
+
+    distribution_message_for_current_state(&record, distribution_id)
+}
+
+fn add_new_sending_state<R: Rng + CryptoRng>(
+    record: &mut SenderKeyRecord,
+    distribution_id: Uuid,
+    csprng: &mut R,
+    config: &SessionConfig,
+) {
+    // libsignal-protocol-java uses 31-bit integers for sender key chain IDs
+    let chain_id = (csprng.random::<u32>()) >> 1;
+    log::info!("Creating SenderKey for distribution {distribution_id} with chain ID {chain_id}");
+
+    let iteration = 0;
+    let sender_key: [u8; 32] = csprng.random();
+    let signing_key = KeyPair::generate(csprng);
+    record.add_sender_key_state(
+        SENDERKEY_MESSAGE_CURRENT_VERSION,
+        chain_id,
+        iteration,
+        &sender_key,
+        signing_key.public_key,
+        Some(signing_key.private_key),
+        config,
+    );
+}
+
+fn distribution_message_for_current_state(
+    sender_key_record: &SenderKeyRecord,
+    distribution_id: Uuid,
+) -> Result<SenderKeyDistributionMessage> {
     let state = sender_key_record
         .sender_key_state()
         .map_err(|_| SignalProtocolError::InvalidSenderKeySession { distribution_id })?;
@@ -7,8 +7,11 @@
 
 #![warn(missing_docs)]
 
+mod succession;
+
 use prost::Message;
 use rand::{CryptoRng, Rng};
+pub use succession::{IdentitySuccession, IdentitySuccessionChain};
 
 use crate::{KeyPair, PrivateKey, PublicKey, Result, SignalProtocolError, proto};
 
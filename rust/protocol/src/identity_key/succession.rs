@@ -0,0 +1,328 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Signed records that move a user from one identity key to another.
+
+use prost::Message;
+use rand::{CryptoRng, Rng};
+
+use super::ALTERNATE_IDENTITY_SIGNATURE_PREFIX_1;
+use crate::{IdentityKey, IdentityKeyPair, Result, SignalProtocolError, proto};
+
+// Shares the first prefix with alternate-identity signatures, but not the second, so a signature
+// for one can never be mistaken for the other.
+const IDENTITY_SUCCESSION_SIGNATURE_PREFIX_2: &[u8] = b"Signal_Identity_Succession";
+
+/// The longest chain [`IdentitySuccessionChain::verify`] will follow.
+///
+/// Rotations are rare; this only exists to bound the work done for a hostile chain.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// A statement that `next` replaces `previous` as a user's identity key.
+///
+/// The record is signed by both keys: the previous key authorizes the change, and the next key
+/// proves that the user actually holds it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentitySuccession {
+    previous: IdentityKey,
+    next: IdentityKey,
+    previous_signature: Box<[u8]>,
+    next_signature: Box<[u8]>,
+}
+
+impl IdentitySuccession {
+    /// Create a record stating that `next` replaces `previous`, signed by both.
+    pub fn new<R: Rng + CryptoRng>(
+        previous: &IdentityKeyPair,
+        next: &IdentityKeyPair,
+        rng: &mut R,
+    ) -> Result<Self> {
+        let previous_key = previous.identity_key().serialize();
+        let next_key = next.identity_key().serialize();
+        let message = signed_message(&previous_key, &next_key);
+        Ok(Self {
+            previous: *previous.identity_key(),
+            next: *next.identity_key(),
+            previous_signature: previous
+                .private_key()
+                .calculate_signature_for_multipart_message(&message, rng)?,
+            next_signature: next
+                .private_key()
+                .calculate_signature_for_multipart_message(&message, rng)?,
+        })
+    }
+
+    /// The identity key being replaced.
+    #[inline]
+    pub fn previous(&self) -> &IdentityKey {
+        &self.previous
+    }
+
+    /// The identity key replacing [`previous`](Self::previous).
+    #[inline]
+    pub fn next(&self) -> &IdentityKey {
+        &self.next
+    }
+
+    /// Check that both keys signed this record.
+    pub fn verify(&self) -> bool {
+        let previous_key = self.previous.serialize();
+        let next_key = self.next.serialize();
+        let message = signed_message(&previous_key, &next_key);
+        self.previous
+            .public_key()
+            .verify_signature_for_multipart_message(&message, &self.previous_signature)
+            && self
+                .next
+                .public_key()
+                .verify_signature_for_multipart_message(&message, &self.next_signature)
+    }
+
+    /// Return a byte slice which can later be deserialized with [`Self::deserialize`].
+    pub fn serialize(&self) -> Vec<u8> {
+        self.to_proto().encode_to_vec()
+    }
+
+    /// Deserialize a record; this does not check the signatures.
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let proto = proto::wire::IdentitySuccession::decode(data)
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        Self::from_proto(proto)
+    }
+
+    fn to_proto(&self) -> proto::wire::IdentitySuccession {
+        proto::wire::IdentitySuccession {
+            previous_identity_key: Some(self.previous.serialize().into_vec()),
+            next_identity_key: Some(self.next.serialize().into_vec()),
+            previous_signature: Some(self.previous_signature.to_vec()),
+            next_signature: Some(self.next_signature.to_vec()),
+        }
+    }
+
+    fn from_proto(proto: proto::wire::IdentitySuccession) -> Result<Self> {
+        let (
+            Some(previous_identity_key),
+            Some(next_identity_key),
+            Some(previous_signature),
+            Some(next_signature),
+        ) = (
+            proto.previous_identity_key,
+            proto.next_identity_key,
+            proto.previous_signature,
+            proto.next_signature,
+        )
+        else {
+            return Err(SignalProtocolError::InvalidProtobufEncoding);
+        };
+        Ok(Self {
+            previous: IdentityKey::decode(&previous_identity_key)?,
+            next: IdentityKey::decode(&next_identity_key)?,
+            previous_signature: previous_signature.into_boxed_slice(),
+            next_signature: next_signature.into_boxed_slice(),
+        })
+    }
+}
+
+fn signed_message<'a>(previous_key: &'a [u8], next_key: &'a [u8]) -> [&'a [u8]; 4] {
+    [
+        ALTERNATE_IDENTITY_SIGNATURE_PREFIX_1,
+        IDENTITY_SUCCESSION_SIGNATURE_PREFIX_2,
+        previous_key,
+        next_key,
+    ]
+}
+
+/// A sequence of [`IdentitySuccession`] records, oldest first, each starting from the key the
+/// previous one ended at.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdentitySuccessionChain {
+    links: Vec<IdentitySuccession>,
+}
+
+impl IdentitySuccessionChain {
+    /// Create an empty chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The records in this chain, oldest first.
+    #[inline]
+    pub fn links(&self) -> &[IdentitySuccession] {
+        &self.links
+    }
+
+    /// The most recent identity key in the chain, if there are any records.
+    pub fn latest(&self) -> Option<&IdentityKey> {
+        self.links.last().map(IdentitySuccession::next)
+    }
+
+    /// Append `link`, which must start from [`latest`](Self::latest).
+    ///
+    /// The signatures are not checked here; see [`verify`](Self::verify).
+    pub fn push(&mut self, link: IdentitySuccession) -> Result<()> {
+        if self
+            .latest()
+            .is_some_and(|latest| latest != link.previous())
+        {
+            return Err(SignalProtocolError::InvalidArgument(
+                "identity succession does not continue the chain".to_string(),
+            ));
+        }
+        self.links.push(link);
+        Ok(())
+    }
+
+    /// Check whether this chain proves that `candidate` succeeded `trusted`.
+    ///
+    /// The chain may include older links from before `trusted` and newer ones after `candidate`;
+    /// only the links in between are checked, and every one of them must be signed by both of its
+    /// keys.
+    pub fn verify(&self, trusted: &IdentityKey, candidate: &IdentityKey) -> bool {
+        if self.links.len() > MAX_CHAIN_LENGTH || trusted == candidate {
+            return false;
+        }
+        let Some(start) = self
+            .links
+            .iter()
+            .position(|link| link.previous() == trusted)
+        else {
+            return false;
+        };
+
+        let mut current = trusted;
+        for link in &self.links[start..] {
+            if link.previous() != current || !link.verify() {
+                return false;
+            }
+            current = link.next();
+            if current == candidate {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Return a byte slice which can later be deserialized with [`Self::deserialize`].
+    pub fn serialize(&self) -> Vec<u8> {
+        proto::wire::IdentitySuccessionChain {
+            links: self
+                .links
+                .iter()
+                .map(IdentitySuccession::to_proto)
+                .collect(),
+        }
+        .encode_to_vec()
+    }
+
+    /// Deserialize a chain; this checks that the links connect, but not their signatures.
+    pub fn deserialize(data: &[u8]) -> Result<Self> {
+        let proto = proto::wire::IdentitySuccessionChain::decode(data)
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        let mut chain = Self::new();
+        for link in proto.links {
+            chain
+                .push(IdentitySuccession::from_proto(link)?)
+                .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        }
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::TryRngCore as _;
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    fn chain_of(keys: &[IdentityKeyPair]) -> IdentitySuccessionChain {
+        let mut rng = OsRng.unwrap_err();
+        let mut chain = IdentitySuccessionChain::new();
+        for pair in keys.windows(2) {
+            chain
+                .push(IdentitySuccession::new(&pair[0], &pair[1], &mut rng).expect("can sign"))
+                .expect("connected");
+        }
+        chain
+    }
+
+    #[test]
+    fn test_succession_chain_verification() {
+        let mut rng = OsRng.unwrap_err();
+        let keys: Vec<_> = (0..4)
+            .map(|_| IdentityKeyPair::generate(&mut rng))
+            .collect();
+        let key = |i: usize| keys[i].identity_key();
+        let chain = chain_of(&keys);
+
+        assert_eq!(chain.latest(), Some(key(3)));
+        assert!(chain.verify(key(0), key(3)));
+        assert!(chain.verify(key(1), key(3)));
+        assert!(chain.verify(key(0), key(2)));
+        // Not backwards, and not to or from keys outside the chain.
+        assert!(!chain.verify(key(3), key(0)));
+        assert!(!chain.verify(key(2), key(2)));
+        let unrelated = IdentityKeyPair::generate(&mut rng);
+        assert!(!chain.verify(unrelated.identity_key(), key(3)));
+        assert!(!chain.verify(key(0), unrelated.identity_key()));
+
+        let roundtripped = IdentitySuccessionChain::deserialize(&chain.serialize()).expect("valid");
+        assert_eq!(roundtripped, chain);
+        assert!(roundtripped.verify(key(0), key(3)));
+    }
+
+    #[test]
+    fn test_succession_requires_both_signatures() {
+        let mut rng = OsRng.unwrap_err();
+        let previous = IdentityKeyPair::generate(&mut rng);
+        let next = IdentityKeyPair::generate(&mut rng);
+        let mallory = IdentityKeyPair::generate(&mut rng);
+
+        let valid = IdentitySuccession::new(&previous, &next, &mut rng).expect("can sign");
+        assert!(valid.verify());
+
+        // Mallory can't claim someone else's key as their successor...
+        let mut claimed = IdentitySuccession::new(&mallory, &next, &mut rng).expect("can sign");
+        claimed.previous = *previous.identity_key();
+        assert!(!claimed.verify());
+
+        // ...nor move someone else's identity to her own key.
+        let mut hijacked =
+            IdentitySuccession::new(&previous, &mallory, &mut rng).expect("can sign");
+        hijacked.next = *next.identity_key();
+        assert!(!hijacked.verify());
+
+        // Alternate-identity signatures are not successions.
+        let alternate = previous
+            .sign_alternate_identity(next.identity_key(), &mut rng)
+            .expect("can sign");
+        let mut confused = valid.clone();
+        confused.previous_signature = alternate;
+        assert!(!confused.verify());
+    }
+
+    #[test]
+    fn test_succession_chain_must_connect() {
+        let mut rng = OsRng.unwrap_err();
+        let keys: Vec<_> = (0..4)
+            .map(|_| IdentityKeyPair::generate(&mut rng))
+            .collect();
+        let mut chain = chain_of(&keys[..2]);
+        let gap = IdentitySuccession::new(&keys[2], &keys[3], &mut rng).expect("can sign");
+        assert!(matches!(
+            chain.push(gap.clone()),
+            Err(SignalProtocolError::InvalidArgument(_))
+        ));
+
+        let disconnected = proto::wire::IdentitySuccessionChain {
+            links: vec![chain.links()[0].to_proto(), gap.to_proto()],
+        }
+        .encode_to_vec();
+        assert!(matches!(
+            IdentitySuccessionChain::deserialize(&disconnected),
+            Err(SignalProtocolError::InvalidProtobufEncoding)
+        ));
+    }
+}
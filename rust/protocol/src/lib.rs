@@ -30,31 +30,43 @@ mod group_cipher;
 mod identity_key;
 pub mod incremental_mac;
 pub mod kem;
+mod prekey_manager;
 mod proto;
 mod protocol;
 mod ratchet;
+mod recovery;
 mod sealed_sender;
 mod sender_keys;
 mod session;
 mod session_cipher;
+mod session_config;
 mod state;
 mod storage;
+mod stream_cipher;
 mod timestamp;
 
 use error::Result;
-pub use error::SignalProtocolError;
+pub use error::{ErrorCategory, LogSafeError, SignalProtocolError};
 pub use fingerprint::{
-    DisplayableFingerprint, Error as FingerprintError, Fingerprint, ScannableFingerprint,
+    DisplayableFingerprint, Error as FingerprintError, Fingerprint, FingerprintComparison,
+    FingerprintIdentity, FingerprintV2, ScannableFingerprint, ScannableFingerprintV2,
 };
 pub use group_cipher::{
-    create_sender_key_distribution_message, group_decrypt, group_encrypt,
-    process_sender_key_distribution_message,
+    GroupEncryptOutcome, SenderKeyRotationPolicy, SenderKeyRotationReason,
+    create_sender_key_distribution_message, create_sender_key_distribution_message_with_config,
+    group_decrypt, group_decrypt_with_config, group_encrypt, group_encrypt_with_rotation_policy,
+    process_sender_key_distribution_message, process_sender_key_distribution_message_with_config,
+    rotate_sender_key, rotate_sender_key_with_config,
 };
-pub use identity_key::{IdentityKey, IdentityKeyPair};
+pub use identity_key::{IdentityKey, IdentityKeyPair, IdentitySuccession, IdentitySuccessionChain};
 pub use libsignal_core::curve::{KeyPair, PrivateKey, PublicKey};
 pub use libsignal_core::{
     Aci, DeviceId, Pni, ProtocolAddress, ServiceId, ServiceIdFixedWidthBinaryBytes, ServiceIdKind,
 };
+pub use prekey_manager::{
+    PreKeyManager, PreKeyManagerConfig, PreKeyUpload, PreKeyUploadBundle, ServerPreKeyCounts,
+    SignedPreKeyUpload, StalePreKeys,
+};
 pub use protocol::{
     CiphertextMessage, CiphertextMessageType, DecryptionErrorMessage, KyberPayload,
     PlaintextContent, PreKeySignalMessage, SenderKeyDistributionMessage, SenderKeyMessage,
@@ -64,26 +76,48 @@ pub use ratchet::{
     AliceSignalProtocolParameters, BobSignalProtocolParameters, UsePQRatchet,
     initialize_alice_session_record, initialize_bob_session_record,
 };
+pub use recovery::{DecryptionErrorAction, DecryptionErrorIgnoreReason, decryption_error_action};
 pub use sealed_sender::{
-    ContentHint, SealedSenderDecryptionResult, SealedSenderV2SentMessage,
-    SealedSenderV2SentMessageRecipient, SenderCertificate, ServerCertificate,
-    UnidentifiedSenderMessageContent, sealed_sender_decrypt, sealed_sender_decrypt_to_usmc,
+    CertificateRevocationSet, CertificateValidationError, ContentHint, SealedSenderDecryptError,
+    SealedSenderDecryptionResult, SealedSenderMultiRecipientEncryptionResult,
+    SealedSenderV2SentMessage, SealedSenderV2SentMessageRecipient, SenderCertificate,
+    SenderCertificateIssuer, ServerCertificate, UnidentifiedSenderMessageContent,
+    sealed_sender_decrypt, sealed_sender_decrypt_to_usmc, sealed_sender_decrypt_with_revocations,
     sealed_sender_encrypt, sealed_sender_encrypt_from_usmc, sealed_sender_multi_recipient_encrypt,
+    sealed_sender_multi_recipient_encrypt_and_log,
+    sealed_sender_multi_recipient_encrypt_skipping_failures, server as sealed_sender_server,
 };
 pub use sender_keys::SenderKeyRecord;
-pub use session::{process_prekey, process_prekey_bundle};
+pub use session::{process_prekey, process_prekey_bundle, process_prekey_bundle_with_config};
 pub use session_cipher::{
-    message_decrypt, message_decrypt_prekey, message_decrypt_signal, message_encrypt,
+    MultiDeviceEncryptionResult, PendingDecryptionUpdates, message_decrypt, message_decrypt_prekey,
+    message_decrypt_prekey_dry_run, message_decrypt_prekey_transactional, message_decrypt_signal,
+    message_decrypt_signal_dry_run, message_decrypt_signal_transactional,
+    message_decrypt_transactional, message_encrypt, message_encrypt_for_all_devices,
+    message_encrypt_with_config,
 };
+pub use session_config::SessionConfig;
 pub use state::{
-    GenericSignedPreKey, KyberPreKeyId, KyberPreKeyRecord, PreKeyBundle, PreKeyBundleContent,
-    PreKeyId, PreKeyRecord, SessionRecord, SessionUsabilityRequirements, SignedPreKeyId,
-    SignedPreKeyRecord,
+    GenericSignedPreKey, KyberPreKeyId, KyberPreKeyKind, KyberPreKeyRecord, PqRatchetStatus,
+    PreKeyBundle, PreKeyBundleContent, PreKeyId, PreKeyRecord, ReceiverChainDiagnostics,
+    SessionDiagnostics, SessionRecord, SessionStateDiagnostics, SessionUsabilityRequirements,
+    SignedPreKeyId, SignedPreKeyRecord,
 };
 pub use storage::{
     Direction, IdentityChange, IdentityKeyStore, InMemIdentityKeyStore, InMemKyberPreKeyStore,
-    InMemPreKeyStore, InMemSenderKeyStore, InMemSessionStore, InMemSignalProtocolStore,
-    InMemSignedPreKeyStore, KyberPreKeyStore, PreKeyStore, ProtocolStore, SenderKeyStore,
-    SessionStore, SignedPreKeyStore,
+    InMemPreKeyStore, InMemSenderKeyStore, InMemSentMessageLogStore, InMemSessionStore,
+    InMemSignalProtocolStore, InMemSignedPreKeyStore, InMemTransactionalProtocolStore,
+    KyberPreKeyStore, PreKeyStore, ProtocolStore, ProtocolStoreTransaction, SavedIdentity,
+    SenderKeyStore, SentMessage, SentMessageLogStore, SessionStore, SignedPreKeyStore,
+    TransactionStep, TransactionalProtocolStore,
+};
+#[cfg(feature = "sqlite")]
+pub use storage::{
+    SQLITE_STORAGE_KEY_LEN, SqliteIdentityKeyStore, SqliteKyberPreKeyStore, SqlitePreKeyStore,
+    SqliteSenderKeyStore, SqliteSessionStore, SqliteSignalProtocolStore, SqliteSignedPreKeyStore,
+};
+pub use stream_cipher::{
+    StreamDecryptor, StreamEncryptor, group_decrypt_stream, group_encrypt_stream,
+    message_decrypt_stream, message_encrypt_stream,
 };
 pub use timestamp::Timestamp;
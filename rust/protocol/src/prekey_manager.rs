@@ -0,0 +1,513 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::time::{Duration, SystemTime};
+
+use prost::Message;
+use rand::{CryptoRng, Rng};
+
+use crate::proto::storage::PreKeyManagerStructure;
+use crate::proto::storage::pre_key_manager_structure::RetiredKey;
+use crate::state::GenericSignedPreKey;
+use crate::{
+    IdentityKeyPair, IdentityKeyStore, KeyPair, KyberPreKeyId, KyberPreKeyKind, KyberPreKeyRecord,
+    KyberPreKeyStore, PreKeyId, PreKeyRecord, PreKeyStore, ProtocolStore, Result,
+    SignalProtocolError, SignedPreKeyId, SignedPreKeyRecord, SignedPreKeyStore, Timestamp, kem,
+};
+
+/// Pre-key IDs are allocated from `1..=MAX_PRE_KEY_ID`, wrapping around.
+///
+/// This matches the range the apps have always used, so IDs allocated here never collide with
+/// existing keys in an unexpected way.
+const MAX_PRE_KEY_ID: u32 = 0xFF_FFFF;
+
+fn next_id(id: u32) -> u32 {
+    id % MAX_PRE_KEY_ID + 1
+}
+
+/// Converts `time` for storage, saturating rather than failing on times outside the `u64` range.
+///
+/// Times before the epoch become 0, which only makes keys look older than they are.
+fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, duration_millis)
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+/// Tunable parameters for a [`PreKeyManager`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreKeyManagerConfig {
+    /// How many one-time pre-keys of each kind to generate in a replenishment batch.
+    pub batch_size: u32,
+    /// Replenish one-time pre-keys when the server reports fewer than this many.
+    pub min_server_pre_keys: u32,
+    /// How long a signed pre-key or last-resort Kyber pre-key is used before being replaced.
+    pub rotation_interval: Duration,
+    /// How long a replaced signed or last-resort key is kept, so that sessions started with it
+    /// just before it was replaced can still be completed.
+    pub retired_key_retention: Duration,
+    /// The type of Kyber pre-keys to generate.
+    pub kyber_key_type: kem::KeyType,
+}
+
+impl Default for PreKeyManagerConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            min_server_pre_keys: 10,
+            rotation_interval: Duration::from_secs(2 * 24 * 60 * 60),
+            retired_key_retention: Duration::from_secs(30 * 24 * 60 * 60),
+            kyber_key_type: kem::KeyType::Kyber1024,
+        }
+    }
+}
+
+/// An unsigned one-time pre-key, in the shape the server expects for uploads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreKeyUpload {
+    pub key_id: u32,
+    pub public_key: Vec<u8>,
+}
+
+/// A signed pre-key (EC or Kyber), in the shape the server expects for uploads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedPreKeyUpload {
+    pub key_id: u32,
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl<T: GenericSignedPreKey> From<&T> for SignedPreKeyUpload {
+    fn from(record: &T) -> Self {
+        let storage = record.get_storage();
+        Self {
+            key_id: storage.id,
+            public_key: storage.public_key.clone(),
+            signature: storage.signature.clone(),
+        }
+    }
+}
+
+/// Everything that should be uploaded to the server after a [`PreKeyManager`] operation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PreKeyUploadBundle {
+    pub signed_pre_key: Option<SignedPreKeyUpload>,
+    pub pq_last_resort_pre_key: Option<SignedPreKeyUpload>,
+    pub pre_keys: Vec<PreKeyUpload>,
+    pub pq_pre_keys: Vec<SignedPreKeyUpload>,
+}
+
+impl PreKeyUploadBundle {
+    /// Whether there is nothing to upload.
+    pub fn is_empty(&self) -> bool {
+        self.signed_pre_key.is_none()
+            && self.pq_last_resort_pre_key.is_none()
+            && self.pre_keys.is_empty()
+            && self.pq_pre_keys.is_empty()
+    }
+}
+
+/// How many one-time pre-keys of each kind the server still has for this device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerPreKeyCounts {
+    pub pre_keys: u32,
+    pub pq_pre_keys: u32,
+}
+
+/// Keys the [`PreKeyManager`] no longer needs, which should be deleted from their stores.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StalePreKeys {
+    pub signed_pre_keys: Vec<SignedPreKeyId>,
+    pub kyber_pre_keys: Vec<KyberPreKeyId>,
+}
+
+/// Manages the lifecycle of this device's pre-keys.
+///
+/// The manager allocates IDs, generates and stores new keys, decides when the signed pre-key and
+/// last-resort Kyber pre-key are due for rotation, and remembers which keys have been retired so
+/// they can be deleted later. Each Kyber pre-key it generates is stored with its
+/// [kind](KyberPreKeyRecord::kind), which is all a store needs to implement
+/// [`KyberPreKeyStore::mark_kyber_pre_key_used`].
+///
+/// The manager's own state must be persisted by the caller (see [`PreKeyManager::serialize`])
+/// alongside the stores it writes to.
+#[derive(Debug, Clone)]
+pub struct PreKeyManager {
+    config: PreKeyManagerConfig,
+    state: PreKeyManagerStructure,
+}
+
+impl PreKeyManager {
+    /// Creates a manager for a device with no pre-keys yet.
+    ///
+    /// ID allocation starts at random points, as the apps have always done.
+    pub fn new<R: Rng + CryptoRng>(config: PreKeyManagerConfig, csprng: &mut R) -> Self {
+        Self {
+            config,
+            state: PreKeyManagerStructure {
+                next_pre_key_id: csprng.random_range(1..=MAX_PRE_KEY_ID),
+                next_signed_pre_key_id: csprng.random_range(1..=MAX_PRE_KEY_ID),
+                next_kyber_pre_key_id: csprng.random_range(1..=MAX_PRE_KEY_ID),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn deserialize(data: &[u8], config: PreKeyManagerConfig) -> Result<Self> {
+        let state = PreKeyManagerStructure::decode(data)
+            .map_err(|_| SignalProtocolError::InvalidProtobufEncoding)?;
+        for next in [
+            state.next_pre_key_id,
+            state.next_signed_pre_key_id,
+            state.next_kyber_pre_key_id,
+        ] {
+            if !(1..=MAX_PRE_KEY_ID).contains(&next) {
+                return Err(SignalProtocolError::InvalidProtobufEncoding);
+            }
+        }
+        Ok(Self { config, state })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.state.encode_to_vec()
+    }
+
+    /// The signed pre-key currently being handed out, if one has been generated.
+    pub fn active_signed_pre_key_id(&self) -> Option<SignedPreKeyId> {
+        Some(self.state.active_signed_pre_key_id)
+            .filter(|id| *id != 0)
+            .map(Into::into)
+    }
+
+    /// The last-resort Kyber pre-key currently being handed out, if one has been generated.
+    pub fn active_last_resort_kyber_pre_key_id(&self) -> Option<KyberPreKeyId> {
+        Some(self.state.active_last_resort_kyber_pre_key_id)
+            .filter(|id| *id != 0)
+            .map(Into::into)
+    }
+
+    /// Brings this device's pre-keys up to date, returning what needs to be uploaded.
+    ///
+    /// Rotates the signed pre-key and last-resort Kyber pre-key if they are missing or older
+    /// than the rotation interval, and generates a batch of each kind of one-time pre-key if the
+    /// server is running low. The returned bundle is empty if nothing needed to change.
+    pub async fn refresh<R: Rng + CryptoRng>(
+        &mut self,
+        store: &mut dyn ProtocolStore,
+        server_counts: ServerPreKeyCounts,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<PreKeyUploadBundle> {
+        let now_millis = epoch_millis(now);
+        let is_due = |id: u32, created_at: u64| {
+            id == 0
+                || now_millis.saturating_sub(created_at)
+                    >= duration_millis(self.config.rotation_interval)
+        };
+        let rotate_signed = is_due(
+            self.state.active_signed_pre_key_id,
+            self.state.active_signed_pre_key_created_at,
+        );
+        let rotate_last_resort = is_due(
+            self.state.active_last_resort_kyber_pre_key_id,
+            self.state.active_last_resort_kyber_pre_key_created_at,
+        );
+
+        let mut bundle = PreKeyUploadBundle::default();
+        if rotate_signed {
+            bundle.signed_pre_key = Some(self.rotate_signed_pre_key(store, now, csprng).await?);
+        }
+        if rotate_last_resort {
+            bundle.pq_last_resort_pre_key = Some(
+                self.rotate_last_resort_kyber_pre_key(store, now, csprng)
+                    .await?,
+            );
+        }
+        if server_counts.pre_keys < self.config.min_server_pre_keys {
+            bundle.pre_keys = self
+                .generate_pre_keys(store, self.config.batch_size, csprng)
+                .await?;
+        }
+        if server_counts.pq_pre_keys < self.config.min_server_pre_keys {
+            bundle.pq_pre_keys = self
+                .generate_kyber_pre_keys(store, self.config.batch_size, now, csprng)
+                .await?;
+        }
+        Ok(bundle)
+    }
+
+    /// Replaces the signed pre-key unconditionally, retiring the previous one.
+    pub async fn rotate_signed_pre_key<R: Rng + CryptoRng>(
+        &mut self,
+        store: &mut dyn ProtocolStore,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<SignedPreKeyUpload> {
+        let identity_key_pair = store.get_identity_key_pair().await?;
+        let id = self.allocate_signed_pre_key_id()?;
+        let now_millis = epoch_millis(now);
+
+        let key_pair = KeyPair::generate(csprng);
+        let signature = identity_key_pair
+            .private_key()
+            .calculate_signature(&key_pair.public_key.serialize(), csprng)?;
+        let record = SignedPreKeyRecord::new(
+            id.into(),
+            Timestamp::from_epoch_millis(now_millis),
+            &key_pair,
+            &signature,
+        );
+        store.save_signed_pre_key(id.into(), &record).await?;
+
+        let previous = std::mem::replace(&mut self.state.active_signed_pre_key_id, id);
+        self.state.active_signed_pre_key_created_at = now_millis;
+        if previous != 0 {
+            self.state.retired_signed_pre_keys.push(RetiredKey {
+                id: previous,
+                retired_at: now_millis,
+            });
+        }
+
+        Ok((&record).into())
+    }
+
+    /// Replaces the last-resort Kyber pre-key unconditionally, retiring the previous one.
+    pub async fn rotate_last_resort_kyber_pre_key<R: Rng + CryptoRng>(
+        &mut self,
+        store: &mut dyn ProtocolStore,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<SignedPreKeyUpload> {
+        let identity_key_pair = store.get_identity_key_pair().await?;
+        let id = self.allocate_kyber_pre_key_id(store).await?;
+        let record = self.generate_kyber_record(
+            &identity_key_pair,
+            id,
+            KyberPreKeyKind::LastResort,
+            now,
+            csprng,
+        )?;
+        store.save_kyber_pre_key(id.into(), &record).await?;
+
+        let now_millis = epoch_millis(now);
+        let previous = std::mem::replace(&mut self.state.active_last_resort_kyber_pre_key_id, id);
+        self.state.active_last_resort_kyber_pre_key_created_at = now_millis;
+        if previous != 0 {
+            self.state
+                .retired_last_resort_kyber_pre_keys
+                .push(RetiredKey {
+                    id: previous,
+                    retired_at: now_millis,
+                });
+        }
+
+        Ok((&record).into())
+    }
+
+    /// Generates and stores `count` one-time EC pre-keys.
+    pub async fn generate_pre_keys<R: Rng + CryptoRng>(
+        &mut self,
+        store: &mut dyn ProtocolStore,
+        count: u32,
+        csprng: &mut R,
+    ) -> Result<Vec<PreKeyUpload>> {
+        let mut uploads = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let id = self.allocate_pre_key_id(store).await?;
+            let key_pair = KeyPair::generate(csprng);
+            store
+                .save_pre_key(id.into(), &PreKeyRecord::new(id.into(), &key_pair))
+                .await?;
+            uploads.push(PreKeyUpload {
+                key_id: id,
+                public_key: key_pair.public_key.serialize().into_vec(),
+            });
+        }
+        Ok(uploads)
+    }
+
+    /// Generates and stores `count` one-time Kyber pre-keys.
+    pub async fn generate_kyber_pre_keys<R: Rng + CryptoRng>(
+        &mut self,
+        store: &mut dyn ProtocolStore,
+        count: u32,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<Vec<SignedPreKeyUpload>> {
+        let identity_key_pair = store.get_identity_key_pair().await?;
+        let mut uploads = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let id = self.allocate_kyber_pre_key_id(store).await?;
+            let record = self.generate_kyber_record(
+                &identity_key_pair,
+                id,
+                KyberPreKeyKind::OneTime,
+                now,
+                csprng,
+            )?;
+            store.save_kyber_pre_key(id.into(), &record).await?;
+            uploads.push((&record).into());
+        }
+        Ok(uploads)
+    }
+
+    /// Forgets retired keys older than the retention period, returning them for deletion.
+    pub fn take_stale_pre_keys(&mut self, now: SystemTime) -> StalePreKeys {
+        let cutoff =
+            epoch_millis(now).saturating_sub(duration_millis(self.config.retired_key_retention));
+        let mut take_stale = |retired: &mut Vec<RetiredKey>| {
+            let (stale, kept) = std::mem::take(retired)
+                .into_iter()
+                .partition::<Vec<_>, _>(|key| key.retired_at <= cutoff);
+            *retired = kept;
+            stale.into_iter().map(|key| key.id).collect::<Vec<_>>()
+        };
+
+        let signed_pre_keys = take_stale(&mut self.state.retired_signed_pre_keys);
+        let kyber_pre_keys = take_stale(&mut self.state.retired_last_resort_kyber_pre_keys);
+
+        StalePreKeys {
+            signed_pre_keys: signed_pre_keys.into_iter().map(Into::into).collect(),
+            kyber_pre_keys: kyber_pre_keys.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    fn generate_kyber_record<R: Rng + CryptoRng>(
+        &self,
+        identity_key_pair: &IdentityKeyPair,
+        id: u32,
+        kind: KyberPreKeyKind,
+        now: SystemTime,
+        csprng: &mut R,
+    ) -> Result<KyberPreKeyRecord> {
+        let key_pair = kem::KeyPair::generate(self.config.kyber_key_type, csprng);
+        let signature = identity_key_pair
+            .private_key()
+            .calculate_signature(&key_pair.public_key.serialize(), csprng)?;
+        Ok(KyberPreKeyRecord::new(
+            id.into(),
+            Timestamp::from_epoch_millis(epoch_millis(now)),
+            &key_pair,
+            &signature,
+        )
+        .with_kind(kind))
+    }
+
+    async fn allocate_pre_key_id(&mut self, store: &dyn ProtocolStore) -> Result<u32> {
+        for _ in 0..MAX_PRE_KEY_ID {
+            let id = self.state.next_pre_key_id;
+            self.state.next_pre_key_id = next_id(id);
+            // After wrapping around, skip any keys that were never used.
+            match store.get_pre_key(PreKeyId::from(id)).await {
+                Err(SignalProtocolError::InvalidPreKeyId) => return Ok(id),
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(SignalProtocolError::PreKeyIdsExhausted)
+    }
+
+    fn allocate_signed_pre_key_id(&mut self) -> Result<u32> {
+        let state = &mut self.state;
+        for _ in 0..MAX_PRE_KEY_ID {
+            let id = state.next_signed_pre_key_id;
+            state.next_signed_pre_key_id = next_id(id);
+            if id != state.active_signed_pre_key_id
+                && !state.retired_signed_pre_keys.iter().any(|key| key.id == id)
+            {
+                return Ok(id);
+            }
+        }
+        Err(SignalProtocolError::PreKeyIdsExhausted)
+    }
+
+    async fn allocate_kyber_pre_key_id(&mut self, store: &dyn ProtocolStore) -> Result<u32> {
+        for _ in 0..MAX_PRE_KEY_ID {
+            let id = self.state.next_kyber_pre_key_id;
+            self.state.next_kyber_pre_key_id = next_id(id);
+            // Skip one-time keys that were never used, as well as active and retired
+            // last-resort keys.
+            match store.get_kyber_pre_key(KyberPreKeyId::from(id)).await {
+                Err(SignalProtocolError::InvalidKyberPreKeyId) => return Ok(id),
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(SignalProtocolError::PreKeyIdsExhausted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures_util::FutureExt;
+    use rand::TryRngCore as _;
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::InMemSignalProtocolStore;
+
+    #[test]
+    fn ids_wrap_around_and_skip_zero() {
+        assert_eq!(next_id(1), 2);
+        assert_eq!(next_id(MAX_PRE_KEY_ID - 1), MAX_PRE_KEY_ID);
+        assert_eq!(next_id(MAX_PRE_KEY_ID), 1);
+    }
+
+    #[test]
+    fn times_before_the_epoch_saturate() {
+        assert_eq!(
+            epoch_millis(SystemTime::UNIX_EPOCH - Duration::from_secs(1)),
+            0
+        );
+        assert_eq!(epoch_millis(SystemTime::UNIX_EPOCH), 0);
+    }
+
+    #[test]
+    fn allocation_skips_outstanding_keys_after_wrapping() {
+        async {
+            let mut csprng = OsRng.unwrap_err();
+            let mut store =
+                InMemSignalProtocolStore::new(IdentityKeyPair::generate(&mut csprng), 1)
+                    .expect("can create store");
+            let mut manager = PreKeyManager::new(PreKeyManagerConfig::default(), &mut csprng);
+
+            // Pretend ID 1 is still outstanding from before the wrap.
+            store
+                .save_pre_key(
+                    1.into(),
+                    &PreKeyRecord::new(1.into(), &KeyPair::generate(&mut csprng)),
+                )
+                .await
+                .expect("can save");
+            manager.state.next_pre_key_id = MAX_PRE_KEY_ID;
+
+            let uploads = manager
+                .generate_pre_keys(&mut store, 2, &mut csprng)
+                .await
+                .expect("can generate");
+            let ids: Vec<u32> = uploads.iter().map(|upload| upload.key_id).collect();
+            assert_eq!(ids, [MAX_PRE_KEY_ID, 2]);
+
+            // Likewise for a last-resort Kyber pre-key with ID 1.
+            manager.state.next_kyber_pre_key_id = 1;
+            manager
+                .rotate_last_resort_kyber_pre_key(&mut store, SystemTime::now(), &mut csprng)
+                .await
+                .expect("can rotate");
+            manager.state.next_kyber_pre_key_id = MAX_PRE_KEY_ID;
+            let uploads = manager
+                .generate_kyber_pre_keys(&mut store, 2, SystemTime::now(), &mut csprng)
+                .await
+                .expect("can generate");
+            let ids: Vec<u32> = uploads.iter().map(|upload| upload.key_id).collect();
+            assert_eq!(ids, [MAX_PRE_KEY_ID, 2]);
+        }
+        .now_or_never()
+        .expect("sync")
+    }
+}
@@ -8,7 +8,7 @@ mod params;
 
 use rand::{CryptoRng, Rng};
 
-pub(crate) use self::keys::{ChainKey, MessageKeyGenerator, RootKey};
+pub(crate) use self::keys::{ChainKey, MessageKeyGenerator, MessageKeys, RootKey};
 pub use self::params::{AliceSignalProtocolParameters, BobSignalProtocolParameters, UsePQRatchet};
 use crate::protocol::CIPHERTEXT_MESSAGE_CURRENT_VERSION;
 use crate::state::SessionState;
@@ -51,6 +51,14 @@ fn spqr_chain_params(self_connection: bool) -> spqr::ChainParams {
     }
 }
 
+fn spqr_min_version(use_pq_ratchet: UsePQRatchet) -> spqr::Version {
+    match use_pq_ratchet {
+        // Allow fallback to no PQR at all while there are clients that don't speak PQR.
+        UsePQRatchet::No | UsePQRatchet::Yes => spqr::Version::V0,
+        UsePQRatchet::Required => spqr::Version::V1,
+    }
+}
+
 pub(crate) fn initialize_alice_session<R: Rng + CryptoRng>(
     parameters: &AliceSignalProtocolParameters,
     mut csprng: &mut R,
@@ -99,24 +107,22 @@ pub(crate) fn initialize_alice_session<R: Rng + CryptoRng>(
 
     let self_session = local_identity == parameters.their_identity_key();
     let pqr_state = match parameters.use_pq_ratchet() {
-        UsePQRatchet::Yes => spqr::initial_state(spqr::Params {
-            auth_key: &pqr_key,
-            version: spqr::Version::V1,
-            direction: spqr::Direction::A2B,
-            // Set min_version to V0 (allow fallback to no PQR at all) while
-            // there are clients that don't speak PQR.  Once all clients speak
-            // PQR, we can up this to V1 to require that all subsequent sessions
-            // use at least V1.
-            min_version: spqr::Version::V0,
-            chain_params: spqr_chain_params(self_session),
-        })
-        .map_err(|e| {
-            // Since this is an error associated with the initial creation of the state,
-            // it must be a problem with the arguments provided.
-            SignalProtocolError::InvalidArgument(format!(
-                "post-quantum ratchet: error creating initial A2B state: {e}"
-            ))
-        })?,
+        use_pq_ratchet @ (UsePQRatchet::Yes | UsePQRatchet::Required) => {
+            spqr::initial_state(spqr::Params {
+                auth_key: &pqr_key,
+                version: spqr::Version::V1,
+                direction: spqr::Direction::A2B,
+                min_version: spqr_min_version(use_pq_ratchet),
+                chain_params: spqr_chain_params(self_session),
+            })
+            .map_err(|e| {
+                // Since this is an error associated with the initial creation of the state,
+                // it must be a problem with the arguments provided.
+                SignalProtocolError::InvalidArgument(format!(
+                    "post-quantum ratchet: error creating initial A2B state: {e}"
+                ))
+            })?
+        }
         UsePQRatchet::No => spqr::SerializedState::new(), // empty
     };
 
@@ -127,6 +133,7 @@ pub(crate) fn initialize_alice_session<R: Rng + CryptoRng>(
         &sending_chain_root_key,
         &parameters.our_base_key_pair().public_key,
         pqr_state,
+        parameters.use_pq_ratchet(),
     )
     .with_receiver_chain(parameters.their_ratchet_key(), &chain_key)
     .with_sender_chain(&sending_ratchet_key, &sending_chain_chain_key);
@@ -193,24 +200,22 @@ pub(crate) fn initialize_bob_session(
 
     let self_session = local_identity == parameters.their_identity_key();
     let pqr_state = match parameters.use_pq_ratchet() {
-        UsePQRatchet::Yes => spqr::initial_state(spqr::Params {
-            auth_key: &pqr_key,
-            version: spqr::Version::V1,
-            direction: spqr::Direction::B2A,
-            // Set min_version to V0 (allow fallback to no PQR at all) while
-            // there are clients that don't speak PQR.  Once all clients speak
-            // PQR, we can up this to V1 to require that all subsequent sessions
-            // use at least V1.
-            min_version: spqr::Version::V0,
-            chain_params: spqr_chain_params(self_session),
-        })
-        .map_err(|e| {
-            // Since this is an error associated with the initial creation of the state,
-            // it must be a problem with the arguments provided.
-            SignalProtocolError::InvalidArgument(format!(
-                "post-quantum ratchet: error creating initial B2A state: {e}"
-            ))
-        })?,
+        use_pq_ratchet @ (UsePQRatchet::Yes | UsePQRatchet::Required) => {
+            spqr::initial_state(spqr::Params {
+                auth_key: &pqr_key,
+                version: spqr::Version::V1,
+                direction: spqr::Direction::B2A,
+                min_version: spqr_min_version(use_pq_ratchet),
+                chain_params: spqr_chain_params(self_session),
+            })
+            .map_err(|e| {
+                // Since this is an error associated with the initial creation of the state,
+                // it must be a problem with the arguments provided.
+                SignalProtocolError::InvalidArgument(format!(
+                    "post-quantum ratchet: error creating initial B2A state: {e}"
+                ))
+            })?
+        }
         UsePQRatchet::No => spqr::SerializedState::new(), // empty
     };
     let session = SessionState::new(
@@ -220,6 +225,7 @@ pub(crate) fn initialize_bob_session(
         &root_key,
         parameters.their_base_key(),
         pqr_state,
+        parameters.use_pq_ratchet(),
     )
     .with_sender_chain(parameters.our_ratchet_key_pair(), &chain_key);
 
@@ -5,10 +5,30 @@
 
 use crate::{IdentityKey, IdentityKeyPair, KeyPair, PublicKey, kem};
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// Whether new sessions use the post-quantum ratchet (SPQR).
+///
+/// The policy is fixed when a session is created, by [`process_prekey_bundle`] on the initiating
+/// side or by [`message_decrypt`] for an incoming pre-key message, and is enforced for every
+/// message decrypted with that session afterwards.
+///
+/// The discriminants are passed across the app bridges, so they must not change.
+///
+/// [`process_prekey_bundle`]: crate::process_prekey_bundle
+/// [`message_decrypt`]: crate::message_decrypt
+#[derive(Clone, Copy, PartialEq, Eq, Debug, derive_more::TryFrom)]
+#[repr(u8)]
+#[try_from(repr)]
 pub enum UsePQRatchet {
-    No,
-    Yes,
+    /// Don't offer the post-quantum ratchet.
+    No = 0,
+    /// Offer the post-quantum ratchet, but fall back to the classical ratchet if the peer doesn't
+    /// use it.
+    ///
+    /// Once the peer has used it, it may not stop; see
+    /// [`SignalProtocolError::PqRatchetDowngrade`](crate::SignalProtocolError::PqRatchetDowngrade).
+    Yes = 1,
+    /// Require the post-quantum ratchet; messages from a peer that doesn't use it are rejected.
+    Required = 2,
 }
 
 impl From<bool> for UsePQRatchet {
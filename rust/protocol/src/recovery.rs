@@ -0,0 +1,88 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Deciding how to respond to a [`DecryptionErrorMessage`].
+//!
+//! When a peer can't decrypt something we sent, they reply with a [`DecryptionErrorMessage`]
+//! naming the message's timestamp and, for 1:1 messages, the ratchet key it was sent with. If
+//! that ratchet key is still our current one, the session itself is probably broken on their end
+//! and must be archived so that the next message starts a new one. If it is not, the session has
+//! already moved on, and at most the message itself needs to be sent again.
+
+use crate::{
+    DecryptionErrorMessage, DeviceId, ProtocolAddress, Result, SentMessage, SentMessageLogStore,
+    SessionRecord,
+};
+
+/// Why a [`DecryptionErrorMessage`] needs no response.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, displaydoc::Display)]
+pub enum DecryptionErrorIgnoreReason {
+    /// the failed message was sent by a different device
+    OtherDevice,
+    /// the failed message used a ratchet key that has since been replaced, and is no longer logged
+    StaleRatchetKey,
+    /// the failed message is no longer logged
+    NotLogged,
+}
+
+/// What to do in response to a [`DecryptionErrorMessage`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecryptionErrorAction {
+    /// The failed message was sent with the current session, so it can't be trusted to work.
+    ///
+    /// Archive the session, then resend `message` if it was still logged. If not, sending any
+    /// message (such as a null message) is enough to get a new session established.
+    ArchiveSessionAndResend { message: Option<SentMessage> },
+    /// The session is fine, but the message itself was lost; resend `message`.
+    ///
+    /// This covers messages sent with a ratchet key that has since been replaced, and sender key
+    /// messages, which don't involve the 1:1 session at all.
+    Resend { message: SentMessage },
+    /// Nothing needs to be done.
+    Ignore(DecryptionErrorIgnoreReason),
+}
+
+/// Decides how to respond to `error`, received from `sender`.
+///
+/// `session` is the current session with `sender`, if any, and `local_device_id` is this
+/// device's ID; errors about messages from other devices of ours are ignored. Does not modify
+/// anything, so the caller is responsible for archiving the session if requested.
+pub async fn decryption_error_action(
+    error: &DecryptionErrorMessage,
+    sender: &ProtocolAddress,
+    local_device_id: DeviceId,
+    session: Option<&SessionRecord>,
+    sent_messages: &dyn SentMessageLogStore,
+) -> Result<DecryptionErrorAction> {
+    if error.device_id() != u32::from(local_device_id) {
+        return Ok(DecryptionErrorAction::Ignore(
+            DecryptionErrorIgnoreReason::OtherDevice,
+        ));
+    }
+
+    let message = sent_messages
+        .load_sent_message(sender, error.timestamp())
+        .await?;
+
+    let Some(ratchet_key) = error.ratchet_key() else {
+        // A sender key message; the 1:1 session wasn't involved.
+        return Ok(match message {
+            Some(message) => DecryptionErrorAction::Resend { message },
+            None => DecryptionErrorAction::Ignore(DecryptionErrorIgnoreReason::NotLogged),
+        });
+    };
+
+    let is_current_session = match session {
+        Some(session) => session.current_ratchet_key_matches(ratchet_key)?,
+        None => false,
+    };
+    Ok(match (is_current_session, message) {
+        (true, message) => DecryptionErrorAction::ArchiveSessionAndResend { message },
+        (false, Some(message)) => DecryptionErrorAction::Resend { message },
+        (false, None) => {
+            DecryptionErrorAction::Ignore(DecryptionErrorIgnoreReason::StaleRatchetKey)
+        }
+    })
+}
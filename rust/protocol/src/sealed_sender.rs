@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: AGPL-3.0-only
 //
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
 use std::sync::LazyLock;
 use std::time::SystemTime;
@@ -15,17 +15,21 @@ use itertools::Itertools;
 use prost::Message;
 use proto::sealed_sender::unidentified_sender_message::message::Type as ProtoMessageType;
 use rand::{CryptoRng, Rng, TryRngCore as _};
+use sha2::{Digest, Sha256};
 use subtle::{Choice, ConstantTimeEq};
 use zerocopy::{FromBytes, Immutable, KnownLayout};
 
 use crate::{
     Aci, CiphertextMessageType, DeviceId, Direction, IdentityKey, IdentityKeyPair,
     IdentityKeyStore, KeyPair, KyberPreKeyStore, PreKeySignalMessage, PreKeyStore, PrivateKey,
-    ProtocolAddress, PublicKey, Result, ServiceId, ServiceIdFixedWidthBinaryBytes, SessionRecord,
-    SessionStore, SignalMessage, SignalProtocolError, SignedPreKeyStore, Timestamp, crypto,
-    message_encrypt, proto, ratchet, session_cipher,
+    ProtocolAddress, PublicKey, Result, SentMessage, SentMessageLogStore, ServiceId,
+    ServiceIdFixedWidthBinaryBytes, SessionConfig, SessionRecord, SessionStore, SignalMessage,
+    SignalProtocolError, SignedPreKeyStore, Timestamp, crypto, message_encrypt, proto, ratchet,
+    session_cipher,
 };
 
+pub mod server;
+
 #[derive(Debug, Clone)]
 pub struct ServerCertificate {
     serialized: Vec<u8>,
@@ -45,6 +49,80 @@ with this test certificate ID, Bad Things will happen.
 */
 const REVOKED_SERVER_CERTIFICATE_KEY_IDS: &[u32] = &[0xDEADC357];
 
+/// Server and sender certificates that should no longer be accepted.
+///
+/// The [default](Self::default) set contains the server certificates this crate already knows to
+/// be revoked; start from [`empty`](Self::empty) to leave those out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertificateRevocationSet {
+    server_certificate_key_ids: HashSet<u32>,
+    sender_certificate_serials: HashSet<[u8; 32]>,
+}
+
+impl CertificateRevocationSet {
+    /// A set with nothing revoked.
+    pub fn empty() -> Self {
+        Self {
+            server_certificate_key_ids: HashSet::new(),
+            sender_certificate_serials: HashSet::new(),
+        }
+    }
+
+    /// Reject server certificates with `key_id`, and any sender certificates they signed.
+    pub fn revoke_server_certificate(&mut self, key_id: u32) {
+        self.server_certificate_key_ids.insert(key_id);
+    }
+
+    /// Reject the sender certificate with the given
+    /// [serial number](SenderCertificate::serial_number).
+    pub fn revoke_sender_certificate(&mut self, serial_number: [u8; 32]) {
+        self.sender_certificate_serials.insert(serial_number);
+    }
+
+    /// Returns `true` if server certificates with `key_id` have been revoked.
+    pub fn is_server_certificate_revoked(&self, key_id: u32) -> bool {
+        self.server_certificate_key_ids.contains(&key_id)
+    }
+
+    /// Returns `true` if `certificate` itself has been revoked.
+    ///
+    /// This does not check the server certificate that signed it.
+    pub fn is_sender_certificate_revoked(&self, certificate: &SenderCertificate) -> bool {
+        self.sender_certificate_serials
+            .contains(&certificate.serial_number())
+    }
+}
+
+impl Default for CertificateRevocationSet {
+    fn default() -> Self {
+        let mut result = Self::empty();
+        result
+            .server_certificate_key_ids
+            .extend(REVOKED_SERVER_CERTIFICATE_KEY_IDS);
+        result
+    }
+}
+
+static DEFAULT_REVOCATIONS: LazyLock<CertificateRevocationSet> =
+    LazyLock::new(CertificateRevocationSet::default);
+
+/// Why a sender or server certificate was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, displaydoc::Display, thiserror::Error)]
+pub enum CertificateValidationError {
+    /// server certificate {0:#x} has been revoked
+    RevokedServerCertificate(u32),
+    /// server certificate is not signed by any trust root
+    UntrustedServerCertificate,
+    /// sender certificate refers to an unknown server certificate
+    UnknownServerCertificate,
+    /// sender certificate is not signed by its server certificate
+    InvalidSignature,
+    /// sender certificate expired at {0:?}
+    Expired(Timestamp),
+    /// sender certificate has been revoked
+    RevokedSenderCertificate,
+}
+
 /// A set of server certificates that can be omitted from sender certificates for space savings,
 /// keyed by ID.
 ///
@@ -156,14 +234,31 @@ impl ServerCertificate {
     }
 
     pub fn validate(&self, trust_root: &PublicKey) -> Result<bool> {
-        if REVOKED_SERVER_CERTIFICATE_KEY_IDS.contains(&self.key_id()?) {
+        Ok(self
+            .validate_with_revocations(trust_root, &DEFAULT_REVOCATIONS)
+            .is_ok())
+    }
+
+    /// Like [`validate`](Self::validate), but checks `revocations` instead of the built-in list,
+    /// and reports why the certificate was rejected.
+    pub fn validate_with_revocations(
+        &self,
+        trust_root: &PublicKey,
+        revocations: &CertificateRevocationSet,
+    ) -> std::result::Result<(), CertificateValidationError> {
+        if revocations.is_server_certificate_revoked(self.key_id) {
             log::error!(
                 "received server certificate with revoked ID {:x}",
-                self.key_id()?
+                self.key_id
             );
-            return Ok(false);
+            return Err(CertificateValidationError::RevokedServerCertificate(
+                self.key_id,
+            ));
+        }
+        if !trust_root.verify_signature(&self.certificate, &self.signature) {
+            return Err(CertificateValidationError::UntrustedServerCertificate);
         }
-        Ok(trust_root.verify_signature(&self.certificate, &self.signature))
+        Ok(())
     }
 
     pub fn key_id(&self) -> Result<u32> {
@@ -333,27 +428,54 @@ impl SenderCertificate {
         trust_roots: &[&PublicKey],
         validation_time: Timestamp,
     ) -> Result<bool> {
-        let signer = self.signer()?;
+        // Unknown server certificate IDs are reported as errors here, for compatibility.
+        let _ = self.signer()?;
+        Ok(self
+            .validate_with_revocations(trust_roots, validation_time, &DEFAULT_REVOCATIONS)
+            .is_ok())
+    }
+
+    /// Like [`validate_with_trust_roots`](Self::validate_with_trust_roots), but checks
+    /// `revocations` instead of the built-in list, and reports why the certificate was rejected.
+    pub fn validate_with_revocations(
+        &self,
+        trust_roots: &[&PublicKey],
+        validation_time: Timestamp,
+        revocations: &CertificateRevocationSet,
+    ) -> std::result::Result<(), CertificateValidationError> {
+        let signer = self
+            .signer()
+            .map_err(|_| CertificateValidationError::UnknownServerCertificate)?;
+
+        if revocations.is_server_certificate_revoked(signer.key_id) {
+            log::error!(
+                "received server certificate with revoked ID {:x}",
+                signer.key_id
+            );
+            return Err(CertificateValidationError::RevokedServerCertificate(
+                signer.key_id,
+            ));
+        }
 
         // Check the signer against every trust root to hide which one was the correct one.
         let mut any_valid = Choice::from(0u8);
         for root in trust_roots {
-            let ok = signer.validate(root)?;
+            let ok = root.verify_signature(&signer.certificate, &signer.signature);
             any_valid |= Choice::from(u8::from(ok));
         }
         if !bool::from(any_valid) {
             log::error!(
                 "sender certificate contained server certificate that wasn't signed by any trust root"
             );
-            return Ok(false);
+            return Err(CertificateValidationError::UntrustedServerCertificate);
         }
 
         if !signer
-            .public_key()?
+            .key
             .verify_signature(&self.certificate, &self.signature)
         {
             log::error!("sender certificate not signed by server");
-            return Ok(false);
+            return Err(CertificateValidationError::InvalidSignature);
         }
 
         if validation_time > self.expiration {
@@ -362,10 +484,23 @@ impl SenderCertificate {
                 self.expiration.epoch_millis(),
                 validation_time.epoch_millis()
             );
-            return Ok(false);
+            return Err(CertificateValidationError::Expired(self.expiration));
         }
 
-        Ok(true)
+        if revocations.is_sender_certificate_revoked(self) {
+            log::error!("sender certificate has been revoked");
+            return Err(CertificateValidationError::RevokedSenderCertificate);
+        }
+
+        Ok(())
+    }
+
+    /// A stable identifier for this certificate, for use with [`CertificateRevocationSet`].
+    ///
+    /// Sender certificates have no serial number of their own, so this is the SHA-256 digest of
+    /// the signed certificate contents.
+    pub fn serial_number(&self) -> [u8; 32] {
+        Sha256::digest(&self.certificate).into()
     }
 
     pub fn signer(&self) -> Result<&ServerCertificate> {
@@ -426,6 +561,90 @@ impl SenderCertificate {
     }
 }
 
+/// Issues sender certificates the way the service does, for use by test servers.
+///
+/// Sender certificates are signed by an intermediate [`ServerCertificate`], which is in turn
+/// signed by the trust root. The intermediate can be [rotated](Self::rotate_server_certificate)
+/// without changing the trust root that clients are configured with.
+pub struct SenderCertificateIssuer {
+    trust_root: KeyPair,
+    server_key: KeyPair,
+    server_certificate: ServerCertificate,
+}
+
+impl SenderCertificateIssuer {
+    /// Create an issuer with a new server certificate, with ID `key_id`, signed by `trust_root`.
+    pub fn new<R: Rng + CryptoRng>(trust_root: KeyPair, key_id: u32, rng: &mut R) -> Result<Self> {
+        let server_key = KeyPair::generate(rng);
+        let server_certificate =
+            ServerCertificate::new(key_id, server_key.public_key, &trust_root.private_key, rng)?;
+        Ok(Self {
+            trust_root,
+            server_key,
+            server_certificate,
+        })
+    }
+
+    /// The public half of the trust root, which clients validate against.
+    pub fn trust_root(&self) -> &PublicKey {
+        &self.trust_root.public_key
+    }
+
+    /// The server certificate currently used to sign sender certificates.
+    pub fn server_certificate(&self) -> &ServerCertificate {
+        &self.server_certificate
+    }
+
+    /// Replace the server certificate with a new one, using the next key ID, and return the old
+    /// one.
+    ///
+    /// Certificates issued before the rotation remain valid unless the returned certificate is
+    /// revoked with [`CertificateRevocationSet::revoke_server_certificate`].
+    pub fn rotate_server_certificate<R: Rng + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<ServerCertificate> {
+        let mut key_id = self.server_certificate.key_id.wrapping_add(1);
+        while REVOKED_SERVER_CERTIFICATE_KEY_IDS.contains(&key_id) {
+            key_id = key_id.wrapping_add(1);
+        }
+        let server_key = KeyPair::generate(rng);
+        let server_certificate = ServerCertificate::new(
+            key_id,
+            server_key.public_key,
+            &self.trust_root.private_key,
+            rng,
+        )?;
+        self.server_key = server_key;
+        Ok(std::mem::replace(
+            &mut self.server_certificate,
+            server_certificate,
+        ))
+    }
+
+    /// Issue a sender certificate, signed by the current server certificate.
+    pub fn issue<R: Rng + CryptoRng>(
+        &self,
+        sender_uuid: String,
+        sender_e164: Option<String>,
+        key: PublicKey,
+        sender_device_id: DeviceId,
+        expiration: Timestamp,
+        rng: &mut R,
+    ) -> Result<SenderCertificate> {
+        SenderCertificate::new(
+            sender_uuid,
+            sender_e164,
+            key,
+            sender_device_id,
+            expiration,
+            self.server_certificate.clone(),
+            &self.server_key.private_key,
+            rng,
+        )
+    }
+}
+
 impl From<ProtoMessageType> for CiphertextMessageType {
     fn from(message_type: ProtoMessageType) -> Self {
         let result = match message_type {
@@ -1374,6 +1593,172 @@ where
     .await
 }
 
+/// Like [`sealed_sender_multi_recipient_encrypt`], but also records the message in
+/// `sent_message_log` for every device in `destinations`.
+///
+/// The message is logged only once encryption has succeeded, under `timestamp`, which should be
+/// the timestamp of the message in `usmc`. This is what later allows a resend in response to a
+/// [`DecryptionErrorMessage`](crate::DecryptionErrorMessage) from any one of those devices.
+pub async fn sealed_sender_multi_recipient_encrypt_and_log<
+    R: Rng + CryptoRng,
+    X: IntoIterator<Item = ServiceId>,
+>(
+    destinations: &[&ProtocolAddress],
+    destination_sessions: &[&SessionRecord],
+    excluded_recipients: X,
+    usmc: &UnidentifiedSenderMessageContent,
+    identity_store: &dyn IdentityKeyStore,
+    timestamp: Timestamp,
+    sent_message_log: &mut dyn SentMessageLogStore,
+    rng: &mut R,
+) -> Result<Vec<u8>>
+where
+    X::IntoIter: ExactSizeIterator,
+{
+    let encrypted = sealed_sender_multi_recipient_encrypt_impl(
+        destinations,
+        destination_sessions,
+        excluded_recipients,
+        usmc,
+        identity_store,
+        rng,
+    )
+    .await?;
+    sent_message_log
+        .log_sent_message(
+            destinations,
+            &SentMessage {
+                timestamp,
+                content: usmc.serialized()?.into(),
+            },
+        )
+        .await?;
+    Ok(encrypted)
+}
+
+/// The result of [`sealed_sender_multi_recipient_encrypt_skipping_failures`].
+#[derive(Debug, Default)]
+pub struct SealedSenderMultiRecipientEncryptionResult {
+    /// The multi-recipient message, in the same format as
+    /// [`sealed_sender_multi_recipient_encrypt`], covering every device not listed below.
+    pub message: Vec<u8>,
+    /// Devices with no usable session (none at all, or only archived ones), or no stored identity.
+    ///
+    /// Fetch a pre-key bundle for each of these, pass it to [`process_prekey_bundle`], and then
+    /// retry with just these devices.
+    ///
+    /// [`process_prekey_bundle`]: crate::process_prekey_bundle
+    pub missing_sessions: Vec<ProtocolAddress>,
+    /// Devices whose identity key is not trusted for sending.
+    ///
+    /// These cannot be retried until the identity key store's trust decision changes.
+    pub untrusted_identities: Vec<ProtocolAddress>,
+    /// Devices belonging to one of the excluded recipients.
+    ///
+    /// A recipient can't be both included and excluded in the same message, so these are dropped;
+    /// they do not need to be retried.
+    pub excluded_devices: Vec<ProtocolAddress>,
+}
+
+impl SealedSenderMultiRecipientEncryptionResult {
+    /// Returns `true` if [`message`](Self::message) covers every requested device that can be
+    /// retried.
+    ///
+    /// [`excluded_devices`](Self::excluded_devices) are not counted, since they are dropped on
+    /// purpose.
+    pub fn is_complete(&self) -> bool {
+        self.missing_sessions.is_empty() && self.untrusted_identities.is_empty()
+    }
+}
+
+/// Like [`sealed_sender_multi_recipient_encrypt`], but skips devices that can't be sent to instead
+/// of failing the whole message.
+///
+/// `destination_sessions` may contain `None` for devices with no session. Devices without a usable
+/// session, with an untrusted identity, or belonging to one of `excluded_recipients` are left out
+/// of the message and reported in the returned [`SealedSenderMultiRecipientEncryptionResult`].
+/// Any other error, such as an invalid registration ID, still fails the whole operation.
+///
+/// If every device is skipped, the message only lists the excluded recipients.
+pub async fn sealed_sender_multi_recipient_encrypt_skipping_failures<
+    R: Rng + CryptoRng,
+    X: IntoIterator<Item = ServiceId>,
+>(
+    destinations: &[&ProtocolAddress],
+    destination_sessions: &[Option<&SessionRecord>],
+    excluded_recipients: X,
+    usmc: &UnidentifiedSenderMessageContent,
+    identity_store: &dyn IdentityKeyStore,
+    rng: &mut R,
+) -> Result<SealedSenderMultiRecipientEncryptionResult> {
+    if destinations.len() != destination_sessions.len() {
+        return Err(SignalProtocolError::InvalidArgument(
+            "must have the same number of destination sessions as addresses".to_string(),
+        ));
+    }
+
+    let excluded_recipients: Vec<ServiceId> = excluded_recipients.into_iter().collect();
+    let excluded_names: HashSet<String> = excluded_recipients
+        .iter()
+        .map(ServiceId::service_id_string)
+        .collect();
+    let mut result = SealedSenderMultiRecipientEncryptionResult::default();
+    let mut usable_destinations = Vec::with_capacity(destinations.len());
+    let mut usable_sessions = Vec::with_capacity(destinations.len());
+
+    for (&destination, &session) in destinations.iter().zip(destination_sessions) {
+        if excluded_names.contains(destination.name()) {
+            result.excluded_devices.push(destination.clone());
+            continue;
+        }
+
+        // Like message_encrypt, check trust against the identity the session was established with.
+        let session_identity = match session.and_then(SessionRecord::session_state) {
+            Some(state) => state.remote_identity_key()?,
+            None => None,
+        };
+        let (Some(session), Some(their_identity)) = (session, session_identity) else {
+            result.missing_sessions.push(destination.clone());
+            continue;
+        };
+
+        if !identity_store
+            .is_trusted_identity(destination, &their_identity, Direction::Sending)
+            .await?
+        {
+            result.untrusted_identities.push(destination.clone());
+            continue;
+        }
+
+        // The message itself is encrypted to the stored identity, which must also exist.
+        if identity_store.get_identity(destination).await?.is_none() {
+            result.missing_sessions.push(destination.clone());
+            continue;
+        }
+
+        usable_destinations.push(destination);
+        usable_sessions.push(session);
+    }
+
+    if !result.untrusted_identities.is_empty() {
+        log::warn!(
+            "skipping {} device(s) with untrusted identities",
+            result.untrusted_identities.len()
+        );
+    }
+
+    result.message = sealed_sender_multi_recipient_encrypt_impl(
+        &usable_destinations,
+        &usable_sessions,
+        excluded_recipients,
+        usmc,
+        identity_store,
+        rng,
+    )
+    .await?;
+    Ok(result)
+}
+
 async fn sealed_sender_multi_recipient_encrypt_impl<
     R: Rng + CryptoRng,
     X: IntoIterator<Item = ServiceId>,
@@ -1996,6 +2381,9 @@ impl SealedSenderDecryptionResult {
 /// the embedded [`SenderCertificate`]. The sender certificate (signed by the [`ServerCertificate`])
 /// is then validated against the `trust_root` baked into the client to ensure that the sender's
 /// identity was not forged.
+///
+/// `timestamp` is used both to check the certificate's expiration and as the time the inner
+/// message was received.
 #[expect(clippy::too_many_arguments)]
 pub async fn sealed_sender_decrypt(
     ciphertext: &[u8],
@@ -2019,6 +2407,85 @@ pub async fn sealed_sender_decrypt(
         ));
     }
 
+    decrypt_validated_usmc(
+        usmc,
+        local_e164,
+        local_uuid,
+        local_device_id,
+        identity_store,
+        session_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        use_pq_ratchet,
+        timestamp,
+    )
+    .await
+}
+
+/// Why [`sealed_sender_decrypt_with_revocations`] failed.
+#[derive(Debug, displaydoc::Display, thiserror::Error)]
+pub enum SealedSenderDecryptError {
+    /// sender certificate rejected: {0}
+    CertificateRejected(#[from] CertificateValidationError),
+    /// {0}
+    Protocol(#[from] SignalProtocolError),
+}
+
+/// Like [`sealed_sender_decrypt`], but checks the sender certificate against `revocations` instead
+/// of the built-in list, and reports why it was rejected.
+#[expect(clippy::too_many_arguments)]
+pub async fn sealed_sender_decrypt_with_revocations(
+    ciphertext: &[u8],
+    trust_root: &PublicKey,
+    revocations: &CertificateRevocationSet,
+    timestamp: Timestamp,
+    local_e164: Option<String>,
+    local_uuid: String,
+    local_device_id: DeviceId,
+    identity_store: &mut dyn IdentityKeyStore,
+    session_store: &mut dyn SessionStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    use_pq_ratchet: ratchet::UsePQRatchet,
+) -> std::result::Result<SealedSenderDecryptionResult, SealedSenderDecryptError> {
+    let usmc = sealed_sender_decrypt_to_usmc(ciphertext, identity_store).await?;
+
+    usmc.sender()?
+        .validate_with_revocations(&[trust_root], timestamp, revocations)?;
+
+    Ok(decrypt_validated_usmc(
+        usmc,
+        local_e164,
+        local_uuid,
+        local_device_id,
+        identity_store,
+        session_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        use_pq_ratchet,
+        timestamp,
+    )
+    .await?)
+}
+
+/// Decrypts the message in `usmc`, whose sender certificate has already been validated.
+#[expect(clippy::too_many_arguments)]
+async fn decrypt_validated_usmc(
+    usmc: UnidentifiedSenderMessageContent,
+    local_e164: Option<String>,
+    local_uuid: String,
+    local_device_id: DeviceId,
+    identity_store: &mut dyn IdentityKeyStore,
+    session_store: &mut dyn SessionStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    use_pq_ratchet: ratchet::UsePQRatchet,
+    timestamp: Timestamp,
+) -> Result<SealedSenderDecryptionResult> {
     let is_local_uuid = local_uuid == usmc.sender()?.sender_uuid()?;
 
     let is_local_e164 = match (local_e164, usmc.sender()?.sender_e164()?) {
@@ -2045,7 +2512,9 @@ pub async fn sealed_sender_decrypt(
                 &remote_address,
                 session_store,
                 identity_store,
+                timestamp.into(),
                 &mut rng,
+                &SessionConfig::default(),
             )
             .await?
         }
@@ -2059,8 +2528,10 @@ pub async fn sealed_sender_decrypt(
                 pre_key_store,
                 signed_pre_key_store,
                 kyber_pre_key_store,
+                timestamp.into(),
                 &mut rng,
                 use_pq_ratchet,
+                &SessionConfig::default(),
             )
             .await?
         }
@@ -0,0 +1,249 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Server-side handling of multi-recipient [`SealedSenderV2SentMessage`]s.
+//!
+//! A sender uploads a single SentMessage covering every recipient; the server checks that it
+//! addresses exactly the devices each recipient currently has, and then fans it out as one
+//! ReceivedMessage per device. None of this requires any keys, which is what makes sealed sender
+//! sealed. These operations are enough to stand in for the real service in tests.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use super::SealedSenderV2SentMessage;
+use crate::{DeviceId, ServiceId};
+
+/// The devices registered for each account, as known to the server.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceDirectory {
+    accounts: HashMap<ServiceId, HashMap<DeviceId, u16>>,
+}
+
+impl DeviceDirectory {
+    /// Create an empty directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the devices registered for `service_id`, as `(device ID, registration ID)` pairs.
+    pub fn set_devices(
+        &mut self,
+        service_id: ServiceId,
+        devices: impl IntoIterator<Item = (DeviceId, u16)>,
+    ) {
+        self.accounts
+            .insert(service_id, devices.into_iter().collect());
+    }
+
+    /// Forget `service_id` and all of its devices.
+    pub fn remove_account(&mut self, service_id: &ServiceId) {
+        self.accounts.remove(service_id);
+    }
+
+    /// Returns the registration ID of `device_id` for `service_id`, if it is registered.
+    pub fn registration_id(&self, service_id: &ServiceId, device_id: DeviceId) -> Option<u16> {
+        self.accounts.get(service_id)?.get(&device_id).copied()
+    }
+
+    /// Check that `message` addresses exactly the registered devices of each of its recipients.
+    ///
+    /// Excluded recipients (those listed with no devices) are not checked; nothing is delivered to
+    /// them. Problems are reported in order of severity: duplicate devices first, then unknown
+    /// recipients, then device lists that don't match the directory.
+    pub fn validate(&self, message: &SealedSenderV2SentMessage<'_>) -> Result<(), ValidationError> {
+        if let Some((service_id, device_id)) = find_duplicate_devices(message).into_iter().next() {
+            return Err(ValidationError::DuplicateDevice(service_id, device_id));
+        }
+
+        let mut unknown = Vec::new();
+        let mut mismatched = Vec::new();
+        for (service_id, recipient) in &message.recipients {
+            if recipient.devices.is_empty() {
+                continue;
+            }
+            let Some(registered) = self.accounts.get(service_id) else {
+                unknown.push(*service_id);
+                continue;
+            };
+
+            let mut mismatch = DeviceMismatch {
+                service_id: *service_id,
+                missing_devices: vec![],
+                extra_devices: vec![],
+                stale_devices: vec![],
+            };
+            for &(device_id, registration_id) in &recipient.devices {
+                match registered.get(&device_id) {
+                    None => mismatch.extra_devices.push(device_id),
+                    Some(&expected) if expected != registration_id => {
+                        mismatch.stale_devices.push(device_id)
+                    }
+                    Some(_) => {}
+                }
+            }
+            mismatch.missing_devices = registered
+                .keys()
+                .filter(|device_id| !recipient.devices.iter().any(|(d, _)| d == *device_id))
+                .copied()
+                .sorted()
+                .collect();
+            mismatch.extra_devices.sort();
+            mismatch.stale_devices.sort();
+
+            if !mismatch.is_empty() {
+                mismatched.push(mismatch);
+            }
+        }
+
+        if !unknown.is_empty() {
+            return Err(ValidationError::UnknownRecipients(unknown));
+        }
+        if !mismatched.is_empty() {
+            return Err(ValidationError::MismatchedDevices(mismatched));
+        }
+        Ok(())
+    }
+}
+
+/// How a recipient's devices in a message differ from the [`DeviceDirectory`].
+///
+/// Each list is sorted by device ID.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceMismatch {
+    /// The recipient.
+    pub service_id: ServiceId,
+    /// Registered devices the message leaves out.
+    pub missing_devices: Vec<DeviceId>,
+    /// Devices in the message that aren't registered.
+    pub extra_devices: Vec<DeviceId>,
+    /// Devices whose registration ID in the message doesn't match the registered one, usually
+    /// because the device was re-registered since the sender's session was created.
+    pub stale_devices: Vec<DeviceId>,
+}
+
+impl DeviceMismatch {
+    fn is_empty(&self) -> bool {
+        self.missing_devices.is_empty()
+            && self.extra_devices.is_empty()
+            && self.stale_devices.is_empty()
+    }
+}
+
+/// Reasons [`DeviceDirectory::validate`] rejects a message.
+#[derive(Clone, Debug, PartialEq, Eq, displaydoc::Display, thiserror::Error)]
+pub enum ValidationError {
+    /// recipient {0:?} lists device {1} more than once
+    DuplicateDevice(ServiceId, DeviceId),
+    /// message addresses unregistered recipients
+    UnknownRecipients(Vec<ServiceId>),
+    /// message devices do not match the registered devices
+    MismatchedDevices(Vec<DeviceMismatch>),
+}
+
+/// Returns every `(recipient, device)` pair that appears more than once in `message`, in the order
+/// of their second appearance.
+///
+/// Parsing merges repeated recipients but does not deduplicate their devices, so a device listed
+/// twice would otherwise receive the message twice.
+pub fn find_duplicate_devices(
+    message: &SealedSenderV2SentMessage<'_>,
+) -> Vec<(ServiceId, DeviceId)> {
+    message
+        .recipients
+        .iter()
+        .flat_map(|(service_id, recipient)| {
+            recipient
+                .devices
+                .iter()
+                .enumerate()
+                .filter(move |(i, (device_id, _))| {
+                    recipient.devices[..*i].iter().any(|(d, _)| d == device_id)
+                })
+                .map(move |(_, (device_id, _))| (*service_id, *device_id))
+        })
+        .collect()
+}
+
+/// The delivery cost of one recipient in a [`SealedSenderV2SentMessage`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecipientSize {
+    /// The recipient.
+    pub service_id: ServiceId,
+    /// How many devices the message will be delivered to; zero for excluded recipients.
+    pub device_count: usize,
+    /// The length of the ReceivedMessage each of those devices gets.
+    pub received_message_len: usize,
+}
+
+impl RecipientSize {
+    /// The total number of bytes delivered to this recipient.
+    pub fn total_len(&self) -> usize {
+        self.device_count * self.received_message_len
+    }
+}
+
+/// Returns the delivery cost of each recipient in `message`, in message order.
+pub fn recipient_sizes(message: &SealedSenderV2SentMessage<'_>) -> Vec<RecipientSize> {
+    message
+        .recipients
+        .iter()
+        .map(|(service_id, recipient)| RecipientSize {
+            service_id: *service_id,
+            device_count: recipient.devices.len(),
+            received_message_len: if recipient.devices.is_empty() {
+                0
+            } else {
+                message
+                    .received_message_parts_for_recipient(recipient)
+                    .as_ref()
+                    .iter()
+                    .map(|part| part.len())
+                    .sum()
+            },
+        })
+        .collect()
+}
+
+/// A ReceivedMessage ready to be delivered to one device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceMessage {
+    /// The recipient account.
+    pub service_id: ServiceId,
+    /// The recipient device.
+    pub device_id: DeviceId,
+    /// The registration ID the sender used for this device.
+    pub registration_id: u16,
+    /// The serialized ReceivedMessage, to be passed to
+    /// [`sealed_sender_decrypt`](crate::sealed_sender_decrypt) by the recipient.
+    pub message: Vec<u8>,
+}
+
+/// Produces the ReceivedMessage for every device in `message`, in message order.
+///
+/// This does not validate the device lists; use [`DeviceDirectory::validate`] first.
+pub fn device_messages(message: &SealedSenderV2SentMessage<'_>) -> Vec<DeviceMessage> {
+    message
+        .recipients
+        .iter()
+        .flat_map(|(service_id, recipient)| {
+            // Every device of a recipient gets the same bytes.
+            let received = message
+                .received_message_parts_for_recipient(recipient)
+                .as_ref()
+                .concat();
+            recipient
+                .devices
+                .iter()
+                .map(move |&(device_id, registration_id)| DeviceMessage {
+                    service_id: *service_id,
+                    device_id,
+                    registration_id,
+                    message: received.clone(),
+                })
+        })
+        .collect()
+}
@@ -4,13 +4,14 @@
 //
 
 use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
 
 use itertools::Itertools;
 use prost::Message;
 
 use crate::crypto::hmac_sha256;
 use crate::proto::storage as storage_proto;
-use crate::{PrivateKey, PublicKey, SignalProtocolError, consts};
+use crate::{PrivateKey, PublicKey, SessionConfig, SignalProtocolError, consts};
 
 /// A distinct error type to keep from accidentally propagating deserialization errors.
 #[derive(Debug)]
@@ -158,6 +159,8 @@ impl SenderKeyState {
                 },
             ),
             sender_message_keys: vec![],
+            created_at: 0,
+            membership_digest: vec![],
         };
 
         Self { state }
@@ -208,16 +211,49 @@ impl SenderKeyState {
         }
     }
 
+    pub(crate) fn created_at(&self) -> Option<SystemTime> {
+        match self.state.created_at {
+            0 => None,
+            secs => Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+
+    pub(crate) fn set_created_at(&mut self, created_at: SystemTime) {
+        self.state.created_at = created_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+    }
+
+    pub(crate) fn membership_digest(&self) -> Option<&[u8]> {
+        if self.state.membership_digest.is_empty() {
+            None
+        } else {
+            Some(&self.state.membership_digest)
+        }
+    }
+
+    pub(crate) fn set_membership_digest(&mut self, digest: &[u8]) {
+        self.state.membership_digest = digest.to_vec();
+    }
+
     pub(crate) fn as_protobuf(&self) -> storage_proto::SenderKeyStateStructure {
         self.state.clone()
     }
 
-    pub(crate) fn add_sender_message_key(&mut self, sender_message_key: &SenderMessageKey) {
+    pub(crate) fn add_sender_message_key(
+        &mut self,
+        sender_message_key: &SenderMessageKey,
+        config: &SessionConfig,
+    ) {
         self.state
             .sender_message_keys
             .push(sender_message_key.as_protobuf());
-        while self.state.sender_message_keys.len() > consts::MAX_MESSAGE_KEYS {
-            self.state.sender_message_keys.remove(0);
+        let key_count = self.state.sender_message_keys.len();
+        if key_count > config.max_message_keys {
+            self.state
+                .sender_message_keys
+                .drain(..key_count - config.max_message_keys);
         }
     }
 
@@ -299,6 +335,7 @@ impl SenderKeyRecord {
         chain_key: &[u8],
         signature_key: PublicKey,
         signature_private_key: Option<PrivateKey>,
+        config: &SessionConfig,
     ) {
         let existing_state = self.remove_state(chain_id, signature_key);
 
@@ -320,9 +357,7 @@ impl SenderKeyRecord {
             Some(state) => state,
         };
 
-        while self.states.len() >= consts::MAX_SENDER_KEY_STATES {
-            self.states.pop_back();
-        }
+        self.states.truncate(config.max_sender_key_states - 1);
 
         self.states.push_front(state);
     }
@@ -395,8 +430,15 @@ mod sender_key_record_add_sender_key_state_tests {
         /// method under test in this module.
         fn add_sender_key_state_record(&mut self, record_key: (PublicKey, u32), chain_key: &[u8]) {
             let (public_key, chain_id) = record_key;
-            self.sender_key_record
-                .add_sender_key_state(1, chain_id, 1, chain_key, public_key, None);
+            self.sender_key_record.add_sender_key_state(
+                1,
+                chain_id,
+                1,
+                chain_key,
+                public_key,
+                None,
+                &SessionConfig::default(),
+            );
         }
 
         fn assert_number_of_states(&self, expected: usize) {
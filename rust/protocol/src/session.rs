@@ -13,8 +13,8 @@ use crate::state::GenericSignedPreKey;
 use crate::{
     CiphertextMessageType, Direction, IdentityKey, IdentityKeyStore, KeyPair, KyberPreKeyId,
     KyberPreKeyStore, PreKeyBundle, PreKeyId, PreKeySignalMessage, PreKeyStore, ProtocolAddress,
-    Result, SessionRecord, SessionStore, SignalProtocolError, SignedPreKeyId, SignedPreKeyStore,
-    ratchet,
+    Result, SessionConfig, SessionRecord, SessionStore, SignalProtocolError, SignedPreKeyId,
+    SignedPreKeyStore, ratchet,
 };
 
 pub struct PreKeysUsed {
@@ -43,6 +43,7 @@ its reference to the various data stores, instead the functions are
 free standing.
  */
 
+#[expect(clippy::too_many_arguments)]
 pub async fn process_prekey<'a>(
     message: &'a PreKeySignalMessage,
     remote_address: &'a ProtocolAddress,
@@ -52,6 +53,35 @@ pub async fn process_prekey<'a>(
     signed_prekey_store: &dyn SignedPreKeyStore,
     kyber_prekey_store: &dyn KyberPreKeyStore,
     use_pq_ratchet: ratchet::UsePQRatchet,
+    now: SystemTime,
+) -> Result<(Option<PreKeysUsed>, IdentityToSave<'a>)> {
+    process_prekey_with_config(
+        message,
+        remote_address,
+        session_record,
+        identity_store,
+        pre_key_store,
+        signed_prekey_store,
+        kyber_prekey_store,
+        use_pq_ratchet,
+        now,
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+#[expect(clippy::too_many_arguments)]
+pub(crate) async fn process_prekey_with_config<'a>(
+    message: &'a PreKeySignalMessage,
+    remote_address: &'a ProtocolAddress,
+    session_record: &mut SessionRecord,
+    identity_store: &dyn IdentityKeyStore,
+    pre_key_store: &dyn PreKeyStore,
+    signed_prekey_store: &dyn SignedPreKeyStore,
+    kyber_prekey_store: &dyn KyberPreKeyStore,
+    use_pq_ratchet: ratchet::UsePQRatchet,
+    now: SystemTime,
+    config: &SessionConfig,
 ) -> Result<(Option<PreKeysUsed>, IdentityToSave<'a>)> {
     let their_identity_key = message.identity_key();
 
@@ -73,6 +103,8 @@ pub async fn process_prekey<'a>(
         pre_key_store,
         identity_store,
         use_pq_ratchet,
+        now,
+        config,
     )
     .await?;
 
@@ -84,6 +116,7 @@ pub async fn process_prekey<'a>(
     Ok((pre_keys_used, identity_to_save))
 }
 
+#[expect(clippy::too_many_arguments)]
 async fn process_prekey_impl(
     message: &PreKeySignalMessage,
     remote_address: &ProtocolAddress,
@@ -93,10 +126,13 @@ async fn process_prekey_impl(
     pre_key_store: &dyn PreKeyStore,
     identity_store: &dyn IdentityKeyStore,
     use_pq_ratchet: ratchet::UsePQRatchet,
+    now: SystemTime,
+    config: &SessionConfig,
 ) -> Result<Option<PreKeysUsed>> {
     if session_record.promote_matching_session(
         message.message_version() as u32,
         &message.base_key().serialize(),
+        config,
     )? {
         // We've already set up a session for this message, we can exit early.
         return Ok(None);
@@ -160,10 +196,14 @@ async fn process_prekey_impl(
 
     let mut new_session = ratchet::initialize_bob_session(&parameters)?;
 
+    // The session is created from the other party's message, so it starts out acknowledged.
+    new_session.set_created_at(now);
+    new_session.set_acknowledged_at(now);
+
     new_session.set_local_registration_id(identity_store.get_local_registration_id().await?);
     new_session.set_remote_registration_id(message.registration_id());
 
-    session_record.promote_state(new_session);
+    session_record.promote_state(new_session, config);
 
     let pre_keys_used = PreKeysUsed {
         one_time_ec_pre_key_id: message.pre_key_id(),
@@ -174,6 +214,30 @@ async fn process_prekey_impl(
 }
 
 pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    bundle: &PreKeyBundle,
+    now: SystemTime,
+    csprng: &mut R,
+    use_pq_ratchet: ratchet::UsePQRatchet,
+) -> Result<()> {
+    process_prekey_bundle_with_config(
+        remote_address,
+        session_store,
+        identity_store,
+        bundle,
+        now,
+        csprng,
+        use_pq_ratchet,
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+/// Like [`process_prekey_bundle`], but keeps at most `config.max_archived_states` previous
+/// sessions in the updated record.
+pub async fn process_prekey_bundle_with_config<R: Rng + CryptoRng>(
     remote_address: &ProtocolAddress,
     session_store: &mut dyn SessionStore,
     identity_store: &mut dyn IdentityKeyStore,
@@ -181,7 +245,10 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
     now: SystemTime,
     mut csprng: &mut R,
     use_pq_ratchet: ratchet::UsePQRatchet,
+    config: &SessionConfig,
 ) -> Result<()> {
+    config.validate()?;
+
     let their_identity_key = bundle.identity_key()?;
 
     if !identity_store
@@ -248,6 +315,7 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         now,
     );
     session.set_unacknowledged_kyber_pre_key_id(bundle.kyber_pre_key_id()?);
+    session.set_created_at(now);
 
     session.set_local_registration_id(identity_store.get_local_registration_id().await?);
     session.set_remote_registration_id(bundle.registration_id()?);
@@ -256,7 +324,7 @@ pub async fn process_prekey_bundle<R: Rng + CryptoRng>(
         .save_identity(remote_address, their_identity_key)
         .await?;
 
-    session_record.promote_state(session);
+    session_record.promote_state(session, config);
 
     session_store
         .store_session(remote_address, &session_record)
@@ -5,15 +5,18 @@
 
 use std::time::SystemTime;
 
+use itertools::Itertools;
 use rand::{CryptoRng, Rng};
 
-use crate::consts::{MAX_FORWARD_JUMPS, MAX_UNACKNOWLEDGED_SESSION_AGE};
-use crate::ratchet::{ChainKey, MessageKeyGenerator, UsePQRatchet};
-use crate::state::{InvalidSessionError, SessionState};
+use crate::ratchet::{ChainKey, MessageKeyGenerator, MessageKeys, UsePQRatchet};
+use crate::session::PreKeysUsed;
+use crate::state::{InvalidSessionError, PqRatchetStatus, SessionState};
 use crate::{
-    CiphertextMessage, CiphertextMessageType, Direction, IdentityKeyStore, KeyPair, KyberPayload,
-    KyberPreKeyStore, PreKeySignalMessage, PreKeyStore, ProtocolAddress, PublicKey, Result,
-    SessionRecord, SessionStore, SignalMessage, SignalProtocolError, SignedPreKeyStore, session,
+    CiphertextMessage, CiphertextMessageType, DeviceId, Direction, IdentityKey, IdentityKeyStore,
+    KeyPair, KyberPayload, KyberPreKeyStore, PreKeySignalMessage, PreKeyStore, ProtocolAddress,
+    ProtocolStore, ProtocolStoreTransaction, PublicKey, Result, ServiceId, SessionConfig,
+    SessionRecord, SessionStore, SignalMessage, SignalProtocolError, SignedPreKeyStore,
+    TransactionalProtocolStore, session,
 };
 
 pub async fn message_encrypt<R: Rng + CryptoRng>(
@@ -24,6 +27,55 @@ pub async fn message_encrypt<R: Rng + CryptoRng>(
     now: SystemTime,
     csprng: &mut R,
 ) -> Result<CiphertextMessage> {
+    message_encrypt_with_config(
+        ptext,
+        remote_address,
+        session_store,
+        identity_store,
+        now,
+        csprng,
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+/// Like [`message_encrypt`], but treats an unacknowledged session as stale according to `config`
+/// rather than the default limit.
+pub async fn message_encrypt_with_config<R: Rng + CryptoRng>(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<CiphertextMessage> {
+    let (message, _) = message_encrypt_with_message_keys(
+        ptext,
+        remote_address,
+        session_store,
+        identity_store,
+        now,
+        csprng,
+        config,
+    )
+    .await?;
+    Ok(message)
+}
+
+/// Like [`message_encrypt_with_config`], but also returns the message keys `ptext` was encrypted
+/// with.
+pub(crate) async fn message_encrypt_with_message_keys<R: Rng + CryptoRng>(
+    ptext: &[u8],
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<(CiphertextMessage, MessageKeys)> {
+    config.validate()?;
+
     let mut session_record = session_store
         .load_session(remote_address)
         .await?
@@ -71,7 +123,7 @@ pub async fn message_encrypt<R: Rng + CryptoRng>(
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
-        if items.timestamp() + MAX_UNACKNOWLEDGED_SESSION_AGE < now {
+        if items.timestamp() + config.max_unacknowledged_session_age < now {
             log::warn!(
                 "stale unacknowledged session for {remote_address} (created at {timestamp_as_unix_time})"
             );
@@ -155,10 +207,96 @@ pub async fn message_encrypt<R: Rng + CryptoRng>(
     session_store
         .store_session(remote_address, &session_record)
         .await?;
-    Ok(message)
+    Ok((message, message_keys))
+}
+
+/// The result of [`message_encrypt_for_all_devices`].
+#[derive(Debug, Default)]
+pub struct MultiDeviceEncryptionResult {
+    /// Successfully encrypted messages, in the order the devices were requested.
+    pub messages: Vec<(DeviceId, CiphertextMessage)>,
+    /// Devices with no usable session (none at all, or an unacknowledged one that has gone stale).
+    ///
+    /// Fetch a pre-key bundle for each of these, pass it to [`process_prekey_bundle`], and then
+    /// retry.
+    ///
+    /// [`process_prekey_bundle`]: crate::process_prekey_bundle
+    pub missing_sessions: Vec<DeviceId>,
+    /// Devices whose identity key is not trusted for sending.
+    ///
+    /// These cannot be retried until the identity key store's trust decision changes.
+    pub untrusted_identities: Vec<DeviceId>,
+    /// Devices that could not be encrypted for any other reason, with the error for each.
+    pub failed: Vec<(DeviceId, SignalProtocolError)>,
+}
+
+impl MultiDeviceEncryptionResult {
+    /// Returns `true` if a message was produced for every requested device.
+    pub fn is_complete(&self) -> bool {
+        self.missing_sessions.is_empty()
+            && self.untrusted_identities.is_empty()
+            && self.failed.is_empty()
+    }
+}
+
+/// Encrypts `ptext` for each of `device_ids` belonging to `service_id`.
+///
+/// Unlike calling [`message_encrypt_with_config`] in a loop, a failure for one device does not
+/// abort the whole operation: sessions for the other devices are still advanced and stored, so
+/// every message produced is returned along with the failures in the
+/// [`MultiDeviceEncryptionResult`]. Only an invalid `device_ids` list or `config` is reported as an
+/// error.
+pub async fn message_encrypt_for_all_devices<R: Rng + CryptoRng>(
+    ptext: &[u8],
+    service_id: ServiceId,
+    device_ids: &[DeviceId],
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<MultiDeviceEncryptionResult> {
+    config.validate()?;
+
+    if !device_ids.iter().all_unique() {
+        return Err(SignalProtocolError::InvalidArgument(
+            "duplicate device IDs".to_owned(),
+        ));
+    }
+
+    let mut result = MultiDeviceEncryptionResult::default();
+    for &device_id in device_ids {
+        let remote_address = service_id.to_protocol_address(device_id);
+        match message_encrypt_with_config(
+            ptext,
+            &remote_address,
+            session_store,
+            identity_store,
+            now,
+            csprng,
+            config,
+        )
+        .await
+        {
+            Ok(message) => result.messages.push((device_id, message)),
+            Err(SignalProtocolError::SessionNotFound(_)) => result.missing_sessions.push(device_id),
+            Err(SignalProtocolError::UntrustedIdentity(_)) => {
+                result.untrusted_identities.push(device_id)
+            }
+            Err(e) => result.failed.push((device_id, e)),
+        }
+    }
+    Ok(result)
 }
 
-#[allow(clippy::too_many_arguments)]
+/// Decrypts `ciphertext`, which must be a [`SignalMessage`] or a [`PreKeySignalMessage`], and
+/// updates the stores.
+///
+/// `now` is recorded as the time the message was received. `config` governs how far ahead of the
+/// receiving chain a message may be, and how many skipped message keys, receiver chains, and
+/// previous sessions the updated record retains; pass [`SessionConfig::default`] for the standard
+/// limits.
+#[expect(clippy::too_many_arguments)]
 pub async fn message_decrypt<R: Rng + CryptoRng>(
     ciphertext: &CiphertextMessage,
     remote_address: &ProtocolAddress,
@@ -167,15 +305,58 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
     pre_key_store: &mut dyn PreKeyStore,
     signed_pre_key_store: &dyn SignedPreKeyStore,
     kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    now: SystemTime,
     csprng: &mut R,
     use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
 ) -> Result<Vec<u8>> {
+    let (ptext, _) = message_decrypt_with_message_keys(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        now,
+        csprng,
+        use_pq_ratchet,
+        config,
+    )
+    .await?;
+    Ok(ptext)
+}
+
+/// Like [`message_decrypt`], but also returns the message keys the plaintext was decrypted with.
+#[expect(clippy::too_many_arguments)]
+pub(crate) async fn message_decrypt_with_message_keys<R: Rng + CryptoRng>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, MessageKeys)> {
     match ciphertext {
         CiphertextMessage::SignalMessage(m) => {
-            message_decrypt_signal(m, remote_address, session_store, identity_store, csprng).await
+            message_decrypt_signal_with_message_keys(
+                m,
+                remote_address,
+                session_store,
+                identity_store,
+                now,
+                csprng,
+                config,
+            )
+            .await
         }
         CiphertextMessage::PreKeySignalMessage(m) => {
-            message_decrypt_prekey(
+            message_decrypt_prekey_with_message_keys(
                 m,
                 remote_address,
                 session_store,
@@ -183,8 +364,10 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
                 pre_key_store,
                 signed_pre_key_store,
                 kyber_pre_key_store,
+                now,
                 csprng,
                 use_pq_ratchet,
+                config,
             )
             .await
         }
@@ -195,7 +378,8 @@ pub async fn message_decrypt<R: Rng + CryptoRng>(
     }
 }
 
-#[allow(clippy::too_many_arguments)]
+/// Like [`message_decrypt`], for a message already known to be a [`PreKeySignalMessage`].
+#[expect(clippy::too_many_arguments)]
 pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
     ciphertext: &PreKeySignalMessage,
     remote_address: &ProtocolAddress,
@@ -204,16 +388,578 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
     pre_key_store: &mut dyn PreKeyStore,
     signed_pre_key_store: &dyn SignedPreKeyStore,
     kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    now: SystemTime,
     csprng: &mut R,
     use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
 ) -> Result<Vec<u8>> {
+    let (ptext, _) = message_decrypt_prekey_with_message_keys(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        now,
+        csprng,
+        use_pq_ratchet,
+        config,
+    )
+    .await?;
+    Ok(ptext)
+}
+
+#[expect(clippy::too_many_arguments)]
+async fn message_decrypt_prekey_with_message_keys<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, MessageKeys)> {
+    config.validate()?;
+
+    let (ptext, updates) = stage_decrypt_prekey(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        now,
+        csprng,
+        use_pq_ratchet,
+        config,
+    )
+    .await?;
+
+    let message_keys = updates.message_keys;
+    updates
+        .apply(&mut SeparateStores {
+            session_store,
+            identity_store,
+            pre_key_store,
+            kyber_pre_key_store,
+        })
+        .await?;
+
+    Ok((ptext, message_keys))
+}
+
+/// Like [`message_decrypt`], for a message already known to be a [`SignalMessage`].
+pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<Vec<u8>> {
+    let (ptext, _) = message_decrypt_signal_with_message_keys(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        now,
+        csprng,
+        config,
+    )
+    .await?;
+    Ok(ptext)
+}
+
+async fn message_decrypt_signal_with_message_keys<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, MessageKeys)> {
+    config.validate()?;
+
+    let (ptext, updates) = stage_decrypt_signal(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        now,
+        csprng,
+        config,
+    )
+    .await?;
+
+    let message_keys = updates.message_keys;
+    updates
+        .apply_session_and_identity(session_store, identity_store)
+        .await?;
+
+    Ok((ptext, message_keys))
+}
+
+/// Like [`message_decrypt`], but applies all resulting store changes in a single
+/// [`ProtocolStoreTransaction`].
+///
+/// If any store operation fails, the transaction is rolled back and the error is returned.
+pub async fn message_decrypt_transactional<R: Rng + CryptoRng, S: TransactionalProtocolStore>(
+    ciphertext: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    store: &mut S,
+    now: SystemTime,
+    csprng: &mut R,
+    use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
+) -> Result<Vec<u8>> {
+    match ciphertext {
+        CiphertextMessage::SignalMessage(m) => {
+            message_decrypt_signal_transactional(m, remote_address, store, now, csprng, config)
+                .await
+        }
+        CiphertextMessage::PreKeySignalMessage(m) => {
+            message_decrypt_prekey_transactional(
+                m,
+                remote_address,
+                store,
+                now,
+                csprng,
+                use_pq_ratchet,
+                config,
+            )
+            .await
+        }
+        _ => Err(SignalProtocolError::InvalidArgument(format!(
+            "message_decrypt_transactional cannot be used to decrypt {:?} messages",
+            ciphertext.message_type()
+        ))),
+    }
+}
+
+/// Like [`message_decrypt_prekey`], but applies all resulting store changes in a single
+/// [`ProtocolStoreTransaction`].
+pub async fn message_decrypt_prekey_transactional<
+    R: Rng + CryptoRng,
+    S: TransactionalProtocolStore,
+>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    store: &mut S,
+    now: SystemTime,
+    csprng: &mut R,
+    use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
+) -> Result<Vec<u8>> {
+    config.validate()?;
+
+    // Stage inside the transaction too, so nothing else can change the stored session or consume
+    // the pre-keys between reading them and writing back the result.
+    store.begin_transaction().await?;
+
+    let result = async {
+        let (ptext, updates) = {
+            let store = &*store;
+            stage_decrypt_prekey(
+                ciphertext,
+                remote_address,
+                store,
+                store,
+                store,
+                store,
+                store,
+                now,
+                csprng,
+                use_pq_ratchet,
+                config,
+            )
+            .await?
+        };
+        updates.apply(store).await?;
+        store.commit_transaction().await.map(|()| ptext)
+    }
+    .await;
+
+    roll_back_on_error(store, result).await
+}
+
+/// Like [`message_decrypt_signal`], but applies all resulting store changes in a single
+/// [`ProtocolStoreTransaction`].
+pub async fn message_decrypt_signal_transactional<
+    R: Rng + CryptoRng,
+    S: TransactionalProtocolStore,
+>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    store: &mut S,
+    now: SystemTime,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<Vec<u8>> {
+    config.validate()?;
+
+    store.begin_transaction().await?;
+
+    let result = async {
+        let (ptext, updates) = {
+            let store = &*store;
+            stage_decrypt_signal(
+                ciphertext,
+                remote_address,
+                store,
+                store,
+                now,
+                csprng,
+                config,
+            )
+            .await?
+        };
+        updates.apply(store).await?;
+        store.commit_transaction().await.map(|()| ptext)
+    }
+    .await;
+
+    roll_back_on_error(store, result).await
+}
+
+/// Rolls back the transaction begun on `store` if `result` is an error.
+///
+/// A rollback failure is logged rather than returned, so the caller sees the original error.
+async fn roll_back_on_error<S: ProtocolStoreTransaction, T>(
+    store: &mut S,
+    result: Result<T>,
+) -> Result<T> {
+    if result.is_err() {
+        if let Err(rollback_error) = store.rollback_transaction().await {
+            log::error!("failed to roll back protocol store transaction: {rollback_error}");
+        }
+    }
+    result
+}
+
+/// Decrypts `ciphertext` without updating any stores.
+///
+/// Returns the plaintext along with the store changes a call to [`message_decrypt_prekey`] would
+/// have made. Once the plaintext has been accepted, pass those changes to
+/// [`PendingDecryptionUpdates::commit`]; to reject the message instead, drop them.
+#[expect(clippy::too_many_arguments)]
+pub async fn message_decrypt_prekey_dry_run<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    pre_key_store: &dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &dyn KyberPreKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, PendingDecryptionUpdates)> {
+    config.validate()?;
+
+    let expected_session = serialized_session_for_dry_run(remote_address, session_store).await?;
+
+    let (ptext, mut updates) = stage_decrypt_prekey(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        now,
+        csprng,
+        use_pq_ratchet,
+        config,
+    )
+    .await?;
+    updates.expected_session = Some(expected_session);
+
+    Ok((ptext, updates))
+}
+
+/// Decrypts `ciphertext` without updating any stores.
+///
+/// Returns the plaintext along with the store changes a call to [`message_decrypt_signal`] would
+/// have made. Once the plaintext has been accepted, pass those changes to
+/// [`PendingDecryptionUpdates::commit`]; to reject the message instead, drop them.
+pub async fn message_decrypt_signal_dry_run<R: Rng + CryptoRng>(
+    ciphertext: &SignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, PendingDecryptionUpdates)> {
+    config.validate()?;
+
+    let expected_session = serialized_session_for_dry_run(remote_address, session_store).await?;
+
+    let (ptext, mut updates) = stage_decrypt_signal(
+        ciphertext,
+        remote_address,
+        session_store,
+        identity_store,
+        now,
+        csprng,
+        config,
+    )
+    .await?;
+    updates.expected_session = Some(expected_session);
+
+    Ok((ptext, updates))
+}
+
+async fn serialized_session_for_dry_run(
+    remote_address: &ProtocolAddress,
+    session_store: &dyn SessionStore,
+) -> Result<Vec<u8>> {
+    session_store
+        .load_session(remote_address)
+        .await?
+        .unwrap_or_else(SessionRecord::new_fresh)
+        .serialize()
+}
+
+/// Store changes resulting from a successful decryption, not yet applied to any store.
+///
+/// Staging these rather than writing them as we go means a failure partway through decryption
+/// never leaves the stores half-updated, and lets [`ProtocolStoreTransaction`] implementations
+/// commit them all at once.
+///
+/// Values returned from the `_dry_run` decryption functions can be committed later with
+/// [`commit`](Self::commit) or [`commit_in_transaction`](Self::commit_in_transaction), or
+/// discarded by dropping them.
+#[must_use]
+pub struct PendingDecryptionUpdates {
+    remote_address: ProtocolAddress,
+    session_record: SessionRecord,
+    their_identity_key: IdentityKey,
+    pre_keys_used: Option<(PreKeysUsed, PublicKey)>,
+    /// The keys the message was decrypted with.
+    message_keys: MessageKeys,
+    /// For a dry run, the serialized session record that decryption started from.
+    expected_session: Option<Vec<u8>>,
+}
+
+impl PendingDecryptionUpdates {
+    /// The address of the sender whose session will be updated.
+    pub fn remote_address(&self) -> &ProtocolAddress {
+        &self.remote_address
+    }
+
+    /// Applies these changes to the given stores.
+    ///
+    /// Fails with [`SignalProtocolError::SessionChanged`] without changing anything if the stored
+    /// session for [`remote_address`](Self::remote_address) has changed since the dry run, e.g.
+    /// because another message from the same sender was decrypted in the meantime, or if a
+    /// one-time pre-key the message used has since been consumed. In that case the message should
+    /// be decrypted again.
+    pub async fn commit(
+        self,
+        session_store: &mut dyn SessionStore,
+        identity_store: &mut dyn IdentityKeyStore,
+        pre_key_store: &mut dyn PreKeyStore,
+        kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    ) -> Result<()> {
+        let mut stores = SeparateStores {
+            session_store,
+            identity_store,
+            pre_key_store,
+            kyber_pre_key_store,
+        };
+        self.check_unchanged(&mut stores).await?;
+        self.apply(&mut stores).await
+    }
+
+    /// Like [`commit`](Self::commit), but applies the changes in a single
+    /// [`ProtocolStoreTransaction`].
+    ///
+    /// The stored session and pre-keys are checked inside the transaction, so they can't change
+    /// between the check and the writes; if they have already changed, the transaction is rolled
+    /// back.
+    pub async fn commit_in_transaction<S: TransactionalProtocolStore>(
+        self,
+        store: &mut S,
+    ) -> Result<()> {
+        self.apply_in_transaction(store).await
+    }
+
+    /// For a dry run, checks that the stores are still in the state decryption started from.
+    async fn check_unchanged(&self, stores: &mut impl DecryptionStores) -> Result<()> {
+        let Some(expected_session) = &self.expected_session else {
+            return Ok(());
+        };
+        let changed = || SignalProtocolError::SessionChanged(self.remote_address.clone());
+
+        let current_session =
+            serialized_session_for_dry_run(&self.remote_address, stores.session_store()).await?;
+        if current_session != *expected_session {
+            return Err(changed());
+        }
+
+        // One-time pre-keys are removed once used, so a missing one means another decryption has
+        // already consumed it. (Last-resort Kyber pre-keys are never removed this way.)
+        if let Some((pre_keys_used, _)) = &self.pre_keys_used {
+            if let Some(pre_key_id) = pre_keys_used.one_time_ec_pre_key_id {
+                match stores.pre_key_store().get_pre_key(pre_key_id).await {
+                    Ok(_) => {}
+                    Err(SignalProtocolError::InvalidPreKeyId) => return Err(changed()),
+                    Err(e) => return Err(e),
+                }
+            }
+            if let Some(kyber_pre_key_id) = pre_keys_used.kyber_pre_key_id {
+                match stores
+                    .kyber_pre_key_store()
+                    .get_kyber_pre_key(kyber_pre_key_id)
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(SignalProtocolError::InvalidKyberPreKeyId) => return Err(changed()),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply(self, stores: &mut impl DecryptionStores) -> Result<()> {
+        stores
+            .identity_store()
+            .save_identity(&self.remote_address, &self.their_identity_key)
+            .await?;
+
+        if let Some((pre_key_used, base_key)) = &self.pre_keys_used {
+            if let Some(kyber_pre_key_id) = pre_key_used.kyber_pre_key_id {
+                stores
+                    .kyber_pre_key_store()
+                    .mark_kyber_pre_key_used(
+                        kyber_pre_key_id,
+                        pre_key_used.signed_ec_pre_key_id,
+                        base_key,
+                    )
+                    .await?;
+            }
+
+            if let Some(pre_key_id) = pre_key_used.one_time_ec_pre_key_id {
+                stores.pre_key_store().remove_pre_key(pre_key_id).await?;
+            }
+        }
+
+        stores
+            .session_store()
+            .store_session(&self.remote_address, &self.session_record)
+            .await
+    }
+
+    /// Applies updates that don't involve pre-keys, i.e. those from a [`SignalMessage`].
+    async fn apply_session_and_identity(
+        self,
+        session_store: &mut dyn SessionStore,
+        identity_store: &mut dyn IdentityKeyStore,
+    ) -> Result<()> {
+        debug_assert!(self.pre_keys_used.is_none());
+        identity_store
+            .save_identity(&self.remote_address, &self.their_identity_key)
+            .await?;
+        session_store
+            .store_session(&self.remote_address, &self.session_record)
+            .await
+    }
+
+    async fn apply_in_transaction<S: TransactionalProtocolStore>(
+        self,
+        store: &mut S,
+    ) -> Result<()> {
+        store.begin_transaction().await?;
+
+        let result = async {
+            self.check_unchanged(store).await?;
+            self.apply(store).await?;
+            store.commit_transaction().await
+        }
+        .await;
+
+        roll_back_on_error(store, result).await
+    }
+}
+
+/// The stores [`PendingDecryptionUpdates`] writes to, whether passed separately or as a single
+/// [`ProtocolStore`].
+trait DecryptionStores {
+    fn session_store(&mut self) -> &mut dyn SessionStore;
+    fn identity_store(&mut self) -> &mut dyn IdentityKeyStore;
+    fn pre_key_store(&mut self) -> &mut dyn PreKeyStore;
+    fn kyber_pre_key_store(&mut self) -> &mut dyn KyberPreKeyStore;
+}
+
+impl<S: ProtocolStore> DecryptionStores for S {
+    fn session_store(&mut self) -> &mut dyn SessionStore {
+        self
+    }
+    fn identity_store(&mut self) -> &mut dyn IdentityKeyStore {
+        self
+    }
+    fn pre_key_store(&mut self) -> &mut dyn PreKeyStore {
+        self
+    }
+    fn kyber_pre_key_store(&mut self) -> &mut dyn KyberPreKeyStore {
+        self
+    }
+}
+
+struct SeparateStores<'a> {
+    session_store: &'a mut dyn SessionStore,
+    identity_store: &'a mut dyn IdentityKeyStore,
+    pre_key_store: &'a mut dyn PreKeyStore,
+    kyber_pre_key_store: &'a mut dyn KyberPreKeyStore,
+}
+
+impl DecryptionStores for SeparateStores<'_> {
+    fn session_store(&mut self) -> &mut dyn SessionStore {
+        self.session_store
+    }
+    fn identity_store(&mut self) -> &mut dyn IdentityKeyStore {
+        self.identity_store
+    }
+    fn pre_key_store(&mut self) -> &mut dyn PreKeyStore {
+        self.pre_key_store
+    }
+    fn kyber_pre_key_store(&mut self) -> &mut dyn KyberPreKeyStore {
+        self.kyber_pre_key_store
+    }
+}
+
+#[expect(clippy::too_many_arguments)]
+async fn stage_decrypt_prekey<R: Rng + CryptoRng>(
+    ciphertext: &PreKeySignalMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    pre_key_store: &dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &dyn KyberPreKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, PendingDecryptionUpdates)> {
     let mut session_record = session_store
         .load_session(remote_address)
         .await?
         .unwrap_or_else(SessionRecord::new_fresh);
 
     // Make sure we log the session state if we fail to process the pre-key.
-    let process_prekey_result = session::process_prekey(
+    let process_prekey_result = session::process_prekey_with_config(
         ciphertext,
         remote_address,
         &mut session_record,
@@ -222,6 +968,8 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
         signed_pre_key_store,
         kyber_pre_key_store,
         use_pq_ratchet,
+        now,
+        config,
     )
     .await;
 
@@ -243,62 +991,50 @@ pub async fn message_decrypt_prekey<R: Rng + CryptoRng>(
         }
     };
 
-    let ptext = decrypt_message_with_record(
+    let (ptext, message_keys) = decrypt_message_with_record(
         remote_address,
         &mut session_record,
         ciphertext.message(),
         CiphertextMessageType::PreKey,
+        now,
         csprng,
+        config,
     )?;
 
-    identity_store
-        .save_identity(
-            identity_to_save.remote_address,
-            identity_to_save.their_identity_key,
-        )
-        .await?;
-
-    if let Some(pre_key_used) = pre_key_used {
-        if let Some(kyber_pre_key_id) = pre_key_used.kyber_pre_key_id {
-            kyber_pre_key_store
-                .mark_kyber_pre_key_used(
-                    kyber_pre_key_id,
-                    pre_key_used.signed_ec_pre_key_id,
-                    ciphertext.base_key(),
-                )
-                .await?;
-        }
-
-        if let Some(pre_key_id) = pre_key_used.one_time_ec_pre_key_id {
-            pre_key_store.remove_pre_key(pre_key_id).await?;
-        }
-    }
-
-    session_store
-        .store_session(remote_address, &session_record)
-        .await?;
+    let updates = PendingDecryptionUpdates {
+        remote_address: identity_to_save.remote_address.clone(),
+        session_record,
+        their_identity_key: *identity_to_save.their_identity_key,
+        pre_keys_used: pre_key_used.map(|used| (used, *ciphertext.base_key())),
+        message_keys,
+        expected_session: None,
+    };
 
-    Ok(ptext)
+    Ok((ptext, updates))
 }
 
-pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
+async fn stage_decrypt_signal<R: Rng + CryptoRng>(
     ciphertext: &SignalMessage,
     remote_address: &ProtocolAddress,
-    session_store: &mut dyn SessionStore,
-    identity_store: &mut dyn IdentityKeyStore,
+    session_store: &dyn SessionStore,
+    identity_store: &dyn IdentityKeyStore,
+    now: SystemTime,
     csprng: &mut R,
-) -> Result<Vec<u8>> {
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, PendingDecryptionUpdates)> {
     let mut session_record = session_store
         .load_session(remote_address)
         .await?
         .ok_or_else(|| SignalProtocolError::SessionNotFound(remote_address.clone()))?;
 
-    let ptext = decrypt_message_with_record(
+    let (ptext, message_keys) = decrypt_message_with_record(
         remote_address,
         &mut session_record,
         ciphertext,
         CiphertextMessageType::Whisper,
+        now,
         csprng,
+        config,
     )?;
 
     // Why are we performing this check after decryption instead of before?
@@ -323,15 +1059,16 @@ pub async fn message_decrypt_signal<R: Rng + CryptoRng>(
         ));
     }
 
-    identity_store
-        .save_identity(remote_address, &their_identity_key)
-        .await?;
-
-    session_store
-        .store_session(remote_address, &session_record)
-        .await?;
+    let updates = PendingDecryptionUpdates {
+        remote_address: remote_address.clone(),
+        session_record,
+        their_identity_key,
+        pre_keys_used: None,
+        message_keys,
+        expected_session: None,
+    };
 
-    Ok(ptext)
+    Ok((ptext, updates))
 }
 
 fn create_decryption_failure_log(
@@ -430,8 +1167,10 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
     record: &mut SessionRecord,
     ciphertext: &SignalMessage,
     original_message_type: CiphertextMessageType,
+    now: SystemTime,
     csprng: &mut R,
-) -> Result<Vec<u8>> {
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, MessageKeys)> {
     debug_assert!(matches!(
         original_message_type,
         CiphertextMessageType::Whisper | CiphertextMessageType::PreKey
@@ -463,11 +1202,13 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
             ciphertext,
             original_message_type,
             remote_address,
+            now,
             csprng,
+            config,
         );
 
         match result {
-            Ok(ptext) => {
+            Ok(decrypted) => {
                 log::info!(
                     "decrypted {:?} message from {} with current session state (base key {})",
                     original_message_type,
@@ -477,7 +1218,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
                         .expect("successful decrypt always has a valid base key"),
                 );
                 record.set_session_state(current_state); // update the state
-                return Ok(ptext);
+                return Ok(decrypted);
             }
             Err(SignalProtocolError::DuplicatedMessage(_, _)) => {
                 return result;
@@ -498,11 +1239,12 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
                                 ciphertext
                             )?
                         );
-                        // Note that we don't propagate `e` here; we always return InvalidMessage,
-                        // as we would for a Whisper message that tried several sessions.
-                        return Err(SignalProtocolError::InvalidMessage(
+                        // Note that we don't propagate `e` here; we return the same error as we
+                        // would for a Whisper message that tried several sessions.
+                        return Err(decryption_failure(
+                            remote_address,
+                            &errs,
                             original_message_type,
-                            "decryption failed",
                         ));
                     }
                     CiphertextMessageType::Whisper => {}
@@ -526,11 +1268,13 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
             ciphertext,
             original_message_type,
             remote_address,
+            now,
             csprng,
+            config,
         );
 
         match result {
-            Ok(ptext) => {
+            Ok(decrypted) => {
                 log::info!(
                     "decrypted {:?} message from {} with PREVIOUS session state (base key {})",
                     original_message_type,
@@ -539,7 +1283,7 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
                         .sender_ratchet_key_for_logging()
                         .expect("successful decrypt always has a valid base key"),
                 );
-                updated_session = Some((ptext, idx, previous));
+                updated_session = Some((decrypted, idx, previous));
                 break;
             }
             Err(SignalProtocolError::DuplicatedMessage(_, _)) => {
@@ -552,9 +1296,9 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
         }
     }
 
-    if let Some((ptext, idx, updated_session)) = updated_session {
-        record.promote_old_session(idx, updated_session);
-        Ok(ptext)
+    if let Some((decrypted, idx, updated_session)) = updated_session {
+        record.promote_old_session(idx, updated_session, config);
+        Ok(decrypted)
     } else {
         let previous_state_count = || record.previous_session_states().len();
 
@@ -578,13 +1322,34 @@ fn decrypt_message_with_record<R: Rng + CryptoRng>(
             "{}",
             create_decryption_failure_log(remote_address, &errs, record, ciphertext)?
         );
-        Err(SignalProtocolError::InvalidMessage(
+        Err(decryption_failure(
+            remote_address,
+            &errs,
             original_message_type,
-            "decryption failed",
         ))
     }
 }
 
+/// The error to report when no session could decrypt a message.
+///
+/// This is [`SignalProtocolError::InvalidMessage`], unless one of the sessions rejected the
+/// message because it was missing the post-quantum ratchet; a downgrade is reported as such so
+/// that it isn't mistaken for an ordinary corrupted message.
+fn decryption_failure(
+    remote_address: &ProtocolAddress,
+    errs: &[SignalProtocolError],
+    original_message_type: CiphertextMessageType,
+) -> SignalProtocolError {
+    if errs
+        .iter()
+        .any(|e| matches!(e, SignalProtocolError::PqRatchetDowngrade(_)))
+    {
+        SignalProtocolError::PqRatchetDowngrade(remote_address.clone())
+    } else {
+        SignalProtocolError::InvalidMessage(original_message_type, "decryption failed")
+    }
+}
+
 #[derive(Clone, Copy)]
 enum CurrentOrPrevious {
     Current,
@@ -606,8 +1371,10 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
     ciphertext: &SignalMessage,
     original_message_type: CiphertextMessageType,
     remote_address: &ProtocolAddress,
+    now: SystemTime,
     csprng: &mut R,
-) -> Result<Vec<u8>> {
+    config: &SessionConfig,
+) -> Result<(Vec<u8>, MessageKeys)> {
     // Check for a completely empty or invalid session state before we do anything else.
     let _ = state.root_key().map_err(|_| {
         SignalProtocolError::InvalidMessage(
@@ -623,9 +1390,19 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
         ));
     }
 
+    let peer_used_pq_ratchet = !ciphertext.pq_ratchet().is_empty();
+    if !peer_used_pq_ratchet
+        && (state.pq_ratchet_required() || state.pq_ratchet_status() == PqRatchetStatus::Active)
+    {
+        return Err(SignalProtocolError::PqRatchetDowngrade(
+            remote_address.clone(),
+        ));
+    }
+
     let their_ephemeral = ciphertext.sender_ratchet_key();
     let counter = ciphertext.counter();
-    let chain_key = get_or_create_chain_key(state, their_ephemeral, remote_address, csprng)?;
+    let chain_key =
+        get_or_create_chain_key(state, their_ephemeral, remote_address, csprng, config)?;
     let message_key_gen = get_or_create_message_key(
         state,
         their_ephemeral,
@@ -633,6 +1410,7 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
         original_message_type,
         &chain_key,
         counter,
+        config,
     )?;
     let pqr_key = state
         .pq_ratchet_recv(ciphertext.pq_ratchet())
@@ -692,9 +1470,11 @@ fn decrypt_message_with_state<R: Rng + CryptoRng>(
         }
     };
 
+    state.mark_acknowledged(now);
     state.clear_unacknowledged_pre_key_message();
+    state.observe_peer_pq_ratchet(peer_used_pq_ratchet);
 
-    Ok(ptext)
+    Ok((ptext, message_keys))
 }
 
 fn get_or_create_chain_key<R: Rng + CryptoRng>(
@@ -702,6 +1482,7 @@ fn get_or_create_chain_key<R: Rng + CryptoRng>(
     their_ephemeral: &PublicKey,
     remote_address: &ProtocolAddress,
     csprng: &mut R,
+    config: &SessionConfig,
 ) -> Result<ChainKey> {
     if let Some(chain) = state.get_receiver_chain_key(their_ephemeral)? {
         log::debug!("{remote_address} has existing receiver chain.");
@@ -719,7 +1500,7 @@ fn get_or_create_chain_key<R: Rng + CryptoRng>(
         .create_chain(their_ephemeral, &our_new_ephemeral.private_key)?;
 
     state.set_root_key(&sender_chain.0);
-    state.add_receiver_chain(their_ephemeral, &receiver_chain.1);
+    state.add_receiver_chain(their_ephemeral, &receiver_chain.1, config);
 
     let current_index = state.get_sender_chain_key()?.index();
     let previous_index = if current_index > 0 {
@@ -740,6 +1521,7 @@ fn get_or_create_message_key(
     original_message_type: CiphertextMessageType,
     chain_key: &ChainKey,
     counter: u32,
+    config: &SessionConfig,
 ) -> Result<MessageKeyGenerator> {
     let chain_index = chain_key.index();
 
@@ -757,14 +1539,15 @@ fn get_or_create_message_key(
 
     let jump = (counter - chain_index) as usize;
 
-    if jump > MAX_FORWARD_JUMPS {
+    if jump > config.max_forward_jumps {
         if state.session_with_self()? {
             log::info!(
                 "{remote_address} Jumping ahead {jump} messages (index: {chain_index}, counter: {counter})"
             );
         } else {
             log::error!(
-                "{remote_address} Exceeded future message limit: {MAX_FORWARD_JUMPS}, index: {chain_index}, counter: {counter})"
+                "{remote_address} Exceeded future message limit: {}, index: {chain_index}, counter: {counter})",
+                config.max_forward_jumps
             );
             return Err(SignalProtocolError::InvalidMessage(
                 original_message_type,
@@ -777,7 +1560,7 @@ fn get_or_create_message_key(
 
     while chain_key.index() < counter {
         let message_keys = chain_key.message_keys();
-        state.set_message_keys(their_ephemeral, message_keys)?;
+        state.set_message_keys(their_ephemeral, message_keys, config)?;
         chain_key = chain_key.next_chain_key();
     }
 
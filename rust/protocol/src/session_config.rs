@@ -0,0 +1,154 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use std::time::Duration;
+
+use crate::{Result, SignalProtocolError, consts};
+
+/// Tunable limits on how much state sessions and sender key records retain.
+///
+/// The [`Default`] values are the limits libsignal has always used. Raising them lets a client
+/// tolerate more out-of-order or bursty traffic, at the cost of larger records and more work
+/// spent on messages that may never arrive. Every field has a sane range enforced by
+/// [`SessionConfig::validate`]; APIs that take a `SessionConfig` reject out-of-range values with
+/// [`SignalProtocolError::InvalidArgument`].
+///
+/// Sessions using the post-quantum ratchet also carry that ratchet's own forward-jump and
+/// skipped-key limits, fixed at the defaults when the session is created. Lowering the limits
+/// here is always effective; raising them beyond the defaults only helps sessions without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// How far ahead of the current chain index a message may be before it is rejected.
+    ///
+    /// Applies to both 1:1 and sender key messages.
+    pub max_forward_jumps: usize,
+    /// How many skipped message keys are kept per receiver chain (or per sender key state).
+    pub max_message_keys: usize,
+    /// How many receiver chains are kept per session.
+    pub max_receiver_chains: usize,
+    /// How many previous sessions are kept in a [`SessionRecord`](crate::SessionRecord).
+    pub max_archived_states: usize,
+    /// How many sender key states are kept in a [`SenderKeyRecord`](crate::SenderKeyRecord).
+    pub max_sender_key_states: usize,
+    /// How long a session may go without a response before it is no longer used for sending.
+    pub max_unacknowledged_session_age: Duration,
+}
+
+impl SessionConfig {
+    const MAX_FORWARD_JUMPS_LIMIT: usize = 1_000_000;
+    const MAX_MESSAGE_KEYS_LIMIT: usize = 100_000;
+    const MAX_RECEIVER_CHAINS_LIMIT: usize = 100;
+    const MAX_ARCHIVED_STATES_LIMIT: usize = 200;
+    const MAX_SENDER_KEY_STATES_LIMIT: usize = 100;
+    const MAX_UNACKNOWLEDGED_SESSION_AGE_LIMIT: Duration = Duration::from_secs(60 * 60 * 24 * 365);
+
+    /// Checks that every limit is within its supported range.
+    ///
+    /// Every count must be at least 1, and none may exceed a fixed upper bound chosen to keep
+    /// records and per-message work from growing without bound.
+    pub fn validate(&self) -> Result<()> {
+        fn check_count(name: &str, value: usize, limit: usize) -> Result<()> {
+            if !(1..=limit).contains(&value) {
+                return Err(SignalProtocolError::InvalidArgument(format!(
+                    "{name} must be between 1 and {limit} (got {value})"
+                )));
+            }
+            Ok(())
+        }
+
+        check_count(
+            "max_forward_jumps",
+            self.max_forward_jumps,
+            Self::MAX_FORWARD_JUMPS_LIMIT,
+        )?;
+        check_count(
+            "max_message_keys",
+            self.max_message_keys,
+            Self::MAX_MESSAGE_KEYS_LIMIT,
+        )?;
+        check_count(
+            "max_receiver_chains",
+            self.max_receiver_chains,
+            Self::MAX_RECEIVER_CHAINS_LIMIT,
+        )?;
+        check_count(
+            "max_archived_states",
+            self.max_archived_states,
+            Self::MAX_ARCHIVED_STATES_LIMIT,
+        )?;
+        check_count(
+            "max_sender_key_states",
+            self.max_sender_key_states,
+            Self::MAX_SENDER_KEY_STATES_LIMIT,
+        )?;
+
+        if self.max_unacknowledged_session_age.is_zero()
+            || self.max_unacknowledged_session_age > Self::MAX_UNACKNOWLEDGED_SESSION_AGE_LIMIT
+        {
+            return Err(SignalProtocolError::InvalidArgument(format!(
+                "max_unacknowledged_session_age must be nonzero and at most {:?} (got {:?})",
+                Self::MAX_UNACKNOWLEDGED_SESSION_AGE_LIMIT,
+                self.max_unacknowledged_session_age
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_forward_jumps: consts::MAX_FORWARD_JUMPS,
+            max_message_keys: consts::MAX_MESSAGE_KEYS,
+            max_receiver_chains: consts::MAX_RECEIVER_CHAINS,
+            max_archived_states: consts::ARCHIVED_STATES_MAX_LENGTH,
+            max_sender_key_states: consts::MAX_SENDER_KEY_STATES,
+            max_unacknowledged_session_age: consts::MAX_UNACKNOWLEDGED_SESSION_AGE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        SessionConfig::default().validate().expect("valid");
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let zero_keys = SessionConfig {
+            max_message_keys: 0,
+            ..Default::default()
+        };
+        assert_matches!(
+            zero_keys.validate(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+
+        let huge_jumps = SessionConfig {
+            max_forward_jumps: usize::MAX,
+            ..Default::default()
+        };
+        assert_matches!(
+            huge_jumps.validate(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+
+        let zero_age = SessionConfig {
+            max_unacknowledged_session_age: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_matches!(
+            zero_age.validate(),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+    }
+}
@@ -7,11 +7,15 @@ mod bundle;
 mod kyber_prekey;
 mod prekey;
 mod session;
+mod session_diagnostics;
 mod signed_prekey;
 
 pub use bundle::{PreKeyBundle, PreKeyBundleContent};
-pub use kyber_prekey::{KyberPreKeyId, KyberPreKeyRecord};
+pub use kyber_prekey::{KyberPreKeyId, KyberPreKeyKind, KyberPreKeyRecord};
 pub use prekey::{PreKeyId, PreKeyRecord};
 pub(crate) use session::{InvalidSessionError, SessionState};
-pub use session::{SessionRecord, SessionUsabilityRequirements};
+pub use session::{PqRatchetStatus, SessionRecord, SessionUsabilityRequirements};
+pub use session_diagnostics::{
+    ReceiverChainDiagnostics, SessionDiagnostics, SessionStateDiagnostics,
+};
 pub use signed_prekey::{GenericSignedPreKey, SignedPreKeyId, SignedPreKeyRecord};
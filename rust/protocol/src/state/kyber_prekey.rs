@@ -8,6 +8,7 @@ use std::fmt;
 use rand::TryRngCore as _;
 
 use crate::proto::storage::SignedPreKeyRecordStructure;
+use crate::proto::storage::signed_pre_key_record_structure::KyberKind;
 use crate::state::GenericSignedPreKey;
 use crate::{PrivateKey, Result, Timestamp, kem};
 
@@ -23,6 +24,16 @@ impl fmt::Display for KyberPreKeyId {
     }
 }
 
+/// Whether a Kyber pre-key may be used once or many times.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum KyberPreKeyKind {
+    /// Uploaded in a batch; deleted as soon as a session is established with it.
+    OneTime,
+    /// Handed out when the server has no one-time keys left; kept until it is rotated out, so
+    /// every use must be checked for replays.
+    LastResort,
+}
+
 #[derive(Debug, Clone)]
 pub struct KyberPreKeyRecord {
     signed_pre_key: SignedPreKeyRecordStructure,
@@ -47,6 +58,27 @@ impl KyberPreKeyRecord {
     pub fn secret_key(&self) -> Result<kem::SecretKey> {
         kem::SecretKey::deserialize(&self.signed_pre_key.private_key)
     }
+
+    /// Which kind of pre-key this is.
+    ///
+    /// Records that never had a kind set, including any saved before the kind was recorded, are
+    /// treated as last-resort keys, since that is never less safe.
+    pub fn kind(&self) -> KyberPreKeyKind {
+        match KyberKind::try_from(self.signed_pre_key.kyber_kind) {
+            Ok(KyberKind::OneTime) => KyberPreKeyKind::OneTime,
+            Ok(KyberKind::LastResort | KyberKind::Unknown) | Err(_) => KyberPreKeyKind::LastResort,
+        }
+    }
+
+    /// Returns a copy of this record marked as `kind`.
+    pub fn with_kind(mut self, kind: KyberPreKeyKind) -> Self {
+        self.signed_pre_key.kyber_kind = match kind {
+            KyberPreKeyKind::OneTime => KyberKind::OneTime,
+            KyberPreKeyKind::LastResort => KyberKind::LastResort,
+        }
+        .into();
+        self
+    }
 }
 
 impl KyberPreKeyRecord {
@@ -13,9 +13,31 @@ use subtle::ConstantTimeEq;
 
 use crate::proto::storage::{RecordStructure, SessionStructure, session_structure};
 use crate::protocol::CIPHERTEXT_MESSAGE_PRE_KYBER_VERSION;
-use crate::ratchet::{ChainKey, MessageKeyGenerator, RootKey};
-use crate::state::{KyberPreKeyId, PreKeyId, SignedPreKeyId};
-use crate::{IdentityKey, KeyPair, PrivateKey, PublicKey, SignalProtocolError, consts, kem};
+use crate::ratchet::{ChainKey, MessageKeyGenerator, RootKey, UsePQRatchet};
+use crate::state::{
+    KyberPreKeyId, PreKeyId, ReceiverChainDiagnostics, SessionDiagnostics, SessionStateDiagnostics,
+    SignedPreKeyId,
+};
+use crate::{
+    IdentityKey, KeyPair, PrivateKey, PublicKey, SessionConfig, SignalProtocolError, Timestamp, kem,
+};
+
+/// How far a session has got in negotiating the post-quantum ratchet (SPQR) with the peer.
+///
+/// The discriminants are passed across the app bridges, so they must not change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum PqRatchetStatus {
+    /// This session was created without offering the post-quantum ratchet.
+    Disabled = 0,
+    /// The post-quantum ratchet was offered, but no message from the peer has been decrypted yet.
+    Offered = 1,
+    /// The peer has used the post-quantum ratchet, and may not stop using it.
+    Active = 2,
+    /// The peer did not use the post-quantum ratchet, so the session uses the classical ratchet
+    /// alone.
+    Declined = 3,
+}
 
 /// A distinct error type to keep from accidentally propagating deserialization errors.
 #[derive(Debug)]
@@ -33,6 +55,21 @@ impl From<InvalidSessionError> for SignalProtocolError {
     }
 }
 
+/// Converts `time` for storage in a [`SessionStructure`], where 0 means "not recorded".
+fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+fn recorded_timestamp(epoch_millis: u64) -> Option<Timestamp> {
+    Some(epoch_millis)
+        .filter(|millis| *millis != 0)
+        .map(Timestamp::from_epoch_millis)
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct UnacknowledgedPreKeyMessageItems<'a> {
     pre_key_id: Option<PreKeyId>,
@@ -145,7 +182,14 @@ impl SessionState {
         root_key: &RootKey,
         alice_base_key: &PublicKey,
         pq_ratchet_state: spqr::SerializedState,
+        use_pq_ratchet: UsePQRatchet,
     ) -> Self {
+        let pq_ratchet_status = match use_pq_ratchet {
+            UsePQRatchet::No => session_structure::PqRatchetStatus::Disabled,
+            UsePQRatchet::Yes | UsePQRatchet::Required => {
+                session_structure::PqRatchetStatus::Offered
+            }
+        };
         Self {
             session: SessionStructure {
                 session_version: version as u32,
@@ -161,6 +205,10 @@ impl SessionState {
                 local_registration_id: 0,
                 alice_base_key: alice_base_key.serialize().into_vec(),
                 pq_ratchet_state,
+                pq_ratchet_status: pq_ratchet_status.into(),
+                pq_ratchet_required: use_pq_ratchet == UsePQRatchet::Required,
+                created_at: 0,
+                acknowledged_at: 0,
             },
         }
     }
@@ -249,10 +297,11 @@ impl SessionState {
         }
     }
 
-    pub fn has_usable_sender_chain(
+    pub(crate) fn has_usable_sender_chain(
         &self,
         now: SystemTime,
         requirements: SessionUsabilityRequirements,
+        config: &SessionConfig,
     ) -> Result<bool, InvalidSessionError> {
         if self.session.sender_chain.is_none() {
             return Ok(false);
@@ -261,7 +310,7 @@ impl SessionState {
             if let Some(pending_pre_key) = &self.session.pending_pre_key {
                 let creation_timestamp =
                     SystemTime::UNIX_EPOCH + Duration::from_secs(pending_pre_key.timestamp);
-                if creation_timestamp + consts::MAX_UNACKNOWLEDGED_SESSION_AGE < now {
+                if creation_timestamp + config.max_unacknowledged_session_age < now {
                     return Ok(false);
                 }
             }
@@ -329,7 +378,12 @@ impl SessionState {
         }
     }
 
-    pub(crate) fn add_receiver_chain(&mut self, sender: &PublicKey, chain_key: &ChainKey) {
+    pub(crate) fn add_receiver_chain(
+        &mut self,
+        sender: &PublicKey,
+        chain_key: &ChainKey,
+        config: &SessionConfig,
+    ) {
         let chain_key = session_structure::chain::ChainKey {
             index: chain_key.index(),
             key: chain_key.key().to_vec(),
@@ -344,20 +398,36 @@ impl SessionState {
 
         self.session.receiver_chains.push(chain);
 
-        if self.session.receiver_chains.len() > consts::MAX_RECEIVER_CHAINS {
+        self.trim_receiver_chains(config.max_receiver_chains);
+    }
+
+    pub(crate) fn with_receiver_chain(mut self, sender: &PublicKey, chain_key: &ChainKey) -> Self {
+        // A freshly-initialized session has no other receiver chains, so the limit is moot.
+        self.add_receiver_chain(sender, chain_key, &SessionConfig::default());
+        self
+    }
+
+    fn trim_receiver_chains(&mut self, max_receiver_chains: usize) {
+        let chain_count = self.session.receiver_chains.len();
+        if chain_count > max_receiver_chains {
             log::info!(
                 "Trimming excessive receiver_chain for session with base key {}, chain count: {}",
                 self.sender_ratchet_key_for_logging()
                     .unwrap_or_else(|e| format!("<error: {}>", e.0)),
-                self.session.receiver_chains.len()
+                chain_count
             );
-            self.session.receiver_chains.remove(0);
+            self.session
+                .receiver_chains
+                .drain(..chain_count - max_receiver_chains);
         }
     }
 
-    pub(crate) fn with_receiver_chain(mut self, sender: &PublicKey, chain_key: &ChainKey) -> Self {
-        self.add_receiver_chain(sender, chain_key);
-        self
+    /// Drops the oldest receiver chains and skipped message keys beyond the limits in `config`.
+    pub(crate) fn prune(&mut self, config: &SessionConfig) {
+        self.trim_receiver_chains(config.max_receiver_chains);
+        for chain in &mut self.session.receiver_chains {
+            chain.message_keys.truncate(config.max_message_keys);
+        }
     }
 
     pub(crate) fn set_sender_chain(&mut self, sender: &KeyPair, next_chain_key: &ChainKey) {
@@ -458,6 +528,7 @@ impl SessionState {
         &mut self,
         sender: &PublicKey,
         message_keys: MessageKeyGenerator,
+        config: &SessionConfig,
     ) -> Result<(), InvalidSessionError> {
         let chain_and_index = self
             .get_receiver_chain(sender)?
@@ -465,9 +536,7 @@ impl SessionState {
         let mut updated_chain = chain_and_index.0;
         updated_chain.message_keys.insert(0, message_keys.into_pb());
 
-        if updated_chain.message_keys.len() > consts::MAX_MESSAGE_KEYS {
-            updated_chain.message_keys.pop();
-        }
+        updated_chain.message_keys.truncate(config.max_message_keys);
 
         self.session.receiver_chains[chain_and_index.1] = updated_chain;
 
@@ -550,6 +619,22 @@ impl SessionState {
         }
     }
 
+    pub(crate) fn set_created_at(&mut self, now: SystemTime) {
+        self.session.created_at = epoch_millis(now);
+    }
+
+    pub(crate) fn set_acknowledged_at(&mut self, now: SystemTime) {
+        self.session.acknowledged_at = epoch_millis(now);
+    }
+
+    /// Records `now` as the time the other party first replied, if this session was still
+    /// waiting for a reply.
+    pub(crate) fn mark_acknowledged(&mut self, now: SystemTime) {
+        if self.session.pending_pre_key.is_some() && self.session.acknowledged_at == 0 {
+            self.set_acknowledged_at(now);
+        }
+    }
+
     pub(crate) fn clear_unacknowledged_pre_key_message(&mut self) {
         // Explicitly destructuring the SessionStructure in case there are new
         // pending fields that need to be cleared.
@@ -567,6 +652,10 @@ impl SessionState {
             local_registration_id: _local_registration_id,
             alice_base_key: _alice_base_key,
             pq_ratchet_state: _pq_ratchet_state,
+            pq_ratchet_status: _pq_ratchet_status,
+            pq_ratchet_required: _pq_ratchet_required,
+            created_at: _created_at,
+            acknowledged_at: _acknowledged_at,
         } = &self.session;
         // ####### IMPORTANT #######
         // Don't forget to clean up new pending fields.
@@ -619,6 +708,67 @@ impl SessionState {
     pub(crate) fn pq_ratchet_state(&self) -> &spqr::SerializedState {
         &self.session.pq_ratchet_state
     }
+
+    pub(crate) fn pq_ratchet_status(&self) -> PqRatchetStatus {
+        use session_structure::PqRatchetStatus as Stored;
+        match Stored::try_from(self.session.pq_ratchet_status).unwrap_or(Stored::Unknown) {
+            Stored::Disabled => PqRatchetStatus::Disabled,
+            Stored::Offered => PqRatchetStatus::Offered,
+            Stored::Active => PqRatchetStatus::Active,
+            Stored::Declined => PqRatchetStatus::Declined,
+            // Sessions from before the status was recorded. Whether the peer has used the ratchet
+            // is unknown, so don't assume it has.
+            Stored::Unknown if self.session.pq_ratchet_state.is_empty() => {
+                PqRatchetStatus::Disabled
+            }
+            Stored::Unknown => PqRatchetStatus::Offered,
+        }
+    }
+
+    pub(crate) fn pq_ratchet_required(&self) -> bool {
+        self.session.pq_ratchet_required
+    }
+
+    /// Records whether the peer used the post-quantum ratchet in a message that was just
+    /// decrypted successfully.
+    pub(crate) fn observe_peer_pq_ratchet(&mut self, peer_used_pq_ratchet: bool) {
+        use session_structure::PqRatchetStatus as Stored;
+        let status = match (self.pq_ratchet_status(), peer_used_pq_ratchet) {
+            (PqRatchetStatus::Offered, true) => Stored::Active,
+            (PqRatchetStatus::Offered, false) => Stored::Declined,
+            // Everything else is settled; a downgrade from Active is rejected before decryption.
+            _ => return,
+        };
+        self.session.pq_ratchet_status = status.into();
+    }
+
+    pub(crate) fn diagnostics(&self) -> SessionStateDiagnostics {
+        SessionStateDiagnostics {
+            session_version: self.session.session_version,
+            sender_chain_index: self
+                .session
+                .sender_chain
+                .as_ref()
+                .and_then(|chain| chain.chain_key.as_ref())
+                .map(|chain_key| chain_key.index),
+            previous_counter: self.session.previous_counter,
+            receiver_chains: self
+                .session
+                .receiver_chains
+                .iter()
+                .map(|chain| ReceiverChainDiagnostics {
+                    chain_index: chain.chain_key.as_ref().map(|chain_key| chain_key.index),
+                    skipped_message_keys: chain.message_keys.len(),
+                })
+                .collect(),
+            has_pq_ratchet_state: !self.session.pq_ratchet_state.is_empty(),
+            created_at: recorded_timestamp(self.session.created_at),
+            acknowledged_at: recorded_timestamp(self.session.acknowledged_at),
+            unacknowledged_since: self.session.pending_pre_key.as_ref().map(|pending| {
+                Timestamp::from_epoch_millis(pending.timestamp.saturating_mul(1000))
+            }),
+        }
+    }
 }
 
 impl From<SessionStructure> for SessionState {
@@ -680,6 +830,7 @@ impl SessionRecord {
         &mut self,
         version: u32,
         alice_base_key: &[u8],
+        config: &SessionConfig,
     ) -> Result<bool, InvalidSessionError> {
         if let Some(current_session) = &self.current_session {
             if current_session.session_version()? == version
@@ -703,7 +854,7 @@ impl SessionRecord {
         }
 
         if let Some((i, state)) = session_to_promote {
-            self.promote_old_session(i, state);
+            self.promote_old_session(i, state, config);
             return Ok(true);
         }
 
@@ -736,24 +887,24 @@ impl SessionRecord {
         &mut self,
         old_session: usize,
         updated_session: SessionState,
+        config: &SessionConfig,
     ) {
         self.previous_sessions.remove(old_session);
-        self.promote_state(updated_session)
+        self.promote_state(updated_session, config)
     }
 
-    pub(crate) fn promote_state(&mut self, new_state: SessionState) {
-        self.archive_current_state_inner();
+    pub(crate) fn promote_state(&mut self, new_state: SessionState, config: &SessionConfig) {
+        self.archive_current_state_inner(config);
         self.current_session = Some(new_state);
     }
 
     // A non-fallible version of archive_current_state.
     //
     // Returns `true` if there was a session to archive, `false` if not.
-    fn archive_current_state_inner(&mut self) -> bool {
+    fn archive_current_state_inner(&mut self, config: &SessionConfig) -> bool {
         if let Some(mut current_session) = self.current_session.take() {
-            if self.previous_sessions.len() >= consts::ARCHIVED_STATES_MAX_LENGTH {
-                self.previous_sessions.pop();
-            }
+            self.previous_sessions
+                .truncate(config.max_archived_states - 1);
             current_session.clear_unacknowledged_pre_key_message();
             self.previous_sessions
                 .insert(0, current_session.session.encode_to_vec());
@@ -764,12 +915,46 @@ impl SessionRecord {
     }
 
     pub fn archive_current_state(&mut self) -> Result<(), SignalProtocolError> {
-        if !self.archive_current_state_inner() {
+        self.archive_current_state_with_config(&SessionConfig::default())
+    }
+
+    /// Like [`archive_current_state`](Self::archive_current_state), but keeps at most
+    /// `config.max_archived_states` previous sessions.
+    pub fn archive_current_state_with_config(
+        &mut self,
+        config: &SessionConfig,
+    ) -> Result<(), SignalProtocolError> {
+        config.validate()?;
+        if !self.archive_current_state_inner(config) {
             log::info!("Skipping archive, current session state is fresh");
         }
         Ok(())
     }
 
+    /// Discards retained state beyond the limits in `config`.
+    ///
+    /// This drops the oldest previous sessions, and in every session the oldest receiver chains
+    /// and skipped message keys. Records are pruned automatically as they are updated, so this is
+    /// only needed to apply a stricter `config` to records created under a more lenient one.
+    pub fn prune(&mut self, config: &SessionConfig) -> Result<(), SignalProtocolError> {
+        config.validate()?;
+
+        if let Some(current_session) = &mut self.current_session {
+            current_session.prune(config);
+        }
+
+        self.previous_sessions.truncate(config.max_archived_states);
+        for previous in &mut self.previous_sessions {
+            let mut state: SessionState = SessionStructure::decode(&previous[..])
+                .map_err(|_| InvalidSessionError("failed to decode previous session protobuf"))?
+                .into();
+            state.prune(config);
+            *previous = state.session.encode_to_vec();
+        }
+
+        Ok(())
+    }
+
     pub fn serialize(&self) -> Result<Vec<u8>, SignalProtocolError> {
         let record = RecordStructure {
             current_session: self.current_session.as_ref().map(|s| s.into()),
@@ -778,10 +963,39 @@ impl SessionRecord {
         Ok(record.encode_to_vec())
     }
 
+    /// Returns a log-safe summary of this record, for debugging decryption failures.
+    ///
+    /// Fails only if an archived session cannot be decoded.
+    pub fn diagnostics(&self) -> Result<SessionDiagnostics, SignalProtocolError> {
+        Ok(SessionDiagnostics {
+            current_session: self.current_session.as_ref().map(SessionState::diagnostics),
+            archived_sessions: self
+                .previous_session_states()
+                .map(|state| Ok(state?.diagnostics()))
+                .collect::<Result<_, InvalidSessionError>>()?,
+        })
+    }
+
     pub fn current_pq_state(&self) -> Option<&spqr::SerializedState> {
         self.current_session.as_ref().map(|s| s.pq_ratchet_state())
     }
 
+    /// Whether the current session uses the post-quantum ratchet, or `None` if there is no
+    /// current session.
+    pub fn pq_ratchet_status(&self) -> Option<PqRatchetStatus> {
+        self.current_session
+            .as_ref()
+            .map(SessionState::pq_ratchet_status)
+    }
+
+    /// Whether the current session was created with [`UsePQRatchet::Required`], or `None` if
+    /// there is no current session.
+    pub fn pq_ratchet_required(&self) -> Option<bool> {
+        self.current_session
+            .as_ref()
+            .map(SessionState::pq_ratchet_required)
+    }
+
     pub fn remote_registration_id(&self) -> Result<u32, SignalProtocolError> {
         Ok(self
             .session_state()
@@ -843,9 +1057,20 @@ impl SessionRecord {
         &self,
         now: SystemTime,
         requirements: SessionUsabilityRequirements,
+    ) -> Result<bool, SignalProtocolError> {
+        self.has_usable_sender_chain_with_config(now, requirements, &SessionConfig::default())
+    }
+
+    /// Like [`has_usable_sender_chain`](Self::has_usable_sender_chain), but uses
+    /// `config.max_unacknowledged_session_age` to decide whether a session is stale.
+    pub fn has_usable_sender_chain_with_config(
+        &self,
+        now: SystemTime,
+        requirements: SessionUsabilityRequirements,
+        config: &SessionConfig,
     ) -> Result<bool, SignalProtocolError> {
         match &self.current_session {
-            Some(session) => Ok(session.has_usable_sender_chain(now, requirements)?),
+            Some(session) => Ok(session.has_usable_sender_chain(now, requirements, config)?),
             None => Ok(false),
         }
     }
@@ -0,0 +1,64 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+use serde::Serialize;
+
+use crate::Timestamp;
+
+/// A summary of a [`SessionRecord`](crate::SessionRecord) that is safe to log.
+///
+/// This contains only counts, indexes, and timestamps; no keys (public or private) or identifiers
+/// are included. It can be serialized (e.g. to JSON) for attaching to bug reports.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SessionDiagnostics {
+    /// The current session, if there is one.
+    pub current_session: Option<SessionStateDiagnostics>,
+    /// The archived (previous) sessions kept for decrypting late messages, newest first.
+    pub archived_sessions: Vec<SessionStateDiagnostics>,
+}
+
+/// A log-safe summary of a single session within a [`SessionDiagnostics`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SessionStateDiagnostics {
+    /// The protocol version of the session.
+    pub session_version: u32,
+    /// The index of the next message to be sent, or `None` if there is no sender chain.
+    pub sender_chain_index: Option<u32>,
+    /// The length of the sender chain before the most recent ratchet step.
+    pub previous_counter: u32,
+    /// Receiver chains, oldest first.
+    pub receiver_chains: Vec<ReceiverChainDiagnostics>,
+    /// Whether the session has post-quantum ratchet (SPQR) state.
+    pub has_pq_ratchet_state: bool,
+    /// When the session was created, or `None` for sessions created before this was recorded.
+    pub created_at: Option<Timestamp>,
+    /// When a message from the other party was first received in this session.
+    ///
+    /// `None` if that hasn't happened yet, or happened before this was recorded.
+    pub acknowledged_at: Option<Timestamp>,
+    /// When this session was created by processing a pre-key bundle, if that session has not
+    /// yet been acknowledged by the other party.
+    ///
+    /// This is stored with one-second precision, and becomes `None` once the other party replies.
+    pub unacknowledged_since: Option<Timestamp>,
+}
+
+impl SessionStateDiagnostics {
+    /// Whether this session is no longer waiting for the other party to reply.
+    ///
+    /// Archived sessions are never waiting, even if the other party never replied.
+    pub fn is_acknowledged(&self) -> bool {
+        self.unacknowledged_since.is_none()
+    }
+}
+
+/// A log-safe summary of one receiver chain within a [`SessionStateDiagnostics`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ReceiverChainDiagnostics {
+    /// The index of the next expected message, or `None` if the chain key is missing.
+    pub chain_index: Option<u32>,
+    /// The number of message keys kept for messages that were skipped over.
+    pub skipped_message_keys: usize,
+}
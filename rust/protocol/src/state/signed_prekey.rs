@@ -70,6 +70,7 @@ pub trait GenericSignedPreKey {
             public_key,
             private_key,
             signature,
+            kyber_kind: 0,
         })
     }
 
@@ -4,17 +4,36 @@
 //
 
 //! Interfaces in [traits] and reference implementations in [inmem] for various mutable stores.
+//!
+//! With the `sqlite` feature enabled, persistent implementations are also available.
 
 #![warn(missing_docs)]
 
 mod inmem;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 mod traits;
 
+/// How many base keys to remember for each last-resort Kyber pre-key.
+///
+/// Once this many have been seen the oldest are forgotten, so that a key that somehow stays in use
+/// for a very long time can't grow its replay set without bound. Last-resort keys are normally
+/// rotated long before this is reached.
+pub(crate) const MAX_BASE_KEYS_PER_LAST_RESORT_KEY: usize = 10_000;
+
 pub use inmem::{
     InMemIdentityKeyStore, InMemKyberPreKeyStore, InMemPreKeyStore, InMemSenderKeyStore,
-    InMemSessionStore, InMemSignalProtocolStore, InMemSignedPreKeyStore,
+    InMemSentMessageLogStore, InMemSessionStore, InMemSignalProtocolStore, InMemSignedPreKeyStore,
+    InMemTransactionalProtocolStore, TransactionStep,
+};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{
+    STORAGE_KEY_LEN as SQLITE_STORAGE_KEY_LEN, SqliteIdentityKeyStore, SqliteKyberPreKeyStore,
+    SqlitePreKeyStore, SqliteSenderKeyStore, SqliteSessionStore, SqliteSignalProtocolStore,
+    SqliteSignedPreKeyStore,
 };
 pub use traits::{
     Direction, IdentityChange, IdentityKeyStore, KyberPreKeyStore, PreKeyStore, ProtocolStore,
-    SenderKeyStore, SessionStore, SignedPreKeyStore,
+    ProtocolStoreTransaction, SavedIdentity, SenderKeyStore, SentMessage, SentMessageLogStore,
+    SessionStore, SignedPreKeyStore, TransactionalProtocolStore,
 };
@@ -8,16 +8,19 @@
 //! These implementations are purely in-memory, and therefore most likely useful for testing.
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use uuid::Uuid;
 
-use crate::storage::traits::{self, IdentityChange};
+use crate::state::GenericSignedPreKey;
+use crate::storage::traits::{self, IdentityChange, SavedIdentity, SentMessage};
 use crate::{
-    CiphertextMessageType, IdentityKey, IdentityKeyPair, KyberPreKeyId, KyberPreKeyRecord,
-    PreKeyId, PreKeyRecord, ProtocolAddress, PublicKey, Result, SenderKeyRecord, SessionRecord,
-    SignalProtocolError, SignedPreKeyId, SignedPreKeyRecord,
+    IdentityKey, IdentityKeyPair, IdentitySuccessionChain, KyberPreKeyId, KyberPreKeyKind,
+    KyberPreKeyRecord, PreKeyId, PreKeyRecord, ProtocolAddress, PublicKey, Result, SenderKeyRecord,
+    SessionRecord, SignalProtocolError, SignedPreKeyId, SignedPreKeyRecord, Timestamp,
 };
 
 /// Reference implementation of [traits::IdentityKeyStore].
@@ -26,6 +29,7 @@ pub struct InMemIdentityKeyStore {
     key_pair: IdentityKeyPair,
     registration_id: u32,
     known_keys: HashMap<ProtocolAddress, IdentityKey>,
+    verified_successions: HashSet<ProtocolAddress>,
 }
 
 impl InMemIdentityKeyStore {
@@ -38,12 +42,14 @@ impl InMemIdentityKeyStore {
             key_pair,
             registration_id,
             known_keys: HashMap::new(),
+            verified_successions: HashSet::new(),
         }
     }
 
     /// Clear the mapping of known keys.
     pub fn reset(&mut self) {
         self.known_keys.clear();
+        self.verified_successions.clear();
     }
 }
 
@@ -70,11 +76,33 @@ impl traits::IdentityKeyStore for InMemIdentityKeyStore {
             Some(k) if k == identity => Ok(IdentityChange::NewOrUnchanged),
             Some(_k) => {
                 self.known_keys.insert(address.clone(), *identity);
+                self.verified_successions.remove(address);
                 Ok(IdentityChange::ReplacedExisting)
             }
         }
     }
 
+    async fn save_identity_with_succession(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        succession: &IdentitySuccessionChain,
+    ) -> Result<SavedIdentity> {
+        let verified_succession = self
+            .known_keys
+            .get(address)
+            .is_some_and(|existing| succession.verify(existing, identity));
+        let change = self.save_identity(address, identity).await?;
+        let verified_succession = verified_succession && change == IdentityChange::ReplacedExisting;
+        if verified_succession {
+            self.verified_successions.insert(address.clone());
+        }
+        Ok(SavedIdentity {
+            change,
+            verified_succession,
+        })
+    }
+
     async fn is_trusted_identity(
         &self,
         address: &ProtocolAddress,
@@ -95,6 +123,10 @@ impl traits::IdentityKeyStore for InMemIdentityKeyStore {
             Some(k) => Ok(Some(k.to_owned())),
         }
     }
+
+    async fn is_verified_succession(&self, address: &ProtocolAddress) -> Result<bool> {
+        Ok(self.verified_successions.contains(address))
+    }
 }
 
 /// Reference implementation of [traits::PreKeyStore].
@@ -195,12 +227,13 @@ impl traits::SignedPreKeyStore for InMemSignedPreKeyStore {
 
 /// Basic implementation of [traits::KyberPreKeyStore].
 ///
-/// Note that this implementation does not clear any keys upon use! This is correct for last-resort
-/// keys, but a real client would normally have a set of one-time keys to use first.
+/// One-time keys are removed as soon as they are used. Last-resort keys are kept, along with a
+/// bounded number of the most recent base keys used with them; those are forgotten when the key is
+/// [removed](Self::remove_kyber_pre_key) or replaced.
 #[derive(Clone)]
 pub struct InMemKyberPreKeyStore {
     kyber_pre_keys: HashMap<KyberPreKeyId, KyberPreKeyRecord>,
-    base_keys_seen: HashMap<(KyberPreKeyId, SignedPreKeyId), Vec<PublicKey>>,
+    base_keys_seen: HashMap<KyberPreKeyId, VecDeque<(SignedPreKeyId, PublicKey)>>,
 }
 
 impl InMemKyberPreKeyStore {
@@ -216,6 +249,14 @@ impl InMemKyberPreKeyStore {
     pub fn all_kyber_pre_key_ids(&self) -> impl Iterator<Item = &KyberPreKeyId> {
         self.kyber_pre_keys.keys()
     }
+
+    /// Removes the entry for `kyber_prekey_id`, along with the base keys seen with it.
+    ///
+    /// Used to clean up last-resort keys after they have been rotated out.
+    pub fn remove_kyber_pre_key(&mut self, kyber_prekey_id: KyberPreKeyId) {
+        self.kyber_pre_keys.remove(&kyber_prekey_id);
+        self.base_keys_seen.remove(&kyber_prekey_id);
+    }
 }
 
 impl Default for InMemKyberPreKeyStore {
@@ -239,8 +280,15 @@ impl traits::KyberPreKeyStore for InMemKyberPreKeyStore {
         kyber_prekey_id: KyberPreKeyId,
         record: &KyberPreKeyRecord,
     ) -> Result<()> {
-        self.kyber_pre_keys
+        let previous = self
+            .kyber_pre_keys
             .insert(kyber_prekey_id, record.to_owned());
+        if previous.is_some_and(|previous| {
+            previous.get_storage().public_key != record.get_storage().public_key
+        }) {
+            // A different key reusing the ID says nothing about the old key's base keys.
+            self.base_keys_seen.remove(&kyber_prekey_id);
+        }
         Ok(())
     }
 
@@ -250,17 +298,28 @@ impl traits::KyberPreKeyStore for InMemKyberPreKeyStore {
         ec_prekey_id: SignedPreKeyId,
         base_key: &PublicKey,
     ) -> Result<()> {
-        let base_keys_seen = self
-            .base_keys_seen
-            .entry((kyber_prekey_id, ec_prekey_id))
-            .or_default();
-        if base_keys_seen.contains(base_key) {
-            return Err(SignalProtocolError::InvalidMessage(
-                CiphertextMessageType::PreKey,
-                "reused base key",
-            ));
+        let record = self
+            .kyber_pre_keys
+            .get(&kyber_prekey_id)
+            .ok_or(SignalProtocolError::InvalidKyberPreKeyId)?;
+        match record.kind() {
+            KyberPreKeyKind::OneTime => {
+                self.kyber_pre_keys.remove(&kyber_prekey_id);
+            }
+            KyberPreKeyKind::LastResort => {
+                let base_keys_seen = self.base_keys_seen.entry(kyber_prekey_id).or_default();
+                if base_keys_seen.contains(&(ec_prekey_id, *base_key)) {
+                    return Err(SignalProtocolError::ReusedKyberPreKeyBaseKey {
+                        kyber_pre_key_id: kyber_prekey_id,
+                        signed_pre_key_id: ec_prekey_id,
+                    });
+                }
+                if base_keys_seen.len() >= super::MAX_BASE_KEYS_PER_LAST_RESORT_KEY {
+                    base_keys_seen.pop_front();
+                }
+                base_keys_seen.push_back((ec_prekey_id, *base_key));
+            }
         }
-        base_keys_seen.push(*base_key);
         Ok(())
     }
 }
@@ -374,6 +433,103 @@ impl traits::SenderKeyStore for InMemSenderKeyStore {
     }
 }
 
+/// Reference implementation of [traits::SentMessageLogStore].
+///
+/// Keeps at most a fixed number of messages, dropping the oldest first, and forgets messages past a
+/// fixed age when [pruned](Self::prune). Content sent to several devices at once is stored only
+/// once.
+#[derive(Clone)]
+pub struct InMemSentMessageLogStore {
+    max_age: Duration,
+    max_messages: usize,
+    messages: BTreeMap<Timestamp, HashMap<ProtocolAddress, Arc<[u8]>>>,
+}
+
+impl InMemSentMessageLogStore {
+    /// Create an empty log that keeps messages for a day, up to 10,000 of them.
+    pub fn new() -> Self {
+        Self::with_limits(Duration::from_secs(24 * 60 * 60), 10_000)
+    }
+
+    /// Create an empty log with the given retention limits.
+    pub fn with_limits(max_age: Duration, max_messages: usize) -> Self {
+        Self {
+            max_age,
+            max_messages,
+            messages: BTreeMap::new(),
+        }
+    }
+
+    /// Forget every message sent more than the maximum age before `now`.
+    ///
+    /// Messages with timestamps after `now` are kept.
+    pub fn prune(&mut self, now: Timestamp) {
+        let cutoff = Timestamp::from_epoch_millis(
+            now.epoch_millis()
+                .saturating_sub(self.max_age.as_millis().try_into().unwrap_or(u64::MAX)),
+        );
+        self.messages = self.messages.split_off(&cutoff);
+    }
+
+    fn enforce_message_limit(&mut self) {
+        while self.messages.len() > self.max_messages {
+            self.messages.pop_first();
+        }
+    }
+}
+
+impl Default for InMemSentMessageLogStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SentMessageLogStore for InMemSentMessageLogStore {
+    async fn log_sent_message(
+        &mut self,
+        recipients: &[&ProtocolAddress],
+        message: &SentMessage,
+    ) -> Result<()> {
+        let content: Arc<[u8]> = Arc::from(&*message.content);
+        let by_recipient = self.messages.entry(message.timestamp).or_default();
+        for &recipient in recipients {
+            by_recipient.insert(recipient.clone(), content.clone());
+        }
+        self.enforce_message_limit();
+        Ok(())
+    }
+
+    async fn load_sent_message(
+        &self,
+        recipient: &ProtocolAddress,
+        timestamp: Timestamp,
+    ) -> Result<Option<SentMessage>> {
+        Ok(self
+            .messages
+            .get(&timestamp)
+            .and_then(|by_recipient| by_recipient.get(recipient))
+            .map(|content| SentMessage {
+                timestamp,
+                content: Box::from(&**content),
+            }))
+    }
+
+    async fn remove_sent_message(
+        &mut self,
+        recipient: &ProtocolAddress,
+        timestamp: Timestamp,
+    ) -> Result<()> {
+        if let Some(by_recipient) = self.messages.get_mut(&timestamp) {
+            by_recipient.remove(recipient);
+            if by_recipient.is_empty() {
+                self.messages.remove(&timestamp);
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Reference implementation of [traits::ProtocolStore].
 #[allow(missing_docs)]
 #[derive(Clone)]
@@ -414,6 +570,12 @@ impl InMemSignalProtocolStore {
     pub fn all_kyber_pre_key_ids(&self) -> impl Iterator<Item = &KyberPreKeyId> {
         self.kyber_pre_key_store.all_kyber_pre_key_ids()
     }
+
+    /// See [`InMemKyberPreKeyStore::remove_kyber_pre_key`].
+    pub fn remove_kyber_pre_key(&mut self, kyber_prekey_id: KyberPreKeyId) {
+        self.kyber_pre_key_store
+            .remove_kyber_pre_key(kyber_prekey_id)
+    }
 }
 
 #[async_trait(?Send)]
@@ -434,6 +596,17 @@ impl traits::IdentityKeyStore for InMemSignalProtocolStore {
         self.identity_store.save_identity(address, identity).await
     }
 
+    async fn save_identity_with_succession(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        succession: &IdentitySuccessionChain,
+    ) -> Result<SavedIdentity> {
+        self.identity_store
+            .save_identity_with_succession(address, identity, succession)
+            .await
+    }
+
     async fn is_trusted_identity(
         &self,
         address: &ProtocolAddress,
@@ -448,6 +621,10 @@ impl traits::IdentityKeyStore for InMemSignalProtocolStore {
     async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
         self.identity_store.get_identity(address).await
     }
+
+    async fn is_verified_succession(&self, address: &ProtocolAddress) -> Result<bool> {
+        self.identity_store.is_verified_succession(address).await
+    }
 }
 
 #[async_trait(?Send)]
@@ -552,3 +729,236 @@ impl traits::SenderKeyStore for InMemSignalProtocolStore {
 }
 
 impl traits::ProtocolStore for InMemSignalProtocolStore {}
+
+/// A mutating step of a [traits::ProtocolStoreTransaction], for use with
+/// [InMemTransactionalProtocolStore::fail_at].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TransactionStep {
+    /// [traits::IdentityKeyStore::save_identity]
+    SaveIdentity,
+    /// [traits::PreKeyStore::remove_pre_key]
+    RemovePreKey,
+    /// [traits::KyberPreKeyStore::mark_kyber_pre_key_used]
+    MarkKyberPreKeyUsed,
+    /// [traits::SessionStore::store_session]
+    StoreSession,
+    /// [traits::ProtocolStoreTransaction::commit_transaction]
+    Commit,
+}
+
+/// Reference implementation of [traits::TransactionalProtocolStore].
+///
+/// Wraps an [InMemSignalProtocolStore], taking a snapshot when a transaction begins and restoring
+/// it on rollback. For testing, the store can be told to fail at a particular [TransactionStep].
+#[derive(Clone)]
+pub struct InMemTransactionalProtocolStore {
+    store: InMemSignalProtocolStore,
+    snapshot: Option<InMemSignalProtocolStore>,
+    fail_at: Option<TransactionStep>,
+}
+
+impl InMemTransactionalProtocolStore {
+    /// Wrap an existing store.
+    pub fn new(store: InMemSignalProtocolStore) -> Self {
+        Self {
+            store,
+            snapshot: None,
+            fail_at: None,
+        }
+    }
+
+    /// Make every subsequent attempt at `step` fail, or clear a previously set failure.
+    pub fn fail_at(&mut self, step: Option<TransactionStep>) {
+        self.fail_at = step;
+    }
+
+    /// Access the wrapped store.
+    pub fn inner(&self) -> &InMemSignalProtocolStore {
+        &self.store
+    }
+
+    /// Unwrap the store, discarding any transaction in progress.
+    pub fn into_inner(self) -> InMemSignalProtocolStore {
+        self.snapshot.unwrap_or(self.store)
+    }
+
+    fn check_step(&self, step: TransactionStep, method: &'static str) -> Result<()> {
+        if self.fail_at == Some(step) {
+            return Err(SignalProtocolError::InvalidState(
+                method,
+                "simulated store failure".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::ProtocolStoreTransaction for InMemTransactionalProtocolStore {
+    async fn begin_transaction(&mut self) -> Result<()> {
+        if self.snapshot.is_some() {
+            return Err(SignalProtocolError::InvalidState(
+                "begin_transaction",
+                "transaction already in progress".to_string(),
+            ));
+        }
+        self.snapshot = Some(self.store.clone());
+        Ok(())
+    }
+
+    async fn commit_transaction(&mut self) -> Result<()> {
+        self.check_step(TransactionStep::Commit, "commit_transaction")?;
+        self.snapshot.take().ok_or_else(|| {
+            SignalProtocolError::InvalidState(
+                "commit_transaction",
+                "no transaction in progress".to_string(),
+            )
+        })?;
+        Ok(())
+    }
+
+    async fn rollback_transaction(&mut self) -> Result<()> {
+        self.store = self.snapshot.take().ok_or_else(|| {
+            SignalProtocolError::InvalidState(
+                "rollback_transaction",
+                "no transaction in progress".to_string(),
+            )
+        })?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityKeyStore for InMemTransactionalProtocolStore {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair> {
+        self.store.get_identity_key_pair().await
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32> {
+        self.store.get_local_registration_id().await
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange> {
+        self.check_step(TransactionStep::SaveIdentity, "save_identity")?;
+        self.store.save_identity(address, identity).await
+    }
+
+    async fn save_identity_with_succession(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        succession: &IdentitySuccessionChain,
+    ) -> Result<SavedIdentity> {
+        self.check_step(
+            TransactionStep::SaveIdentity,
+            "save_identity_with_succession",
+        )?;
+        self.store
+            .save_identity_with_succession(address, identity, succession)
+            .await
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        direction: traits::Direction,
+    ) -> Result<bool> {
+        self.store
+            .is_trusted_identity(address, identity, direction)
+            .await
+    }
+
+    async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
+        self.store.get_identity(address).await
+    }
+
+    async fn is_verified_succession(&self, address: &ProtocolAddress) -> Result<bool> {
+        self.store.is_verified_succession(address).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::PreKeyStore for InMemTransactionalProtocolStore {
+    async fn get_pre_key(&self, id: PreKeyId) -> Result<PreKeyRecord> {
+        self.store.get_pre_key(id).await
+    }
+
+    async fn save_pre_key(&mut self, id: PreKeyId, record: &PreKeyRecord) -> Result<()> {
+        self.store.save_pre_key(id, record).await
+    }
+
+    async fn remove_pre_key(&mut self, id: PreKeyId) -> Result<()> {
+        self.check_step(TransactionStep::RemovePreKey, "remove_pre_key")?;
+        self.store.remove_pre_key(id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SignedPreKeyStore for InMemTransactionalProtocolStore {
+    async fn get_signed_pre_key(&self, id: SignedPreKeyId) -> Result<SignedPreKeyRecord> {
+        self.store.get_signed_pre_key(id).await
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<()> {
+        self.store.save_signed_pre_key(id, record).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::KyberPreKeyStore for InMemTransactionalProtocolStore {
+    async fn get_kyber_pre_key(&self, kyber_prekey_id: KyberPreKeyId) -> Result<KyberPreKeyRecord> {
+        self.store.get_kyber_pre_key(kyber_prekey_id).await
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<()> {
+        self.store.save_kyber_pre_key(kyber_prekey_id, record).await
+    }
+
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<()> {
+        self.check_step(
+            TransactionStep::MarkKyberPreKeyUsed,
+            "mark_kyber_pre_key_used",
+        )?;
+        self.store
+            .mark_kyber_pre_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SessionStore for InMemTransactionalProtocolStore {
+    async fn load_session(&self, address: &ProtocolAddress) -> Result<Option<SessionRecord>> {
+        self.store.load_session(address).await
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<()> {
+        self.check_step(TransactionStep::StoreSession, "store_session")?;
+        self.store.store_session(address, record).await
+    }
+}
+
+impl traits::ProtocolStore for InMemTransactionalProtocolStore {}
+
+impl traits::TransactionalProtocolStore for InMemTransactionalProtocolStore {}
@@ -0,0 +1,892 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Implementations for stores defined in [super::traits] that persist to a SQLite database.
+//!
+//! Serialized records (sessions, sender keys, pre-keys, and the local identity key pair) are
+//! encrypted at rest with AES-256-GCM-SIV under a caller-provided storage key. Addresses and
+//! pre-key IDs are stored in the clear so that they can be used as lookup keys.
+//!
+//! Like the [in-memory stores](super::inmem), the individual stores share a single underlying
+//! database and can be borrowed separately from a [SqliteSignalProtocolStore].
+
+use std::path::Path;
+use std::rc::Rc;
+
+use aes_gcm_siv::aead::{Aead, Payload};
+use aes_gcm_siv::{Aes256GcmSiv, KeyInit, Nonce};
+use async_trait::async_trait;
+use rand::{Rng as _, TryRngCore as _};
+use rusqlite::{Connection, OptionalExtension as _, params};
+use uuid::Uuid;
+
+use crate::state::GenericSignedPreKey;
+use crate::storage::traits::{self, IdentityChange, SavedIdentity};
+use crate::{
+    IdentityKey, IdentityKeyPair, IdentitySuccessionChain, KyberPreKeyId, KyberPreKeyKind,
+    KyberPreKeyRecord, PreKeyId, PreKeyRecord, ProtocolAddress, PublicKey, Result, SenderKeyRecord,
+    SessionRecord, SignalProtocolError, SignedPreKeyId, SignedPreKeyRecord,
+};
+
+/// Schema migrations, applied in order. The database's `user_version` records how many of these
+/// have been applied.
+///
+/// Never edit an existing entry; add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // Version 1: initial schema.
+    "CREATE TABLE local_identity (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        key_pair BLOB NOT NULL,
+        registration_id INTEGER NOT NULL
+    );
+    CREATE TABLE identities (
+        name TEXT NOT NULL,
+        device_id INTEGER NOT NULL,
+        identity_key BLOB NOT NULL,
+        PRIMARY KEY (name, device_id)
+    );
+    CREATE TABLE sessions (
+        name TEXT NOT NULL,
+        device_id INTEGER NOT NULL,
+        record BLOB NOT NULL,
+        PRIMARY KEY (name, device_id)
+    );
+    CREATE TABLE pre_keys (
+        id INTEGER PRIMARY KEY,
+        record BLOB NOT NULL
+    );
+    CREATE TABLE signed_pre_keys (
+        id INTEGER PRIMARY KEY,
+        record BLOB NOT NULL
+    );
+    CREATE TABLE kyber_pre_keys (
+        id INTEGER PRIMARY KEY,
+        record BLOB NOT NULL
+    );
+    CREATE TABLE kyber_base_keys_seen (
+        kyber_pre_key_id INTEGER NOT NULL,
+        signed_pre_key_id INTEGER NOT NULL,
+        base_key BLOB NOT NULL,
+        PRIMARY KEY (kyber_pre_key_id, signed_pre_key_id, base_key)
+    );
+    CREATE TABLE sender_keys (
+        name TEXT NOT NULL,
+        device_id INTEGER NOT NULL,
+        distribution_id BLOB NOT NULL,
+        record BLOB NOT NULL,
+        PRIMARY KEY (name, device_id, distribution_id)
+    );",
+    // Version 2: remember identities saved with a verified succession.
+    "ALTER TABLE identities ADD COLUMN verified_succession INTEGER NOT NULL DEFAULT 0;",
+];
+
+const NONCE_LEN: usize = 12;
+
+/// Length of the key passed to [SqliteSignalProtocolStore::open].
+pub const STORAGE_KEY_LEN: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+#[error("database error: {0}")]
+struct DatabaseError(String);
+
+fn db_error(method: &'static str) -> impl FnOnce(rusqlite::Error) -> SignalProtocolError {
+    move |e| SignalProtocolError::for_application_callback(method)(DatabaseError(e.to_string()))
+}
+
+/// The connection and record cipher shared by every store in a [SqliteSignalProtocolStore].
+struct Database {
+    conn: Connection,
+    cipher: Aes256GcmSiv,
+}
+
+impl Database {
+    fn new(mut conn: Connection, storage_key: &[u8; STORAGE_KEY_LEN]) -> Result<Rc<Self>> {
+        migrate(&mut conn)?;
+        Ok(Rc::new(Self {
+            conn,
+            cipher: Aes256GcmSiv::new(&(*storage_key).into()),
+        }))
+    }
+
+    /// Encrypts `plaintext`, binding it to `context` so that blobs can't be swapped between rows.
+    fn seal(&self, context: &str, plaintext: &[u8]) -> Vec<u8> {
+        let nonce: [u8; NONCE_LEN] = rand::rngs::OsRng.unwrap_err().random();
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: context.as_bytes(),
+                },
+            )
+            .expect("can encrypt arbitrary data");
+        [&nonce[..], &ciphertext[..]].concat()
+    }
+
+    fn open(&self, context: &str, blob: &[u8]) -> Result<Vec<u8>> {
+        let corrupt = || {
+            SignalProtocolError::InvalidState(
+                "load stored record",
+                format!("cannot decrypt {context} (corrupt, or wrong storage key)"),
+            )
+        };
+        if blob.len() < NONCE_LEN {
+            return Err(corrupt());
+        }
+        let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: context.as_bytes(),
+                },
+            )
+            .map_err(|_| corrupt())
+    }
+
+    fn load<P: rusqlite::Params>(
+        &self,
+        method: &'static str,
+        sql: &str,
+        params: P,
+        context: &str,
+    ) -> Result<Option<Vec<u8>>> {
+        let blob: Option<Vec<u8>> = self
+            .conn
+            .query_row(sql, params, |row| row.get(0))
+            .optional()
+            .map_err(db_error(method))?;
+        blob.map(|blob| self.open(context, &blob)).transpose()
+    }
+
+    fn execute<P: rusqlite::Params>(
+        &self,
+        method: &'static str,
+        sql: &str,
+        params: P,
+    ) -> Result<usize> {
+        self.conn.execute(sql, params).map_err(db_error(method))
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: u32 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(db_error("migrate"))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        return Err(SignalProtocolError::InvalidState(
+            "migrate",
+            format!(
+                "database schema version {version} is newer than the latest supported ({})",
+                MIGRATIONS.len()
+            ),
+        ));
+    }
+
+    let tx = conn.transaction().map_err(db_error("migrate"))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        log::info!("migrating protocol store to schema version {}", i + 1);
+        tx.execute_batch(migration).map_err(db_error("migrate"))?;
+        tx.pragma_update(None, "user_version", i + 1)
+            .map_err(db_error("migrate"))?;
+    }
+    tx.commit().map_err(db_error("migrate"))
+}
+
+fn address_context(table: &str, address: &ProtocolAddress) -> String {
+    format!("{table}:{}.{}", address.name(), address.device_id())
+}
+
+/// SQLite implementation of [traits::IdentityKeyStore].
+pub struct SqliteIdentityKeyStore {
+    db: Rc<Database>,
+}
+
+impl SqliteIdentityKeyStore {
+    /// Set the identity the store represents, replacing any previous one.
+    ///
+    /// `key_pair` corresponds to [traits::IdentityKeyStore::get_identity_key_pair], and
+    /// `registration_id` corresponds to [traits::IdentityKeyStore::get_local_registration_id].
+    pub fn set_local_identity(
+        &mut self,
+        key_pair: &IdentityKeyPair,
+        registration_id: u32,
+    ) -> Result<()> {
+        let key_pair = self.db.seal("local_identity", &key_pair.serialize());
+        self.db.execute(
+            "set_local_identity",
+            "INSERT OR REPLACE INTO local_identity (id, key_pair, registration_id) VALUES (0, ?1, ?2)",
+            params![key_pair, registration_id],
+        )?;
+        Ok(())
+    }
+
+    fn local_identity_missing(method: &'static str) -> SignalProtocolError {
+        SignalProtocolError::InvalidState(method, "local identity has not been set".to_string())
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityKeyStore for SqliteIdentityKeyStore {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair> {
+        let bytes = self
+            .db
+            .load(
+                "get_identity_key_pair",
+                "SELECT key_pair FROM local_identity WHERE id = 0",
+                [],
+                "local_identity",
+            )?
+            .ok_or_else(|| Self::local_identity_missing("get_identity_key_pair"))?;
+        IdentityKeyPair::try_from(&bytes[..])
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32> {
+        self.db
+            .conn
+            .query_row(
+                "SELECT registration_id FROM local_identity WHERE id = 0",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error("get_local_registration_id"))?
+            .ok_or_else(|| Self::local_identity_missing("get_local_registration_id"))
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange> {
+        let change = match self.get_identity(address).await? {
+            None => IdentityChange::NewOrUnchanged,
+            Some(k) if &k == identity => return Ok(IdentityChange::NewOrUnchanged),
+            Some(_k) => IdentityChange::ReplacedExisting,
+        };
+        self.db.execute(
+            "save_identity",
+            "INSERT OR REPLACE INTO identities (name, device_id, identity_key) VALUES (?1, ?2, ?3)",
+            params![
+                address.name(),
+                u32::from(address.device_id()),
+                &identity.serialize()[..]
+            ],
+        )?;
+        Ok(change)
+    }
+
+    async fn save_identity_with_succession(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        succession: &IdentitySuccessionChain,
+    ) -> Result<SavedIdentity> {
+        let verified_succession = self
+            .get_identity(address)
+            .await?
+            .is_some_and(|existing| succession.verify(&existing, identity));
+        let change = self.save_identity(address, identity).await?;
+        let verified_succession = verified_succession && change == IdentityChange::ReplacedExisting;
+        if verified_succession {
+            self.db.execute(
+                "save_identity_with_succession",
+                "UPDATE identities SET verified_succession = 1 WHERE name = ?1 AND device_id = ?2",
+                params![address.name(), u32::from(address.device_id())],
+            )?;
+        }
+        Ok(SavedIdentity {
+            change,
+            verified_succession,
+        })
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        _direction: traits::Direction,
+    ) -> Result<bool> {
+        match self.get_identity(address).await? {
+            None => {
+                Ok(true) // first use
+            }
+            Some(k) => Ok(&k == identity),
+        }
+    }
+
+    async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
+        let bytes: Option<Vec<u8>> = self
+            .db
+            .conn
+            .query_row(
+                "SELECT identity_key FROM identities WHERE name = ?1 AND device_id = ?2",
+                params![address.name(), u32::from(address.device_id())],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error("get_identity"))?;
+        bytes.map(|bytes| IdentityKey::decode(&bytes)).transpose()
+    }
+
+    async fn is_verified_succession(&self, address: &ProtocolAddress) -> Result<bool> {
+        Ok(self
+            .db
+            .conn
+            .query_row(
+                "SELECT verified_succession FROM identities WHERE name = ?1 AND device_id = ?2",
+                params![address.name(), u32::from(address.device_id())],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error("is_verified_succession"))?
+            .unwrap_or(false))
+    }
+}
+
+/// SQLite implementation of [traits::PreKeyStore].
+pub struct SqlitePreKeyStore {
+    db: Rc<Database>,
+}
+
+#[async_trait(?Send)]
+impl traits::PreKeyStore for SqlitePreKeyStore {
+    async fn get_pre_key(&self, id: PreKeyId) -> Result<PreKeyRecord> {
+        let bytes = self
+            .db
+            .load(
+                "get_pre_key",
+                "SELECT record FROM pre_keys WHERE id = ?1",
+                [u32::from(id)],
+                &format!("pre_key:{id}"),
+            )?
+            .ok_or(SignalProtocolError::InvalidPreKeyId)?;
+        PreKeyRecord::deserialize(&bytes)
+    }
+
+    async fn save_pre_key(&mut self, id: PreKeyId, record: &PreKeyRecord) -> Result<()> {
+        let record = self.db.seal(&format!("pre_key:{id}"), &record.serialize()?);
+        self.db.execute(
+            "save_pre_key",
+            "INSERT OR REPLACE INTO pre_keys (id, record) VALUES (?1, ?2)",
+            params![u32::from(id), record],
+        )?;
+        Ok(())
+    }
+
+    async fn remove_pre_key(&mut self, id: PreKeyId) -> Result<()> {
+        // If id does not exist this silently does nothing
+        self.db.execute(
+            "remove_pre_key",
+            "DELETE FROM pre_keys WHERE id = ?1",
+            [u32::from(id)],
+        )?;
+        Ok(())
+    }
+}
+
+/// SQLite implementation of [traits::SignedPreKeyStore].
+pub struct SqliteSignedPreKeyStore {
+    db: Rc<Database>,
+}
+
+#[async_trait(?Send)]
+impl traits::SignedPreKeyStore for SqliteSignedPreKeyStore {
+    async fn get_signed_pre_key(&self, id: SignedPreKeyId) -> Result<SignedPreKeyRecord> {
+        let bytes = self
+            .db
+            .load(
+                "get_signed_pre_key",
+                "SELECT record FROM signed_pre_keys WHERE id = ?1",
+                [u32::from(id)],
+                &format!("signed_pre_key:{id}"),
+            )?
+            .ok_or(SignalProtocolError::InvalidSignedPreKeyId)?;
+        SignedPreKeyRecord::deserialize(&bytes)
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<()> {
+        let record = self
+            .db
+            .seal(&format!("signed_pre_key:{id}"), &record.serialize()?);
+        self.db.execute(
+            "save_signed_pre_key",
+            "INSERT OR REPLACE INTO signed_pre_keys (id, record) VALUES (?1, ?2)",
+            params![u32::from(id), record],
+        )?;
+        Ok(())
+    }
+}
+
+/// SQLite implementation of [traits::KyberPreKeyStore].
+///
+/// Like [InMemKyberPreKeyStore](super::InMemKyberPreKeyStore), this deletes one-time keys upon use
+/// and remembers a bounded number of base keys for each last-resort key.
+pub struct SqliteKyberPreKeyStore {
+    db: Rc<Database>,
+}
+
+impl SqliteKyberPreKeyStore {
+    fn load_record(&self, kyber_prekey_id: KyberPreKeyId) -> Result<Option<KyberPreKeyRecord>> {
+        self.db
+            .load(
+                "get_kyber_pre_key",
+                "SELECT record FROM kyber_pre_keys WHERE id = ?1",
+                [u32::from(kyber_prekey_id)],
+                &format!("kyber_pre_key:{kyber_prekey_id}"),
+            )?
+            .map(|bytes| KyberPreKeyRecord::deserialize(&bytes))
+            .transpose()
+    }
+
+    fn forget_base_keys(&self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.db.execute(
+            "forget_kyber_base_keys",
+            "DELETE FROM kyber_base_keys_seen WHERE kyber_pre_key_id = ?1",
+            [u32::from(kyber_prekey_id)],
+        )?;
+        Ok(())
+    }
+
+    /// Removes the entry for `kyber_prekey_id`, along with the base keys seen with it.
+    ///
+    /// Used to clean up last-resort keys after they have been rotated out.
+    pub fn remove_kyber_pre_key(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        // If id does not exist this silently does nothing
+        self.db.execute(
+            "remove_kyber_pre_key",
+            "DELETE FROM kyber_pre_keys WHERE id = ?1",
+            [u32::from(kyber_prekey_id)],
+        )?;
+        self.forget_base_keys(kyber_prekey_id)
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::KyberPreKeyStore for SqliteKyberPreKeyStore {
+    async fn get_kyber_pre_key(&self, kyber_prekey_id: KyberPreKeyId) -> Result<KyberPreKeyRecord> {
+        self.load_record(kyber_prekey_id)?
+            .ok_or(SignalProtocolError::InvalidKyberPreKeyId)
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<()> {
+        if self.load_record(kyber_prekey_id)?.is_some_and(|previous| {
+            previous.get_storage().public_key != record.get_storage().public_key
+        }) {
+            // A different key reusing the ID says nothing about the old key's base keys.
+            self.forget_base_keys(kyber_prekey_id)?;
+        }
+
+        let record = self.db.seal(
+            &format!("kyber_pre_key:{kyber_prekey_id}"),
+            &record.serialize()?,
+        );
+        self.db.execute(
+            "save_kyber_pre_key",
+            "INSERT OR REPLACE INTO kyber_pre_keys (id, record) VALUES (?1, ?2)",
+            params![u32::from(kyber_prekey_id), record],
+        )?;
+        Ok(())
+    }
+
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<()> {
+        let record = self
+            .load_record(kyber_prekey_id)?
+            .ok_or(SignalProtocolError::InvalidKyberPreKeyId)?;
+        if record.kind() == KyberPreKeyKind::OneTime {
+            return self.remove_kyber_pre_key(kyber_prekey_id);
+        }
+
+        let inserted = self.db.execute(
+            "mark_kyber_pre_key_used",
+            "INSERT OR IGNORE INTO kyber_base_keys_seen \
+             (kyber_pre_key_id, signed_pre_key_id, base_key) VALUES (?1, ?2, ?3)",
+            params![
+                u32::from(kyber_prekey_id),
+                u32::from(ec_prekey_id),
+                &base_key.serialize()[..]
+            ],
+        )?;
+        if inserted == 0 {
+            return Err(SignalProtocolError::ReusedKyberPreKeyBaseKey {
+                kyber_pre_key_id: kyber_prekey_id,
+                signed_pre_key_id: ec_prekey_id,
+            });
+        }
+
+        // Rows are inserted in rowid order, so this keeps the most recent ones.
+        self.db.execute(
+            "mark_kyber_pre_key_used",
+            "DELETE FROM kyber_base_keys_seen WHERE kyber_pre_key_id = ?1 AND rowid NOT IN \
+             (SELECT rowid FROM kyber_base_keys_seen WHERE kyber_pre_key_id = ?1 \
+              ORDER BY rowid DESC LIMIT ?2)",
+            params![
+                u32::from(kyber_prekey_id),
+                super::MAX_BASE_KEYS_PER_LAST_RESORT_KEY
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+/// SQLite implementation of [traits::SessionStore].
+pub struct SqliteSessionStore {
+    db: Rc<Database>,
+}
+
+#[async_trait(?Send)]
+impl traits::SessionStore for SqliteSessionStore {
+    async fn load_session(&self, address: &ProtocolAddress) -> Result<Option<SessionRecord>> {
+        self.db
+            .load(
+                "load_session",
+                "SELECT record FROM sessions WHERE name = ?1 AND device_id = ?2",
+                params![address.name(), u32::from(address.device_id())],
+                &address_context("session", address),
+            )?
+            .map(|bytes| SessionRecord::deserialize(&bytes))
+            .transpose()
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<()> {
+        let record = self
+            .db
+            .seal(&address_context("session", address), &record.serialize()?);
+        self.db.execute(
+            "store_session",
+            "INSERT OR REPLACE INTO sessions (name, device_id, record) VALUES (?1, ?2, ?3)",
+            params![address.name(), u32::from(address.device_id()), record],
+        )?;
+        Ok(())
+    }
+}
+
+/// SQLite implementation of [traits::SenderKeyStore].
+pub struct SqliteSenderKeyStore {
+    db: Rc<Database>,
+}
+
+impl SqliteSenderKeyStore {
+    fn context(sender: &ProtocolAddress, distribution_id: Uuid) -> String {
+        format!(
+            "{}:{distribution_id}",
+            address_context("sender_key", sender)
+        )
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SenderKeyStore for SqliteSenderKeyStore {
+    async fn store_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+        record: &SenderKeyRecord,
+    ) -> Result<()> {
+        let record = self.db.seal(
+            &Self::context(sender, distribution_id),
+            &record.serialize()?,
+        );
+        self.db.execute(
+            "store_sender_key",
+            "INSERT OR REPLACE INTO sender_keys (name, device_id, distribution_id, record) \
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                sender.name(),
+                u32::from(sender.device_id()),
+                &distribution_id.as_bytes()[..],
+                record
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+    ) -> Result<Option<SenderKeyRecord>> {
+        self.db
+            .load(
+                "load_sender_key",
+                "SELECT record FROM sender_keys \
+                 WHERE name = ?1 AND device_id = ?2 AND distribution_id = ?3",
+                params![
+                    sender.name(),
+                    u32::from(sender.device_id()),
+                    &distribution_id.as_bytes()[..]
+                ],
+                &Self::context(sender, distribution_id),
+            )?
+            .map(|bytes| SenderKeyRecord::deserialize(&bytes))
+            .transpose()
+    }
+}
+
+/// SQLite implementation of [traits::ProtocolStore].
+///
+/// All the stores share one database connection. This type also implements
+/// [traits::ProtocolStoreTransaction] using SQLite transactions on that connection.
+#[allow(missing_docs)]
+pub struct SqliteSignalProtocolStore {
+    pub session_store: SqliteSessionStore,
+    pub pre_key_store: SqlitePreKeyStore,
+    pub signed_pre_key_store: SqliteSignedPreKeyStore,
+    pub kyber_pre_key_store: SqliteKyberPreKeyStore,
+    pub identity_store: SqliteIdentityKeyStore,
+    pub sender_key_store: SqliteSenderKeyStore,
+    db: Rc<Database>,
+}
+
+impl SqliteSignalProtocolStore {
+    /// Open (or create) the database at `path`, upgrading its schema if necessary.
+    ///
+    /// `storage_key` is used to encrypt and authenticate every stored record. Opening an existing
+    /// database with a different key will succeed, but loading any record will fail.
+    pub fn open(path: impl AsRef<Path>, storage_key: &[u8; STORAGE_KEY_LEN]) -> Result<Self> {
+        let conn = Connection::open(path).map_err(db_error("open"))?;
+        Ok(Self::from_database(Database::new(conn, storage_key)?))
+    }
+
+    /// Create a store backed by a fresh in-memory database, mostly useful for testing.
+    pub fn open_in_memory(storage_key: &[u8; STORAGE_KEY_LEN]) -> Result<Self> {
+        let conn = Connection::open_in_memory().map_err(db_error("open_in_memory"))?;
+        Ok(Self::from_database(Database::new(conn, storage_key)?))
+    }
+
+    fn from_database(db: Rc<Database>) -> Self {
+        Self {
+            session_store: SqliteSessionStore { db: db.clone() },
+            pre_key_store: SqlitePreKeyStore { db: db.clone() },
+            signed_pre_key_store: SqliteSignedPreKeyStore { db: db.clone() },
+            kyber_pre_key_store: SqliteKyberPreKeyStore { db: db.clone() },
+            identity_store: SqliteIdentityKeyStore { db: db.clone() },
+            sender_key_store: SqliteSenderKeyStore { db: db.clone() },
+            db,
+        }
+    }
+
+    /// See [SqliteIdentityKeyStore::set_local_identity].
+    pub fn set_local_identity(
+        &mut self,
+        key_pair: &IdentityKeyPair,
+        registration_id: u32,
+    ) -> Result<()> {
+        self.identity_store
+            .set_local_identity(key_pair, registration_id)
+    }
+
+    /// See [SqliteKyberPreKeyStore::remove_kyber_pre_key].
+    pub fn remove_kyber_pre_key(&mut self, kyber_prekey_id: KyberPreKeyId) -> Result<()> {
+        self.kyber_pre_key_store
+            .remove_kyber_pre_key(kyber_prekey_id)
+    }
+
+    /// Returns the schema version of the underlying database.
+    pub fn schema_version(&self) -> Result<u32> {
+        self.db
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(db_error("schema_version"))
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::ProtocolStoreTransaction for SqliteSignalProtocolStore {
+    async fn begin_transaction(&mut self) -> Result<()> {
+        self.db
+            .conn
+            .execute_batch("BEGIN IMMEDIATE")
+            .map_err(db_error("begin_transaction"))
+    }
+
+    async fn commit_transaction(&mut self) -> Result<()> {
+        self.db
+            .conn
+            .execute_batch("COMMIT")
+            .map_err(db_error("commit_transaction"))
+    }
+
+    async fn rollback_transaction(&mut self) -> Result<()> {
+        self.db
+            .conn
+            .execute_batch("ROLLBACK")
+            .map_err(db_error("rollback_transaction"))
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::IdentityKeyStore for SqliteSignalProtocolStore {
+    async fn get_identity_key_pair(&self) -> Result<IdentityKeyPair> {
+        self.identity_store.get_identity_key_pair().await
+    }
+
+    async fn get_local_registration_id(&self) -> Result<u32> {
+        self.identity_store.get_local_registration_id().await
+    }
+
+    async fn save_identity(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+    ) -> Result<IdentityChange> {
+        self.identity_store.save_identity(address, identity).await
+    }
+
+    async fn save_identity_with_succession(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        succession: &IdentitySuccessionChain,
+    ) -> Result<SavedIdentity> {
+        self.identity_store
+            .save_identity_with_succession(address, identity, succession)
+            .await
+    }
+
+    async fn is_trusted_identity(
+        &self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        direction: traits::Direction,
+    ) -> Result<bool> {
+        self.identity_store
+            .is_trusted_identity(address, identity, direction)
+            .await
+    }
+
+    async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>> {
+        self.identity_store.get_identity(address).await
+    }
+
+    async fn is_verified_succession(&self, address: &ProtocolAddress) -> Result<bool> {
+        self.identity_store.is_verified_succession(address).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::PreKeyStore for SqliteSignalProtocolStore {
+    async fn get_pre_key(&self, id: PreKeyId) -> Result<PreKeyRecord> {
+        self.pre_key_store.get_pre_key(id).await
+    }
+
+    async fn save_pre_key(&mut self, id: PreKeyId, record: &PreKeyRecord) -> Result<()> {
+        self.pre_key_store.save_pre_key(id, record).await
+    }
+
+    async fn remove_pre_key(&mut self, id: PreKeyId) -> Result<()> {
+        self.pre_key_store.remove_pre_key(id).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SignedPreKeyStore for SqliteSignalProtocolStore {
+    async fn get_signed_pre_key(&self, id: SignedPreKeyId) -> Result<SignedPreKeyRecord> {
+        self.signed_pre_key_store.get_signed_pre_key(id).await
+    }
+
+    async fn save_signed_pre_key(
+        &mut self,
+        id: SignedPreKeyId,
+        record: &SignedPreKeyRecord,
+    ) -> Result<()> {
+        self.signed_pre_key_store
+            .save_signed_pre_key(id, record)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::KyberPreKeyStore for SqliteSignalProtocolStore {
+    async fn get_kyber_pre_key(&self, kyber_prekey_id: KyberPreKeyId) -> Result<KyberPreKeyRecord> {
+        self.kyber_pre_key_store
+            .get_kyber_pre_key(kyber_prekey_id)
+            .await
+    }
+
+    async fn save_kyber_pre_key(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        record: &KyberPreKeyRecord,
+    ) -> Result<()> {
+        self.kyber_pre_key_store
+            .save_kyber_pre_key(kyber_prekey_id, record)
+            .await
+    }
+
+    async fn mark_kyber_pre_key_used(
+        &mut self,
+        kyber_prekey_id: KyberPreKeyId,
+        ec_prekey_id: SignedPreKeyId,
+        base_key: &PublicKey,
+    ) -> Result<()> {
+        self.kyber_pre_key_store
+            .mark_kyber_pre_key_used(kyber_prekey_id, ec_prekey_id, base_key)
+            .await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SessionStore for SqliteSignalProtocolStore {
+    async fn load_session(&self, address: &ProtocolAddress) -> Result<Option<SessionRecord>> {
+        self.session_store.load_session(address).await
+    }
+
+    async fn store_session(
+        &mut self,
+        address: &ProtocolAddress,
+        record: &SessionRecord,
+    ) -> Result<()> {
+        self.session_store.store_session(address, record).await
+    }
+}
+
+#[async_trait(?Send)]
+impl traits::SenderKeyStore for SqliteSignalProtocolStore {
+    async fn store_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+        record: &SenderKeyRecord,
+    ) -> Result<()> {
+        self.sender_key_store
+            .store_sender_key(sender, distribution_id, record)
+            .await
+    }
+
+    async fn load_sender_key(
+        &mut self,
+        sender: &ProtocolAddress,
+        distribution_id: Uuid,
+    ) -> Result<Option<SenderKeyRecord>> {
+        self.sender_key_store
+            .load_sender_key(sender, distribution_id)
+            .await
+    }
+}
+
+impl traits::ProtocolStore for SqliteSignalProtocolStore {}
+
+impl traits::TransactionalProtocolStore for SqliteSignalProtocolStore {}
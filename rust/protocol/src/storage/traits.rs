@@ -14,7 +14,9 @@ use crate::state::{
     KyberPreKeyId, KyberPreKeyRecord, PreKeyId, PreKeyRecord, SessionRecord, SignedPreKeyId,
     SignedPreKeyRecord,
 };
-use crate::{IdentityKey, IdentityKeyPair, ProtocolAddress, PublicKey};
+use crate::{
+    IdentityKey, IdentityKeyPair, IdentitySuccessionChain, ProtocolAddress, PublicKey, Timestamp,
+};
 
 // TODO: consider moving this enum into utils.rs?
 /// Each Signal message can be considered to have exactly two participants, a sender and receiver.
@@ -40,6 +42,18 @@ pub enum IdentityChange {
     ReplacedExisting,
 }
 
+/// The result of [IdentityKeyStore::save_identity_with_succession].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SavedIdentity {
+    /// Whether an existing identity was replaced.
+    pub change: IdentityChange,
+    /// Whether the replacement was proven by a valid [IdentitySuccessionChain] from the identity
+    /// that was previously stored.
+    ///
+    /// Only ever set alongside [IdentityChange::ReplacedExisting].
+    pub verified_succession: bool,
+}
+
 /// Interface defining the identity store, which may be in-memory, on-disk, etc.
 ///
 /// Signal clients usually use the identity store in a [TOFU] manner, but this is not required.
@@ -69,6 +83,33 @@ pub trait IdentityKeyStore {
         identity: &IdentityKey,
     ) -> Result<IdentityChange>;
 
+    /// Record an identity into the store, along with proof that it succeeded the identity
+    /// previously stored for `address`.
+    ///
+    /// If `succession` is a valid chain from the stored identity to `identity`, the result reports
+    /// [IdentityChange::ReplacedExisting] with [SavedIdentity::verified_succession] set, so that
+    /// the change can be presented as a planned rotation rather than an unexplained new key.
+    /// Otherwise this behaves exactly like [Self::save_identity].
+    ///
+    /// The default implementation only reports the flag. Stores that persist it override this so
+    /// that [Self::is_verified_succession] can report it later.
+    async fn save_identity_with_succession(
+        &mut self,
+        address: &ProtocolAddress,
+        identity: &IdentityKey,
+        succession: &IdentitySuccessionChain,
+    ) -> Result<SavedIdentity> {
+        let verified_succession = match self.get_identity(address).await? {
+            Some(existing) => succession.verify(&existing, identity),
+            None => false,
+        };
+        let change = self.save_identity(address, identity).await?;
+        Ok(SavedIdentity {
+            change,
+            verified_succession: verified_succession && change == IdentityChange::ReplacedExisting,
+        })
+    }
+
     /// Return whether an identity is trusted for the role specified by `direction`.
     async fn is_trusted_identity(
         &self,
@@ -79,6 +120,15 @@ pub trait IdentityKeyStore {
 
     /// Return the public identity for the given `address`, if known.
     async fn get_identity(&self, address: &ProtocolAddress) -> Result<Option<IdentityKey>>;
+
+    /// Return whether the identity currently stored for `address` was recorded by
+    /// [Self::save_identity_with_succession] as a verified succession.
+    ///
+    /// The flag is cleared whenever [Self::save_identity] replaces the identity. Stores that don't
+    /// persist the flag always return `false`.
+    async fn is_verified_succession(&self, _address: &ProtocolAddress) -> Result<bool> {
+        Ok(false)
+    }
 }
 
 /// Interface for storing pre-keys downloaded from a server.
@@ -113,7 +163,8 @@ pub trait SignedPreKeyStore {
 
 /// Interface for storing signed Kyber pre-keys downloaded from a server.
 ///
-/// NB: libsignal makes no distinction between one-time and last-resort pre-keys.
+/// Each record knows whether it is a one-time or a last-resort key; see
+/// [`KyberPreKeyRecord::kind`].
 #[async_trait(?Send)]
 pub trait KyberPreKeyStore {
     /// Look up the signed kyber pre-key corresponding to `kyber_prekey_id`.
@@ -128,9 +179,17 @@ pub trait KyberPreKeyStore {
 
     /// Mark the entry for `kyber_prekey_id` as "used".
     ///
-    /// A one-time Kyber pre-key should be deleted after this point. A last-resort pre-key should
-    /// not immediately be deleted, but should check whether the same combination of pre-keys was
-    /// used with the given base key before, and produce an error if so.
+    /// A [one-time](crate::KyberPreKeyKind::OneTime) Kyber pre-key should be deleted after this
+    /// point. A [last-resort](crate::KyberPreKeyKind::LastResort) pre-key should not immediately
+    /// be deleted, but should check whether the same combination of pre-keys was used with the
+    /// given base key before, and produce [`ReusedKyberPreKeyBaseKey`] if so. The base keys seen
+    /// for a last-resort key can be discarded once that key is removed.
+    ///
+    /// The key's kind comes from its stored record, so a `kyber_prekey_id` that isn't present in
+    /// the store (never saved, or already removed) should produce [`InvalidKyberPreKeyId`].
+    ///
+    /// [`ReusedKyberPreKeyBaseKey`]: crate::SignalProtocolError::ReusedKyberPreKeyBaseKey
+    /// [`InvalidKyberPreKeyId`]: crate::SignalProtocolError::InvalidKyberPreKeyId
     async fn mark_kyber_pre_key_used(
         &mut self,
         kyber_prekey_id: KyberPreKeyId,
@@ -179,12 +238,86 @@ pub trait SenderKeyStore {
     ) -> Result<Option<SenderKeyRecord>>;
 }
 
+/// A message kept in a [SentMessageLogStore] so that it can be sent again.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SentMessage {
+    /// The timestamp the message was sent with, which recipients refer to when reporting problems
+    /// (see [DecryptionErrorMessage](crate::DecryptionErrorMessage)).
+    pub timestamp: Timestamp,
+    /// The content to resend.
+    ///
+    /// For messages logged by [sealed_sender_multi_recipient_encrypt_and_log], this is the
+    /// serialized [UnidentifiedSenderMessageContent].
+    ///
+    /// [sealed_sender_multi_recipient_encrypt_and_log]: crate::sealed_sender_multi_recipient_encrypt_and_log
+    /// [UnidentifiedSenderMessageContent]: crate::UnidentifiedSenderMessageContent
+    pub content: Box<[u8]>,
+}
+
+/// Interface for remembering which messages were sent to which devices, so that they can be
+/// resent if a recipient reports that it couldn't decrypt them.
+///
+/// Records are per recipient device and keyed by timestamp. Implementations are expected to bound
+/// how long and how many messages they keep; a message that is no longer logged simply can't be
+/// resent.
+#[async_trait(?Send)]
+pub trait SentMessageLogStore {
+    /// Record that `message` was sent to each of `recipients`.
+    async fn log_sent_message(
+        &mut self,
+        recipients: &[&ProtocolAddress],
+        message: &SentMessage,
+    ) -> Result<()>;
+
+    /// Look up the message sent to `recipient` at `timestamp`, if it is still logged.
+    async fn load_sent_message(
+        &self,
+        recipient: &ProtocolAddress,
+        timestamp: Timestamp,
+    ) -> Result<Option<SentMessage>>;
+
+    /// Forget the message sent to `recipient` at `timestamp`, for example once it has been
+    /// delivered.
+    async fn remove_sent_message(
+        &mut self,
+        recipient: &ProtocolAddress,
+        timestamp: Timestamp,
+    ) -> Result<()>;
+}
+
 /// Mixes in all the store interfaces defined in this module.
 pub trait ProtocolStore:
     SessionStore + PreKeyStore + SignedPreKeyStore + KyberPreKeyStore + IdentityKeyStore
 {
 }
 
+/// Interface for grouping several store mutations into a single atomic unit.
+///
+/// Decrypting a message can touch the session, identity, pre-key, and Kyber pre-key stores. If the
+/// process crashes between those writes, the stores end up inconsistent (e.g. a session is saved
+/// but the one-time pre-key it consumed is not removed). Stores that can provide atomicity
+/// implement this trait so that [`message_decrypt_transactional`] and friends can stage every
+/// mutation and commit them together.
+///
+/// [`message_decrypt_transactional`]: crate::message_decrypt_transactional
+#[async_trait(?Send)]
+pub trait ProtocolStoreTransaction {
+    /// Start a transaction. Mutations made through the store after this call should not be
+    /// durable until [`Self::commit_transaction`] succeeds.
+    async fn begin_transaction(&mut self) -> Result<()>;
+
+    /// Make all mutations since [`Self::begin_transaction`] durable.
+    ///
+    /// If this fails, the caller will follow up with [`Self::rollback_transaction`].
+    async fn commit_transaction(&mut self) -> Result<()>;
+
+    /// Discard all mutations since [`Self::begin_transaction`].
+    async fn rollback_transaction(&mut self) -> Result<()>;
+}
+
+/// A [ProtocolStore] whose mutations can be applied atomically using [ProtocolStoreTransaction].
+pub trait TransactionalProtocolStore: ProtocolStore + ProtocolStoreTransaction {}
+
 impl IdentityChange {
     /// Convenience constructor from a boolean `changed` flag.
     ///
@@ -0,0 +1,496 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+//! Encryption of large payloads as a stream of authenticated chunks.
+//!
+//! A stream starts with an ordinary 1:1 or sender key message (the "key message"), whose plaintext
+//! is a short header giving the chunk size. The stream is keyed from the ratchet keys that key
+//! message was encrypted with, so only that one message goes through the ratchet and the payload
+//! itself never has to be held in memory in full on either side. Because the keys are not part of
+//! the plaintext, the key message must be decrypted with [`message_decrypt_stream`] or
+//! [`group_decrypt_stream`] rather than the usual functions.
+//!
+//! The stream consists of chunks of the chunk size chosen by the sender, each followed by a MAC
+//! that covers every chunk so far (see [`incremental_mac`](crate::incremental_mac)). The last,
+//! possibly empty, partial chunk is followed by a final tag, so that a stream truncated at a chunk
+//! boundary is rejected.
+
+use std::time::SystemTime;
+
+use aes::Aes256;
+use aes::cipher::{KeyIvInit, StreamCipher, StreamCipherError};
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, Rng};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::group_cipher::{group_decrypt_with_message_key, group_encrypt_with_message_key};
+use crate::incremental_mac::{Incremental, calculate_chunk_size};
+use crate::ratchet::MessageKeys;
+use crate::session_cipher::{message_decrypt_with_message_keys, message_encrypt_with_message_keys};
+use crate::{
+    CiphertextMessage, IdentityKeyStore, KyberPreKeyStore, PreKeyStore, ProtocolAddress, Result,
+    SenderKeyMessage, SenderKeyStore, SessionConfig, SessionStore, SignalProtocolError,
+    SignedPreKeyStore, UsePQRatchet,
+};
+
+const HEADER_VERSION: u8 = 1;
+const HEADER_LEN: usize = 1 + 4;
+const MAC_LEN: usize = 32;
+/// The largest chunk size [`calculate_chunk_size`] picks, which bounds the decryptor's buffer.
+const MAX_CHUNK_SIZE: usize = 2 * 1024 * 1024;
+
+type StreamCipherImpl = ctr::Ctr32BE<Aes256>;
+
+struct StreamKeys {
+    cipher: StreamCipherImpl,
+    mac: Incremental<Hmac<Sha256>>,
+    final_tag_key: [u8; 32],
+}
+
+impl StreamKeys {
+    /// Derives the stream keys from the key material of the key message.
+    fn derive(key_material: &[u8], chunk_size: usize) -> Self {
+        let mut derived = [0u8; 96];
+        hkdf::Hkdf::<Sha256>::new(None, key_material)
+            .expand(b"Signal Streaming Message Keys", &mut derived)
+            .expect("valid output length");
+        let (cipher_key, rest) = derived.split_at(32);
+        let (mac_key, final_tag_key) = rest.split_at(32);
+
+        let zero_nonce = [0u8; 16];
+        Self {
+            cipher: StreamCipherImpl::new(cipher_key.into(), zero_nonce[..].into()),
+            mac: Incremental::new(
+                Hmac::<Sha256>::new_from_slice(mac_key).expect("HMAC accepts any key size"),
+                chunk_size,
+            ),
+            final_tag_key: final_tag_key.try_into().expect("correct length"),
+        }
+    }
+
+    fn apply_keystream(&mut self, buf: &mut [u8]) -> std::result::Result<(), StreamCipherError> {
+        self.cipher.try_apply_keystream(buf)
+    }
+
+    /// Feeds one chunk of ciphertext to the MAC, returning the MAC if the chunk was full.
+    fn mac_chunk(&mut self, ciphertext: &[u8]) -> Option<[u8; MAC_LEN]> {
+        self.mac.update(ciphertext).last().map(Into::into)
+    }
+
+    fn final_tag(&self) -> [u8; MAC_LEN] {
+        let mut hmac =
+            Hmac::<Sha256>::new_from_slice(&self.final_tag_key).expect("HMAC accepts any key size");
+        hmac.update(&self.mac.clone().finalize());
+        hmac.finalize().into_bytes().into()
+    }
+}
+
+/// The key material for a stream started by a 1:1 message.
+fn session_key_material(message_keys: &MessageKeys) -> Vec<u8> {
+    [
+        &message_keys.cipher_key()[..],
+        &message_keys.mac_key()[..],
+        &message_keys.iv()[..],
+    ]
+    .concat()
+}
+
+fn encode_header(chunk_size: usize) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[0] = HEADER_VERSION;
+    header[1..].copy_from_slice(
+        &u32::try_from(chunk_size)
+            .expect("chunk size fits in u32")
+            .to_be_bytes(),
+    );
+    header
+}
+
+fn decode_header(header: &[u8]) -> Result<usize> {
+    let header: &[u8; HEADER_LEN] = header
+        .try_into()
+        .map_err(|_| SignalProtocolError::InvalidStreamMessage("bad stream header length"))?;
+    let (version, chunk_size) = header.split_first().expect("non-empty");
+    if *version != HEADER_VERSION {
+        return Err(SignalProtocolError::InvalidStreamMessage(
+            "unknown stream header version",
+        ));
+    }
+    let chunk_size = u32::from_be_bytes(chunk_size.try_into().expect("correct length"))
+        .try_into()
+        .expect("u32 fits in usize");
+    if !(1..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return Err(SignalProtocolError::InvalidStreamMessage(
+            "invalid chunk size",
+        ));
+    }
+    Ok(chunk_size)
+}
+
+/// Encrypts a payload as a stream; see the [module documentation](self).
+///
+/// Created by [`message_encrypt_stream`] or [`group_encrypt_stream`].
+pub struct StreamEncryptor {
+    keys: StreamKeys,
+    chunk_size: usize,
+    pending: Vec<u8>,
+}
+
+impl StreamEncryptor {
+    fn new(key_material: &[u8], chunk_size: usize) -> Self {
+        Self {
+            keys: StreamKeys::derive(key_material, chunk_size),
+            chunk_size,
+            pending: Vec::with_capacity(chunk_size),
+        }
+    }
+
+    fn apply_keystream(keys: &mut StreamKeys, buf: &mut [u8]) -> Result<()> {
+        keys.apply_keystream(buf)
+            .map_err(|_| SignalProtocolError::InvalidArgument("stream is too long".to_owned()))
+    }
+
+    /// Encrypts more of the payload, returning any stream bytes that are ready to send.
+    ///
+    /// Bytes are only returned once a whole chunk is available.
+    pub fn update(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut output = vec![];
+        let mut plaintext = plaintext;
+        while !plaintext.is_empty() {
+            let wanted = self.chunk_size - self.pending.len();
+            let (now, later) = plaintext.split_at(wanted.min(plaintext.len()));
+            self.pending.extend_from_slice(now);
+            plaintext = later;
+
+            if self.pending.len() == self.chunk_size {
+                Self::apply_keystream(&mut self.keys, &mut self.pending)?;
+                let mac = self
+                    .keys
+                    .mac_chunk(&self.pending)
+                    .expect("chunk is complete");
+                output.extend_from_slice(&self.pending);
+                output.extend_from_slice(&mac);
+                self.pending.clear();
+            }
+        }
+        Ok(output)
+    }
+
+    /// Encrypts any remaining partial chunk and appends the final tag.
+    pub fn finalize(mut self) -> Result<Vec<u8>> {
+        let mut output = self.pending;
+        Self::apply_keystream(&mut self.keys, &mut output)?;
+        let mac = self.keys.mac_chunk(&output);
+        debug_assert!(mac.is_none(), "partial chunk can't complete a chunk");
+        output.extend_from_slice(&self.keys.final_tag());
+        Ok(output)
+    }
+}
+
+/// Decrypts a stream produced by [`StreamEncryptor`].
+///
+/// Created by [`message_decrypt_stream`] or [`group_decrypt_stream`].
+///
+/// Plaintext is only returned once the chunk containing it has been authenticated. Callers must
+/// still discard everything received if [`finalize`](Self::finalize) fails, since that is the only
+/// point at which truncation is detected.
+pub struct StreamDecryptor {
+    keys: StreamKeys,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl StreamDecryptor {
+    /// Starts decrypting using the key material and decrypted header of the key message.
+    fn new(key_material: &[u8], header: &[u8]) -> Result<Self> {
+        let chunk_size = decode_header(header)?;
+        Ok(Self {
+            keys: StreamKeys::derive(key_material, chunk_size),
+            chunk_size,
+            buffer: vec![],
+        })
+    }
+
+    fn apply_keystream(keys: &mut StreamKeys, buf: &mut [u8]) -> Result<()> {
+        keys.apply_keystream(buf)
+            .map_err(|_| SignalProtocolError::InvalidStreamMessage("stream is too long"))
+    }
+
+    /// Decrypts more of the stream, returning any plaintext that has been authenticated.
+    pub fn update(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        self.buffer.extend_from_slice(ciphertext);
+
+        // The final segment is always shorter than a whole chunk plus its MAC, so anything at
+        // least that long must start with a whole chunk.
+        let segment_len = self.chunk_size + MAC_LEN;
+        let mut output = vec![];
+        let mut consumed = 0;
+        while self.buffer.len() - consumed >= segment_len {
+            let (chunk, mac) =
+                self.buffer[consumed..consumed + segment_len].split_at(self.chunk_size);
+            let expected = self.keys.mac_chunk(chunk).expect("chunk is complete");
+            if !bool::from(expected.ct_eq(mac)) {
+                return Err(SignalProtocolError::InvalidStreamMessage(
+                    "MAC verification failed",
+                ));
+            }
+            let start = output.len();
+            output.extend_from_slice(chunk);
+            Self::apply_keystream(&mut self.keys, &mut output[start..])?;
+            consumed += segment_len;
+        }
+        self.buffer.drain(..consumed);
+        Ok(output)
+    }
+
+    /// Checks the final tag, returning the plaintext of the last partial chunk.
+    pub fn finalize(mut self) -> Result<Vec<u8>> {
+        let tag_start = self.buffer.len().checked_sub(MAC_LEN).ok_or(
+            SignalProtocolError::InvalidStreamMessage("stream truncated"),
+        )?;
+        let (last_chunk, tag) = self.buffer.split_at(tag_start);
+
+        let mac = self.keys.mac_chunk(last_chunk);
+        debug_assert!(mac.is_none(), "partial chunk can't complete a chunk");
+        if !bool::from(self.keys.final_tag().ct_eq(tag)) {
+            return Err(SignalProtocolError::InvalidStreamMessage(
+                "MAC verification failed",
+            ));
+        }
+
+        let mut output = last_chunk.to_vec();
+        Self::apply_keystream(&mut self.keys, &mut output)?;
+        Ok(output)
+    }
+}
+
+/// Starts a stream to `remote_address`, returning the key message to send ahead of the stream.
+///
+/// `size_hint` is the expected length of the payload, used only to pick a chunk size. The key
+/// message is an ordinary 1:1 message; the recipient passes it to [`message_decrypt_stream`].
+pub async fn message_encrypt_stream<R: Rng + CryptoRng>(
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    now: SystemTime,
+    size_hint: usize,
+    csprng: &mut R,
+) -> Result<(CiphertextMessage, StreamEncryptor)> {
+    let chunk_size = calculate_chunk_size::<Sha256>(size_hint);
+    let (key_message, message_keys) = message_encrypt_with_message_keys(
+        &encode_header(chunk_size),
+        remote_address,
+        session_store,
+        identity_store,
+        now,
+        csprng,
+        &SessionConfig::default(),
+    )
+    .await?;
+    let encryptor = StreamEncryptor::new(&session_key_material(&message_keys), chunk_size);
+    Ok((key_message, encryptor))
+}
+
+/// Decrypts the key message of a stream from `remote_address`, updating the stores as
+/// [`message_decrypt`](crate::message_decrypt) does.
+///
+/// The returned decryptor is then fed the stream itself.
+#[expect(clippy::too_many_arguments)]
+pub async fn message_decrypt_stream<R: Rng + CryptoRng>(
+    key_message: &CiphertextMessage,
+    remote_address: &ProtocolAddress,
+    session_store: &mut dyn SessionStore,
+    identity_store: &mut dyn IdentityKeyStore,
+    pre_key_store: &mut dyn PreKeyStore,
+    signed_pre_key_store: &dyn SignedPreKeyStore,
+    kyber_pre_key_store: &mut dyn KyberPreKeyStore,
+    now: SystemTime,
+    csprng: &mut R,
+    use_pq_ratchet: UsePQRatchet,
+    config: &SessionConfig,
+) -> Result<StreamDecryptor> {
+    let (header, message_keys) = message_decrypt_with_message_keys(
+        key_message,
+        remote_address,
+        session_store,
+        identity_store,
+        pre_key_store,
+        signed_pre_key_store,
+        kyber_pre_key_store,
+        now,
+        csprng,
+        use_pq_ratchet,
+        config,
+    )
+    .await?;
+    StreamDecryptor::new(&session_key_material(&message_keys), &header)
+}
+
+/// Starts a stream to a group, returning the key message to send ahead of the stream.
+///
+/// `size_hint` is the expected length of the payload, used only to pick a chunk size. The key
+/// message is an ordinary sender key message; recipients pass it to [`group_decrypt_stream`].
+pub async fn group_encrypt_stream<R: Rng + CryptoRng>(
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+    distribution_id: Uuid,
+    size_hint: usize,
+    csprng: &mut R,
+) -> Result<(SenderKeyMessage, StreamEncryptor)> {
+    let chunk_size = calculate_chunk_size::<Sha256>(size_hint);
+    let (key_message, message_key) = group_encrypt_with_message_key(
+        sender_key_store,
+        sender,
+        distribution_id,
+        &encode_header(chunk_size),
+        csprng,
+    )
+    .await?;
+    let encryptor = StreamEncryptor::new(message_key.seed(), chunk_size);
+    Ok((key_message, encryptor))
+}
+
+/// Decrypts the key message of a stream from `sender` to a group, updating the store as
+/// [`group_decrypt`](crate::group_decrypt) does.
+///
+/// The returned decryptor is then fed the stream itself.
+pub async fn group_decrypt_stream(
+    key_message: &[u8],
+    sender_key_store: &mut dyn SenderKeyStore,
+    sender: &ProtocolAddress,
+) -> Result<StreamDecryptor> {
+    let (header, message_key) = group_decrypt_with_message_key(
+        key_message,
+        sender_key_store,
+        sender,
+        &SessionConfig::default(),
+    )
+    .await?;
+    StreamDecryptor::new(message_key.seed(), &header)
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    const KEY_MATERIAL: &[u8] = &[1; 32];
+
+    fn encrypt_in_pieces(
+        encryptor: &mut StreamEncryptor,
+        plaintext: &[u8],
+        piece: usize,
+    ) -> Vec<u8> {
+        let mut ciphertext = vec![];
+        for chunk in plaintext.chunks(piece) {
+            ciphertext.extend(encryptor.update(chunk).expect("can encrypt"));
+        }
+        ciphertext
+    }
+
+    fn decrypt_in_pieces(
+        key_material: &[u8],
+        header: &[u8],
+        ciphertext: &[u8],
+        piece: usize,
+    ) -> Result<Vec<u8>> {
+        let mut decryptor = StreamDecryptor::new(key_material, header)?;
+        let mut plaintext = vec![];
+        for chunk in ciphertext.chunks(piece) {
+            let decrypted = decryptor.update(chunk)?;
+            // Never more than a chunk's worth of ciphertext is held back.
+            assert!(decryptor.buffer.len() < decryptor.chunk_size + MAC_LEN);
+            plaintext.extend(decrypted);
+        }
+        plaintext.extend(decryptor.finalize()?);
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn round_trip() {
+        let chunk_size = calculate_chunk_size::<Sha256>(0);
+
+        for len in [
+            0,
+            1,
+            chunk_size - 1,
+            chunk_size,
+            chunk_size + 1,
+            3 * chunk_size + 17,
+        ] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let chunk_size = calculate_chunk_size::<Sha256>(len);
+            let header = encode_header(chunk_size);
+            let mut encryptor = StreamEncryptor::new(KEY_MATERIAL, chunk_size);
+
+            let mut ciphertext = encrypt_in_pieces(&mut encryptor, &plaintext, 1000);
+            ciphertext.extend(encryptor.finalize().expect("can finalize"));
+            if len > 0 {
+                assert_ne!(&ciphertext[..len], &plaintext[..]);
+            }
+
+            for piece in [1000, 7 * 1024, ciphertext.len().max(1)] {
+                assert_eq!(
+                    decrypt_in_pieces(KEY_MATERIAL, &header, &ciphertext, piece).expect("valid"),
+                    plaintext,
+                    "len {len}, piece {piece}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_tampering_and_truncation() {
+        let chunk_size = calculate_chunk_size::<Sha256>(0);
+        let plaintext = vec![0x42; 2 * chunk_size];
+        let header = encode_header(chunk_size);
+
+        let mut encryptor = StreamEncryptor::new(KEY_MATERIAL, chunk_size);
+        let mut ciphertext = encrypt_in_pieces(&mut encryptor, &plaintext, chunk_size);
+        ciphertext.extend(encryptor.finalize().expect("can finalize"));
+
+        let mut tampered = ciphertext.clone();
+        tampered[10] ^= 1;
+        assert_matches!(
+            decrypt_in_pieces(KEY_MATERIAL, &header, &tampered, 4096),
+            Err(SignalProtocolError::InvalidStreamMessage(
+                "MAC verification failed"
+            ))
+        );
+
+        // Dropping the final tag leaves a stream that ends on a chunk boundary.
+        let truncated = &ciphertext[..ciphertext.len() - MAC_LEN];
+        assert_matches!(
+            decrypt_in_pieces(KEY_MATERIAL, &header, truncated, 4096),
+            Err(SignalProtocolError::InvalidStreamMessage(_))
+        );
+        let truncated = &ciphertext[..chunk_size + MAC_LEN];
+        assert_matches!(
+            decrypt_in_pieces(KEY_MATERIAL, &header, truncated, 4096),
+            Err(SignalProtocolError::InvalidStreamMessage(_))
+        );
+
+        assert_matches!(
+            decrypt_in_pieces(&[2; 32], &header, &ciphertext, 4096),
+            Err(SignalProtocolError::InvalidStreamMessage(
+                "MAC verification failed"
+            ))
+        );
+
+        assert_matches!(
+            StreamDecryptor::new(KEY_MATERIAL, &header[1..]),
+            Err(SignalProtocolError::InvalidStreamMessage(_))
+        );
+        assert_matches!(
+            StreamDecryptor::new(KEY_MATERIAL, &encode_header(MAX_CHUNK_SIZE + 1)),
+            Err(SignalProtocolError::InvalidStreamMessage(
+                "invalid chunk size"
+            ))
+        );
+    }
+}
@@ -5,7 +5,7 @@
 
 mod support;
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use futures_util::FutureExt;
 use libsignal_protocol::*;
@@ -1299,3 +1299,391 @@ fn group_message_key_limit() -> Result<(), SignalProtocolError> {
     .now_or_never()
     .expect("sync")
 }
+
+#[test]
+fn group_decrypt_with_config_limits() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        let sent_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+
+        let recv_distribution_message =
+            SenderKeyDistributionMessage::try_from(sent_distribution_message.serialized())?;
+
+        process_sender_key_distribution_message(
+            &sender_address,
+            &recv_distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        let mut ciphertexts = Vec::with_capacity(20);
+        for i in 0..ciphertexts.capacity() {
+            ciphertexts.push(
+                group_encrypt(
+                    &mut alice_store,
+                    &sender_address,
+                    distribution_id,
+                    format!("message {i}").as_bytes(),
+                    &mut csprng,
+                )
+                .await?
+                .serialized()
+                .to_vec(),
+            );
+        }
+
+        let config = SessionConfig {
+            max_forward_jumps: 10,
+            max_message_keys: 3,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            group_decrypt_with_config(&ciphertexts[11], &mut bob_store, &sender_address, &config)
+                .await,
+            Err(SignalProtocolError::InvalidMessage(
+                CiphertextMessageType::SenderKey,
+                "message from too far into the future"
+            ))
+        ));
+
+        assert_eq!(
+            group_decrypt_with_config(&ciphertexts[5], &mut bob_store, &sender_address, &config)
+                .await?,
+            b"message 5"
+        );
+        // Only the keys for the newest three skipped messages were kept.
+        assert!(matches!(
+            group_decrypt_with_config(&ciphertexts[1], &mut bob_store, &sender_address, &config)
+                .await,
+            Err(SignalProtocolError::DuplicatedMessage(6, 1))
+        ));
+        assert_eq!(
+            group_decrypt_with_config(&ciphertexts[2], &mut bob_store, &sender_address, &config)
+                .await?,
+            b"message 2"
+        );
+
+        // The default limits allow a jump that the stricter config rejected.
+        assert_eq!(
+            group_decrypt(&ciphertexts[19], &mut bob_store, &sender_address).await?,
+            b"message 19"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_encrypt_with_rotation_policy_requires_rotation() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        let bob = ServiceId::from(Aci::from_uuid_bytes([0xBB; 16]));
+        let carol = ServiceId::from(Aci::from_uuid_bytes([0xCC; 16]));
+        let dave = ServiceId::from(Aci::from_uuid_bytes([0xDD; 16]));
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let policy = SenderKeyRotationPolicy {
+            max_messages: Some(2),
+            max_age: Some(Duration::from_secs(60 * 60)),
+            rotate_on_membership_change: true,
+        };
+
+        let distribution_message = rotate_sender_key(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &[bob, carol],
+            start,
+            &mut csprng,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &sender_address,
+            &distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        // Member order and duplicates don't matter.
+        let GroupEncryptOutcome::Encrypted(first) = group_encrypt_with_rotation_policy(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            b"first",
+            &policy,
+            &[carol, bob, carol],
+            start,
+            &mut csprng,
+        )
+        .await?
+        else {
+            panic!("expected the message to be encrypted");
+        };
+
+        assert!(matches!(
+            group_encrypt_with_rotation_policy(
+                &mut alice_store,
+                &sender_address,
+                distribution_id,
+                b"too old",
+                &policy,
+                &[bob, carol],
+                start + Duration::from_secs(2 * 60 * 60),
+                &mut csprng,
+            )
+            .await?,
+            GroupEncryptOutcome::RotationRequired(SenderKeyRotationReason::MaxAge)
+        ));
+
+        assert!(matches!(
+            group_encrypt_with_rotation_policy(
+                &mut alice_store,
+                &sender_address,
+                distribution_id,
+                b"new member",
+                &policy,
+                &[bob, carol, dave],
+                start,
+                &mut csprng,
+            )
+            .await?,
+            GroupEncryptOutcome::RotationRequired(SenderKeyRotationReason::MembershipChanged)
+        ));
+
+        assert!(matches!(
+            group_encrypt_with_rotation_policy(
+                &mut alice_store,
+                &sender_address,
+                distribution_id,
+                b"second",
+                &policy,
+                &[bob, carol],
+                start,
+                &mut csprng,
+            )
+            .await?,
+            GroupEncryptOutcome::Encrypted(_)
+        ));
+
+        assert!(matches!(
+            group_encrypt_with_rotation_policy(
+                &mut alice_store,
+                &sender_address,
+                distribution_id,
+                b"third",
+                &policy,
+                &[bob, carol],
+                start,
+                &mut csprng,
+            )
+            .await?,
+            GroupEncryptOutcome::RotationRequired(SenderKeyRotationReason::MessageLimit)
+        ));
+
+        let distribution_message = rotate_sender_key(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &[bob, carol, dave],
+            start,
+            &mut csprng,
+        )
+        .await?;
+        assert_ne!(
+            distribution_message.chain_id()?,
+            SenderKeyMessage::try_from(first.serialized())?.chain_id()
+        );
+        process_sender_key_distribution_message(
+            &sender_address,
+            &distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        let GroupEncryptOutcome::Encrypted(after_rotation) = group_encrypt_with_rotation_policy(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            b"after rotation",
+            &policy,
+            &[bob, carol, dave],
+            start,
+            &mut csprng,
+        )
+        .await?
+        else {
+            panic!("expected the message to be encrypted");
+        };
+
+        assert_eq!(
+            group_decrypt(after_rotation.serialized(), &mut bob_store, &sender_address).await?,
+            b"after rotation"
+        );
+        // Messages sent with the old chain can still be decrypted.
+        assert_eq!(
+            group_decrypt(first.serialized(), &mut bob_store, &sender_address).await?,
+            b"first"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_encrypt_with_rotation_policy_keeps_adopted_creation_time()
+-> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+
+        let bob = ServiceId::from(Aci::from_uuid_bytes([0xBB; 16]));
+        let carol = ServiceId::from(Aci::from_uuid_bytes([0xCC; 16]));
+
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let max_age = SenderKeyRotationPolicy {
+            max_age: Some(Duration::from_secs(60 * 60)),
+            ..Default::default()
+        };
+        let membership = SenderKeyRotationPolicy {
+            rotate_on_membership_change: true,
+            ..Default::default()
+        };
+
+        // A sender key created without a rotation policy tracks neither its age nor its members.
+        create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+
+        assert!(matches!(
+            group_encrypt_with_rotation_policy(
+                &mut alice_store,
+                &sender_address,
+                distribution_id,
+                b"adopts members",
+                &membership,
+                &[bob],
+                start,
+                &mut csprng,
+            )
+            .await?,
+            GroupEncryptOutcome::Encrypted(_)
+        ));
+
+        // This adopts a creation time before finding that the members changed.
+        assert!(matches!(
+            group_encrypt_with_rotation_policy(
+                &mut alice_store,
+                &sender_address,
+                distribution_id,
+                b"new member",
+                &SenderKeyRotationPolicy {
+                    rotate_on_membership_change: true,
+                    ..max_age
+                },
+                &[bob, carol],
+                start,
+                &mut csprng,
+            )
+            .await?,
+            GroupEncryptOutcome::RotationRequired(SenderKeyRotationReason::MembershipChanged)
+        ));
+
+        // The adopted creation time was kept.
+        assert!(matches!(
+            group_encrypt_with_rotation_policy(
+                &mut alice_store,
+                &sender_address,
+                distribution_id,
+                b"too old",
+                &max_age,
+                &[bob, carol],
+                start + Duration::from_secs(2 * 60 * 60),
+                &mut csprng,
+            )
+            .await?,
+            GroupEncryptOutcome::RotationRequired(SenderKeyRotationReason::MaxAge)
+        ));
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_encrypt_with_default_rotation_policy_never_rotates() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+
+        create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+
+        for i in 0..5 {
+            assert!(matches!(
+                group_encrypt_with_rotation_policy(
+                    &mut alice_store,
+                    &sender_address,
+                    distribution_id,
+                    format!("message {i}").as_bytes(),
+                    &SenderKeyRotationPolicy::default(),
+                    &[],
+                    SystemTime::now(),
+                    &mut csprng,
+                )
+                .await?,
+                GroupEncryptOutcome::Encrypted(_)
+            ));
+        }
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -0,0 +1,150 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+mod support;
+
+use std::time::{Duration, SystemTime};
+
+use assert_matches::assert_matches;
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::TryRngCore as _;
+use rand::rngs::OsRng;
+use support::*;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[test]
+fn pre_key_manager_lifecycle() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = test_in_memory_protocol_store()?;
+        let identity_key = *store.get_identity_key_pair().await?.identity_key();
+        let config = PreKeyManagerConfig {
+            batch_size: 5,
+            ..Default::default()
+        };
+        let mut manager = PreKeyManager::new(config, &mut csprng);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let empty_server = ServerPreKeyCounts {
+            pre_keys: 0,
+            pq_pre_keys: 0,
+        };
+        let full_server = ServerPreKeyCounts {
+            pre_keys: 100,
+            pq_pre_keys: 100,
+        };
+
+        let initial = manager
+            .refresh(&mut store, empty_server, start, &mut csprng)
+            .await?;
+        assert_eq!(initial.pre_keys.len(), 5);
+        assert_eq!(initial.pq_pre_keys.len(), 5);
+        let signed = initial.signed_pre_key.expect("generated");
+        let last_resort = initial.pq_last_resort_pre_key.expect("generated");
+
+        assert!(
+            identity_key
+                .public_key()
+                .verify_signature(&signed.public_key, &signed.signature)
+        );
+        assert!(
+            identity_key
+                .public_key()
+                .verify_signature(&last_resort.public_key, &last_resort.signature)
+        );
+        for upload in &initial.pq_pre_keys {
+            let record = store.get_kyber_pre_key(upload.key_id.into()).await?;
+            assert_eq!(SignedPreKeyUpload::from(&record), *upload);
+            assert_eq!(record.kind(), KyberPreKeyKind::OneTime);
+        }
+        assert_eq!(
+            store
+                .get_kyber_pre_key(last_resort.key_id.into())
+                .await?
+                .kind(),
+            KyberPreKeyKind::LastResort
+        );
+        for upload in &initial.pre_keys {
+            let record = store.get_pre_key(upload.key_id.into()).await?;
+            assert_eq!(&*record.public_key()?.serialize(), &upload.public_key[..]);
+        }
+
+        // Nothing is due yet, and the manager's state survives a round trip.
+        let mut manager = PreKeyManager::deserialize(&manager.serialize(), config)?;
+        assert!(
+            manager
+                .refresh(&mut store, full_server, start + DAY, &mut csprng)
+                .await?
+                .is_empty()
+        );
+
+        // The store tells the two kinds apart from the records the manager generated.
+        let base_key = KeyPair::generate(&mut csprng).public_key;
+        let signed_id = SignedPreKeyId::from(signed.key_id);
+        let last_resort_id = KyberPreKeyId::from(last_resort.key_id);
+        store
+            .mark_kyber_pre_key_used(last_resort_id, signed_id, &base_key)
+            .await?;
+        assert_matches!(
+            store
+                .mark_kyber_pre_key_used(last_resort_id, signed_id, &base_key)
+                .await,
+            Err(SignalProtocolError::ReusedKyberPreKeyBaseKey {
+                kyber_pre_key_id,
+                signed_pre_key_id,
+            }) if kyber_pre_key_id == last_resort_id && signed_pre_key_id == signed_id
+        );
+        let one_time_id = KyberPreKeyId::from(initial.pq_pre_keys[0].key_id);
+        store
+            .mark_kyber_pre_key_used(one_time_id, signed_id, &base_key)
+            .await?;
+        assert_matches!(
+            store.get_kyber_pre_key(one_time_id).await,
+            Err(SignalProtocolError::InvalidKyberPreKeyId)
+        );
+        assert_matches!(
+            store
+                .mark_kyber_pre_key_used(one_time_id, signed_id, &base_key)
+                .await,
+            Err(SignalProtocolError::InvalidKyberPreKeyId)
+        );
+
+        let rotated = manager
+            .refresh(&mut store, full_server, start + 3 * DAY, &mut csprng)
+            .await?;
+        assert!(rotated.pre_keys.is_empty() && rotated.pq_pre_keys.is_empty());
+        assert_ne!(
+            rotated.signed_pre_key.expect("rotated").key_id,
+            signed.key_id
+        );
+        assert_ne!(
+            rotated.pq_last_resort_pre_key.expect("rotated").key_id,
+            last_resort.key_id
+        );
+
+        // Retired keys are kept for the retention period, then handed back for deletion.
+        assert_eq!(
+            manager.take_stale_pre_keys(start + 10 * DAY),
+            StalePreKeys::default()
+        );
+        assert_eq!(
+            manager.take_stale_pre_keys(start + 40 * DAY),
+            StalePreKeys {
+                signed_pre_keys: vec![signed_id],
+                kyber_pre_keys: vec![last_resort_id],
+            }
+        );
+        assert_eq!(
+            manager.take_stale_pre_keys(start + 40 * DAY),
+            StalePreKeys::default()
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -4,7 +4,7 @@
 //
 
 mod support;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use futures_util::FutureExt;
 use libsignal_protocol::*;
@@ -133,6 +133,97 @@ fn test_sender_cert() -> Result<(), SignalProtocolError> {
     Ok(())
 }
 
+#[test]
+fn test_sender_cert_issuance_and_revocation() -> Result<(), SignalProtocolError> {
+    let mut rng = OsRng.unwrap_err();
+    let mut issuer = SenderCertificateIssuer::new(KeyPair::generate(&mut rng), 1, &mut rng)?;
+    let trust_root = *issuer.trust_root();
+    let other_root = KeyPair::generate(&mut rng).public_key;
+
+    let sender_key = KeyPair::generate(&mut rng).public_key;
+    let expires = Timestamp::from_epoch_millis(1605722925);
+    let issue = |issuer: &SenderCertificateIssuer, rng: &mut _| {
+        issuer.issue(
+            "9d0652a3-dcc3-4d11-975f-74d61598733f".to_owned(),
+            None,
+            sender_key,
+            DeviceId::new(1).unwrap(),
+            expires,
+            rng,
+        )
+    };
+
+    let first = issue(&issuer, &mut rng)?;
+    let mut revocations = CertificateRevocationSet::default();
+    assert_eq!(
+        first.validate_with_revocations(&[&other_root, &trust_root], expires, &revocations),
+        Ok(())
+    );
+    assert_eq!(
+        first.validate_with_revocations(&[&other_root], expires, &revocations),
+        Err(CertificateValidationError::UntrustedServerCertificate)
+    );
+    assert_eq!(
+        first.validate_with_revocations(&[&trust_root], expires.add_millis(1), &revocations),
+        Err(CertificateValidationError::Expired(expires))
+    );
+
+    // Rotating the server certificate leaves earlier certificates valid until it is revoked.
+    let retired = issuer.rotate_server_certificate(&mut rng)?;
+    assert_eq!(retired.key_id()?, 1);
+    assert_eq!(issuer.server_certificate().key_id()?, 2);
+    let second = issue(&issuer, &mut rng)?;
+    assert_eq!(second.signer()?.key_id()?, 2);
+    assert!(first.validate(&trust_root, expires)?);
+
+    revocations.revoke_server_certificate(retired.key_id()?);
+    assert_eq!(
+        first.validate_with_revocations(&[&trust_root], expires, &revocations),
+        Err(CertificateValidationError::RevokedServerCertificate(1))
+    );
+    assert_eq!(
+        retired.validate_with_revocations(&trust_root, &revocations),
+        Err(CertificateValidationError::RevokedServerCertificate(1))
+    );
+    assert_eq!(
+        second.validate_with_revocations(&[&trust_root], expires, &revocations),
+        Ok(())
+    );
+
+    // Individual sender certificates can be revoked too, and their serial survives serialization.
+    let reparsed = SenderCertificate::deserialize(second.serialized()?)?;
+    assert_eq!(reparsed.serial_number(), second.serial_number());
+    assert_ne!(first.serial_number(), second.serial_number());
+    revocations.revoke_sender_certificate(second.serial_number());
+    assert_eq!(
+        reparsed.validate_with_revocations(&[&trust_root], expires, &revocations),
+        Err(CertificateValidationError::RevokedSenderCertificate)
+    );
+
+    // The built-in revocations are only included by default.
+    let revoked_issuer =
+        SenderCertificateIssuer::new(KeyPair::generate(&mut rng), 0xDEADC357, &mut rng)?;
+    let revoked = revoked_issuer.server_certificate();
+    assert_eq!(
+        revoked.validate_with_revocations(
+            revoked_issuer.trust_root(),
+            &CertificateRevocationSet::default()
+        ),
+        Err(CertificateValidationError::RevokedServerCertificate(
+            0xDEADC357
+        ))
+    );
+    assert_eq!(
+        revoked.validate_with_revocations(
+            revoked_issuer.trust_root(),
+            &CertificateRevocationSet::empty()
+        ),
+        Ok(())
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_sealed_sender() -> Result<(), SignalProtocolError> {
     async {
@@ -305,6 +396,125 @@ fn test_sealed_sender() -> Result<(), SignalProtocolError> {
     .expect("sync")
 }
 
+#[test]
+fn test_sealed_sender_decrypt_with_revocations() -> Result<(), SignalProtocolError> {
+    async {
+        let mut rng = OsRng.unwrap_err();
+
+        let alice_device_id = DeviceId::new(23).unwrap();
+        let bob_device_id = DeviceId::new(42).unwrap();
+
+        let alice_uuid = "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string();
+        let bob_uuid = "796abedb-ca4e-4f18-8803-1fde5b921f9f".to_string();
+
+        let bob_uuid_address = ProtocolAddress::new(bob_uuid.clone(), bob_device_id);
+
+        let mut alice_store = support::test_in_memory_protocol_store()?;
+        let mut bob_store = support::test_in_memory_protocol_store()?;
+
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut rng).await?;
+
+        process_prekey_bundle(
+            &bob_uuid_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut rng,
+            UsePQRatchet::Yes,
+        )
+        .await?;
+
+        let trust_root = KeyPair::generate(&mut rng);
+        let server_key = KeyPair::generate(&mut rng);
+
+        let server_cert =
+            ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)?;
+
+        let expires = Timestamp::from_epoch_millis(1605722925);
+
+        let sender_cert = SenderCertificate::new(
+            alice_uuid.clone(),
+            None,
+            alice_pubkey,
+            alice_device_id,
+            expires,
+            server_cert,
+            &server_key.private_key,
+            &mut rng,
+        )?;
+
+        let alice_ptext = vec![1, 2, 3, 23, 99];
+        let alice_ctext = sealed_sender_encrypt(
+            &bob_uuid_address,
+            &sender_cert,
+            &alice_ptext,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now(),
+            &mut rng,
+        )
+        .await?;
+
+        let mut revocations = CertificateRevocationSet::empty();
+        revocations.revoke_sender_certificate(sender_cert.serial_number());
+
+        let result = sealed_sender_decrypt_with_revocations(
+            &alice_ctext,
+            &trust_root.public_key,
+            &revocations,
+            expires.sub_millis(1),
+            None,
+            bob_uuid.clone(),
+            bob_device_id,
+            &mut bob_store.identity_store,
+            &mut bob_store.session_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+            UsePQRatchet::Yes,
+        )
+        .await;
+        assert!(
+            matches!(
+                result,
+                Err(SealedSenderDecryptError::CertificateRejected(
+                    CertificateValidationError::RevokedSenderCertificate
+                ))
+            ),
+            "{result:?}"
+        );
+
+        // Rejecting the certificate didn't use up the message, so it can still be decrypted once
+        // the certificate is accepted.
+        let bob_ptext = sealed_sender_decrypt_with_revocations(
+            &alice_ctext,
+            &trust_root.public_key,
+            &CertificateRevocationSet::empty(),
+            expires.sub_millis(1),
+            None,
+            bob_uuid.clone(),
+            bob_device_id,
+            &mut bob_store.identity_store,
+            &mut bob_store.session_store,
+            &mut bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &mut bob_store.kyber_pre_key_store,
+            UsePQRatchet::Yes,
+        )
+        .await
+        .expect("certificate is accepted");
+        assert_eq!(bob_ptext.message, alice_ptext);
+        assert_eq!(bob_ptext.sender_uuid, alice_uuid);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
 #[test]
 fn test_sender_key_in_sealed_sender() -> Result<(), SignalProtocolError> {
     async {
@@ -933,8 +1143,10 @@ fn test_decryption_error_in_sealed_sender() -> Result<(), SignalProtocolError> {
             &mut alice_store.pre_key_store,
             &alice_store.signed_pre_key_store,
             &mut alice_store.kyber_pre_key_store,
+            SystemTime::now(),
             &mut rng,
             UsePQRatchet::Yes,
+            &SessionConfig::default(),
         )
         .await?;
 
@@ -1124,3 +1336,478 @@ fn test_sealed_sender_multi_recipient_redundant_empty_devices() -> Result<(), Si
     .now_or_never()
     .expect("sync")
 }
+
+#[test]
+fn test_sealed_sender_multi_recipient_encrypt_and_log() -> Result<(), SignalProtocolError> {
+    async {
+        let mut rng = OsRng.unwrap_err();
+
+        let alice_device_id = DeviceId::new(23).unwrap();
+        let alice_uuid = "9d0652a3-dcc3-4d11-975f-74d61598733f".to_string();
+        let bob_uuid = "796abedb-ca4e-4f18-8803-1fde5b921f9f".to_string();
+
+        let bob_addresses = [
+            ProtocolAddress::new(bob_uuid.clone(), DeviceId::new(1).unwrap()),
+            ProtocolAddress::new(bob_uuid.clone(), DeviceId::new(2).unwrap()),
+        ];
+
+        let mut alice_store = support::test_in_memory_protocol_store()?;
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        let mut bob_store = support::test_in_memory_protocol_store()?;
+        for address in &bob_addresses {
+            let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut rng).await?;
+            process_prekey_bundle(
+                address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut rng,
+                UsePQRatchet::Yes,
+            )
+            .await?;
+        }
+
+        let trust_root = KeyPair::generate(&mut rng);
+        let server_key = KeyPair::generate(&mut rng);
+        let server_cert =
+            ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)?;
+        let sender_cert = SenderCertificate::new(
+            alice_uuid,
+            None,
+            alice_pubkey,
+            alice_device_id,
+            Timestamp::from_epoch_millis(1605722925),
+            server_cert,
+            &server_key.private_key,
+            &mut rng,
+        )?;
+
+        let alice_usmc = UnidentifiedSenderMessageContent::new(
+            CiphertextMessageType::Plaintext,
+            sender_cert,
+            vec![1, 2, 3, 23, 99],
+            ContentHint::Resendable,
+            None,
+        )?;
+
+        let sent_at = Timestamp::from_epoch_millis(1605722000);
+        let mut log = InMemSentMessageLogStore::new();
+        let recipients = [&bob_addresses[0], &bob_addresses[1]];
+        let sessions = alice_store
+            .session_store
+            .load_existing_sessions(&recipients)?;
+
+        sealed_sender_multi_recipient_encrypt_and_log(
+            &recipients,
+            &sessions,
+            [],
+            &alice_usmc,
+            &alice_store.identity_store,
+            sent_at,
+            &mut log,
+            &mut rng,
+        )
+        .await?;
+
+        let expected = SentMessage {
+            timestamp: sent_at,
+            content: alice_usmc.serialized()?.into(),
+        };
+        for address in &bob_addresses {
+            assert_eq!(
+                log.load_sent_message(address, sent_at).await?,
+                Some(expected.clone())
+            );
+        }
+
+        // A send that fails is not logged.
+        let mut archived = SessionRecord::clone(sessions[1]);
+        archived.archive_current_state()?;
+        let failed_at = sent_at.add_millis(1);
+        assert!(
+            sealed_sender_multi_recipient_encrypt_and_log(
+                &recipients,
+                &[sessions[0], &archived],
+                [],
+                &alice_usmc,
+                &alice_store.identity_store,
+                failed_at,
+                &mut log,
+                &mut rng,
+            )
+            .await
+            .is_err()
+        );
+        assert_eq!(
+            log.load_sent_message(&bob_addresses[0], failed_at).await?,
+            None
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_in_memory_sent_message_log_retention() -> Result<(), SignalProtocolError> {
+    async {
+        let bob_1 = ProtocolAddress::new("bob".to_owned(), DeviceId::new(1).unwrap());
+        let bob_2 = ProtocolAddress::new("bob".to_owned(), DeviceId::new(2).unwrap());
+        let message_at = |millis: u64| SentMessage {
+            timestamp: Timestamp::from_epoch_millis(millis),
+            content: Box::from(&millis.to_be_bytes()[..]),
+        };
+
+        let mut log = InMemSentMessageLogStore::with_limits(Duration::from_secs(10), 3);
+        for millis in [1_000, 2_000, 3_000] {
+            log.log_sent_message(&[&bob_1, &bob_2], &message_at(millis))
+                .await?;
+        }
+
+        // Records are per device.
+        log.remove_sent_message(&bob_1, Timestamp::from_epoch_millis(1_000))
+            .await?;
+        assert_eq!(
+            log.load_sent_message(&bob_1, Timestamp::from_epoch_millis(1_000))
+                .await?,
+            None
+        );
+        assert_eq!(
+            log.load_sent_message(&bob_2, Timestamp::from_epoch_millis(1_000))
+                .await?,
+            Some(message_at(1_000))
+        );
+
+        // Over the count limit, the oldest message goes first...
+        log.log_sent_message(&[&bob_1], &message_at(4_000)).await?;
+        assert_eq!(
+            log.load_sent_message(&bob_2, Timestamp::from_epoch_millis(1_000))
+                .await?,
+            None
+        );
+        assert_eq!(
+            log.load_sent_message(&bob_2, Timestamp::from_epoch_millis(2_000))
+                .await?,
+            Some(message_at(2_000))
+        );
+
+        // ...while the age limit only applies when pruning, relative to the time given.
+        log.log_sent_message(&[&bob_1], &message_at(u64::MAX))
+            .await?;
+        assert_eq!(
+            log.load_sent_message(&bob_2, Timestamp::from_epoch_millis(3_000))
+                .await?,
+            Some(message_at(3_000))
+        );
+
+        log.prune(Timestamp::from_epoch_millis(13_500));
+        assert_eq!(
+            log.load_sent_message(&bob_2, Timestamp::from_epoch_millis(3_000))
+                .await?,
+            None
+        );
+        assert_eq!(
+            log.load_sent_message(&bob_1, Timestamp::from_epoch_millis(4_000))
+                .await?,
+            Some(message_at(4_000))
+        );
+        assert_eq!(
+            log.load_sent_message(&bob_1, Timestamp::from_epoch_millis(u64::MAX))
+                .await?,
+            Some(message_at(u64::MAX))
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_sealed_sender_multi_recipient_encrypt_skipping_failures() -> Result<(), SignalProtocolError>
+{
+    async {
+        let mut rng = OsRng.unwrap_err();
+
+        let bob_uuid = "796abedb-ca4e-4f18-8803-1fde5b921f9f".to_string();
+        let carol_uuid = "38381c3b-2606-4ca7-9310-7cb927f2ab4a".to_string();
+        let dave_uuid = "b2ad3c2c-3a5b-4b4e-9bc6-9bd4aaa3b2b5".to_string();
+
+        let bob_address = ProtocolAddress::new(bob_uuid.clone(), DeviceId::new(1).unwrap());
+        let bob_new_device = ProtocolAddress::new(bob_uuid.clone(), DeviceId::new(2).unwrap());
+        let carol_address = ProtocolAddress::new(carol_uuid.clone(), DeviceId::new(1).unwrap());
+        let dave_address = ProtocolAddress::new(dave_uuid.clone(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = support::test_in_memory_protocol_store()?;
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        for address in [&bob_address, &carol_address] {
+            let mut their_store = support::test_in_memory_protocol_store()?;
+            let bundle = create_pre_key_bundle(&mut their_store, &mut rng).await?;
+            process_prekey_bundle(
+                address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bundle,
+                SystemTime::now(),
+                &mut rng,
+                UsePQRatchet::Yes,
+            )
+            .await?;
+        }
+
+        // Carol's identity changes after her session was set up.
+        alice_store
+            .identity_store
+            .save_identity(
+                &carol_address,
+                IdentityKeyPair::generate(&mut rng).identity_key(),
+            )
+            .await?;
+
+        let trust_root = KeyPair::generate(&mut rng);
+        let server_key = KeyPair::generate(&mut rng);
+        let server_cert =
+            ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)?;
+        let sender_cert = SenderCertificate::new(
+            "9d0652a3-dcc3-4d11-975f-74d61598733f".to_owned(),
+            None,
+            alice_pubkey,
+            DeviceId::new(23).unwrap(),
+            Timestamp::from_epoch_millis(1605722925),
+            server_cert,
+            &server_key.private_key,
+            &mut rng,
+        )?;
+        let alice_usmc = UnidentifiedSenderMessageContent::new(
+            CiphertextMessageType::Plaintext,
+            sender_cert,
+            vec![1, 2, 3, 23, 99],
+            ContentHint::Default,
+            None,
+        )?;
+
+        let bob_session = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("present");
+        let carol_session = alice_store
+            .load_session(&carol_address)
+            .await?
+            .expect("present");
+        let dave_id = ServiceId::parse_from_service_id_string(&dave_uuid).expect("valid");
+
+        let result = sealed_sender_multi_recipient_encrypt_skipping_failures(
+            &[&bob_address, &bob_new_device, &carol_address, &dave_address],
+            &[Some(&bob_session), None, Some(&carol_session), None],
+            [dave_id],
+            &alice_usmc,
+            &alice_store.identity_store,
+            &mut rng,
+        )
+        .await?;
+
+        assert!(!result.is_complete());
+        assert_eq!(result.missing_sessions, [bob_new_device]);
+        assert_eq!(result.untrusted_identities, [carol_address]);
+        assert_eq!(result.excluded_devices, [dave_address.clone()]);
+
+        let sent = SealedSenderV2SentMessage::parse(&result.message)?;
+        assert_eq!(sent.recipients.len(), 2);
+        let bob_id = ServiceId::parse_from_service_id_string(&bob_uuid).expect("valid");
+        assert_eq!(
+            sent.recipients[&bob_id]
+                .devices
+                .iter()
+                .map(|(device_id, _)| *device_id)
+                .collect::<Vec<_>>(),
+            [DeviceId::new(1).unwrap()]
+        );
+        assert!(sent.recipients[&dave_id].devices.is_empty());
+
+        // Excluded devices never need to be retried, so they don't make the result incomplete.
+        let result = sealed_sender_multi_recipient_encrypt_skipping_failures(
+            &[&bob_address, &dave_address],
+            &[Some(&bob_session), None],
+            [dave_id],
+            &alice_usmc,
+            &alice_store.identity_store,
+            &mut rng,
+        )
+        .await?;
+        assert!(result.is_complete());
+        assert_eq!(result.excluded_devices, [dave_address]);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_sealed_sender_v2_server_validation_and_fan_out() -> Result<(), SignalProtocolError> {
+    use sealed_sender_server::{
+        DeviceDirectory, DeviceMismatch, ValidationError, device_messages, find_duplicate_devices,
+        recipient_sizes,
+    };
+
+    async {
+        let mut rng = OsRng.unwrap_err();
+
+        let bob_uuid = "796abedb-ca4e-4f18-8803-1fde5b921f9f".to_string();
+        let carol_uuid = "38381c3b-2606-4ca7-9310-7cb927f2ab4a".to_string();
+        let bob_id = ServiceId::parse_from_service_id_string(&bob_uuid).expect("valid");
+        let carol_id = ServiceId::parse_from_service_id_string(&carol_uuid).expect("valid");
+
+        let bob_addresses = [
+            ProtocolAddress::new(bob_uuid.clone(), DeviceId::new(1).unwrap()),
+            ProtocolAddress::new(bob_uuid.clone(), DeviceId::new(2).unwrap()),
+        ];
+        let carol_address = ProtocolAddress::new(carol_uuid.clone(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = support::test_in_memory_protocol_store()?;
+        let alice_pubkey = *alice_store.get_identity_key_pair().await?.public_key();
+
+        let mut recipient_stores = vec![];
+        for address in bob_addresses.iter().chain([&carol_address]) {
+            let mut store = support::test_in_memory_protocol_store()?;
+            let bundle = create_pre_key_bundle(&mut store, &mut rng).await?;
+            process_prekey_bundle(
+                address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bundle,
+                SystemTime::now(),
+                &mut rng,
+                UsePQRatchet::Yes,
+            )
+            .await?;
+            recipient_stores.push(store);
+        }
+
+        let trust_root = KeyPair::generate(&mut rng);
+        let server_key = KeyPair::generate(&mut rng);
+        let server_cert =
+            ServerCertificate::new(1, server_key.public_key, &trust_root.private_key, &mut rng)?;
+        let sender_cert = SenderCertificate::new(
+            "9d0652a3-dcc3-4d11-975f-74d61598733f".to_owned(),
+            None,
+            alice_pubkey,
+            DeviceId::new(23).unwrap(),
+            Timestamp::from_epoch_millis(1605722925),
+            server_cert,
+            &server_key.private_key,
+            &mut rng,
+        )?;
+        let alice_usmc = UnidentifiedSenderMessageContent::new(
+            CiphertextMessageType::Plaintext,
+            sender_cert,
+            vec![1, 2, 3, 23, 99],
+            ContentHint::Default,
+            None,
+        )?;
+
+        let recipients = [&bob_addresses[0], &bob_addresses[1], &carol_address];
+        let sent = sealed_sender_multi_recipient_encrypt(
+            &recipients,
+            &alice_store
+                .session_store
+                .load_existing_sessions(&recipients)?,
+            [],
+            &alice_usmc,
+            &alice_store.identity_store,
+            &mut rng,
+        )
+        .await?;
+        let sent = SealedSenderV2SentMessage::parse(&sent)?;
+
+        let mut registration_ids = vec![];
+        for store in &recipient_stores {
+            registration_ids.push(u16::try_from(store.get_local_registration_id().await?).unwrap());
+        }
+        let mut directory = DeviceDirectory::new();
+        directory.set_devices(
+            bob_id,
+            [
+                (DeviceId::new(1).unwrap(), registration_ids[0]),
+                (DeviceId::new(2).unwrap(), registration_ids[1]),
+            ],
+        );
+        directory.set_devices(carol_id, [(DeviceId::new(1).unwrap(), registration_ids[2])]);
+        assert_eq!(directory.validate(&sent), Ok(()));
+        assert!(find_duplicate_devices(&sent).is_empty());
+
+        // Each device gets a message it can decrypt, and the sizes add up.
+        let deliveries = device_messages(&sent);
+        assert_eq!(deliveries.len(), 3);
+        for (delivery, store) in deliveries.iter().zip(&recipient_stores) {
+            let usmc =
+                sealed_sender_decrypt_to_usmc(&delivery.message, &store.identity_store).await?;
+            assert_eq!(usmc.contents()?, [1, 2, 3, 23, 99]);
+        }
+        let sizes = recipient_sizes(&sent);
+        assert_eq!(
+            sizes.iter().map(|size| size.total_len()).sum::<usize>(),
+            deliveries
+                .iter()
+                .map(|delivery| delivery.message.len())
+                .sum::<usize>()
+        );
+        assert_eq!(sizes[0].device_count, 2);
+
+        // Bob has re-registered device 2 and added device 3; Carol is gone.
+        directory.set_devices(
+            bob_id,
+            [
+                (DeviceId::new(1).unwrap(), registration_ids[0]),
+                (DeviceId::new(2).unwrap(), registration_ids[1] ^ 1),
+                (DeviceId::new(3).unwrap(), 1234),
+            ],
+        );
+        assert_eq!(
+            directory.validate(&sent),
+            Err(ValidationError::MismatchedDevices(vec![DeviceMismatch {
+                service_id: bob_id,
+                missing_devices: vec![DeviceId::new(3).unwrap()],
+                extra_devices: vec![],
+                stale_devices: vec![DeviceId::new(2).unwrap()],
+            }]))
+        );
+        directory.remove_account(&carol_id);
+        assert_eq!(
+            directory.validate(&sent),
+            Err(ValidationError::UnknownRecipients(vec![carol_id]))
+        );
+
+        // Listing a device twice is caught before anything else.
+        let twice = [&bob_addresses[0], &bob_addresses[0]];
+        let sent_twice = sealed_sender_multi_recipient_encrypt(
+            &twice,
+            &alice_store.session_store.load_existing_sessions(&twice)?,
+            [],
+            &alice_usmc,
+            &alice_store.identity_store,
+            &mut rng,
+        )
+        .await?;
+        let sent_twice = SealedSenderV2SentMessage::parse(&sent_twice)?;
+        assert_eq!(
+            find_duplicate_devices(&sent_twice),
+            [(bob_id, DeviceId::new(1).unwrap())]
+        );
+        assert_eq!(
+            directory.validate(&sent_twice),
+            Err(ValidationError::DuplicateDevice(
+                bob_id,
+                DeviceId::new(1).unwrap()
+            ))
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
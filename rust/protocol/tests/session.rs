@@ -2597,6 +2597,882 @@ fn prekey_message_to_archived_session() -> TestResult {
     .expect("sync")
 }
 
+#[test]
+fn prekey_message_transaction_failure_does_not_update_stores() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let alice_store_builder = TestStoreBuilder::new()
+            .with_pre_key(0.into())
+            .with_signed_pre_key(0.into())
+            .with_kyber_pre_key(0.into());
+        let alice_pre_key_bundle =
+            alice_store_builder.make_bundle_with_latest_keys(alice_address.device_id());
+        let alice_store = alice_store_builder.store;
+
+        let mut bob_store = TestStoreBuilder::new().store;
+        process_prekey_bundle(
+            &alice_address,
+            &mut bob_store.session_store,
+            &mut bob_store.identity_store,
+            &alice_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+        )
+        .await
+        .expect("can receive bundle");
+
+        let bob_ciphertext = encrypt(&mut bob_store, &alice_address, "from Bob")
+            .await
+            .expect("valid");
+        assert_eq!(bob_ciphertext.message_type(), CiphertextMessageType::PreKey);
+
+        let mut alice_store = InMemTransactionalProtocolStore::new(alice_store);
+
+        for step in [
+            TransactionStep::SaveIdentity,
+            TransactionStep::MarkKyberPreKeyUsed,
+            TransactionStep::RemovePreKey,
+            TransactionStep::StoreSession,
+            TransactionStep::Commit,
+        ] {
+            alice_store.fail_at(Some(step));
+            assert_matches!(
+                message_decrypt_transactional(
+                    &bob_ciphertext,
+                    &bob_address,
+                    &mut alice_store,
+                    SystemTime::now(),
+                    &mut csprng,
+                    UsePQRatchet::Yes,
+                    &SessionConfig::default(),
+                )
+                .await,
+                Err(SignalProtocolError::InvalidState(_, _)),
+                "{step:?}"
+            );
+
+            assert_eq!(
+                alice_store.get_identity(&bob_address).await?,
+                None,
+                "{step:?}"
+            );
+            assert!(
+                alice_store.load_session(&bob_address).await?.is_none(),
+                "{step:?}"
+            );
+            assert!(alice_store.get_pre_key(0.into()).await.is_ok(), "{step:?}");
+        }
+
+        // With no failures, the message decrypts and the kyber pre-key was never marked as used
+        // by any of the failed attempts.
+        alice_store.fail_at(None);
+        let received_message = message_decrypt_transactional(
+            &bob_ciphertext,
+            &bob_address,
+            &mut alice_store,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+            &SessionConfig::default(),
+        )
+        .await?;
+        assert_eq!(received_message, b"from Bob");
+
+        assert!(alice_store.get_identity(&bob_address).await?.is_some());
+        assert!(alice_store.load_session(&bob_address).await?.is_some());
+        assert!(alice_store.get_pre_key(0.into()).await.is_err());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn encrypt_for_all_devices_reports_missing_and_untrusted() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let bob_service_id = ServiceId::from(Aci::from_uuid_bytes([0xBB; 16]));
+        let device_ids = [1, 2, 3].map(|id| DeviceId::new(id).unwrap());
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+
+        // Alice has sessions with Bob's first two devices, but not the third.
+        for &device_id in &device_ids[..2] {
+            let mut bob_device_store = test_in_memory_protocol_store()?;
+            let bundle = create_pre_key_bundle(&mut bob_device_store, &mut csprng).await?;
+            process_prekey_bundle(
+                &bob_service_id.to_protocol_address(device_id),
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bundle,
+                SystemTime::now(),
+                &mut csprng,
+                UsePQRatchet::Yes,
+            )
+            .await?;
+        }
+
+        // Bob's second device's identity has changed since the session was established.
+        alice_store
+            .identity_store
+            .save_identity(
+                &bob_service_id.to_protocol_address(device_ids[1]),
+                IdentityKeyPair::generate(&mut csprng).identity_key(),
+            )
+            .await?;
+
+        let result = message_encrypt_for_all_devices(
+            b"hello",
+            bob_service_id,
+            &device_ids,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now(),
+            &mut csprng,
+            &SessionConfig::default(),
+        )
+        .await?;
+
+        assert!(!result.is_complete());
+        assert_eq!(result.messages.len(), 1);
+        assert_eq!(result.messages[0].0, device_ids[0]);
+        assert_eq!(
+            result.messages[0].1.message_type(),
+            CiphertextMessageType::PreKey
+        );
+        assert_eq!(result.untrusted_identities, [device_ids[1]]);
+        assert_eq!(result.missing_sessions, [device_ids[2]]);
+        assert_matches!(result.failed.as_slice(), []);
+
+        // The staleness limit comes from the config.
+        let config = SessionConfig {
+            max_unacknowledged_session_age: Duration::from_secs(60),
+            ..SessionConfig::default()
+        };
+        let result = message_encrypt_for_all_devices(
+            b"hello",
+            bob_service_id,
+            &device_ids[..1],
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now() + Duration::from_secs(60 * 60),
+            &mut csprng,
+            &config,
+        )
+        .await?;
+        assert_matches!(result.messages.as_slice(), []);
+        assert_eq!(result.missing_sessions, [device_ids[0]]);
+
+        assert_matches!(
+            message_encrypt_for_all_devices(
+                b"hello",
+                bob_service_id,
+                &[device_ids[0], device_ids[0]],
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                SystemTime::now(),
+                &mut csprng,
+                &SessionConfig::default(),
+            )
+            .await,
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+async fn decrypt_with_config(
+    store: &mut InMemSignalProtocolStore,
+    remote_address: &ProtocolAddress,
+    msg: &CiphertextMessage,
+    config: &SessionConfig,
+) -> Result<Vec<u8>, SignalProtocolError> {
+    message_decrypt(
+        msg,
+        remote_address,
+        &mut store.session_store,
+        &mut store.identity_store,
+        &mut store.pre_key_store,
+        &store.signed_pre_key_store,
+        &mut store.kyber_pre_key_store,
+        SystemTime::now(),
+        &mut OsRng.unwrap_err(),
+        UsePQRatchet::Yes,
+        config,
+    )
+    .await
+}
+
+#[test]
+fn session_config_limits_are_enforced() -> TestResult {
+    async {
+        let (alice_session_record, bob_session_record) = initialize_sessions_v4()?;
+
+        let alice_address =
+            ProtocolAddress::new("+14159999999".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14158888888".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store = TestStoreBuilder::new().store;
+
+        alice_store
+            .store_session(&bob_address, &alice_session_record)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session_record)
+            .await?;
+
+        let config = SessionConfig {
+            max_forward_jumps: 10,
+            max_message_keys: 3,
+            ..Default::default()
+        };
+
+        let mut inflight = vec![];
+        for i in 0..20 {
+            inflight.push(encrypt(&mut alice_store, &bob_address, &format!("message {i}")).await?);
+        }
+
+        let invalid_config = SessionConfig {
+            max_message_keys: 0,
+            ..config
+        };
+        assert_matches!(
+            decrypt_with_config(
+                &mut bob_store,
+                &alice_address,
+                &inflight[0],
+                &invalid_config
+            )
+            .await,
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+
+        assert_matches!(
+            decrypt_with_config(&mut bob_store, &alice_address, &inflight[11], &config).await,
+            Err(SignalProtocolError::InvalidMessage(
+                CiphertextMessageType::Whisper,
+                "decryption failed"
+            ))
+        );
+
+        // Skipping messages 0 through 4 only retains the keys for the newest three.
+        assert_eq!(
+            decrypt_with_config(&mut bob_store, &alice_address, &inflight[5], &config).await?,
+            b"message 5"
+        );
+        assert_matches!(
+            decrypt_with_config(&mut bob_store, &alice_address, &inflight[1], &config).await,
+            Err(SignalProtocolError::DuplicatedMessage(6, 1))
+        );
+        assert_eq!(
+            decrypt_with_config(&mut bob_store, &alice_address, &inflight[2], &config).await?,
+            b"message 2"
+        );
+
+        // The default limits allow a jump that the stricter config rejected.
+        assert_eq!(
+            decrypt_with_config(
+                &mut bob_store,
+                &alice_address,
+                &inflight[19],
+                &SessionConfig::default()
+            )
+            .await?,
+            b"message 19"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn process_prekey_bundle_with_config_limits_archived_states() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let bob_address =
+            ProtocolAddress::new("+14158888888".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        let config = SessionConfig {
+            max_archived_states: 1,
+            ..Default::default()
+        };
+        let invalid_config = SessionConfig {
+            max_archived_states: 0,
+            ..config
+        };
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        assert_matches!(
+            process_prekey_bundle_with_config(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+                UsePQRatchet::Yes,
+                &invalid_config,
+            )
+            .await,
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+
+        for _ in 0..3 {
+            let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+            process_prekey_bundle_with_config(
+                &bob_address,
+                &mut alice_store.session_store,
+                &mut alice_store.identity_store,
+                &bob_pre_key_bundle,
+                SystemTime::now(),
+                &mut csprng,
+                UsePQRatchet::Yes,
+                &config,
+            )
+            .await?;
+        }
+
+        let record = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session exists");
+        assert_eq!(record.diagnostics()?.archived_sessions.len(), 1);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn session_record_prune_drops_skipped_message_keys() -> TestResult {
+    async {
+        let (alice_session_record, bob_session_record) = initialize_sessions_v4()?;
+
+        let alice_address =
+            ProtocolAddress::new("+14159999999".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14158888888".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store = TestStoreBuilder::new().store;
+
+        alice_store
+            .store_session(&bob_address, &alice_session_record)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session_record)
+            .await?;
+
+        let mut inflight = vec![];
+        for i in 0..6 {
+            inflight.push(encrypt(&mut alice_store, &bob_address, &format!("message {i}")).await?);
+        }
+
+        assert_eq!(
+            decrypt(
+                &mut bob_store,
+                &alice_address,
+                &inflight[5],
+                UsePQRatchet::Yes
+            )
+            .await?,
+            b"message 5"
+        );
+
+        let mut record = bob_store
+            .load_session(&alice_address)
+            .await?
+            .expect("session exists");
+        assert_matches!(
+            record.prune(&SessionConfig {
+                max_archived_states: 0,
+                ..Default::default()
+            }),
+            Err(SignalProtocolError::InvalidArgument(_))
+        );
+        record.prune(&SessionConfig {
+            max_message_keys: 2,
+            ..Default::default()
+        })?;
+        bob_store.store_session(&alice_address, &record).await?;
+
+        assert_matches!(
+            decrypt(
+                &mut bob_store,
+                &alice_address,
+                &inflight[2],
+                UsePQRatchet::Yes
+            )
+            .await,
+            Err(SignalProtocolError::DuplicatedMessage(6, 2))
+        );
+        assert_eq!(
+            decrypt(
+                &mut bob_store,
+                &alice_address,
+                &inflight[4],
+                UsePQRatchet::Yes
+            )
+            .await?,
+            b"message 4"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn session_diagnostics_track_chains_and_acknowledgement() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(0.into())
+            .with_signed_pre_key(0.into())
+            .with_kyber_pre_key(0.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(bob_address.device_id());
+        let mut bob_store = bob_store_builder.store;
+        let mut alice_store = TestStoreBuilder::new().store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+        )
+        .await?;
+
+        let alice_diagnostics = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session exists")
+            .diagnostics()?;
+        assert!(alice_diagnostics.archived_sessions.is_empty());
+        let alice_current = alice_diagnostics
+            .current_session
+            .expect("has current session");
+        assert_eq!(alice_current.sender_chain_index, Some(0));
+        assert!(alice_current.has_pq_ratchet_state);
+        assert!(alice_current.created_at.is_some());
+        assert_eq!(alice_current.acknowledged_at, None);
+        assert!(!alice_current.is_acknowledged());
+
+        let mut inflight = vec![];
+        for i in 0..3 {
+            inflight.push(encrypt(&mut alice_store, &bob_address, &format!("message {i}")).await?);
+        }
+        // Deliver only the last message, leaving keys for the first two behind.
+        let bob_received_at = Timestamp::from_epoch_millis(1_800_000_000_000);
+        assert_eq!(
+            message_decrypt(
+                &inflight[2],
+                &alice_address,
+                &mut bob_store.session_store,
+                &mut bob_store.identity_store,
+                &mut bob_store.pre_key_store,
+                &bob_store.signed_pre_key_store,
+                &mut bob_store.kyber_pre_key_store,
+                bob_received_at.into(),
+                &mut csprng,
+                UsePQRatchet::Yes,
+                &SessionConfig::default(),
+            )
+            .await?,
+            b"message 2"
+        );
+
+        let bob_diagnostics = bob_store
+            .load_session(&alice_address)
+            .await?
+            .expect("session exists")
+            .diagnostics()?;
+        let bob_current = bob_diagnostics
+            .current_session
+            .expect("has current session");
+        assert!(bob_current.is_acknowledged());
+        assert_eq!(bob_current.created_at, Some(bob_received_at));
+        assert_eq!(bob_current.acknowledged_at, Some(bob_received_at));
+        let newest_receiver_chain = bob_current
+            .receiver_chains
+            .last()
+            .expect("has receiver chain");
+        assert_eq!(newest_receiver_chain.chain_index, Some(3));
+        assert_eq!(newest_receiver_chain.skipped_message_keys, 2);
+
+        let CiphertextMessage::SignalMessage(reply) =
+            encrypt(&mut bob_store, &alice_address, "reply").await?
+        else {
+            panic!("expected a whisper message");
+        };
+        let alice_received_at = bob_received_at.add_millis(1000);
+        message_decrypt_signal(
+            &reply,
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            alice_received_at.into(),
+            &mut csprng,
+            &SessionConfig::default(),
+        )
+        .await?;
+        let alice_record = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session exists");
+        let alice_diagnostics = alice_record.diagnostics()?;
+        let alice_current = alice_diagnostics
+            .current_session
+            .as_ref()
+            .expect("has current session");
+        assert!(alice_current.is_acknowledged());
+        assert_eq!(alice_current.acknowledged_at, Some(alice_received_at));
+
+        // The JSON form carries only counts, indexes, and timestamps.
+        let json = serde_json::to_value(&alice_diagnostics).expect("can serialize");
+        assert_eq!(json["archived_sessions"], serde_json::json!([]));
+        assert_eq!(json["current_session"]["sender_chain_index"], 0);
+        let json = json.to_string();
+        let alice_base_key_hex = hex::encode(alice_record.alice_base_key()?);
+        assert!(!json.contains(&alice_base_key_hex));
+
+        // Archived sessions keep their own diagnostics.
+        let mut archived_record = alice_record.clone();
+        archived_record.archive_current_state()?;
+        let archived_diagnostics = archived_record.diagnostics()?;
+        assert_eq!(archived_diagnostics.current_session, None);
+        assert_eq!(
+            archived_diagnostics.archived_sessions,
+            [alice_diagnostics
+                .current_session
+                .expect("has current session")]
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn dry_run_decrypt_commits_only_on_request() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(0.into())
+            .with_signed_pre_key(0.into())
+            .with_kyber_pre_key(0.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(bob_address.device_id());
+        let mut bob_store = bob_store_builder.store;
+        let mut alice_store = TestStoreBuilder::new().store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+        )
+        .await?;
+
+        let CiphertextMessage::PreKeySignalMessage(pre_key_message) =
+            encrypt(&mut alice_store, &bob_address, "first").await?
+        else {
+            panic!("expected a pre-key message");
+        };
+
+        let (ptext, pending) = message_decrypt_prekey_dry_run(
+            &pre_key_message,
+            &alice_address,
+            &bob_store.session_store,
+            &bob_store.identity_store,
+            &bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &bob_store.kyber_pre_key_store,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+            &SessionConfig::default(),
+        )
+        .await?;
+        assert_eq!(ptext, b"first");
+        assert_eq!(pending.remote_address(), &alice_address);
+
+        // Nothing has been written yet.
+        assert!(bob_store.load_session(&alice_address).await?.is_none());
+        assert!(bob_store.get_identity(&alice_address).await?.is_none());
+        assert!(bob_store.get_pre_key(0.into()).await.is_ok());
+
+        pending
+            .commit(
+                &mut bob_store.session_store,
+                &mut bob_store.identity_store,
+                &mut bob_store.pre_key_store,
+                &mut bob_store.kyber_pre_key_store,
+            )
+            .await?;
+        assert!(bob_store.load_session(&alice_address).await?.is_some());
+        assert!(bob_store.get_identity(&alice_address).await?.is_some());
+        assert!(bob_store.get_pre_key(0.into()).await.is_err());
+
+        let reply = encrypt(&mut bob_store, &alice_address, "reply").await?;
+        decrypt(&mut alice_store, &bob_address, &reply, UsePQRatchet::Yes).await?;
+
+        let CiphertextMessage::SignalMessage(second) =
+            encrypt(&mut alice_store, &bob_address, "second").await?
+        else {
+            panic!("expected a whisper message");
+        };
+        let session_before = bob_store
+            .load_session(&alice_address)
+            .await?
+            .expect("session exists")
+            .serialize()?;
+
+        // A discarded dry run leaves the session untouched, so the message can be decrypted again.
+        let (ptext, pending) = message_decrypt_signal_dry_run(
+            &second,
+            &alice_address,
+            &bob_store.session_store,
+            &bob_store.identity_store,
+            SystemTime::now(),
+            &mut csprng,
+            &SessionConfig::default(),
+        )
+        .await?;
+        assert_eq!(ptext, b"second");
+        drop(pending);
+        assert_eq!(
+            bob_store
+                .load_session(&alice_address)
+                .await?
+                .expect("session exists")
+                .serialize()?,
+            session_before
+        );
+
+        let mut bob_store = InMemTransactionalProtocolStore::new(bob_store);
+        let (ptext, pending) = message_decrypt_signal_dry_run(
+            &second,
+            &alice_address,
+            &bob_store,
+            &bob_store,
+            SystemTime::now(),
+            &mut csprng,
+            &SessionConfig::default(),
+        )
+        .await?;
+        assert_eq!(ptext, b"second");
+        pending.commit_in_transaction(&mut bob_store).await?;
+
+        assert_matches!(
+            message_decrypt_signal_transactional(
+                &second,
+                &alice_address,
+                &mut bob_store,
+                SystemTime::now(),
+                &mut csprng,
+                &SessionConfig::default(),
+            )
+            .await,
+            Err(SignalProtocolError::DuplicatedMessage(_, _))
+        );
+
+        // Decryption happens inside the transaction, so the failure above must have rolled it back
+        // rather than leaving it open for the next message.
+        let CiphertextMessage::SignalMessage(third) =
+            encrypt(&mut alice_store, &bob_address, "third").await?
+        else {
+            panic!("expected a whisper message");
+        };
+        let ptext = message_decrypt_signal_transactional(
+            &third,
+            &alice_address,
+            &mut bob_store,
+            SystemTime::now(),
+            &mut csprng,
+            &SessionConfig::default(),
+        )
+        .await?;
+        assert_eq!(ptext, b"third");
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn dry_run_decrypt_rejects_stale_commit() -> TestResult {
+    async {
+        let (alice_session_record, bob_session_record) = initialize_sessions_v4()?;
+
+        let alice_address =
+            ProtocolAddress::new("+14159999999".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14158888888".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store = TestStoreBuilder::new().store;
+
+        alice_store
+            .store_session(&bob_address, &alice_session_record)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session_record)
+            .await?;
+
+        let CiphertextMessage::SignalMessage(first) =
+            encrypt(&mut alice_store, &bob_address, "first").await?
+        else {
+            panic!("expected a whisper message");
+        };
+        let second = encrypt(&mut alice_store, &bob_address, "second").await?;
+
+        let (_, pending) = message_decrypt_signal_dry_run(
+            &first,
+            &alice_address,
+            &bob_store.session_store,
+            &bob_store.identity_store,
+            SystemTime::now(),
+            &mut OsRng.unwrap_err(),
+            &SessionConfig::default(),
+        )
+        .await?;
+
+        // Decrypting another message in the meantime advances the session.
+        decrypt(&mut bob_store, &alice_address, &second, UsePQRatchet::Yes).await?;
+
+        assert_matches!(
+            pending
+                .commit(
+                    &mut bob_store.session_store,
+                    &mut bob_store.identity_store,
+                    &mut bob_store.pre_key_store,
+                    &mut bob_store.kyber_pre_key_store,
+                )
+                .await,
+            Err(SignalProtocolError::SessionChanged(_))
+        );
+
+        // The skipped key for the first message is still available.
+        assert_eq!(
+            decrypt(
+                &mut bob_store,
+                &alice_address,
+                &CiphertextMessage::SignalMessage(first),
+                UsePQRatchet::Yes
+            )
+            .await?,
+            b"first"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn dry_run_decrypt_rejects_commit_after_pre_key_consumed() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(0.into())
+            .with_signed_pre_key(0.into())
+            .with_kyber_pre_key(0.into());
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(bob_address.device_id());
+        let mut bob_store = bob_store_builder.store;
+        let mut alice_store = TestStoreBuilder::new().store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+        )
+        .await?;
+
+        let CiphertextMessage::PreKeySignalMessage(pre_key_message) =
+            encrypt(&mut alice_store, &bob_address, "first").await?
+        else {
+            panic!("expected a pre-key message");
+        };
+
+        let (_, pending) = message_decrypt_prekey_dry_run(
+            &pre_key_message,
+            &alice_address,
+            &bob_store.session_store,
+            &bob_store.identity_store,
+            &bob_store.pre_key_store,
+            &bob_store.signed_pre_key_store,
+            &bob_store.kyber_pre_key_store,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+            &SessionConfig::default(),
+        )
+        .await?;
+
+        // The session is untouched, but the one-time pre-key is gone.
+        bob_store.remove_pre_key(0.into()).await?;
+
+        assert_matches!(
+            pending.commit_in_transaction(&mut bob_store).await,
+            Err(SignalProtocolError::SessionChanged(_))
+        );
+        assert!(bob_store.load_session(&alice_address).await?.is_none());
+        assert!(bob_store.get_identity(&alice_address).await?.is_none());
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
 #[expect(clippy::needless_range_loop)]
 fn run_session_interaction(alice_session: SessionRecord, bob_session: SessionRecord) -> TestResult {
     async {
@@ -3656,10 +4532,7 @@ fn prekey_message_sent_from_different_user_is_rejected() {
         )
         .await
         .expect_err("should be rejected");
-        assert_matches!(
-            err,
-            SignalProtocolError::InvalidMessage(CiphertextMessageType::PreKey, "reused base key")
-        );
+        assert_matches!(err, SignalProtocolError::ReusedKyberPreKeyBaseKey { .. });
         assert!(
             bob_store
                 .load_session(&mallory_address)
@@ -3672,3 +4545,457 @@ fn prekey_message_sent_from_different_user_is_rejected() {
     .now_or_never()
     .expect("sync")
 }
+
+#[test]
+fn one_time_kyber_pre_key_is_removed_after_use() {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut bob_store_builder = TestStoreBuilder::new();
+        bob_store_builder.add_signed_pre_key(IdChoice::Next);
+        bob_store_builder.add_kyber_pre_key(IdChoice::Next);
+        let bob_pre_key_bundle =
+            bob_store_builder.make_bundle_with_latest_keys(DeviceId::new(1).unwrap());
+
+        let bob_store = &mut bob_store_builder.store;
+        let kyber_pre_key_id = bob_pre_key_bundle.kyber_pre_key_id().expect("valid");
+        let record = bob_store
+            .get_kyber_pre_key(kyber_pre_key_id)
+            .await
+            .expect("present")
+            .with_kind(KyberPreKeyKind::OneTime);
+        bob_store
+            .save_kyber_pre_key(kyber_pre_key_id, &record)
+            .await
+            .expect("can save");
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::No,
+        )
+        .await
+        .expect("valid");
+
+        let pre_key_message = support::encrypt(&mut alice_store, &bob_address, "hi")
+            .await
+            .expect("valid");
+        _ = support::decrypt(
+            bob_store,
+            &alice_address,
+            &pre_key_message,
+            UsePQRatchet::No,
+        )
+        .await
+        .expect("decrypts");
+
+        assert_matches!(
+            bob_store.get_kyber_pre_key(kyber_pre_key_id).await,
+            Err(SignalProtocolError::InvalidKyberPreKeyId)
+        );
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn identity_succession_is_reported_when_saving_identity() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = test_in_memory_protocol_store()?;
+        let address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+
+        let original = IdentityKeyPair::generate(&mut csprng);
+        let rotated = IdentityKeyPair::generate(&mut csprng);
+        let unrelated = IdentityKeyPair::generate(&mut csprng);
+
+        store
+            .save_identity(&address, original.identity_key())
+            .await?;
+        assert!(
+            !store
+                .is_trusted_identity(&address, rotated.identity_key(), Direction::Sending)
+                .await?
+        );
+
+        let mut chain = IdentitySuccessionChain::new();
+        chain.push(IdentitySuccession::new(&original, &rotated, &mut csprng)?)?;
+        let chain = IdentitySuccessionChain::deserialize(&chain.serialize())?;
+
+        assert_eq!(
+            store
+                .save_identity_with_succession(&address, rotated.identity_key(), &chain)
+                .await?,
+            SavedIdentity {
+                change: IdentityChange::ReplacedExisting,
+                verified_succession: true,
+            }
+        );
+        assert!(
+            store
+                .is_trusted_identity(&address, rotated.identity_key(), Direction::Sending)
+                .await?
+        );
+        assert!(store.is_verified_succession(&address).await?);
+
+        // Saving the same key again is not a change at all.
+        assert_eq!(
+            store
+                .save_identity_with_succession(&address, rotated.identity_key(), &chain)
+                .await?,
+            SavedIdentity {
+                change: IdentityChange::NewOrUnchanged,
+                verified_succession: false,
+            }
+        );
+
+        // A key the chain doesn't lead to is an ordinary change.
+        assert_eq!(
+            store
+                .save_identity_with_succession(&address, unrelated.identity_key(), &chain)
+                .await?,
+            SavedIdentity {
+                change: IdentityChange::ReplacedExisting,
+                verified_succession: false,
+            }
+        );
+        assert!(!store.is_verified_succession(&address).await?);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn decryption_error_actions_follow_the_current_ratchet_key() -> TestResult {
+    async {
+        let (alice_session_record, bob_session_record) = initialize_sessions_v4()?;
+
+        let alice_device_id = DeviceId::new(1).unwrap();
+        let alice_address = ProtocolAddress::new("+14159999999".to_owned(), alice_device_id);
+        let bob_address =
+            ProtocolAddress::new("+14158888888".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = TestStoreBuilder::new().store;
+        let mut bob_store = TestStoreBuilder::new().store;
+        alice_store
+            .store_session(&bob_address, &alice_session_record)
+            .await?;
+        bob_store
+            .store_session(&alice_address, &bob_session_record)
+            .await?;
+
+        const SENT_AT: Timestamp = Timestamp::from_epoch_millis(1000);
+        let logged = SentMessage {
+            timestamp: SENT_AT,
+            content: Box::from(&b"hello"[..]),
+        };
+        let mut log = InMemSentMessageLogStore::new();
+        log.log_sent_message(&[&bob_address], &logged).await?;
+        let empty_log = InMemSentMessageLogStore::new();
+
+        let sent = encrypt(&mut alice_store, &bob_address, "hello").await?;
+        let error = DecryptionErrorMessage::for_original(
+            sent.serialize(),
+            sent.message_type(),
+            SENT_AT,
+            1,
+        )?;
+
+        let session = alice_store.load_session(&bob_address).await?;
+
+        // The failed message used the current session, which must be replaced.
+        assert_eq!(
+            decryption_error_action(
+                &error,
+                &bob_address,
+                alice_device_id,
+                session.as_ref(),
+                &log
+            )
+            .await?,
+            DecryptionErrorAction::ArchiveSessionAndResend {
+                message: Some(logged.clone())
+            }
+        );
+        assert_eq!(
+            decryption_error_action(
+                &error,
+                &bob_address,
+                alice_device_id,
+                session.as_ref(),
+                &empty_log
+            )
+            .await?,
+            DecryptionErrorAction::ArchiveSessionAndResend { message: None }
+        );
+        assert_eq!(
+            decryption_error_action(
+                &error,
+                &bob_address,
+                DeviceId::new(2).unwrap(),
+                session.as_ref(),
+                &log
+            )
+            .await?,
+            DecryptionErrorAction::Ignore(DecryptionErrorIgnoreReason::OtherDevice)
+        );
+
+        // Once Bob's reply advances the ratchet, the session is no longer implicated.
+        let _ = decrypt(&mut bob_store, &alice_address, &sent, UsePQRatchet::No).await?;
+        let reply = encrypt(&mut bob_store, &alice_address, "hi").await?;
+        let _ = decrypt(&mut alice_store, &bob_address, &reply, UsePQRatchet::No).await?;
+        let session = alice_store.load_session(&bob_address).await?;
+
+        assert_eq!(
+            decryption_error_action(
+                &error,
+                &bob_address,
+                alice_device_id,
+                session.as_ref(),
+                &log
+            )
+            .await?,
+            DecryptionErrorAction::Resend {
+                message: logged.clone()
+            }
+        );
+        assert_eq!(
+            decryption_error_action(
+                &error,
+                &bob_address,
+                alice_device_id,
+                session.as_ref(),
+                &empty_log
+            )
+            .await?,
+            DecryptionErrorAction::Ignore(DecryptionErrorIgnoreReason::StaleRatchetKey)
+        );
+
+        // Sender key messages never implicate the session.
+        let group_error = DecryptionErrorMessage::for_original(
+            &[],
+            CiphertextMessageType::SenderKey,
+            SENT_AT,
+            1,
+        )?;
+        assert_eq!(
+            decryption_error_action(
+                &group_error,
+                &bob_address,
+                alice_device_id,
+                session.as_ref(),
+                &empty_log
+            )
+            .await?,
+            DecryptionErrorAction::Ignore(DecryptionErrorIgnoreReason::NotLogged)
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn test_pq_ratchet_negotiation() -> TestResult {
+    struct Established {
+        alice_store: InMemSignalProtocolStore,
+        alice_address: ProtocolAddress,
+        bob_store: InMemSignalProtocolStore,
+        bob_address: ProtocolAddress,
+        bob_decrypt_result: Result<Vec<u8>, SignalProtocolError>,
+    }
+
+    /// Starts a session from Alice to Bob, and has Bob decrypt the first message.
+    async fn establish(
+        alice_pqr: UsePQRatchet,
+        bob_pqr: UsePQRatchet,
+    ) -> Result<Established, SignalProtocolError> {
+        let mut csprng = OsRng.unwrap_err();
+        let bob_device_id = DeviceId::new(1).unwrap();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address = ProtocolAddress::new("+14151111112".to_owned(), bob_device_id);
+
+        let bob_store_builder = TestStoreBuilder::new()
+            .with_pre_key(1.into())
+            .with_signed_pre_key(2.into())
+            .with_kyber_pre_key(3.into());
+        let bundle = bob_store_builder.make_bundle_with_latest_keys(bob_device_id);
+        let mut bob_store = bob_store_builder.store;
+        let mut alice_store = TestStoreBuilder::new().store;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bundle,
+            SystemTime::now(),
+            &mut csprng,
+            alice_pqr,
+        )
+        .await?;
+        let alice_session = alice_store
+            .load_session(&bob_address)
+            .await?
+            .expect("session found");
+        assert_eq!(
+            alice_session.pq_ratchet_required(),
+            Some(alice_pqr == UsePQRatchet::Required)
+        );
+
+        let outgoing = encrypt(&mut alice_store, &bob_address, "hi Bob").await?;
+        let incoming = CiphertextMessage::PreKeySignalMessage(PreKeySignalMessage::try_from(
+            outgoing.serialize(),
+        )?);
+        let bob_decrypt_result = decrypt(&mut bob_store, &alice_address, &incoming, bob_pqr).await;
+        Ok(Established {
+            alice_store,
+            alice_address,
+            bob_store,
+            bob_address,
+            bob_decrypt_result,
+        })
+    }
+
+    async fn pq_ratchet_status(
+        store: &InMemSignalProtocolStore,
+        address: &ProtocolAddress,
+    ) -> Result<Option<PqRatchetStatus>, SignalProtocolError> {
+        Ok(store
+            .load_session(address)
+            .await?
+            .and_then(|session| session.pq_ratchet_status()))
+    }
+
+    async {
+        // Both sides use the ratchet; once each has heard from the other, it's active.
+        let Established {
+            mut alice_store,
+            alice_address,
+            mut bob_store,
+            bob_address,
+            bob_decrypt_result,
+        } = establish(UsePQRatchet::Yes, UsePQRatchet::Yes).await?;
+        bob_decrypt_result?;
+        assert_eq!(
+            pq_ratchet_status(&alice_store, &bob_address).await?,
+            Some(PqRatchetStatus::Offered)
+        );
+        assert_eq!(
+            pq_ratchet_status(&bob_store, &alice_address).await?,
+            Some(PqRatchetStatus::Active)
+        );
+        let reply = encrypt(&mut bob_store, &alice_address, "hi Alice").await?;
+        let _ = decrypt(&mut alice_store, &bob_address, &reply, UsePQRatchet::Yes).await?;
+        assert_eq!(
+            pq_ratchet_status(&alice_store, &bob_address).await?,
+            Some(PqRatchetStatus::Active)
+        );
+
+        // After that, a message without it is rejected even though the ratchet wasn't required.
+        let CiphertextMessage::SignalMessage(reply) =
+            encrypt(&mut bob_store, &alice_address, "downgraded").await?
+        else {
+            panic!("expected a SignalMessage");
+        };
+        let downgraded = SignalMessage::new(
+            reply.message_version(),
+            &[0; 32],
+            *reply.sender_ratchet_key(),
+            reply.counter(),
+            0,
+            reply.body(),
+            bob_store.get_identity_key_pair().await?.identity_key(),
+            alice_store.get_identity_key_pair().await?.identity_key(),
+            &[],
+        )?;
+        assert_matches!(
+            decrypt(
+                &mut alice_store,
+                &bob_address,
+                &CiphertextMessage::SignalMessage(downgraded),
+                UsePQRatchet::Yes,
+            )
+            .await,
+            Err(SignalProtocolError::PqRatchetDowngrade(address)) if address == bob_address
+        );
+        assert_eq!(
+            pq_ratchet_status(&alice_store, &bob_address).await?,
+            Some(PqRatchetStatus::Active)
+        );
+
+        // A peer that doesn't use the ratchet is tolerated when it's only preferred...
+        let Established {
+            mut alice_store,
+            alice_address,
+            mut bob_store,
+            bob_address,
+            bob_decrypt_result,
+        } = establish(UsePQRatchet::Yes, UsePQRatchet::No).await?;
+        bob_decrypt_result?;
+        assert_eq!(
+            pq_ratchet_status(&bob_store, &alice_address).await?,
+            Some(PqRatchetStatus::Disabled)
+        );
+        let reply = encrypt(&mut bob_store, &alice_address, "hi Alice").await?;
+        let _ = decrypt(&mut alice_store, &bob_address, &reply, UsePQRatchet::Yes).await?;
+        assert_eq!(
+            pq_ratchet_status(&alice_store, &bob_address).await?,
+            Some(PqRatchetStatus::Declined)
+        );
+
+        // ...but not when it's required, on either side of the session.
+        let Established {
+            mut alice_store,
+            alice_address,
+            mut bob_store,
+            bob_address,
+            bob_decrypt_result,
+        } = establish(UsePQRatchet::Required, UsePQRatchet::No).await?;
+        bob_decrypt_result?;
+        let reply = encrypt(&mut bob_store, &alice_address, "hi Alice").await?;
+        assert_matches!(
+            decrypt(
+                &mut alice_store,
+                &bob_address,
+                &reply,
+                UsePQRatchet::Required
+            )
+            .await,
+            Err(SignalProtocolError::PqRatchetDowngrade(_))
+        );
+        assert_eq!(
+            pq_ratchet_status(&alice_store, &bob_address).await?,
+            Some(PqRatchetStatus::Offered)
+        );
+
+        let Established {
+            alice_address,
+            bob_store,
+            bob_decrypt_result,
+            ..
+        } = establish(UsePQRatchet::No, UsePQRatchet::Required).await?;
+        assert_matches!(
+            bob_decrypt_result,
+            Err(SignalProtocolError::PqRatchetDowngrade(_))
+        );
+        assert_eq!(pq_ratchet_status(&bob_store, &alice_address).await?, None);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
@@ -0,0 +1,379 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+#![cfg(feature = "sqlite")]
+
+mod support;
+
+use std::time::SystemTime;
+
+use assert_matches::assert_matches;
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::rngs::OsRng;
+use rand::{Rng, TryRngCore as _};
+use support::*;
+use uuid::Uuid;
+
+type TestResult = Result<(), SignalProtocolError>;
+
+fn test_sqlite_protocol_store(
+    storage_key: &[u8; SQLITE_STORAGE_KEY_LEN],
+) -> Result<SqliteSignalProtocolStore, SignalProtocolError> {
+    let mut csprng = OsRng.unwrap_err();
+    let mut store = SqliteSignalProtocolStore::open_in_memory(storage_key)?;
+    // Valid registration IDs fit in 14 bits.
+    let registration_id: u8 = csprng.random();
+    store.set_local_identity(
+        &IdentityKeyPair::generate(&mut csprng),
+        registration_id.into(),
+    )?;
+    Ok(store)
+}
+
+#[test]
+fn sqlite_basic_session() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = test_sqlite_protocol_store(&csprng.random())?;
+        let mut bob_store = test_sqlite_protocol_store(&csprng.random())?;
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+        )
+        .await?;
+
+        let alice_ciphertext = message_encrypt(
+            b"from Alice",
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(
+            alice_ciphertext.message_type(),
+            CiphertextMessageType::PreKey
+        );
+
+        let received = message_decrypt_transactional(
+            &alice_ciphertext,
+            &alice_address,
+            &mut bob_store,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+            &SessionConfig::default(),
+        )
+        .await?;
+        assert_eq!(received, b"from Alice");
+
+        // The one-time pre-key was consumed.
+        let pre_key_id = bob_pre_key_bundle
+            .pre_key_id()?
+            .expect("has one-time pre-key");
+        assert_matches!(
+            bob_store.get_pre_key(pre_key_id).await,
+            Err(SignalProtocolError::InvalidPreKeyId)
+        );
+
+        let bob_ciphertext = message_encrypt(
+            b"from Bob",
+            &alice_address,
+            &mut bob_store.session_store,
+            &mut bob_store.identity_store,
+            SystemTime::now(),
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(
+            bob_ciphertext.message_type(),
+            CiphertextMessageType::Whisper
+        );
+
+        let received = message_decrypt(
+            &bob_ciphertext,
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &mut alice_store.pre_key_store,
+            &alice_store.signed_pre_key_store,
+            &mut alice_store.kyber_pre_key_store,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+            &SessionConfig::default(),
+        )
+        .await?;
+        assert_eq!(received, b"from Bob");
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn sqlite_group_basic_encrypt_decrypt() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_sqlite_protocol_store(&csprng.random())?;
+        let mut bob_store = test_sqlite_protocol_store(&csprng.random())?;
+
+        let sent_distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+
+        let recv_distribution_message =
+            SenderKeyDistributionMessage::try_from(sent_distribution_message.serialized())?;
+
+        let alice_ciphertext = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            "space camp?".as_bytes(),
+            &mut csprng,
+        )
+        .await?;
+
+        process_sender_key_distribution_message(
+            &sender_address,
+            &recv_distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        let bob_plaintext = group_decrypt(
+            alice_ciphertext.serialized(),
+            &mut bob_store,
+            &sender_address,
+        )
+        .await?;
+
+        assert_eq!(
+            String::from_utf8(bob_plaintext).expect("valid utf8"),
+            "space camp?"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn sqlite_records_persist_and_are_encrypted_at_rest() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let path = std::env::temp_dir().join(format!(
+            "libsignal-protocol-test-{:016x}.sqlite",
+            csprng.random::<u64>()
+        ));
+        let storage_key: [u8; SQLITE_STORAGE_KEY_LEN] = csprng.random();
+
+        let (session, _) = initialize_sessions_v4()?;
+        let serialized_session = session.serialize()?;
+
+        {
+            let mut store = SqliteSignalProtocolStore::open(&path, &storage_key)?;
+            assert_eq!(store.schema_version()?, 2);
+            store.store_session(&address, &session).await?;
+        }
+
+        let raw_record: Vec<u8> = rusqlite::Connection::open(&path)
+            .and_then(|conn| conn.query_row("SELECT record FROM sessions", [], |row| row.get(0)))
+            .expect("can read database directly");
+        assert!(
+            !raw_record
+                .windows(serialized_session.len())
+                .any(|window| window == serialized_session),
+            "session record stored in plaintext"
+        );
+
+        {
+            let store = SqliteSignalProtocolStore::open(&path, &storage_key)?;
+            let loaded = store
+                .load_session(&address)
+                .await?
+                .expect("session was persisted");
+            assert_eq!(loaded.serialize()?, serialized_session);
+        }
+
+        {
+            let wrong_key: [u8; SQLITE_STORAGE_KEY_LEN] = csprng.random();
+            let store = SqliteSignalProtocolStore::open(&path, &wrong_key)?;
+            assert_matches!(
+                store.load_session(&address).await,
+                Err(SignalProtocolError::InvalidState(_, _))
+            );
+        }
+
+        std::fs::remove_file(&path).expect("can clean up");
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn sqlite_remembers_verified_identity_succession() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = test_sqlite_protocol_store(&csprng.random())?;
+        let address = ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+
+        let original = IdentityKeyPair::generate(&mut csprng);
+        let rotated = IdentityKeyPair::generate(&mut csprng);
+        let unrelated = IdentityKeyPair::generate(&mut csprng);
+
+        let mut chain = IdentitySuccessionChain::new();
+        chain.push(IdentitySuccession::new(&original, &rotated, &mut csprng)?)?;
+
+        store
+            .save_identity(&address, original.identity_key())
+            .await?;
+        assert!(!store.is_verified_succession(&address).await?);
+
+        assert_eq!(
+            store
+                .save_identity_with_succession(&address, rotated.identity_key(), &chain)
+                .await?,
+            SavedIdentity {
+                change: IdentityChange::ReplacedExisting,
+                verified_succession: true,
+            }
+        );
+        assert!(store.is_verified_succession(&address).await?);
+
+        // Re-saving the same key keeps the flag; replacing it without a succession clears it.
+        store
+            .save_identity(&address, rotated.identity_key())
+            .await?;
+        assert!(store.is_verified_succession(&address).await?);
+        store
+            .save_identity(&address, unrelated.identity_key())
+            .await?;
+        assert!(!store.is_verified_succession(&address).await?);
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn sqlite_rejects_reused_kyber_base_key() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = test_sqlite_protocol_store(&csprng.random())?;
+        let identity_key_pair = store.get_identity_key_pair().await?;
+        let base_key = KeyPair::generate(&mut csprng).public_key;
+
+        let record = KyberPreKeyRecord::generate(
+            kem::KeyType::Kyber1024,
+            1.into(),
+            identity_key_pair.private_key(),
+        )?
+        .with_kind(KyberPreKeyKind::LastResort);
+        store.save_kyber_pre_key(1.into(), &record).await?;
+
+        store
+            .mark_kyber_pre_key_used(1.into(), 2.into(), &base_key)
+            .await?;
+        assert_matches!(
+            store
+                .mark_kyber_pre_key_used(1.into(), 2.into(), &base_key)
+                .await,
+            Err(SignalProtocolError::ReusedKyberPreKeyBaseKey { .. })
+        );
+        // A different signed pre-key makes it a different combination.
+        store
+            .mark_kyber_pre_key_used(1.into(), 3.into(), &base_key)
+            .await?;
+        // The last-resort key itself is kept.
+        _ = store.get_kyber_pre_key(1.into()).await?;
+
+        // Once the key is removed and its ID reused, the old base keys are forgotten.
+        store.remove_kyber_pre_key(1.into())?;
+        let replacement = KyberPreKeyRecord::generate(
+            kem::KeyType::Kyber1024,
+            1.into(),
+            identity_key_pair.private_key(),
+        )?
+        .with_kind(KyberPreKeyKind::LastResort);
+        store.save_kyber_pre_key(1.into(), &replacement).await?;
+        store
+            .mark_kyber_pre_key_used(1.into(), 2.into(), &base_key)
+            .await?;
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn sqlite_removes_one_time_kyber_pre_key_on_use() -> TestResult {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+        let mut store = test_sqlite_protocol_store(&csprng.random())?;
+        let identity_key_pair = store.get_identity_key_pair().await?;
+        let base_key = KeyPair::generate(&mut csprng).public_key;
+
+        let record = KyberPreKeyRecord::generate(
+            kem::KeyType::Kyber1024,
+            1.into(),
+            identity_key_pair.private_key(),
+        )?
+        .with_kind(KyberPreKeyKind::OneTime);
+        store.save_kyber_pre_key(1.into(), &record).await?;
+        assert_eq!(
+            store.get_kyber_pre_key(1.into()).await?.kind(),
+            KyberPreKeyKind::OneTime
+        );
+
+        store
+            .mark_kyber_pre_key_used(1.into(), 2.into(), &base_key)
+            .await?;
+        assert_matches!(
+            store.get_kyber_pre_key(1.into()).await,
+            Err(SignalProtocolError::InvalidKyberPreKeyId)
+        );
+        assert_matches!(
+            store
+                .mark_kyber_pre_key_used(1.into(), 2.into(), &base_key)
+                .await,
+            Err(SignalProtocolError::InvalidKyberPreKeyId)
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
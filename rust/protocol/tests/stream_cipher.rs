@@ -0,0 +1,211 @@
+//
+// Copyright 2025 Signal Messenger, LLC.
+// SPDX-License-Identifier: AGPL-3.0-only
+//
+
+mod support;
+
+use std::time::SystemTime;
+
+use assert_matches::assert_matches;
+use futures_util::FutureExt;
+use libsignal_protocol::*;
+use rand::TryRngCore as _;
+use rand::rngs::OsRng;
+use support::*;
+use uuid::Uuid;
+
+fn encrypt_stream(
+    mut encryptor: StreamEncryptor,
+    payload: &[u8],
+) -> Result<Vec<u8>, SignalProtocolError> {
+    let mut stream = vec![];
+    for piece in payload.chunks(10_000) {
+        stream.extend(encryptor.update(piece)?);
+    }
+    stream.extend(encryptor.finalize()?);
+    Ok(stream)
+}
+
+fn decrypt_stream(
+    mut decryptor: StreamDecryptor,
+    stream: &[u8],
+) -> Result<Vec<u8>, SignalProtocolError> {
+    let mut received = vec![];
+    for piece in stream.chunks(8_192) {
+        received.extend(decryptor.update(piece)?);
+    }
+    received.extend(decryptor.finalize()?);
+    Ok(received)
+}
+
+async fn decrypt_key_message(
+    store: &mut InMemSignalProtocolStore,
+    remote_address: &ProtocolAddress,
+    key_message: &CiphertextMessage,
+) -> Result<StreamDecryptor, SignalProtocolError> {
+    let mut csprng = OsRng.unwrap_err();
+    message_decrypt_stream(
+        key_message,
+        remote_address,
+        &mut store.session_store,
+        &mut store.identity_store,
+        &mut store.pre_key_store,
+        &store.signed_pre_key_store,
+        &mut store.kyber_pre_key_store,
+        SystemTime::now(),
+        &mut csprng,
+        UsePQRatchet::Yes,
+        &SessionConfig::default(),
+    )
+    .await
+}
+
+#[test]
+fn session_stream_encrypt_decrypt() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let alice_address =
+            ProtocolAddress::new("+14151111111".to_owned(), DeviceId::new(1).unwrap());
+        let bob_address =
+            ProtocolAddress::new("+14151111112".to_owned(), DeviceId::new(1).unwrap());
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        let bob_pre_key_bundle = create_pre_key_bundle(&mut bob_store, &mut csprng).await?;
+        process_prekey_bundle(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            &bob_pre_key_bundle,
+            SystemTime::now(),
+            &mut csprng,
+            UsePQRatchet::Yes,
+        )
+        .await?;
+
+        let payload: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+
+        let (first_key_message, encryptor) = message_encrypt_stream(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now(),
+            payload.len(),
+            &mut csprng,
+        )
+        .await?;
+        let first_stream = encrypt_stream(encryptor, &payload)?;
+        assert_eq!(
+            first_key_message.message_type(),
+            CiphertextMessageType::PreKey
+        );
+
+        let (second_key_message, encryptor) = message_encrypt_stream(
+            &bob_address,
+            &mut alice_store.session_store,
+            &mut alice_store.identity_store,
+            SystemTime::now(),
+            payload.len(),
+            &mut csprng,
+        )
+        .await?;
+        let second_stream = encrypt_stream(encryptor, &payload)?;
+        // Each stream is keyed by its own ratchet step.
+        assert_ne!(first_stream, second_stream);
+
+        let first_decryptor =
+            decrypt_key_message(&mut bob_store, &alice_address, &first_key_message).await?;
+        let second_decryptor =
+            decrypt_key_message(&mut bob_store, &alice_address, &second_key_message).await?;
+        assert_matches!(
+            decrypt_stream(first_decryptor, &second_stream),
+            Err(SignalProtocolError::InvalidStreamMessage(
+                "MAC verification failed"
+            ))
+        );
+        assert_eq!(decrypt_stream(second_decryptor, &second_stream)?, payload);
+
+        // The key message can't be used to start the stream twice.
+        assert_matches!(
+            decrypt_key_message(&mut bob_store, &alice_address, &second_key_message).await,
+            Err(SignalProtocolError::DuplicatedMessage(_, _))
+        );
+
+        // The session carries on as usual afterwards.
+        let reply = encrypt(&mut bob_store, &alice_address, "after the stream").await?;
+        assert_eq!(
+            decrypt(&mut alice_store, &bob_address, &reply, UsePQRatchet::Yes).await?,
+            b"after the stream"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
+
+#[test]
+fn group_stream_encrypt_decrypt() -> Result<(), SignalProtocolError> {
+    async {
+        let mut csprng = OsRng.unwrap_err();
+
+        let sender_address =
+            ProtocolAddress::new("+14159999111".to_owned(), DeviceId::new(1).unwrap());
+        let distribution_id = Uuid::from_u128(0xd1d1d1d1_7000_11eb_b32a_33b8a8a487a6);
+
+        let mut alice_store = test_in_memory_protocol_store()?;
+        let mut bob_store = test_in_memory_protocol_store()?;
+
+        let distribution_message = create_sender_key_distribution_message(
+            &sender_address,
+            distribution_id,
+            &mut alice_store,
+            &mut csprng,
+        )
+        .await?;
+        process_sender_key_distribution_message(
+            &sender_address,
+            &distribution_message,
+            &mut bob_store,
+        )
+        .await?;
+
+        let payload: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+
+        let (key_message, encryptor) = group_encrypt_stream(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            payload.len(),
+            &mut csprng,
+        )
+        .await?;
+        let stream = encrypt_stream(encryptor, &payload)?;
+
+        let decryptor =
+            group_decrypt_stream(key_message.serialized(), &mut bob_store, &sender_address).await?;
+        assert_eq!(decrypt_stream(decryptor, &stream)?, payload);
+
+        // The stream itself didn't advance the sender key chain.
+        let next = group_encrypt(
+            &mut alice_store,
+            &sender_address,
+            distribution_id,
+            b"after the stream",
+            &mut csprng,
+        )
+        .await?;
+        assert_eq!(next.iteration(), key_message.iteration() + 1);
+        assert_eq!(
+            group_decrypt(next.serialized(), &mut bob_store, &sender_address).await?,
+            b"after the stream"
+        );
+
+        Ok(())
+    }
+    .now_or_never()
+    .expect("sync")
+}
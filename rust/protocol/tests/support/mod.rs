@@ -60,8 +60,10 @@ pub async fn decrypt(
         &mut store.pre_key_store,
         &store.signed_pre_key_store,
         &mut store.kyber_pre_key_store,
+        SystemTime::now(),
         &mut csprng,
         use_pq_ratchet,
+        &SessionConfig::default(),
     )
     .await
 }
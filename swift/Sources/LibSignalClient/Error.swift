@@ -6,33 +6,39 @@
 import Foundation
 import SignalFfi
 
+/// An error thrown by libsignal.
+///
+/// Errors that come from the protocol carry its stable numeric code as `protocolErrorCode`, also
+/// available as ``SignalError/protocolErrorCode``. The hundreds digit of a code identifies the
+/// error's category, such as "replay or duplicate" or "untrusted identity". Codes are never reused,
+/// so they can be recorded as-is.
 public enum SignalError: Error {
-    case invalidState(String)
-    case internalError(String)
+    case invalidState(String, protocolErrorCode: UInt32? = nil)
+    case internalError(String, protocolErrorCode: UInt32? = nil)
     case nullParameter(String)
-    case invalidArgument(String)
+    case invalidArgument(String, protocolErrorCode: UInt32? = nil)
     case invalidType(String)
     case invalidUtf8String(String)
-    case protobufError(String)
-    case legacyCiphertextVersion(String)
-    case unknownCiphertextVersion(String)
-    case unrecognizedMessageVersion(String)
-    case invalidMessage(String)
-    case invalidKey(String)
-    case invalidSignature(String)
+    case protobufError(String, protocolErrorCode: UInt32? = nil)
+    case legacyCiphertextVersion(String, protocolErrorCode: UInt32? = nil)
+    case unknownCiphertextVersion(String, protocolErrorCode: UInt32? = nil)
+    case unrecognizedMessageVersion(String, protocolErrorCode: UInt32? = nil)
+    case invalidMessage(String, protocolErrorCode: UInt32? = nil)
+    case invalidKey(String, protocolErrorCode: UInt32? = nil)
+    case invalidSignature(String, protocolErrorCode: UInt32? = nil)
     case invalidAttestationData(String)
     case fingerprintVersionMismatch(theirs: UInt32, ours: UInt32)
     case fingerprintParsingError(String)
-    case sealedSenderSelfSend(String)
-    case untrustedIdentity(String)
-    case invalidKeyIdentifier(String)
-    case sessionNotFound(String)
-    case invalidSession(String)
-    case invalidRegistrationId(address: ProtocolAddress, message: String)
-    case invalidProtocolAddress(name: String, deviceId: UInt32, message: String)
-    case invalidSenderKeySession(distributionId: UUID, message: String)
-    case duplicatedMessage(String)
-    case verificationFailed(String)
+    case sealedSenderSelfSend(String, protocolErrorCode: UInt32? = nil)
+    case untrustedIdentity(String, protocolErrorCode: UInt32? = nil)
+    case invalidKeyIdentifier(String, protocolErrorCode: UInt32? = nil)
+    case sessionNotFound(String, protocolErrorCode: UInt32? = nil)
+    case invalidSession(String, protocolErrorCode: UInt32? = nil)
+    case invalidRegistrationId(address: ProtocolAddress, message: String, protocolErrorCode: UInt32? = nil)
+    case invalidProtocolAddress(name: String, deviceId: UInt32, message: String, protocolErrorCode: UInt32? = nil)
+    case invalidSenderKeySession(distributionId: UUID, message: String, protocolErrorCode: UInt32? = nil)
+    case duplicatedMessage(String, protocolErrorCode: UInt32? = nil)
+    case verificationFailed(String, protocolErrorCode: UInt32? = nil)
     case nicknameCannotBeEmpty(String)
     case nicknameCannotStartWithDigit(String)
     case missingSeparator(String)
@@ -50,7 +56,7 @@ public enum SignalError: Error {
     case ioError(String)
     case invalidMediaInput(String)
     case unsupportedMediaInput(String)
-    case callbackError(String)
+    case callbackError(String, protocolErrorCode: UInt32? = nil)
     case webSocketError(String)
     case connectionTimeoutError(String)
     case requestTimeoutError(String)
@@ -74,6 +80,38 @@ public enum SignalError: Error {
     case unknown(UInt32, String)
 }
 
+extension SignalError {
+    /// The stable numeric code of the protocol error this was thrown for, if any.
+    public var protocolErrorCode: UInt32? {
+        switch self {
+        case .invalidState(_, let code),
+            .internalError(_, let code),
+            .invalidArgument(_, let code),
+            .protobufError(_, let code),
+            .legacyCiphertextVersion(_, let code),
+            .unknownCiphertextVersion(_, let code),
+            .unrecognizedMessageVersion(_, let code),
+            .invalidMessage(_, let code),
+            .invalidKey(_, let code),
+            .invalidSignature(_, let code),
+            .sealedSenderSelfSend(_, let code),
+            .untrustedIdentity(_, let code),
+            .invalidKeyIdentifier(_, let code),
+            .sessionNotFound(_, let code),
+            .invalidSession(_, let code),
+            .invalidRegistrationId(_, _, let code),
+            .invalidProtocolAddress(_, _, _, let code),
+            .invalidSenderKeySession(_, _, let code),
+            .duplicatedMessage(_, let code),
+            .verificationFailed(_, let code),
+            .callbackError(_, let code):
+            return code
+        default:
+            return nil
+        }
+    }
+}
+
 internal typealias SignalFfiErrorRef = OpaquePointer
 
 internal func convertError(_ error: SignalFfiErrorRef?) -> Error? {
@@ -89,6 +127,18 @@ internal func convertError(_ error: SignalFfiErrorRef?) -> Error? {
     }
 }
 
+/// Returns the stable code of the protocol error behind `error`, if there is one.
+private func getProtocolErrorCode(_ error: SignalFfiErrorRef) -> UInt32? {
+    var code: UInt32 = 0
+    // Not every error comes from the protocol. Don't use checkError for the failure, or
+    // we'd end up back here.
+    if let notAProtocolError = signal_error_get_protocol_error_code(&code, error) {
+        signal_error_free(notAProtocolError)
+        return nil
+    }
+    return code
+}
+
 internal func checkError(_ error: SignalFfiErrorRef?) throws {
     guard let error = error else { return }
 
@@ -98,41 +148,42 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
         signal_error_get_message($0, error)
     }
     defer { signal_error_free(error) }
+    let protocolErrorCode = getProtocolErrorCode(error)
 
     switch SignalErrorCode(errType) {
     case SignalErrorCodeCancelled:
         // Special case: don't use SignalError for this one.
         throw CancellationError()
     case SignalErrorCodeInvalidState:
-        throw SignalError.invalidState(errStr)
+        throw SignalError.invalidState(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInternalError:
-        throw SignalError.internalError(errStr)
+        throw SignalError.internalError(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeNullParameter:
         throw SignalError.nullParameter(errStr)
     case SignalErrorCodeInvalidArgument:
-        throw SignalError.invalidArgument(errStr)
+        throw SignalError.invalidArgument(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidType:
         throw SignalError.invalidType(errStr)
     case SignalErrorCodeInvalidUtf8String:
         throw SignalError.invalidUtf8String(errStr)
     case SignalErrorCodeProtobufError:
-        throw SignalError.protobufError(errStr)
+        throw SignalError.protobufError(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeLegacyCiphertextVersion:
-        throw SignalError.legacyCiphertextVersion(errStr)
+        throw SignalError.legacyCiphertextVersion(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeUnknownCiphertextVersion:
-        throw SignalError.unknownCiphertextVersion(errStr)
+        throw SignalError.unknownCiphertextVersion(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeUnrecognizedMessageVersion:
-        throw SignalError.unrecognizedMessageVersion(errStr)
+        throw SignalError.unrecognizedMessageVersion(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidMessage:
-        throw SignalError.invalidMessage(errStr)
+        throw SignalError.invalidMessage(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeFingerprintParsingError:
         throw SignalError.fingerprintParsingError(errStr)
     case SignalErrorCodeSealedSenderSelfSend:
-        throw SignalError.sealedSenderSelfSend(errStr)
+        throw SignalError.sealedSenderSelfSend(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidKey:
-        throw SignalError.invalidKey(errStr)
+        throw SignalError.invalidKey(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidSignature:
-        throw SignalError.invalidSignature(errStr)
+        throw SignalError.invalidSignature(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidAttestationData:
         throw SignalError.invalidAttestationData(errStr)
     case SignalErrorCodeFingerprintVersionMismatch:
@@ -144,33 +195,42 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
         }
         throw SignalError.fingerprintVersionMismatch(theirs: theirs, ours: ours)
     case SignalErrorCodeUntrustedIdentity:
-        throw SignalError.untrustedIdentity(errStr)
+        throw SignalError.untrustedIdentity(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidKeyIdentifier:
-        throw SignalError.invalidKeyIdentifier(errStr)
+        throw SignalError.invalidKeyIdentifier(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeSessionNotFound:
-        throw SignalError.sessionNotFound(errStr)
+        throw SignalError.sessionNotFound(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidSession:
-        throw SignalError.invalidSession(errStr)
+        throw SignalError.invalidSession(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidRegistrationId:
         let address: ProtocolAddress = try invokeFnReturningNativeHandle {
             signal_error_get_address($0, error)
         }
-        throw SignalError.invalidRegistrationId(address: address, message: errStr)
+        throw SignalError.invalidRegistrationId(address: address, message: errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeInvalidProtocolAddress:
         var deviceId: UInt32 = 0
         let name = try invokeFnReturningString {
             signal_error_get_invalid_protocol_address($0, &deviceId, error)
         }
-        throw SignalError.invalidProtocolAddress(name: name, deviceId: deviceId, message: errStr)
+        throw SignalError.invalidProtocolAddress(
+            name: name,
+            deviceId: deviceId,
+            message: errStr,
+            protocolErrorCode: protocolErrorCode
+        )
     case SignalErrorCodeInvalidSenderKeySession:
         let distributionId = try invokeFnReturningUuid {
             signal_error_get_uuid($0, error)
         }
-        throw SignalError.invalidSenderKeySession(distributionId: distributionId, message: errStr)
+        throw SignalError.invalidSenderKeySession(
+            distributionId: distributionId,
+            message: errStr,
+            protocolErrorCode: protocolErrorCode
+        )
     case SignalErrorCodeDuplicatedMessage:
-        throw SignalError.duplicatedMessage(errStr)
+        throw SignalError.duplicatedMessage(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeVerificationFailure:
-        throw SignalError.verificationFailed(errStr)
+        throw SignalError.verificationFailed(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeUsernameCannotBeEmpty:
         throw SignalError.nicknameCannotBeEmpty(errStr)
     case SignalErrorCodeUsernameCannotStartWithDigit:
@@ -206,7 +266,7 @@ internal func checkError(_ error: SignalFfiErrorRef?) throws {
     case SignalErrorCodeUnsupportedMediaInput:
         throw SignalError.unsupportedMediaInput(errStr)
     case SignalErrorCodeCallbackError:
-        throw SignalError.callbackError(errStr)
+        throw SignalError.callbackError(errStr, protocolErrorCode: protocolErrorCode)
     case SignalErrorCodeWebSocket:
         throw SignalError.webSocketError(errStr)
     case SignalErrorCodeConnectionTimedOut:
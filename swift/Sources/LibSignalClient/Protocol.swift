@@ -6,6 +6,20 @@
 import Foundation
 import SignalFfi
 
+/// Whether new sessions use the post-quantum ratchet.
+public enum UsePqRatchet: UInt8, Sendable {
+    // This needs to be kept in sync with the Rust version of the enum.
+
+    /// Don't offer the post-quantum ratchet.
+    case no = 0
+
+    /// Offer the post-quantum ratchet, but fall back to the classical ratchet if the peer doesn't use it.
+    case yes = 1
+
+    /// Require the post-quantum ratchet; messages from a peer that doesn't use it are rejected.
+    case required = 2
+}
+
 public func signalEncrypt<Bytes: ContiguousBytes>(
     message: Bytes,
     for address: ProtocolAddress,
@@ -65,7 +79,7 @@ public func signalDecryptPreKey(
     signedPreKeyStore: SignedPreKeyStore,
     kyberPreKeyStore: KyberPreKeyStore,
     context: StoreContext,
-    usePqRatchet: Bool
+    usePqRatchet: UsePqRatchet
 ) throws -> Data {
     return try withAllBorrowed(message, address) { messageHandle, addressHandle in
         try withSessionStore(sessionStore, context) { ffiSessionStore in
@@ -74,7 +88,7 @@ public func signalDecryptPreKey(
                     try withSignedPreKeyStore(signedPreKeyStore, context) { ffiSignedPreKeyStore in
                         try withKyberPreKeyStore(kyberPreKeyStore, context) { ffiKyberPreKeyStore in
                             try invokeFnReturningData {
-                                signal_decrypt_pre_key_message(
+                                signal_decrypt_pre_key_message_with_pq_ratchet_policy(
                                     $0,
                                     messageHandle.const(),
                                     addressHandle.const(),
@@ -83,7 +97,7 @@ public func signalDecryptPreKey(
                                     ffiPreKeyStore,
                                     ffiSignedPreKeyStore,
                                     ffiKyberPreKeyStore,
-                                    usePqRatchet
+                                    usePqRatchet.rawValue
                                 )
                             }
                         }
@@ -94,6 +108,30 @@ public func signalDecryptPreKey(
     }
 }
 
+public func signalDecryptPreKey(
+    message: PreKeySignalMessage,
+    from address: ProtocolAddress,
+    sessionStore: SessionStore,
+    identityStore: IdentityKeyStore,
+    preKeyStore: PreKeyStore,
+    signedPreKeyStore: SignedPreKeyStore,
+    kyberPreKeyStore: KyberPreKeyStore,
+    context: StoreContext,
+    usePqRatchet: Bool
+) throws -> Data {
+    return try signalDecryptPreKey(
+        message: message,
+        from: address,
+        sessionStore: sessionStore,
+        identityStore: identityStore,
+        preKeyStore: preKeyStore,
+        signedPreKeyStore: signedPreKeyStore,
+        kyberPreKeyStore: kyberPreKeyStore,
+        context: context,
+        usePqRatchet: usePqRatchet ? UsePqRatchet.yes : UsePqRatchet.no
+    )
+}
+
 public func processPreKeyBundle(
     _ bundle: PreKeyBundle,
     for address: ProtocolAddress,
@@ -101,19 +139,19 @@ public func processPreKeyBundle(
     identityStore: IdentityKeyStore,
     now: Date = Date(),
     context: StoreContext,
-    usePqRatchet: Bool
+    usePqRatchet: UsePqRatchet
 ) throws {
     return try withAllBorrowed(bundle, address) { bundleHandle, addressHandle in
         try withSessionStore(sessionStore, context) { ffiSessionStore in
             try withIdentityKeyStore(identityStore, context) { ffiIdentityStore in
                 try checkError(
-                    signal_process_prekey_bundle(
+                    signal_process_prekey_bundle_with_pq_ratchet_policy(
                         bundleHandle.const(),
                         addressHandle.const(),
                         ffiSessionStore,
                         ffiIdentityStore,
                         UInt64(now.timeIntervalSince1970 * 1000),
-                        usePqRatchet
+                        usePqRatchet.rawValue
                     )
                 )
             }
@@ -121,6 +159,26 @@ public func processPreKeyBundle(
     }
 }
 
+public func processPreKeyBundle(
+    _ bundle: PreKeyBundle,
+    for address: ProtocolAddress,
+    sessionStore: SessionStore,
+    identityStore: IdentityKeyStore,
+    now: Date = Date(),
+    context: StoreContext,
+    usePqRatchet: Bool
+) throws {
+    try processPreKeyBundle(
+        bundle,
+        for: address,
+        sessionStore: sessionStore,
+        identityStore: identityStore,
+        now: now,
+        context: context,
+        usePqRatchet: usePqRatchet ? UsePqRatchet.yes : UsePqRatchet.no
+    )
+}
+
 public func groupEncrypt<Bytes: ContiguousBytes>(
     _ message: Bytes,
     from sender: ProtocolAddress,
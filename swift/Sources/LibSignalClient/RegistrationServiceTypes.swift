@@ -239,7 +239,8 @@ public enum ChallengeOption: Hashable, Sendable {
 /// Account attributes sent as part of a ``RegistrationService/registerAccount(accountPassword:skipDeviceTransfer:accountAttributes:apnPushToken:aciPublicKey:pniPublicKey:aciSignedPreKey:pniSignedPreKey:aciPqLastResortPreKey:pniPqLastResortPreKey:)`` request.
 public class RegisterAccountAttributes: NativeHandleOwner<SignalMutPointerRegistrationAccountAttributes> {
     /// Constructs the set of attributes to pass to the server.
-    /// - Throws: ``SignalError/invalidArgument(_:)`` if the `unidentifiedAccessKey` is not 16 bytes.
+    /// - Throws: ``SignalError/invalidArgument(_:protocolErrorCode:)`` if the `unidentifiedAccessKey` is not
+    ///   16 bytes.
     public convenience init(
         recoveryPassword: Data,
         aciRegistrationId: UInt16,
@@ -115,8 +115,8 @@ public class SvrB {
     /// - Returns: A ``StoreBackupResponse`` containing the forward secrecy token, metadata, and
     ///   secret data.
     /// - Throws:
-    ///   - ``SignalError/invalidArgument(_:)`` if `previousSecretData` is malformed. There's no
-    ///     choice here but to **start a new chain**.
+    ///   - ``SignalError/invalidArgument(_:protocolErrorCode:)`` if `previousSecretData` is malformed.
+    ///     There's no choice here but to **start a new chain**.
     ///   - ``SignalError/rateLimitedError(retryAfter:message:)`` if the server is rate limiting
     ///     this client. This is **retryable** after waiting the designated delay.
     ///   - ``SignalError/connectionFailed(_:)``, ``SignalError/ioError(_:)``, or
@@ -169,8 +169,8 @@ public class SvrB {
     ///     creation.
     /// - Returns: The forward secrecy token needed to derive keys for decrypting the backup.
     /// - Throws:
-    ///   - ``SignalError/invalidArgument(_:)`` if the backup metadata is malformed. In this case
-    ///     the user's data is **not recoverable**.
+    ///   - ``SignalError/invalidArgument(_:protocolErrorCode:)`` if the backup metadata is malformed. In
+    ///     this case the user's data is **not recoverable**.
     ///   - ``SignalError/svrRestoreFailed(triesRemaining:message:)`` if restoration fails. This
     ///     should never happen but if it does the user's data is **not recoverable**.
     ///   - ``SignalError/svrDataMissing(_:)`` if the backup data is not found on the server,
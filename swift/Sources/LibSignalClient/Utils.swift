@@ -325,7 +325,7 @@ internal func rethrowCallbackErrors<Store, Result>(
         return try withUnsafeMutablePointer(to: &context) {
             try body($0)
         }
-    } catch SignalError.callbackError(_) where context.error != nil {
+    } catch SignalError.callbackError(_, _) where context.error != nil {
         throw context.error!
     }
 }
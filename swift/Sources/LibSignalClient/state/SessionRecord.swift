@@ -6,6 +6,23 @@
 import Foundation
 import SignalFfi
 
+/// How far a session has got in negotiating the post-quantum ratchet with the peer.
+public enum PqRatchetStatus: UInt8, Sendable {
+    // This needs to be kept in sync with the Rust version of the enum.
+
+    /// The session was created without offering the post-quantum ratchet.
+    case disabled = 0
+
+    /// The post-quantum ratchet was offered, but no message from the peer has been decrypted yet.
+    case offered = 1
+
+    /// The peer has used the post-quantum ratchet, and may not stop using it.
+    case active = 2
+
+    /// The peer did not use the post-quantum ratchet, so the session uses the classical ratchet.
+    case declined = 3
+}
+
 public class SessionRecord: ClonableHandleOwner<SignalMutPointerSessionRecord> {
     override internal class func destroyNativeHandle(
         _ handle: NonNull<SignalMutPointerSessionRecord>
@@ -63,6 +80,18 @@ public class SessionRecord: ClonableHandleOwner<SignalMutPointerSessionRecord> {
         }
     }
 
+    /// How far the current session has got in negotiating the post-quantum ratchet.
+    ///
+    /// Throws if there is no current session.
+    public func pqRatchetStatus() throws -> PqRatchetStatus {
+        let rawValue = try self.withNativeHandle { nativeHandle in
+            try invokeFnReturningInteger {
+                signal_session_record_get_pq_ratchet_status($0, nativeHandle.const())
+            }
+        }
+        return PqRatchetStatus(rawValue: rawValue)!
+    }
+
     public func remoteRegistrationId() throws -> UInt32 {
         return try self.withNativeHandle { nativeHandle in
             try invokeFnReturningInteger {
@@ -89,8 +89,8 @@ public class GroupSendEndorsementsResponse: ByteArray, @unchecked Sendable {
     ///
     /// `localUser` should be included in `groupMembers`.
     ///
-    /// - Throws: ``SignalError/verificationFailed(_:)`` if the endorsements are not valid for any
-    ///   reason
+    /// - Throws: ``SignalError/verificationFailed(_:protocolErrorCode:)`` if the endorsements are not valid
+    ///   for any reason
     public func receive(
         groupMembers: some Collection<ServiceId>,
         localUser: Aci,
@@ -137,8 +137,8 @@ public class GroupSendEndorsementsResponse: ByteArray, @unchecked Sendable {
     ///
     /// `localUser` should be included in `groupMembers`.
     ///
-    /// - Throws: ``SignalError/verificationFailed(_:)`` if the endorsements are not valid for any
-    ///   reason
+    /// - Throws: ``SignalError/verificationFailed(_:protocolErrorCode:)`` if the endorsements are not valid
+    ///   for any reason
     public func receive(
         groupMembers: some Sequence<UuidCiphertext>,
         localUser: UuidCiphertext,
@@ -32,7 +32,7 @@ public class GroupSendFullToken: ByteArray, @unchecked Sendable {
     ///
     /// The correct `keyPair` must be selected based on ``expiration``.
     ///
-    /// - Throws: ``SignalError/verificationFailed(_:)`` if the token is invalid
+    /// - Throws: ``SignalError/verificationFailed(_:protocolErrorCode:)`` if the token is invalid
     public func verify(
         userIds: [ServiceId],
         now: Date = Date(),
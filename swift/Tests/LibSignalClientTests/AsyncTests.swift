@@ -137,7 +137,7 @@ final class AsyncTests: TestCaseBase {
                 )
             }
             XCTFail("should have failed")
-        } catch SignalError.invalidArgument(_) {
+        } catch SignalError.invalidArgument(_, _) {
             // good
         }
     }
@@ -27,14 +27,14 @@ final class BridgingTests: XCTestCase {
         do {
             try checkError(signal_testing_error_on_borrow_sync(nil))
             XCTFail("should have failed")
-        } catch SignalError.invalidArgument(_) {
+        } catch SignalError.invalidArgument(_, _) {
             // good
         }
 
         do {
             try checkError(signal_testing_error_on_borrow_async(nil))
             XCTFail("should have failed")
-        } catch SignalError.invalidArgument(_) {
+        } catch SignalError.invalidArgument(_, _) {
             // good
         }
 
@@ -43,7 +43,7 @@ final class BridgingTests: XCTestCase {
                 signal_testing_error_on_borrow_io($0, fakeAsyncRuntime(), nil)
             }
             XCTFail("should have failed")
-        } catch SignalError.invalidArgument(_) {
+        } catch SignalError.invalidArgument(_, _) {
             // good
         }
     }
@@ -52,14 +52,14 @@ final class BridgingTests: XCTestCase {
         do {
             try checkError(signal_testing_panic_on_borrow_sync(nil))
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
 
         do {
             try checkError(signal_testing_panic_on_borrow_async(nil))
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
 
@@ -68,7 +68,7 @@ final class BridgingTests: XCTestCase {
                 signal_testing_panic_on_borrow_io($0, fakeAsyncRuntime(), nil)
             }
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
     }
@@ -77,14 +77,14 @@ final class BridgingTests: XCTestCase {
         do {
             try checkError(signal_testing_panic_on_load_sync(nil, nil))
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
 
         do {
             try checkError(signal_testing_panic_on_load_async(nil, nil))
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
 
@@ -93,7 +93,7 @@ final class BridgingTests: XCTestCase {
                 signal_testing_panic_on_load_io($0, fakeAsyncRuntime(), nil, nil)
             }
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
     }
@@ -102,14 +102,14 @@ final class BridgingTests: XCTestCase {
         do {
             try checkError(signal_testing_panic_in_body_sync(nil))
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
 
         do {
             try checkError(signal_testing_panic_in_body_async(nil))
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
 
@@ -118,7 +118,7 @@ final class BridgingTests: XCTestCase {
                 signal_testing_panic_in_body_io($0, fakeAsyncRuntime(), nil)
             }
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
     }
@@ -127,14 +127,14 @@ final class BridgingTests: XCTestCase {
         do {
             try invokeFnIgnoringResult { signal_testing_error_on_return_sync($0, nil) }
             XCTFail("should have failed")
-        } catch SignalError.invalidArgument(_) {
+        } catch SignalError.invalidArgument(_, _) {
             // good
         }
 
         do {
             try invokeFnIgnoringResult { signal_testing_error_on_return_async($0, nil) }
             XCTFail("should have failed")
-        } catch SignalError.invalidArgument(_) {
+        } catch SignalError.invalidArgument(_, _) {
             // good
         }
 
@@ -143,7 +143,7 @@ final class BridgingTests: XCTestCase {
                 signal_testing_error_on_return_io($0, fakeAsyncRuntime(), nil)
             }
             XCTFail("should have failed")
-        } catch SignalError.invalidArgument(_) {
+        } catch SignalError.invalidArgument(_, _) {
             // good
         }
     }
@@ -152,14 +152,14 @@ final class BridgingTests: XCTestCase {
         do {
             try invokeFnIgnoringResult { signal_testing_panic_on_return_sync($0, nil) }
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
 
         do {
             try invokeFnIgnoringResult { signal_testing_panic_on_return_async($0, nil) }
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
 
@@ -168,7 +168,7 @@ final class BridgingTests: XCTestCase {
                 signal_testing_panic_on_return_io($0, fakeAsyncRuntime(), nil)
             }
             XCTFail("should have failed")
-        } catch SignalError.internalError(_) {
+        } catch SignalError.internalError(_, _) {
             // good
         }
     }
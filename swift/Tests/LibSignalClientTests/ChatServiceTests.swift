@@ -121,7 +121,7 @@ final class ChatServiceTests: TestCaseBase {
 
         do {
             try failWithError("RequestHasInvalidHeader")
-        } catch SignalError.internalError(_) {}
+        } catch SignalError.internalError(_, _) {}
     }
 
     func testConstructRequest() throws {
@@ -95,7 +95,7 @@ class CryptoTests: TestCaseBase {
         var encryptedWithBadTag = encryptedData
         encryptedWithBadTag[encryptedWithBadTag.count - 1] ^= 0xFF
         XCTAssertThrowsError(try Aes256GcmEncryptedData(concatenated: encryptedWithBadTag).decrypt(key: key)) {
-            guard case SignalError.invalidMessage(_) = $0 else {
+            guard case SignalError.invalidMessage(_, _) = $0 else {
                 XCTFail("wrong error: \($0)")
                 return
             }
@@ -82,7 +82,7 @@ final class NetTests {
         }
         do {
             try failWithError("InvalidArgument")
-        } catch SignalError.invalidArgument(let message) {
+        } catch SignalError.invalidArgument(let message, _) {
             #expect(message == "invalid argument: request was invalid: fake reason")
         }
         do {
@@ -19,7 +19,7 @@ class PinTests: TestCaseBase {
 
     func testBadEncodedHash() {
         XCTAssertThrowsError(try verifyLocalPin(Array("password".utf8), againstEncodedHash: "not-a-hash")) {
-            guard case SignalError.invalidArgument(_) = $0 else {
+            guard case SignalError.invalidArgument(_, _) = $0 else {
                 XCTFail("wrong error: \($0)")
                 return
             }
@@ -57,7 +57,7 @@ class PublicAPITests: TestCaseBase {
         do {
             _ = try ProtocolAddress(name: "addr1", deviceId: 128)
             XCTFail("should have thrown")
-        } catch SignalError.invalidProtocolAddress(let name, let deviceId, message: _) {
+        } catch SignalError.invalidProtocolAddress(let name, let deviceId, message: _, protocolErrorCode: _) {
             XCTAssertEqual(deviceId, 128)
             XCTAssertEqual(name, "addr1")
         }
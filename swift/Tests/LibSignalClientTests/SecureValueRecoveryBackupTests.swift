@@ -70,7 +70,7 @@ final class SecureValueRecoveryBackupTests: TestCaseBase {
             )
         } errorHandler: { error in
             guard let signalError = error as? SignalError,
-                case .invalidArgument(let message) = signalError
+                case .invalidArgument(let message, _) = signalError
             else {
                 XCTFail("Expected SignalError.invalidArgument, got \(error)")
                 return
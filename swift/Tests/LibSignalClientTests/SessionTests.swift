@@ -49,7 +49,7 @@ class SessionTests: TestCaseBase {
                 signedPreKeyStore: bob_store,
                 kyberPreKeyStore: bob_store,
                 context: NullContext(),
-                usePqRatchet: true
+                usePqRatchet: .yes
             )
 
             XCTAssertEqual(ptext_a, ptext_b)
@@ -118,7 +118,7 @@ class SessionTests: TestCaseBase {
                     signedPreKeyStore: bob_store,
                     kyberPreKeyStore: bob_store,
                     context: NullContext(),
-                    usePqRatchet: true
+                    usePqRatchet: .yes
                 ),
                 "should fail to decrypt"
             ) { error in
@@ -178,7 +178,7 @@ class SessionTests: TestCaseBase {
             identityStore: alice_store,
             now: Date(timeIntervalSinceReferenceDate: 0),
             context: NullContext(),
-            usePqRatchet: true
+            usePqRatchet: .yes
         )
 
         let initial_session = try! alice_store.loadSession(for: bob_address, context: NullContext())!
@@ -322,10 +322,10 @@ class SessionTests: TestCaseBase {
                 signedPreKeyStore: bob_store,
                 kyberPreKeyStore: bob_store,
                 context: NullContext(),
-                usePqRatchet: true
+                usePqRatchet: .yes
             )
             XCTFail("should have thrown")
-        } catch SignalError.invalidMessage(_) {
+        } catch SignalError.invalidMessage(_, _) {
             // okay, this is what InMemorySignalProtocolStore throws in this case
         } catch {
             XCTFail("unexpected error: \(error)")
@@ -431,7 +431,7 @@ class SessionTests: TestCaseBase {
             signedPreKeyStore: bob_store,
             kyberPreKeyStore: bob_store,
             context: NullContext(),
-            usePqRatchet: true
+            usePqRatchet: .yes
         )
 
         XCTAssertEqual(plaintext, message)
@@ -675,8 +675,9 @@ class SessionTests: TestCaseBase {
                 context: NullContext()
             )
             XCTFail("should have thrown")
-        } catch SignalError.invalidRegistrationId(address: let address, message: _) {
+        } catch SignalError.invalidRegistrationId(address: let address, message: _, protocolErrorCode: let code) {
             XCTAssertEqual(address, bob_address)
+            XCTAssertEqual(code, 208)
         }
     }
 
@@ -782,7 +783,7 @@ class SessionTests: TestCaseBase {
             signedPreKeyStore: alice_store,
             kyberPreKeyStore: alice_store,
             context: NullContext(),
-            usePqRatchet: true
+            usePqRatchet: .yes
         )
 
         let bob_message = try signalEncrypt(
@@ -902,7 +903,7 @@ private func initializeSessionsV4(
         sessionStore: alice_store,
         identityStore: alice_store,
         context: NullContext(),
-        usePqRatchet: true
+        usePqRatchet: .yes
     )
 
     XCTAssertEqual(try! alice_store.loadSession(for: bob_address, context: NullContext())?.hasCurrentState, true)
@@ -910,6 +911,10 @@ private func initializeSessionsV4(
         try! alice_store.loadSession(for: bob_address, context: NullContext())?.remoteRegistrationId(),
         try! bob_store.localRegistrationId(context: NullContext())
     )
+    XCTAssertEqual(
+        try! alice_store.loadSession(for: bob_address, context: NullContext())?.pqRatchetStatus(),
+        .offered
+    )
 
     // Bob does the same:
     try! bob_store.storePreKey(
@@ -302,7 +302,7 @@ class ZKGroupTests {
         do {
             try serverPublicParams.verifySignature(message: alteredMessage, notarySignature: signature)
             Issue.record("should have thrown")
-        } catch SignalError.verificationFailed(_) {
+        } catch SignalError.verificationFailed(_, _) {
             // good
         }
     }